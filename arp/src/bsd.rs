@@ -0,0 +1,369 @@
+//! The FreeBSD and macOS implementation using `PF_ROUTE` socket messages.
+
+use std::{
+    ffi::{CStr, CString},
+    io,
+    mem,
+    net::Ipv4Addr,
+    ptr,
+    slice,
+    time::Duration,
+};
+
+use eui48::{EUI48LEN, MacAddress};
+use futures::future;
+use libc::{self, c_int, c_uint, c_ushort, c_void, pid_t, sockaddr_dl, sockaddr_in};
+
+const RTM_VERSION: u8 = 5;
+const RTM_ADD: u8 = 0x1;
+const RTM_DELETE: u8 = 0x2;
+const RTM_GET: u8 = 0x4;
+
+const RTF_HOST: c_int = 0x4;
+const RTF_STATIC: c_int = 0x800;
+/// Called `RTF_LLDATA` on FreeBSD, but has the same value.
+const RTF_LLINFO: c_int = 0x400;
+
+const RTA_DST: c_int = 0x1;
+const RTA_GATEWAY: c_int = 0x2;
+
+const IFT_ETHER: u8 = 0x6;
+
+/// The `rt_metrics` structure, which is only used as opaque space in the messages.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rt_metrics {
+    rmx: [u32; 14],
+}
+#[cfg(target_os = "freebsd")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct rt_metrics {
+    rmx: [libc::c_ulong; 14],
+}
+
+/// The routing message header.
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct rt_msghdr {
+    rtm_msglen: c_ushort,
+    rtm_version: u8,
+    rtm_type: u8,
+    rtm_index: c_ushort,
+    rtm_flags: c_int,
+    rtm_addrs: c_int,
+    rtm_pid: pid_t,
+    rtm_seq: c_int,
+    rtm_errno: c_int,
+    rtm_use: c_int,
+    rtm_inits: u32,
+    rtm_rmx: rt_metrics,
+}
+#[cfg(target_os = "freebsd")]
+#[repr(C)]
+struct rt_msghdr {
+    rtm_msglen: c_ushort,
+    rtm_version: u8,
+    rtm_type: u8,
+    rtm_index: c_ushort,
+    _rtm_spare1: c_ushort,
+    rtm_flags: c_int,
+    rtm_addrs: c_int,
+    rtm_pid: pid_t,
+    rtm_seq: c_int,
+    rtm_errno: c_int,
+    rtm_fmask: c_int,
+    rtm_inits: libc::c_ulong,
+    rtm_rmx: rt_metrics,
+}
+
+/// The routing message carrying a destination and a link-layer gateway.
+#[repr(C)]
+struct rt_msg {
+    header: rt_msghdr,
+    destination: sockaddr_in,
+    gateway: sockaddr_dl,
+}
+
+/// The entry is added synchronously, so there is nothing to wait for.
+pub(crate) type Handle = ();
+
+#[derive(Debug)]
+pub enum Error {
+    Interface(io::Error),
+    Socket(io::Error),
+    Syscall(io::Error),
+}
+
+pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<Handle, Error> {
+    let index = interface_index(&iface).map_err(|error| Error::Interface(error))?;
+
+    let mut msg = message(RTM_ADD, ip);
+    msg.header.rtm_flags = RTF_HOST | RTF_STATIC | RTF_LLINFO;
+    msg.header.rtm_addrs = RTA_DST | RTA_GATEWAY;
+    msg.header.rtm_index = index as c_ushort;
+    msg.header.rtm_msglen = mem::size_of::<rt_msg>() as c_ushort;
+
+    msg.gateway.sdl_len = mem::size_of::<sockaddr_dl>() as u8;
+    msg.gateway.sdl_family = libc::AF_LINK as u8;
+    msg.gateway.sdl_index = index as c_ushort;
+    msg.gateway.sdl_type = IFT_ETHER;
+    msg.gateway.sdl_alen = EUI48LEN as u8;
+    unsafe {
+        ptr::copy_nonoverlapping(
+            hwaddr.as_bytes().as_ptr(),
+            msg.gateway.sdl_data.as_mut_ptr() as *mut u8,
+            EUI48LEN,
+        )
+    };
+
+    let fd = route_socket().map_err(|error| Error::Socket(error))?;
+    let result = send(fd, &msg);
+    unsafe { libc::close(fd) };
+    result.map_err(|error| Error::Syscall(error))?;
+
+    Ok(())
+}
+
+pub(crate) fn remove(ip: Ipv4Addr, iface: String) -> Result<(), Error> {
+    let index = interface_index(&iface).map_err(|error| Error::Interface(error))?;
+
+    let mut msg = message(RTM_DELETE, ip);
+    msg.header.rtm_flags = RTF_HOST | RTF_LLINFO;
+    msg.header.rtm_addrs = RTA_DST;
+    msg.header.rtm_index = index as c_ushort;
+    msg.header.rtm_msglen =
+        (mem::size_of::<rt_msghdr>() + mem::size_of::<sockaddr_in>()) as c_ushort;
+
+    let fd = route_socket().map_err(|error| Error::Socket(error))?;
+    let result = send(fd, &msg);
+    unsafe { libc::close(fd) };
+    match result {
+        Err(ref error) if error.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        result => result.map_err(|error| Error::Syscall(error)),
+    }
+}
+
+/// Reads the hardware address of the ARP entry of `ip` back.
+#[allow(unused)]
+pub(crate) fn get(ip: Ipv4Addr) -> io::Result<Option<MacAddress>> {
+    let mut msg = message(RTM_GET, ip);
+    msg.header.rtm_flags = RTF_HOST | RTF_LLINFO;
+    msg.header.rtm_addrs = RTA_DST;
+    msg.header.rtm_msglen =
+        (mem::size_of::<rt_msghdr>() + mem::size_of::<sockaddr_in>()) as c_ushort;
+
+    let fd = route_socket()?;
+    let result = send(fd, &msg).and_then(|_| receive(fd, msg.header.rtm_seq));
+    unsafe { libc::close(fd) };
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(ref error) if error.raw_os_error() == Some(libc::ESRCH) => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    /*
+    The addresses follow the header in the order of their RTA_* bits,
+    each padded to the platform alignment.
+    */
+    let header = unsafe { &*(reply.as_ptr() as *const rt_msghdr) };
+    let mut offset = mem::size_of::<rt_msghdr>();
+    for bit in 0..8 {
+        if header.rtm_addrs & (1 << bit) == 0 {
+            continue;
+        }
+        if offset >= reply.len() {
+            break;
+        }
+        let length = reply[offset] as usize;
+        if 1 << bit == RTA_GATEWAY {
+            if offset + length > reply.len() || length < 8 {
+                break;
+            }
+            let gateway = unsafe { &*(reply[offset..].as_ptr() as *const sockaddr_dl) };
+            if gateway.sdl_family as c_int != libc::AF_LINK || gateway.sdl_alen as usize != EUI48LEN
+            {
+                return Ok(None);
+            }
+            let start = offset + 8 + gateway.sdl_nlen as usize;
+            if start + EUI48LEN > reply.len() {
+                break;
+            }
+            return Ok(MacAddress::from_bytes(&reply[start..start + EUI48LEN]).ok());
+        }
+        offset += align(length);
+    }
+    Ok(None)
+}
+
+pub(crate) fn mac_of_interface(iface: &str) -> io::Result<MacAddress> {
+    let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut result = None;
+    let mut current = addrs;
+    while !current.is_null() {
+        let entry = unsafe { &*current };
+        current = entry.ifa_next;
+        if entry.ifa_addr.is_null() {
+            continue;
+        }
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) };
+        if name.to_bytes() != iface.as_bytes() {
+            continue;
+        }
+        if unsafe { (*entry.ifa_addr).sa_family } as c_int != libc::AF_LINK {
+            continue;
+        }
+        let link = unsafe { &*(entry.ifa_addr as *const sockaddr_dl) };
+        let data = unsafe {
+            slice::from_raw_parts(
+                link.sdl_data.as_ptr() as *const u8,
+                link.sdl_nlen as usize + link.sdl_alen as usize,
+            )
+        };
+        result = Some(if link.sdl_alen as usize == EUI48LEN {
+            MacAddress::from_bytes(&data[link.sdl_nlen as usize..]).ok()
+        } else {
+            Some(MacAddress::nil())
+        });
+        break;
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+
+    match result {
+        Some(Some(address)) => Ok(address),
+        _ => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Interface {} has no hardware address", iface),
+        )),
+    }
+}
+
+fn message(kind: u8, ip: Ipv4Addr) -> rt_msg {
+    let mut msg: rt_msg = unsafe { mem::zeroed() };
+    msg.header.rtm_version = RTM_VERSION;
+    msg.header.rtm_type = kind;
+    msg.header.rtm_pid = unsafe { libc::getpid() };
+    msg.header.rtm_seq = 1;
+
+    msg.destination.sin_len = mem::size_of::<sockaddr_in>() as u8;
+    msg.destination.sin_family = libc::AF_INET as u8;
+    msg.destination.sin_addr.s_addr = u32::from(ip).to_be();
+    msg
+}
+
+fn interface_index(iface: &str) -> io::Result<c_uint> {
+    let name =
+        CString::new(iface).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+fn route_socket() -> io::Result<c_int> {
+    match unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_INET) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(fd),
+    }
+}
+
+fn send(fd: c_int, msg: &rt_msg) -> io::Result<()> {
+    let length = msg.header.rtm_msglen as usize;
+    match unsafe { libc::write(fd, msg as *const rt_msg as *const c_void, length) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the kernel replies until the one to our request arrives.
+fn receive(fd: c_int, seq: c_int) -> io::Result<Vec<u8>> {
+    let pid = unsafe { libc::getpid() };
+    let mut buffer = vec![0u8; 512];
+    loop {
+        let length = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) };
+        if length < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if (length as usize) < mem::size_of::<rt_msghdr>() {
+            continue;
+        }
+        let header = unsafe { &*(buffer.as_ptr() as *const rt_msghdr) };
+        if header.rtm_pid == pid && header.rtm_seq == seq {
+            buffer.truncate(length as usize);
+            return Ok(buffer);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn align(length: usize) -> usize {
+    let word = mem::size_of::<u32>();
+    if length == 0 {
+        word
+    } else {
+        (length + word - 1) & !(word - 1)
+    }
+}
+#[cfg(target_os = "freebsd")]
+fn align(length: usize) -> usize {
+    let word = mem::size_of::<libc::c_long>();
+    if length == 0 {
+        word
+    } else {
+        (length + word - 1) & !(word - 1)
+    }
+}
+
+
+/// Probing is not supported, so a probe is never started.
+pub(crate) type Probe = future::Empty<bool, io::Error>;
+
+/// The packet sockets are only implemented on Linux.
+pub(crate) fn probe(_ip: Ipv4Addr, _iface: &str, _timeout: Duration) -> io::Result<Probe> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "ARP probing is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, str::FromStr};
+
+    use super::*;
+
+    /// Set to an Ethernet interface and an unused address of its network, e.g. `em0 192.168.0.201`.
+    const TEST_ENTRY_VARIABLE: &str = "DHCP_ARP_TEST_ENTRY";
+
+    #[test]
+    fn adds_reads_back_and_removes_entry() {
+        // Installing ARP entries requires root privileges.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let entry = match env::var(TEST_ENTRY_VARIABLE) {
+            Ok(entry) => entry,
+            Err(_) => return,
+        };
+        let mut parts = entry.split_whitespace();
+        let iface = parts.next().unwrap().to_owned();
+        let ip = Ipv4Addr::from_str(parts.next().unwrap()).unwrap();
+        let hwaddr = MacAddress::new([0x02, 0x00, 0x5e, 0x10, 0x20, 0x30]);
+
+        add(hwaddr, ip, iface.clone()).unwrap();
+        assert_eq!(get(ip).unwrap(), Some(hwaddr));
+        remove(ip, iface.clone()).unwrap();
+        assert_eq!(get(ip).unwrap(), None);
+        remove(ip, iface).unwrap();
+    }
+
+    #[test]
+    fn fails_on_missing_interface() {
+        assert!(mac_of_interface("nonexistent0").is_err());
+    }
+}
@@ -6,20 +6,39 @@ mod os;
 #[cfg(target_os = "windows")]
 #[path = "windows.rs"]
 mod os;
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+#[path = "bsd.rs"]
+mod os;
 
 extern crate eui48;
+extern crate futures;
 
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
 extern crate libc;
 #[cfg(target_os = "linux")]
+extern crate mio;
+#[cfg(target_os = "linux")]
 #[macro_use]
 extern crate nix;
+#[cfg(target_os = "linux")]
+extern crate tokio;
 #[cfg(target_os = "windows")]
 extern crate tokio_process;
 
-use std::net::Ipv4Addr;
+use std::{io, net::Ipv4Addr, time::Duration};
+
+use eui48::{EUI48LEN, MacAddress};
+use futures::{Future, Poll};
 
-use eui48::MacAddress;
+/// The Ethernet header and the Ethernet/IPv4 ARP packet.
+const SIZE_ARP_FRAME: usize = 42;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ARP_HARDWARE_ETHERNET: u16 = 1;
+const ARP_OPERATION_REQUEST: u16 = 1;
+const OFFSET_ETHERTYPE: usize = 12;
+const OFFSET_SENDER_IP: usize = 28;
+const OFFSET_TARGET_IP: usize = 38;
 
 /// The OS-polymorphic OS-error.
 #[derive(Debug)]
@@ -31,15 +50,125 @@ impl From<os::Error> for Error {
     }
 }
 
-#[cfg(target_os = "linux")]
-pub type Arp = ();
+/// The handle of an added ARP entry.
+///
+/// On Windows it runs the `netsh` processes adding the entry while it is polled
+/// and deletes the entry once dropped, so it must be kept as long as the entry is needed.
+/// On the other systems the entry is already added.
+pub struct Arp(os::Handle);
+
+#[cfg(target_os = "windows")]
+impl Arp {
+    /// Takes over another addition of the same address, which rebinds the entry.
+    ///
+    /// Used instead of replacing the handle, whose drop would delete the entry.
+    pub fn append(&mut self, other: Arp) {
+        self.0.append(other.0)
+    }
+}
+
 #[cfg(target_os = "windows")]
-pub type Arp = (
-    Option<tokio_process::OutputAsync>,
-    Option<tokio_process::OutputAsync>,
-);
+impl Future for Arp {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        Ok(self.0.poll()?)
+    }
+}
 
 /// The facade function choosing the OS implementation.
 pub fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<Arp, Error> {
-    Ok(os::add(hwaddr, ip, iface)?)
+    Ok(Arp(os::add(hwaddr, ip, iface)?))
+}
+
+/// Removes the ARP entry of `ip` if there is one.
+///
+/// Removing a missing entry is not an error.
+pub fn remove(ip: Ipv4Addr, iface: String) -> Result<(), Error> {
+    Ok(os::remove(ip, iface)?)
+}
+
+/// Resolves the hardware address of the network interface with the given name.
+pub fn mac_of_interface(iface: &str) -> io::Result<MacAddress> {
+    os::mac_of_interface(iface)
+}
+
+/// The ARP probe waiting for a packet from the probed address.
+///
+/// Resolves to `true` if any ARP packet from the address arrives within the timeout.
+/// The socket is registered with the tokio reactor, so the probe must be polled on it.
+pub struct Probe(os::Probe);
+
+impl Future for Probe {
+    type Item = bool;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
+/// Checks whether some host on the interface uses the address with an ARP probe.
+///
+/// The probe is an ARP request with the zero sender address (RFC 5227 §2.1.1),
+/// so it does not pollute the ARP caches of the other hosts.
+/// The request is sent at once and the reply is waited for by the returned future.
+/// Requires the privileges to open raw sockets.
+pub fn probe(ip: Ipv4Addr, iface: &str, timeout: Duration) -> io::Result<Probe> {
+    Ok(Probe(os::probe(ip, iface, timeout)?))
+}
+
+/// Builds the broadcast Ethernet frame with the ARP probe for `ip`.
+pub(crate) fn probe_frame(hwaddr: MacAddress, ip: Ipv4Addr) -> [u8; SIZE_ARP_FRAME] {
+    let mut frame = [0u8; SIZE_ARP_FRAME];
+    frame[..EUI48LEN].copy_from_slice(MacAddress::broadcast().as_bytes());
+    frame[EUI48LEN..OFFSET_ETHERTYPE].copy_from_slice(hwaddr.as_bytes());
+    let header = [
+        ETHERTYPE_ARP,
+        ARP_HARDWARE_ETHERNET,
+        ETHERTYPE_IPV4,
+        (EUI48LEN as u16) << 8 | 4,
+        ARP_OPERATION_REQUEST,
+    ];
+    for (i, word) in header.iter().enumerate() {
+        let offset = OFFSET_ETHERTYPE + i * 2;
+        frame[offset..offset + 2].copy_from_slice(&[(word >> 8) as u8, *word as u8]);
+    }
+    frame[22..OFFSET_SENDER_IP].copy_from_slice(hwaddr.as_bytes());
+    // the sender IP address and the target hardware address are left zero
+    frame[OFFSET_TARGET_IP..].copy_from_slice(&ip.octets());
+    frame
+}
+
+/// Checks whether the Ethernet frame is an ARP packet sent from `ip`.
+///
+/// Both replies and requests count, since a host announcing or probing
+/// the address is going to use it as well (RFC 5227 §2.1.1).
+pub(crate) fn is_sent_from(frame: &[u8], ip: Ipv4Addr) -> bool {
+    frame.len() >= SIZE_ARP_FRAME
+        && frame[OFFSET_ETHERTYPE..OFFSET_ETHERTYPE + 2]
+            == [(ETHERTYPE_ARP >> 8) as u8, ETHERTYPE_ARP as u8]
+        && frame[OFFSET_SENDER_IP..OFFSET_SENDER_IP + 4] == ip.octets()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_only_arp_packets_from_probed_address() {
+        let ip = Ipv4Addr::new(192, 168, 0, 100);
+        let probe = probe_frame(MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]), ip);
+        assert_eq!(&probe[12..22], &[0x08, 0x06, 0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+        assert!(!is_sent_from(&probe, ip));
+
+        // the reply swaps the sender and target of the probe
+        let mut reply = probe;
+        reply[21] = 2;
+        reply[OFFSET_SENDER_IP..OFFSET_SENDER_IP + 4].copy_from_slice(&ip.octets());
+        assert!(is_sent_from(&reply, ip));
+        assert!(!is_sent_from(&reply, Ipv4Addr::new(192, 168, 0, 101)));
+        assert!(!is_sent_from(&reply[..SIZE_ARP_FRAME - 1], ip));
+    }
 }
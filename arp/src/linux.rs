@@ -1,26 +1,48 @@
-//! The Linux implementation using SIOCSARP syscall.
+//! The Linux implementation using SIOCSARP and SIOCGIFHWADDR syscalls.
 
 use std::{
     cmp,
+    ffi::CString,
+    io,
     mem,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ptr,
+    time::{Duration, Instant},
 };
 
 use eui48::{EUI48LEN, MacAddress};
-use libc::{self, arpreq, c_char, c_int, c_ushort};
+use futures::{Async, Future, Poll};
+use libc::{self, arpreq, c_char, c_int, c_uint, c_ushort, c_void, sockaddr, sockaddr_ll};
+use mio::{self, unix::EventedFd, Evented, PollOpt, Ready, Token};
 use nix::{
     self,
     sys::socket::{self, AddressFamily, SockFlag, SockType},
 };
+use tokio::{reactor::PollEvented2, timer::Delay};
 
 const ARPHRD_ETHER: c_ushort = 0x01;
 const AF_INET: c_ushort = 0x02;
 const ATF_COM: c_int = 0x02;
 
 const MAX_IFACE_LEN: usize = 15;
+const ETH_P_ARP: u16 = 0x0806;
+const SIZE_BUFFER: usize = 1500;
+
+/// The `ifreq` structure with only the `ifr_hwaddr` member of its union.
+#[repr(C)]
+struct ifreq {
+    ifr_name: [c_char; MAX_IFACE_LEN + 1],
+    ifr_hwaddr: sockaddr,
+    /// The union is as large as its biggest `ifmap` member.
+    _padding: [u8; 8],
+}
 
 ioctl_write_ptr_bad!(siocsarp, libc::SIOCSARP, arpreq);
+ioctl_write_ptr_bad!(siocdarp, libc::SIOCDARP, arpreq);
+ioctl_read_bad!(siocgifhwaddr, libc::SIOCGIFHWADDR, ifreq);
+
+/// The entry is added synchronously, so there is nothing to wait for.
+pub(crate) type Handle = ();
 
 #[derive(Debug)]
 pub enum Error {
@@ -28,7 +50,7 @@ pub enum Error {
     Syscall(nix::Error),
 }
 
-pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<super::Arp, Error> {
+pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<Handle, Error> {
     let mut req: arpreq = unsafe { mem::zeroed() };
 
     let addr = SocketAddr::new(IpAddr::V4(ip), 0);
@@ -71,3 +93,276 @@ pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<sup
 
     Ok(())
 }
+
+pub(crate) fn remove(ip: Ipv4Addr, iface: String) -> Result<(), Error> {
+    let mut req: arpreq = unsafe { mem::zeroed() };
+
+    let addr = SocketAddr::new(IpAddr::V4(ip), 0);
+    req.arp_pa = unsafe {
+        *socket::SockAddr::Inet(socket::InetAddr::from_std(&addr))
+            .as_ffi_pair()
+            .0
+    };
+
+    let iface_len = cmp::min(iface.len(), MAX_IFACE_LEN);
+    unsafe {
+        ptr::copy_nonoverlapping(
+            iface.as_ptr() as *const c_char,
+            req.arp_dev.as_mut_ptr(),
+            iface_len,
+        )
+    };
+
+    let fd = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        None,
+    )
+    .map_err(Error::Socket)?;
+
+    let result = unsafe { siocdarp(fd, &req) };
+    let _ = nix::unistd::close(fd);
+    match result {
+        Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::ENXIO)) => Ok(()),
+        Err(error) => Err(Error::Syscall(error)),
+    }
+}
+
+pub(crate) fn mac_of_interface(iface: &str) -> io::Result<MacAddress> {
+    let mut req: ifreq = unsafe { mem::zeroed() };
+
+    let iface_len = cmp::min(iface.len(), MAX_IFACE_LEN);
+    unsafe {
+        ptr::copy_nonoverlapping(
+            iface.as_ptr() as *const c_char,
+            req.ifr_name.as_mut_ptr(),
+            iface_len,
+        )
+    };
+
+    let fd = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        None,
+    ).map_err(to_io_error)?;
+
+    let result = unsafe { siocgifhwaddr(fd, &mut req) };
+    let _ = nix::unistd::close(fd);
+    result.map_err(to_io_error)?;
+
+    let mut bytes = [0u8; EUI48LEN];
+    for (byte, data) in bytes.iter_mut().zip(req.ifr_hwaddr.sa_data.iter()) {
+        *byte = *data as u8;
+    }
+    Ok(MacAddress::new(bytes))
+}
+
+/// The packet socket closed on drop.
+struct PacketSocket(c_int);
+
+impl Evented for PacketSocket {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0).deregister(poll)
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// The probe sent through the packet socket bound to the interface.
+pub(crate) struct Probe {
+    socket: PollEvented2<PacketSocket>,
+    ip: Ipv4Addr,
+    deadline: Delay,
+}
+
+impl Future for Probe {
+    type Item = bool;
+    type Error = io::Error;
+
+    #[allow(clippy::io_other_error)]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut buffer = [0u8; SIZE_BUFFER];
+        loop {
+            if let Async::NotReady = self.socket.poll_read_ready(Ready::readable())? {
+                break;
+            }
+            let fd = self.socket.get_ref().0;
+            let size =
+                unsafe { libc::recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len(), 0) };
+            if size < 0 {
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => {
+                        self.socket.clear_read_ready(Ready::readable())?;
+                        break;
+                    }
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                }
+            }
+            if super::is_sent_from(&buffer[..size as usize], self.ip) {
+                return Ok(Async::Ready(true));
+            }
+        }
+
+        match self.deadline.poll() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(false)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(error) => Err(io::Error::new(io::ErrorKind::Other, error)),
+        }
+    }
+}
+
+pub(crate) fn probe(ip: Ipv4Addr, iface: &str, timeout: Duration) -> io::Result<Probe> {
+    let index = interface_index(iface)?;
+    let hwaddr = mac_of_interface(iface)?;
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            c_int::from(ETH_P_ARP.to_be()),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = PacketSocket(fd);
+
+    let mut addr: sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as c_ushort;
+    addr.sll_protocol = ETH_P_ARP.to_be();
+    addr.sll_ifindex = index as c_int;
+    let result = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const sockaddr_ll as *const sockaddr,
+            mem::size_of::<sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // a single frame fits the empty send buffer of the new socket
+    let frame = super::probe_frame(hwaddr, ip);
+    let result = unsafe { libc::send(fd, frame.as_ptr() as *const c_void, frame.len(), 0) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Probe {
+        socket: PollEvented2::new(socket),
+        ip,
+        deadline: Delay::new(Instant::now() + timeout),
+    })
+}
+
+fn interface_index(iface: &str) -> io::Result<c_uint> {
+    let name =
+        CString::new(iface).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+#[allow(clippy::io_other_error)]
+fn to_io_error(error: nix::Error) -> io::Error {
+    match error {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        error => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, str::FromStr};
+
+    use super::*;
+
+    /// Set to an Ethernet interface and an unused address, e.g. `eth0 192.168.0.201`.
+    const TEST_ENTRY_VARIABLE: &str = "DHCP_ARP_TEST_ENTRY";
+
+    fn is_in_arp_table(ip: Ipv4Addr) -> bool {
+        fs::read_to_string("/proc/net/arp")
+            .unwrap()
+            .lines()
+            .skip(1)
+            .any(|line| line.split_whitespace().next() == Some(&ip.to_string()))
+    }
+
+    #[test]
+    fn resolves_loopback_mac() {
+        assert_eq!(mac_of_interface("lo").unwrap(), MacAddress::nil());
+    }
+
+    #[test]
+    fn fails_on_missing_interface() {
+        assert!(mac_of_interface("nonexistent0").is_err());
+    }
+
+    #[test]
+    fn fails_to_probe_on_missing_interface() {
+        let ip = Ipv4Addr::new(127, 0, 0, 201);
+        assert!(probe(ip, "nonexistent0", Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn ignores_removal_of_missing_entry() {
+        // Deleting ARP entries requires root privileges.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        remove(Ipv4Addr::new(127, 0, 0, 201), "lo".to_owned()).unwrap();
+    }
+
+    #[test]
+    fn adds_and_removes_entry() {
+        // Installing ARP entries requires root privileges.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let entry = match env::var(TEST_ENTRY_VARIABLE) {
+            Ok(entry) => entry,
+            Err(_) => return,
+        };
+        let mut parts = entry.split_whitespace();
+        let iface = parts.next().unwrap().to_owned();
+        let ip = Ipv4Addr::from_str(parts.next().unwrap()).unwrap();
+        let hwaddr = MacAddress::new([0x02, 0x00, 0x5e, 0x10, 0x20, 0x30]);
+
+        add(hwaddr, ip, iface.clone()).unwrap();
+        assert!(is_in_arp_table(ip));
+        remove(ip, iface.clone()).unwrap();
+        assert!(!is_in_arp_table(ip));
+        remove(ip, iface).unwrap();
+    }
+}
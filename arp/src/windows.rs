@@ -1,13 +1,15 @@
-//! The Windows implementation using `netsh` subprocess.
+//! The Windows implementation using `netsh` and `getmac` subprocesses.
 
-use std::{io, net::Ipv4Addr, process::Command};
+use std::{io, net::Ipv4Addr, process::Command, time::Duration};
 
 use eui48::{MacAddress, MacAddressFormat};
-use tokio_process::CommandExt;
+use futures::{future, Async, Future, Poll};
+use tokio_process::{CommandExt, OutputAsync};
 
 #[derive(Debug)]
 pub enum Error {
     Process(io::Error),
+    Netsh(String),
 }
 
 impl From<io::Error> for Error {
@@ -16,27 +18,172 @@ impl From<io::Error> for Error {
     }
 }
 
-pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<super::Arp, Error> {
-    Ok((
-        Some(
-            Command::new("netsh")
-                .arg("interface")
-                .arg("ip")
-                .arg("delete")
-                .arg("neighbors")
-                .arg(iface.to_owned())
-                .output_async(),
-        ),
-        Some(
-            Command::new("netsh")
-                .arg("interface")
-                .arg("ip")
-                .arg("add")
-                .arg("neighbors")
-                .arg(iface.to_owned())
-                .arg(ip.to_string())
-                .arg(hwaddr.to_string(MacAddressFormat::Canonical))
-                .output_async(),
-        ),
+/// The ARP entry of a single address, which is deleted once the handle is dropped.
+///
+/// The stale entry of the address is deleted first, since `netsh` does not replace
+/// an existing entry, and the new one is added after that. `tokio_process` kills
+/// a child process once its future is dropped, so the processes are run while
+/// the handle is polled and the handle must be kept as long as the entry is needed.
+pub(crate) struct Handle {
+    ip: Ipv4Addr,
+    iface: String,
+    hwaddr: MacAddress,
+    /// The running `netsh` process.
+    process: Option<Process>,
+    /// Whether the entry must be added again with `hwaddr` once the running process finishes.
+    is_outdated: bool,
+    /// Whether the entry may have been added, so it must be deleted on drop.
+    is_added: bool,
+}
+
+enum Process {
+    /// Deletes the stale entry, whose failure is not reported.
+    Delete(OutputAsync),
+    Add(OutputAsync),
+}
+
+impl Handle {
+    /// Takes over another addition of the same address, whose hardware address wins.
+    pub(crate) fn append(&mut self, other: Handle) {
+        self.hwaddr = other.hwaddr;
+        self.is_outdated = true;
+    }
+}
+
+impl Future for Handle {
+    type Item = ();
+    type Error = Error;
+
+    /// Resolves once the entry has been added.
+    ///
+    /// Reports the failed processes one by one, so it may be polled again after an error.
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.process.take() {
+                None => {
+                    if !self.is_outdated {
+                        return Ok(Async::Ready(()));
+                    }
+                    let delete = delete(self.ip, &self.iface).output_async();
+                    self.process = Some(Process::Delete(delete));
+                }
+                Some(Process::Delete(mut child)) => match child.poll() {
+                    Ok(Async::NotReady) => {
+                        self.process = Some(Process::Delete(child));
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(_)) | Err(_) => {
+                        self.is_outdated = false;
+                        self.is_added = true;
+                        let add = Command::new("netsh")
+                            .arg("interface")
+                            .arg("ip")
+                            .arg("add")
+                            .arg("neighbors")
+                            .arg(self.iface.to_owned())
+                            .arg(self.ip.to_string())
+                            .arg(self.hwaddr.to_string(MacAddressFormat::Canonical))
+                            .output_async();
+                        self.process = Some(Process::Add(add));
+                    }
+                },
+                Some(Process::Add(mut child)) => match child.poll() {
+                    Ok(Async::NotReady) => {
+                        self.process = Some(Process::Add(child));
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(output)) => {
+                        if !output.status.success() {
+                            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+                            if stdout != "The object already exists." {
+                                return Err(Error::Netsh(stdout));
+                            }
+                        }
+                    }
+                    Err(error) => return Err(Error::Process(error)),
+                },
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    /// Deletes the entry of the address only, so the other entries of the interface are kept.
+    fn drop(&mut self) {
+        // the running process is killed first, so it cannot add the entry back
+        self.process = None;
+        if self.is_added {
+            // there is no way to report the failure from here, like with `remove`
+            let _ = delete(self.ip, &self.iface).spawn();
+        }
+    }
+}
+
+/// The entry is added once the handle is polled.
+pub(crate) fn add(hwaddr: MacAddress, ip: Ipv4Addr, iface: String) -> Result<Handle, Error> {
+    Ok(Handle {
+        ip,
+        iface,
+        hwaddr,
+        process: None,
+        is_outdated: true,
+        is_added: false,
+    })
+}
+
+/// The `netsh` process is not waited for, so its failure on a missing entry is ignored.
+pub(crate) fn remove(ip: Ipv4Addr, iface: String) -> Result<(), Error> {
+    delete(ip, &iface).spawn()?;
+    Ok(())
+}
+
+/// Builds the `netsh` command deleting the entry of a single address.
+fn delete(ip: Ipv4Addr, iface: &str) -> Command {
+    let mut command = Command::new("netsh");
+    command
+        .arg("interface")
+        .arg("ip")
+        .arg("delete")
+        .arg("neighbors")
+        .arg(iface)
+        .arg(ip.to_string());
+    command
+}
+
+pub(crate) fn mac_of_interface(iface: &str) -> io::Result<MacAddress> {
+    let output = Command::new("getmac")
+        .arg("/v")
+        .arg("/nh")
+        .arg("/fo")
+        .arg("csv")
+        .output()?;
+
+    // "Connection Name","Network Adapter","Physical Address","Transport Name"
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let columns: Vec<&str> = line
+            .split(',')
+            .map(|column| column.trim_matches('"'))
+            .collect();
+        if columns.len() < 3 || columns[0] != iface {
+            continue;
+        }
+        return MacAddress::parse_str(columns[2])
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Interface {} is not found", iface),
+    ))
+}
+
+/// Probing is not supported, so a probe is never started.
+pub(crate) type Probe = future::Empty<bool, io::Error>;
+
+/// The packet sockets are only implemented on Linux.
+pub(crate) fn probe(_ip: Ipv4Addr, _iface: &str, _timeout: Duration) -> io::Result<Probe> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "ARP probing is not supported on this platform",
     ))
 }
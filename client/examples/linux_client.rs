@@ -23,13 +23,12 @@ use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
 };
 
-use eui48::MacAddress;
 use tokio::prelude::*;
 use tokio::reactor::Handle;
 
 use dhcp_client::{Client, Command};
 use dhcp_framed::{DhcpFramed, DhcpSinkItem, DhcpStreamItem};
-use dhcp_protocol::{DHCP_PORT_CLIENT, SIZE_MESSAGE_MINIMAL};
+use dhcp_protocol::{OptionTag, DHCP_PORT_CLIENT, SIZE_MESSAGE_MINIMAL};
 use ifcontrol::Iface;
 use net2::UdpBuilder;
 use tokio::net::UdpSocket;
@@ -66,14 +65,15 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
-            let result =
-                try_ready!(self.inner.poll()).expect("The client returned None but it must not");
-            info!("{:?}", result);
-            self.counter += 1;
-            if self.counter >= 5 {
-                self.inner.start_send(Command::Release { message: None })?;
-                self.inner.poll_complete()?;
-                break;
+            match try_ready!(self.inner.poll()) {
+                Some(result) => {
+                    info!("{:?}", result);
+                    self.counter += 1;
+                    if self.counter >= 5 {
+                        self.inner.start_send(Command::Stop { message: None })?;
+                    }
+                }
+                None => break,
             }
         }
         Ok(Async::Ready(()))
@@ -111,11 +111,12 @@ fn main() {
     let address_request = Some(Ipv4Addr::new(192, 168, 0, 60));
     let address_time = Some(60);
     let max_message_size = Some(SIZE_MESSAGE_MINIMAL as u16);
+    let parameter_list = vec![OptionTag::NtpServers, OptionTag::DomainName];
 
-    let client = SuperClient::new(Client::new(
+    let client = SuperClient::new(Client::new_on_interface(
         stream,
         sink,
-        MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+        iface_str,
         None,
         None,
         server_address,
@@ -123,7 +124,8 @@ fn main() {
         address_request,
         address_time,
         max_message_size,
-    ));
+        parameter_list,
+    ).expect("Hardware address resolving error"));
 
     let future = client.map_err(|error| error!("Error: {}", error));
 
@@ -24,8 +24,40 @@ use futures::{Async, Future, Poll, Stream};
 use rand::{self, Rng};
 use tokio::timer::{Delay, Error};
 
-/// This `value`, this `-value` or `0` is added to each timeout in seconds.
-const AMPLITUDE: i32 = 1;
+/// The RFC 2131 initial timeout in seconds.
+const TIMEOUT_INITIAL: u64 = 4;
+/// The RFC 2131 maximal timeout in seconds.
+const TIMEOUT_MAXIMAL: u64 = 64;
+/// The RFC 2131 randomization amplitude in seconds.
+const JITTER: u64 = 1;
+
+/// The retransmission schedule parameters.
+///
+/// The defaults follow RFC 2131 §4.1. The window may be widened on slow or lossy links
+/// or tightened on fast LANs.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffConfig {
+    /// The timeout before the first retransmission.
+    pub initial: Duration,
+    /// The maximal timeout, inclusively. The request is expired after it.
+    pub max: Duration,
+    /// The maximal random offset added to or subtracted from each timeout.
+    pub jitter: Duration,
+    /// The number of retransmissions after which the request is expired
+    /// even if `max` has not been reached yet.
+    pub max_retransmissions: Option<u32>,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            initial: Duration::from_secs(TIMEOUT_INITIAL),
+            max: Duration::from_secs(TIMEOUT_MAXIMAL),
+            jitter: Duration::from_secs(JITTER),
+            max_retransmissions: None,
+        }
+    }
+}
 
 /// Binary exponential backoff algorithm implemented as a `Stream`.
 ///
@@ -37,40 +69,42 @@ pub struct Backoff {
     with_rand: Duration,
     /// The timeout after which the timer is expired.
     maximal: Duration,
+    /// The randomization amplitude.
+    jitter: Duration,
+    /// The timeouts elapsed so far.
+    attempts: u32,
+    /// The number of retransmissions after which the timer is expired.
+    max_retransmissions: Option<u32>,
     /// The timer himself.
     timeout: Delay,
 }
 
 impl Backoff {
     /// Constructs a timer and starts it.
-    ///
-    /// * `minimal`
-    /// The initial timeout duration.
-    ///
-    /// * `maximal`
-    /// The maximal timeout duration, inclusively.
-    pub fn new(minimal: Duration, maximal: Duration) -> Backoff {
-        let with_rand = Self::randomize(&minimal);
+    pub fn new(config: &BackoffConfig) -> Backoff {
+        let with_rand = Self::randomize(&config.initial, &config.jitter);
 
         Backoff {
-            current: minimal,
+            current: config.initial,
             with_rand,
-            maximal,
+            maximal: config.max,
+            jitter: config.jitter,
+            attempts: 0,
+            max_retransmissions: config.max_retransmissions,
             timeout: Delay::new(Instant::now() + with_rand),
         }
     }
 
-    /// Construct a duration with -1/0/+1 second random offset.
-    fn randomize(duration: &Duration) -> Duration {
-        let offset: i32 = rand::thread_rng().gen_range(-AMPLITUDE, AMPLITUDE + 1);
-        let mut duration = Duration::from(duration.to_owned());
-        if offset > 0 {
-            duration += Duration::from_secs(offset as u64);
-        }
-        if offset < 0 {
-            duration -= Duration::from_secs((-offset) as u64);
-        }
-        duration
+    /// Construct a duration with a uniformly random offset from `-jitter` to `+jitter`.
+    ///
+    /// The offset is chosen in milliseconds, which RFC 2131 §4.1 allows, so the
+    /// retransmissions of many clients started at once are not quantized to whole seconds.
+    fn randomize(duration: &Duration, jitter: &Duration) -> Duration {
+        let jitter_millis = jitter.as_secs() * 1_000 + u64::from(jitter.subsec_millis());
+        let offset = rand::thread_rng().gen_range(0, jitter_millis * 2 + 1);
+        (*duration + Duration::from_millis(offset))
+            .checked_sub(*jitter)
+            .unwrap_or_else(|| Duration::from_secs(0))
     }
 }
 
@@ -83,8 +117,57 @@ impl Stream for Backoff {
         try_ready!(self.timeout.poll());
         let seconds = self.with_rand.as_secs();
         self.current *= 2;
-        self.with_rand = Self::randomize(&self.current);
+        self.attempts += 1;
+        self.with_rand = Self::randomize(&self.current, &self.jitter);
         self.timeout = Delay::new(Instant::now() + self.with_rand);
-        Ok(Async::Ready(Some((seconds, self.current > self.maximal))))
+        let is_exhausted = match self.max_retransmissions {
+            Some(max) => self.attempts > max,
+            None => false,
+        };
+        Ok(Async::Ready(Some((
+            seconds,
+            self.current > self.maximal || is_exhausted,
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_timeouts_within_jitter() {
+        let config = BackoffConfig {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(16),
+            jitter: Duration::from_millis(250),
+            max_retransmissions: None,
+        };
+        let backoff = Backoff::new(&config);
+        assert!(backoff.with_rand >= config.initial - config.jitter);
+        assert!(backoff.with_rand <= config.initial + config.jitter);
+
+        let mut current = config.initial;
+        while current <= config.max {
+            for _ in 0..100 {
+                let with_rand = Backoff::randomize(&current, &config.jitter);
+                assert!(with_rand >= current - config.jitter);
+                assert!(with_rand <= current + config.jitter);
+            }
+            current *= 2;
+        }
+    }
+
+    #[test]
+    fn randomizes_below_one_second() {
+        let duration = Duration::from_secs(4);
+        let jitter = Duration::from_secs(1);
+
+        let mut tenths = (0..1000)
+            .map(|_| Backoff::randomize(&duration, &jitter).subsec_millis() / 100)
+            .collect::<Vec<u32>>();
+        tenths.sort();
+        tenths.dedup();
+        assert!(tenths.len() > 5, "Offsets are quantized: {:?}", tenths);
     }
 }
@@ -14,8 +14,14 @@ pub struct MessageBuilder {
     client_id: Vec<u8>,
     /// The optional machine hostname.
     hostname: Option<String>,
+    /// The optional fully qualified domain name replacing the hostname.
+    fqdn: Option<ClientFqdn>,
+    /// The optional vendor class identifier, e.g. of a PXE client.
+    vendor_class_id: Option<Vec<u8>>,
     /// The optional maximum DHCP message size the client will accept.
     max_message_size: Option<u16>,
+    /// The default requested options followed by the user ones.
+    parameter_list: Vec<u8>,
 }
 
 impl MessageBuilder {
@@ -25,31 +31,59 @@ impl MessageBuilder {
         client_id: Vec<u8>,
         hostname: Option<String>,
         max_message_size: Option<u16>,
+        parameter_list: Vec<OptionTag>,
     ) -> Self {
+        let mut list = Self::default_parameter_list();
+        for tag in parameter_list.into_iter().map(OptionTag::code) {
+            if !list.contains(&tag) {
+                list.push(tag);
+            }
+        }
+
         MessageBuilder {
             client_hardware_address,
             client_id,
             hostname,
+            fqdn: None,
+            vendor_class_id: None,
             max_message_size,
+            parameter_list: list,
         }
     }
 
+    /// Sets the FQDN the server is asked to register in DNS on behalf of the client.
+    pub fn set_fqdn(&mut self, fqdn: Option<String>) {
+        self.fqdn = fqdn.map(|name| ClientFqdn {
+            flags: ClientFqdn::FLAG_S | ClientFqdn::FLAG_E,
+            name: name.trim_right_matches('.').to_owned(),
+        });
+    }
+
+    /// Sets the vendor class identifier sent in `DHCPDISCOVER`, `DHCPREQUEST` and `DHCPINFORM`.
+    pub fn set_vendor_class_id(&mut self, vendor_class_id: Option<Vec<u8>>) {
+        self.vendor_class_id = vendor_class_id;
+    }
+
     /// Creates a general `DHCPDISCOVER` message.
     pub fn discover(
         &self,
         transaction_id: u32,
+        seconds: u16,
         is_broadcast: bool,
         address_request: Option<Ipv4Addr>,
         address_time: Option<u32>,
+        rapid_commit: bool,
     ) -> Message {
         let mut options = Options::default();
         self.append_default_options(&mut options);
+        options.class_id = self.vendor_class_id.to_owned();
 
         options.dhcp_message_type = Some(MessageType::DhcpDiscover);
         options.dhcp_max_message_size = self.max_message_size;
-        options.parameter_list = Some(Self::parameter_list());
+        options.parameter_list = Some(self.parameter_list.to_owned());
         options.address_request = address_request;
         options.address_time = address_time;
+        options.rapid_commit = rapid_commit;
 
         Message {
             operation_code: OperationCode::BootRequest,
@@ -58,7 +92,7 @@ impl MessageBuilder {
             hardware_options: Default::default(),
 
             transaction_id,
-            seconds: Default::default(),
+            seconds,
             is_broadcast,
 
             client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
@@ -78,6 +112,7 @@ impl MessageBuilder {
     pub fn request_selecting(
         &self,
         transaction_id: u32,
+        seconds: u16,
         is_broadcast: bool,
         address_request: Ipv4Addr,
         address_time: Option<u32>,
@@ -85,11 +120,12 @@ impl MessageBuilder {
     ) -> Message {
         let mut options = Options::default();
         self.append_default_options(&mut options);
+        options.class_id = self.vendor_class_id.to_owned();
 
         options.dhcp_message_type = Some(MessageType::DhcpRequest);
         options.dhcp_max_message_size = self.max_message_size;
         options.dhcp_server_id = Some(dhcp_server_id);
-        options.parameter_list = Some(Self::parameter_list());
+        options.parameter_list = Some(self.parameter_list.to_owned());
         options.address_request = Some(address_request);
         options.address_time = address_time;
 
@@ -100,7 +136,7 @@ impl MessageBuilder {
             hardware_options: Default::default(),
 
             transaction_id,
-            seconds: Default::default(),
+            seconds,
             is_broadcast,
 
             client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
@@ -120,16 +156,18 @@ impl MessageBuilder {
     pub fn request_init_reboot(
         &self,
         transaction_id: u32,
+        seconds: u16,
         is_broadcast: bool,
         address_request: Ipv4Addr,
         address_time: Option<u32>,
     ) -> Message {
         let mut options = Options::default();
         self.append_default_options(&mut options);
+        options.class_id = self.vendor_class_id.to_owned();
 
         options.dhcp_message_type = Some(MessageType::DhcpRequest);
         options.dhcp_max_message_size = self.max_message_size;
-        options.parameter_list = Some(Self::parameter_list());
+        options.parameter_list = Some(self.parameter_list.to_owned());
         options.address_request = Some(address_request);
         options.address_time = address_time;
 
@@ -140,7 +178,7 @@ impl MessageBuilder {
             hardware_options: Default::default(),
 
             transaction_id,
-            seconds: Default::default(),
+            seconds,
             is_broadcast,
 
             client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
@@ -160,16 +198,18 @@ impl MessageBuilder {
     pub fn request_renew(
         &self,
         transaction_id: u32,
+        seconds: u16,
         is_broadcast: bool,
         client_ip_address: Ipv4Addr,
         address_time: Option<u32>,
     ) -> Message {
         let mut options = Options::default();
         self.append_default_options(&mut options);
+        options.class_id = self.vendor_class_id.to_owned();
 
         options.dhcp_message_type = Some(MessageType::DhcpRequest);
         options.dhcp_max_message_size = self.max_message_size;
-        options.parameter_list = Some(Self::parameter_list());
+        options.parameter_list = Some(self.parameter_list.to_owned());
         options.address_time = address_time;
 
         Message {
@@ -179,7 +219,7 @@ impl MessageBuilder {
             hardware_options: Default::default(),
 
             transaction_id,
-            seconds: Default::default(),
+            seconds,
             is_broadcast,
 
             client_ip_address,
@@ -199,15 +239,17 @@ impl MessageBuilder {
     pub fn inform(
         &self,
         transaction_id: u32,
+        seconds: u16,
         is_broadcast: bool,
         client_ip_address: Ipv4Addr,
     ) -> Message {
         let mut options = Options::default();
         self.append_default_options(&mut options);
+        options.class_id = self.vendor_class_id.to_owned();
 
         options.dhcp_message_type = Some(MessageType::DhcpInform);
         options.dhcp_max_message_size = self.max_message_size;
-        options.parameter_list = Some(Self::parameter_list());
+        options.parameter_list = Some(self.parameter_list.to_owned());
 
         Message {
             operation_code: OperationCode::BootRequest,
@@ -216,7 +258,7 @@ impl MessageBuilder {
             hardware_options: Default::default(),
 
             transaction_id,
-            seconds: Default::default(),
+            seconds,
             is_broadcast,
 
             client_ip_address,
@@ -310,14 +352,23 @@ impl MessageBuilder {
     }
 
     fn append_default_options(&self, options: &mut Options) {
-        options.hostname = self.hostname.to_owned();
+        /*
+        RFC 4702 §3.1
+        Clients that send the Client FQDN option in their messages MUST NOT
+        also send the Host Name option.
+        */
+        if self.fqdn.is_some() {
+            options.client_fqdn = self.fqdn.to_owned();
+        } else {
+            options.hostname = self.hostname.to_owned();
+        }
         options.client_id = Some(self.client_id.to_owned());
     }
 
-    fn parameter_list() -> Vec<u8> {
+    fn default_parameter_list() -> Vec<u8> {
         vec![
-            OptionTag::SubnetMask as u8,
-            OptionTag::DomainNameServers as u8,
+            OptionTag::SubnetMask.code(),
+            OptionTag::DomainNameServers.code(),
             /*
             RFC 3442
             DHCP clients that support this option and send a parameter request
@@ -327,9 +378,46 @@ impl MessageBuilder {
             request list prior to both the Router option code and the Static
             Routes option code, if present.
             */
-            OptionTag::ClasslessStaticRoutes as u8,
-            OptionTag::Routers as u8,
-            OptionTag::StaticRoutes as u8,
+            OptionTag::ClasslessStaticRoutes.code(),
+            OptionTag::Routers.code(),
+            OptionTag::StaticRoutes.code(),
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_custom_parameters_once() {
+        let builder = MessageBuilder::new(
+            MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            vec![1, 2, 3],
+            None,
+            None,
+            vec![
+                OptionTag::NtpServers,
+                OptionTag::DomainName,
+                OptionTag::VendorSpecific,
+                OptionTag::SubnetMask,
+            ],
+        );
+
+        let discover = builder.discover(0, 0, false, None, None, false);
+        let parameter_list = discover.options.parameter_list.unwrap();
+
+        for tag in &[
+            OptionTag::NtpServers,
+            OptionTag::DomainName,
+            OptionTag::VendorSpecific,
+        ] {
+            assert!(parameter_list.contains(&tag.code()));
+        }
+        let subnet_mask = OptionTag::SubnetMask.code();
+        assert_eq!(
+            parameter_list.iter().filter(|&&tag| tag == subnet_mask).count(),
+            1
+        );
+    }
+}
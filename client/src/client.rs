@@ -1,15 +1,24 @@
 //! The main DHCP client module.
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use eui48::MacAddress;
-use futures::StartSend;
+use futures::{sync::mpsc, StartSend};
 use hostname;
-use tokio::{io, prelude::*};
+use tokio::{io, prelude::*, timer::Delay};
 
-use dhcp_protocol::{Message, MessageType, DHCP_PORT_SERVER};
+use dhcp_arp;
+use dhcp_protocol::{AuthKey, Message, MessageType, OptionTag, DHCP_PORT_SERVER};
 
+use backoff::BackoffConfig;
 use builder::MessageBuilder;
+use error::ClientError;
+use event::ClientEvent;
+use oneshot::AcquireAndRelease;
 use state::{DhcpState, State};
 
 /// May be used to request stuff explicitly.
@@ -18,6 +27,8 @@ struct RequestOptions {
     address_request: Option<Ipv4Addr>,
     /// Explicit lease time request.
     address_time: Option<u32>,
+    /// Whether the two-message exchange is requested.
+    rapid_commit: bool,
 }
 
 /// The `Client` future result type.
@@ -30,6 +41,9 @@ pub struct Configuration {
     pub domain_name_servers: Option<Vec<Ipv4Addr>>,
     pub static_routes: Option<Vec<(Ipv4Addr, Ipv4Addr)>>,
     pub classless_static_routes: Option<Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>>,
+    pub ntp_servers: Option<Vec<Ipv4Addr>>,
+    pub domain_name: Option<String>,
+    pub domain_search: Option<Vec<String>>,
 }
 
 impl Configuration {
@@ -55,6 +69,9 @@ impl Configuration {
             domain_name_servers: response.options.domain_name_servers,
             static_routes: response.options.static_routes,
             classless_static_routes: response.options.classless_static_routes,
+            ntp_servers: response.options.ntp_servers,
+            domain_name: response.options.domain_name,
+            domain_search: response.options.domain_search,
         }
     }
 }
@@ -65,6 +82,11 @@ pub enum Command {
     Release {
         message: Option<String>,
     },
+    /// Releases the lease if the client is bound and ends the client `Stream`
+    /// after the `DHCPRELEASE` is flushed. May be sent in any state.
+    Stop {
+        message: Option<String>,
+    },
     Decline {
         address: Ipv4Addr,
         message: Option<String>,
@@ -72,10 +94,27 @@ pub enum Command {
     Inform {
         address: Ipv4Addr,
     },
+    /// Unicasts a `DHCPREQUEST` to the leasing server at once without waiting for T1,
+    /// e.g. after a network change. Fails if the client is not bound.
+    /// The response is handled by the client `Stream`.
+    Renew,
 }
 
-type DhcpStreamItem = (SocketAddr, Message);
-type DhcpSinkItem = (SocketAddr, (Message, Option<u16>));
+/// Generates the transaction ID (`xid`) for each new transaction.
+///
+/// Is called once per DISCOVER, INIT-REBOOT, renewal and rebinding session,
+/// so the retransmissions within a session keep the same `xid`.
+pub type XidGenerator = Box<FnMut() -> u32 + Send>;
+
+/// Starts checking whether the gateway of the cached lease is present, e.g. with `dhcp_arp::probe`.
+///
+/// Is called with the gateway address once before the INIT-REBOOT attempt.
+/// The returned future is polled by the client, so it must not block.
+pub type GatewayProbe =
+    Box<FnMut(Ipv4Addr) -> Box<Future<Item = bool, Error = io::Error> + Send> + Send>;
+
+pub(crate) type DhcpStreamItem = (SocketAddr, Message);
+pub(crate) type DhcpSinkItem = (SocketAddr, (Message, Option<u16>));
 
 /// The struct implementing the `Future` trait.
 pub struct Client<I, O>
@@ -88,6 +127,23 @@ where
     builder: MessageBuilder,
     state: State,
     options: RequestOptions,
+    /// Set by `Command::Stop`, after which the `Stream` ends.
+    is_stopped: bool,
+    /// The cached gateway and its probe identifying the network before INIT-REBOOT.
+    network_check: Option<(Ipv4Addr, GatewayProbe)>,
+    /// The started gateway probe the INIT-REBOOT attempt waits for.
+    gateway_check: Option<(Ipv4Addr, Box<Future<Item = bool, Error = io::Error> + Send>)>,
+    /// The maximal time to get bound.
+    acquisition_timeout: Option<Duration>,
+    /// Started when the client begins to acquire a lease and reset once it is bound.
+    acquisition_deadline: Option<Delay>,
+    /// The key the responses are verified and the requests are signed with.
+    auth_key: Option<AuthKey>,
+    /// The replay detection counter of the signed requests.
+    replay_detection: u64,
+    /// The last replay detection values of the authenticated responses, keyed by the server,
+    /// because every server has its own counter.
+    server_replay_detections: HashMap<Ipv4Addr, u64>,
 }
 
 impl<I, O> Client<I, O>
@@ -134,13 +190,18 @@ where
     /// your current or previous address, or an address from its dynamic pool.
     ///
     /// * `address_time`
-    /// The requested lease time.
+    /// The requested lease time sent in both `DHCPDISCOVER` and `DHCPREQUEST`.
     /// If not set, a server will choose the lease time by itself.
     /// The server may lease the address for different amount of time if it decides so.
     ///
     /// * `max_message_size`
     /// The maximum DHCP message size.
     ///
+    /// * `parameter_list`
+    /// The options to request in addition to the default ones
+    /// (subnet mask, DNS servers, routers and static routes).
+    ///
+    #[allow(clippy::doc_lazy_continuation)]
     pub fn new(
         stream: I,
         sink: O,
@@ -152,6 +213,7 @@ where
         address_request: Option<Ipv4Addr>,
         address_time: Option<u32>,
         max_message_size: Option<u16>,
+        parameter_list: Vec<OptionTag>,
     ) -> Self {
         let hostname: Option<String> = if hostname.is_none() {
             hostname::get_hostname()
@@ -166,11 +228,13 @@ where
             client_id,
             hostname,
             max_message_size,
+            parameter_list,
         );
 
         let mut options = RequestOptions {
             address_request,
             address_time,
+            rapid_commit: false,
         };
 
         let dhcp_state = match client_address {
@@ -189,9 +253,224 @@ where
             builder,
             state,
             options,
+            is_stopped: false,
+            network_check: None,
+            gateway_check: None,
+            acquisition_timeout: None,
+            acquisition_deadline: None,
+            auth_key: None,
+            // the counter must keep increasing after restarts, so it starts from the current time
+            replay_detection: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs() << 32)
+                .unwrap_or_default(),
+            server_replay_detections: HashMap::new(),
         }
     }
 
+    /// Creates a client with the hardware address of the network interface it is bound to.
+    ///
+    /// * `iface`
+    /// The name of the interface the socket is bound to.
+    ///
+    /// The other parameters are described in `Client::new`.
+    #[allow(clippy::too_many_arguments, clippy::doc_lazy_continuation)]
+    pub fn new_on_interface(
+        stream: I,
+        sink: O,
+        iface: &str,
+        client_id: Option<Vec<u8>>,
+        hostname: Option<String>,
+        server_address: Option<Ipv4Addr>,
+        client_address: Option<Ipv4Addr>,
+        address_request: Option<Ipv4Addr>,
+        address_time: Option<u32>,
+        max_message_size: Option<u16>,
+        parameter_list: Vec<OptionTag>,
+    ) -> io::Result<Self> {
+        let client_hardware_address = dhcp_arp::mac_of_interface(iface)?;
+        info!("Using the hardware address {} of {}", client_hardware_address, iface);
+
+        Ok(Self::new(
+            stream,
+            sink,
+            client_hardware_address,
+            client_id,
+            hostname,
+            server_address,
+            client_address,
+            address_request,
+            address_time,
+            max_message_size,
+            parameter_list,
+        ))
+    }
+
+    /// Creates a client only asking for the local configuration parameters.
+    ///
+    /// A client with an externally configured address sends a `DHCPINFORM` at once,
+    /// retransmitting it with the backoff, and yields the `Configuration` from
+    /// the `DHCPACK` without acquiring a lease. The `Stream` ends after that.
+    ///
+    /// * `client_address`
+    /// The externally configured address sent in the `ciaddr` field.
+    ///
+    /// The other parameters are described in `Client::new`.
+    #[allow(clippy::too_many_arguments, clippy::doc_lazy_continuation)]
+    pub fn new_inform(
+        stream: I,
+        sink: O,
+        client_hardware_address: MacAddress,
+        client_id: Option<Vec<u8>>,
+        hostname: Option<String>,
+        server_address: Option<Ipv4Addr>,
+        client_address: Ipv4Addr,
+        max_message_size: Option<u16>,
+        parameter_list: Vec<OptionTag>,
+    ) -> Self {
+        let mut client = Self::new(
+            stream,
+            sink,
+            client_hardware_address,
+            client_id,
+            hostname,
+            server_address,
+            None,
+            None,
+            None,
+            max_message_size,
+            parameter_list,
+        );
+        client.options.address_request = Some(client_address);
+        client.state = State::new(DhcpState::InitInform, server_address, false);
+        client
+    }
+
+    /// Sets the channel the client reports its events to.
+    ///
+    /// The events let a user interface show the client progress.
+    /// If not set, the events are not reported.
+    pub fn set_event_sender(&mut self, events: mpsc::UnboundedSender<ClientEvent>) {
+        self.state.set_events(events);
+    }
+
+    /// Returns the transaction ID of the current session.
+    ///
+    /// Before the first session starts, it is the one the session is going to use.
+    pub fn current_xid(&self) -> u32 {
+        self.state.xid()
+    }
+
+    /// Sets the transaction ID of the first session, e.g. to correlate it with external logs.
+    ///
+    /// The further sessions use the `xid` generator.
+    /// If not called, the first one is generated as well.
+    pub fn set_xid(&mut self, xid: u32) {
+        self.state.set_initial_xid(xid);
+    }
+
+    /// Replaces the random transaction ID generator.
+    ///
+    /// May be used to embed correlation tokens into the `xid` field.
+    pub fn set_xid_generator(&mut self, xid_generator: XidGenerator) {
+        self.state.set_xid_generator(xid_generator);
+    }
+
+    /// Makes the client ask servers to broadcast their responses (RFC 2131 §4.1).
+    ///
+    /// Set it if the network stack cannot receive unicast datagrams before
+    /// its address is configured. The stacks receiving them through raw sockets
+    /// may leave it cleared, so the servers unicast the responses to `yiaddr`.
+    /// The flag is never set once the client has an address. If not called, it is cleared.
+    /// Takes effect from the next request.
+    pub fn set_broadcast(&mut self, is_broadcast: bool) {
+        self.state.set_broadcast(is_broadcast);
+    }
+
+    /// Replaces the RFC 2131 retransmission schedule.
+    ///
+    /// Takes effect from the next request.
+    pub fn set_backoff(&mut self, backoff: BackoffConfig) {
+        self.state.set_backoff(backoff);
+    }
+
+    /// Makes the client check the network identity before reusing the cached address.
+    ///
+    /// If the client is started in INIT-REBOOT state and the probe does not find
+    /// the `gateway` cached with the lease, the client is on another network,
+    /// so it starts in INIT state instead without requesting the cached address.
+    /// If not set, the cached address is always tried first.
+    pub fn set_network_check(&mut self, gateway: Ipv4Addr, probe: GatewayProbe) {
+        self.network_check = Some((gateway, probe));
+    }
+
+    /// Makes the client ask servers for the two-message exchange (RFC 4039).
+    ///
+    /// Servers not supporting it are handled with the usual four-message one.
+    /// Takes effect from the next `DHCPDISCOVER`.
+    pub fn set_rapid_commit(&mut self, rapid_commit: bool) {
+        self.options.rapid_commit = rapid_commit;
+    }
+
+    /// Replaces the requested lease time.
+    ///
+    /// A short lease time may be requested on unstable networks, so the server reclaims
+    /// the address soon after the client disappears. It is only a preference:
+    /// the timers are always computed from the lease time the server actually grants.
+    /// Takes effect from the next `DHCPDISCOVER` or `DHCPREQUEST`.
+    pub fn set_address_time(&mut self, address_time: Option<u32>) {
+        self.options.address_time = address_time;
+    }
+
+    /// Limits the total time to get bound.
+    ///
+    /// The retransmission backoff only expires a single request, so a client which gets
+    /// no acceptable responses may keep acquiring a lease for a long time.
+    /// If the client is not bound in time, the `Stream` returns `ClientError::Timeout`.
+    /// The timeout is started anew on each acquisition and does not apply to renewals.
+    pub fn set_acquisition_timeout(&mut self, acquisition_timeout: Option<Duration>) {
+        self.acquisition_timeout = acquisition_timeout;
+        self.acquisition_deadline = None;
+    }
+
+    /// Makes the client send its fully qualified domain name (RFC 4702).
+    ///
+    /// The server is asked to update both the A and PTR DNS records of the client,
+    /// so the FQDN is sent instead of the hostname.
+    /// Takes effect from the next request.
+    pub fn set_fqdn(&mut self, fqdn: Option<String>) {
+        self.builder.set_fqdn(fqdn);
+    }
+
+    /// Makes the client identify its vendor and configuration (RFC 2132 §9.13).
+    ///
+    /// Is required by PXE and other vendor-specific flows, where the server chooses
+    /// the configuration by the class of the client.
+    /// Takes effect from the next request.
+    pub fn set_vendor_class_id(&mut self, vendor_class_id: Option<Vec<u8>>) {
+        self.builder.set_vendor_class_id(vendor_class_id);
+    }
+
+    /// Sets the key of the RFC 3118 delayed authentication.
+    ///
+    /// The responses without a valid HMAC-MD5 signature or with a replay detection value
+    /// not greater than the previous one are dropped, and every request is signed.
+    /// The HMAC is verified and computed by the socket, so the `DhcpFramed` the client
+    /// works over must be given the same key with `DhcpFramed::set_auth_key`.
+    /// If not set, the messages are not authenticated.
+    /// Takes effect from the next request.
+    pub fn set_auth_key(&mut self, auth_key: Option<AuthKey>) {
+        self.auth_key = auth_key;
+    }
+
+    /// Consumes the client and returns a future acquiring a lease and releasing it afterwards.
+    ///
+    /// If `hold` is set, the lease is held and renewed for that duration before `DHCPRELEASE`.
+    /// The future resolves to the last received `Configuration`.
+    pub fn acquire_and_release(self, hold: Option<Duration>) -> AcquireAndRelease<I, O> {
+        AcquireAndRelease::new(self, hold)
+    }
+
     /// Chooses the packet destination address according to the RFC 2131 rules.
     fn destination(&mut self) -> Ipv4Addr {
         /*
@@ -218,14 +497,74 @@ where
         }
     }
 
-    /// Sends a request.
-    fn send_request(&mut self, request: Message) -> io::Result<()> {
+    /// Makes a `Configuration` from a `DHCPACK` and reports it.
+    fn configure(&mut self, response: Message) -> Configuration {
+        let configuration = Configuration::from_response(response);
+        self.state.report(ClientEvent::Bound(configuration.to_owned()));
+        configuration
+    }
+
+    /// Checks whether the acquisition has been lasting for too long.
+    ///
+    /// # Errors
+    /// `ClientError::Timeout` if the acquisition timeout has expired.
+    fn poll_acquisition_deadline(&mut self) -> Result<(), ClientError> {
+        let timeout = match self.acquisition_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        match self.state.dhcp_state() {
+            DhcpState::Bound
+            | DhcpState::Renewing
+            | DhcpState::RenewingSent
+            | DhcpState::Rebinding
+            | DhcpState::RebindingSent
+            | DhcpState::Informed => {
+                self.acquisition_deadline = None;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let deadline = self
+            .acquisition_deadline
+            .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+        match deadline.poll() {
+            Ok(Async::Ready(_)) => {
+                self.acquisition_deadline = None;
+                warn!("No lease acquired in {:?}", timeout);
+                Err(ClientError::Timeout)
+            }
+            Ok(Async::NotReady) => Ok(()),
+            Err(error) => panic!("Timer error: {}", error),
+        }
+    }
+
+    /// Sends a request after the previous one has been flushed.
+    ///
+    /// Returns `NotReady` without sending anything while the previous request is pending,
+    /// so the caller must stay in its state and retry on the next poll.
+    ///
+    /// # Errors
+    /// `ClientError::Protocol` if the sink rejects the request.
+    fn send_request(&mut self, mut request: Message) -> Poll<(), ClientError> {
+        try_ready!(self.sink.poll_complete());
+
+        if let Some(ref auth_key) = self.auth_key {
+            self.replay_detection += 1;
+            request.set_authentication(auth_key, self.replay_detection);
+        }
+
         let destination = self.destination();
         log_send!(request, destination);
 
         let destination = SocketAddr::new(IpAddr::V4(destination), DHCP_PORT_SERVER);
-        start_send!(self.sink, destination, (request, None));
-        Ok(())
+        match self.sink.start_send((destination, (request, None))) {
+            Ok(AsyncSink::Ready) => Ok(Async::Ready(())),
+            Ok(AsyncSink::NotReady(_)) => Ok(Async::NotReady),
+            Err(error) => Err(ClientError::Protocol(error.to_string())),
+        }
     }
 }
 
@@ -235,7 +574,7 @@ where
     O: Sink<SinkItem = DhcpSinkItem, SinkError = io::Error> + Send + Sync,
 {
     type Item = Configuration;
-    type Error = io::Error;
+    type Error = ClientError;
 
     /// Yields a `Configuration` after each configuration update.
     ///
@@ -286,6 +625,12 @@ where
     ///
     /// [RFC 2131](https://tools.ietf.org/html/rfc2131)
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.is_stopped {
+            try_ready!(self.sink.poll_complete());
+            return Ok(Async::Ready(None));
+        }
+        self.poll_acquisition_deadline()?;
+
         loop {
             poll_complete!(self.sink);
 
@@ -309,14 +654,18 @@ where
                     DhcpRequest broadcast message.
                     */
 
+                    let seconds = self.state.seconds();
+                    self.state.set_discover_seconds(seconds);
                     let request = self.builder.discover(
                         self.state.xid(),
+                        seconds,
                         self.state.is_broadcast(),
                         self.options.address_request,
                         self.options.address_time,
+                        self.options.rapid_commit,
                     );
 
-                    self.send_request(request)?;
+                    try_ready!(self.send_request(request));
                     self.state
                         .transcend(current, DhcpState::SelectingSent, None);
                 }
@@ -328,7 +677,7 @@ where
                             continue;
                         }
                         Ok(Async::NotReady) => {
-                            poll_backoff!(self.state.timer_offer);
+                            poll_backoff!(self.state, timer_offer);
                             self.state.transcend(current, DhcpState::Selecting, None);
                             continue;
                         }
@@ -341,7 +690,34 @@ where
                     let dhcp_message_type = validate!(response, addr);
                     log_receive!(response, addr.ip());
                     check_xid!(self.state.xid(), response.transaction_id);
-                    check_message_type!(dhcp_message_type, MessageType::DhcpOffer);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
+
+                    match dhcp_message_type {
+                        MessageType::DhcpOffer => {}
+                        /*
+                        RFC 4039 §4
+                        If the client receives a DHCPACK message with a Rapid Commit option
+                        in response to its DHCPDISCOVER message with a Rapid Commit option,
+                        it proceeds to the BOUND state. A DHCPOFFER means the server has
+                        chosen the usual four-message exchange.
+                        */
+                        MessageType::DhcpAck
+                            if self.options.rapid_commit && response.options.rapid_commit =>
+                        {
+                            self.state
+                                .transcend(current, DhcpState::Bound, Some(&response));
+                            return Ok(Async::Ready(Some(self.configure(response))));
+                        }
+                        _ => {
+                            warn!("Got an unexpected DHCP message type {}", dhcp_message_type);
+                            continue;
+                        }
+                    }
+
+                    self.state.report(ClientEvent::OfferReceived {
+                        address: response.your_ip_address,
+                        dhcp_server_id: expect!(response.options.dhcp_server_id),
+                    });
                     self.state
                         .transcend(current, DhcpState::Requesting, Some(&response));
                 }
@@ -352,15 +728,26 @@ where
                     the client is initialized and moves to BOUND state.
                     */
 
+                    // the requested lease time is preferred to the offered one
+                    let address_time = self
+                        .options
+                        .address_time
+                        .or(Some(self.state.offered_time()));
+                    /*
+                    RFC 2131 §4.4.1
+                    The client MUST use the same value in the DHCPREQUEST 'secs'
+                    field as in the original DHCPDISCOVER message.
+                    */
                     let request = self.builder.request_selecting(
                         self.state.xid(),
+                        self.state.discover_seconds(),
                         self.state.is_broadcast(),
                         self.state.offered_address(),
-                        Some(self.state.offered_time()),
+                        address_time,
                         expect!(self.state.dhcp_server_id()),
                     );
 
-                    self.send_request(request)?;
+                    try_ready!(self.send_request(request));
                     self.state
                         .transcend(current, DhcpState::RequestingSent, None);
                 }
@@ -373,7 +760,8 @@ where
                         }
                         Ok(Async::NotReady) => {
                             let next = poll_backoff!(
-                                self.state.timer_ack,
+                                self.state,
+                                timer_ack,
                                 DhcpState::Requesting,
                                 DhcpState::Init
                             );
@@ -389,12 +777,16 @@ where
                     let dhcp_message_type = validate!(response, addr);
                     log_receive!(response, addr.ip());
                     check_xid!(self.state.xid(), response.transaction_id);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
 
                     match dhcp_message_type {
                         MessageType::DhcpNak => {
                             warn!("Got {} in {} state", dhcp_message_type, current);
+                            self.state.report(ClientEvent::NakReceived);
                             self.state.transcend(current, DhcpState::Init, None);
-                            continue;
+                            return Err(ClientError::Nak {
+                                message: response.options.dhcp_message,
+                            });
                         }
                         MessageType::DhcpAck => {}
                         _ => {
@@ -405,7 +797,7 @@ where
 
                     self.state
                         .transcend(current, DhcpState::Bound, Some(&response));
-                    return Ok(Async::Ready(Some(Configuration::from_response(response))));
+                    return Ok(Async::Ready(Some(self.configure(response))));
                 }
 
                 current @ DhcpState::InitReboot => {
@@ -416,6 +808,30 @@ where
                     'requested IP address' option in the DhcpRequest message.
                     */
 
+                    if let Some((gateway, mut probe)) = self.network_check.take() {
+                        self.gateway_check = Some((gateway, probe(gateway)));
+                    }
+                    if let Some((gateway, mut check)) = self.gateway_check.take() {
+                        let is_present = match check.poll() {
+                            Ok(Async::Ready(is_present)) => is_present,
+                            Ok(Async::NotReady) => {
+                                self.gateway_check = Some((gateway, check));
+                                return Ok(Async::NotReady);
+                            }
+                            Err(error) => {
+                                // the cached address is tried as if there were no check
+                                warn!("Gateway {} probing error: {}", gateway, error);
+                                true
+                            }
+                        };
+                        if !is_present {
+                            info!("Gateway {} is absent, the network has changed", gateway);
+                            self.options.address_request = None;
+                            self.state.transcend(current, DhcpState::Init, None);
+                            continue;
+                        }
+                    }
+
                     self.state.transcend(current, DhcpState::Rebooting, None);
                 }
                 current @ DhcpState::Rebooting => {
@@ -428,12 +844,13 @@ where
 
                     let request = self.builder.request_init_reboot(
                         self.state.xid(),
+                        self.state.seconds(),
                         self.state.is_broadcast(),
                         expect!(self.options.address_request),
                         self.options.address_time,
                     );
 
-                    self.send_request(request)?;
+                    try_ready!(self.send_request(request));
                     self.state
                         .transcend(current, DhcpState::RebootingSent, None);
                 }
@@ -446,7 +863,8 @@ where
                         }
                         Ok(Async::NotReady) => {
                             let next = poll_backoff!(
-                                self.state.timer_ack,
+                                self.state,
+                                timer_ack,
                                 DhcpState::InitReboot,
                                 DhcpState::Init
                             );
@@ -462,12 +880,16 @@ where
                     let dhcp_message_type = validate!(response, addr);
                     log_receive!(response, addr.ip());
                     check_xid!(self.state.xid(), response.transaction_id);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
 
                     match dhcp_message_type {
                         MessageType::DhcpNak => {
                             warn!("Got {} in {} state", dhcp_message_type, current);
+                            self.state.report(ClientEvent::NakReceived);
                             self.state.transcend(current, DhcpState::Init, None);
-                            continue;
+                            return Err(ClientError::Nak {
+                                message: response.options.dhcp_message,
+                            });
                         }
                         MessageType::DhcpAck => {}
                         _ => {
@@ -478,7 +900,7 @@ where
 
                     self.state
                         .transcend(current, DhcpState::Bound, Some(&response));
-                    return Ok(Async::Ready(Some(Configuration::from_response(response))));
+                    return Ok(Async::Ready(Some(self.configure(response))));
                 }
 
                 current @ DhcpState::Bound => {
@@ -494,6 +916,10 @@ where
                     */
 
                     poll_delay!(self.state.timer_renewal);
+                    if self.state.is_lease_infinite() {
+                        self.state.run_timer_renewal();
+                        continue;
+                    }
                     self.state.transcend(current, DhcpState::Renewing, None);
                 }
                 current @ DhcpState::Renewing => {
@@ -508,12 +934,13 @@ where
 
                     let request = self.builder.request_renew(
                         self.state.xid(),
+                        self.state.seconds(),
                         self.state.is_broadcast(),
                         self.state.assigned_address(),
                         self.options.address_time,
                     );
 
-                    self.send_request(request)?;
+                    try_ready!(self.send_request(request));
                     self.state.transcend(current, DhcpState::RenewingSent, None);
                 }
                 current @ DhcpState::RenewingSent => {
@@ -525,7 +952,8 @@ where
                         }
                         Ok(Async::NotReady) => {
                             let next = poll_forthon!(
-                                self.state.timer_rebinding,
+                                self.state,
+                                timer_rebinding,
                                 DhcpState::Renewing,
                                 DhcpState::Rebinding
                             );
@@ -541,11 +969,27 @@ where
                     let dhcp_message_type = validate!(response, addr);
                     log_receive!(response, addr.ip());
                     check_xid!(self.state.xid(), response.transaction_id);
-                    check_message_type!(dhcp_message_type, MessageType::DhcpAck);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
+
+                    match dhcp_message_type {
+                        MessageType::DhcpNak => {
+                            warn!("Got {} in {} state", dhcp_message_type, current);
+                            self.state.report(ClientEvent::NakReceived);
+                            self.state.transcend(current, DhcpState::Init, None);
+                            return Err(ClientError::Nak {
+                                message: response.options.dhcp_message,
+                            });
+                        }
+                        MessageType::DhcpAck => {}
+                        _ => {
+                            warn!("Got an unexpected DHCP message type {}", dhcp_message_type);
+                            continue;
+                        }
+                    }
 
                     self.state
                         .transcend(current, DhcpState::Bound, Some(&response));
-                    return Ok(Async::Ready(Some(Configuration::from_response(response))));
+                    return Ok(Async::Ready(Some(self.configure(response))));
                 }
                 current @ DhcpState::Rebinding => {
                     /*
@@ -562,12 +1006,13 @@ where
 
                     let request = self.builder.request_renew(
                         self.state.xid(),
+                        self.state.seconds(),
                         self.state.is_broadcast(),
                         self.state.assigned_address(),
                         self.options.address_time,
                     );
 
-                    self.send_request(request)?;
+                    try_ready!(self.send_request(request));
                     self.state
                         .transcend(current, DhcpState::RebindingSent, None);
                 }
@@ -580,9 +1025,16 @@ where
                         }
                         Ok(Async::NotReady) => {
                             let next = poll_forthon!(
-                                self.state.timer_expiration,
+                                self.state,
+                                timer_expiration,
                                 DhcpState::Rebinding,
-                                DhcpState::Init
+                                {
+                                    self.state.report(ClientEvent::LeaseLost {
+                                        address: self.state.assigned_address(),
+                                    });
+                                    self.state.transcend(current, DhcpState::Init, None);
+                                    return Err(ClientError::LeaseExpired);
+                                }
                             );
                             self.state.transcend(current, next, None);
                             continue;
@@ -596,12 +1048,92 @@ where
                     let dhcp_message_type = validate!(response, addr);
                     log_receive!(response, addr.ip());
                     check_xid!(self.state.xid(), response.transaction_id);
-                    check_message_type!(dhcp_message_type, MessageType::DhcpAck);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
+
+                    match dhcp_message_type {
+                        MessageType::DhcpNak => {
+                            warn!("Got {} in {} state", dhcp_message_type, current);
+                            self.state.report(ClientEvent::NakReceived);
+                            self.state.transcend(current, DhcpState::Init, None);
+                            return Err(ClientError::Nak {
+                                message: response.options.dhcp_message,
+                            });
+                        }
+                        MessageType::DhcpAck => {}
+                        _ => {
+                            warn!("Got an unexpected DHCP message type {}", dhcp_message_type);
+                            continue;
+                        }
+                    }
 
                     self.state
                         .transcend(current, DhcpState::Bound, Some(&response));
-                    return Ok(Async::Ready(Some(Configuration::from_response(response))));
+                    return Ok(Async::Ready(Some(self.configure(response))));
                 }
+
+                current @ DhcpState::InitInform => {
+                    self.state.transcend(current, DhcpState::Informing, None);
+                }
+                current @ DhcpState::Informing => {
+                    /*
+                    RFC 2131 §4.4.3
+                    The client sends a DHCPINFORM message. The client may request
+                    specific configuration parameters by including the 'parameter
+                    request list' option. The client generates and records a random
+                    transaction identifier and inserts that identifier into the 'xid'
+                    field. The client places its own network address in the 'ciaddr'
+                    field. The DHCP client SHOULD NOT request lease time parameters.
+                    */
+
+                    let request = self.builder.inform(
+                        self.state.xid(),
+                        self.state.seconds(),
+                        self.state.is_broadcast(),
+                        expect!(self.options.address_request),
+                    );
+
+                    try_ready!(self.send_request(request));
+                    self.state
+                        .transcend(current, DhcpState::InformingSent, None);
+                }
+                current @ DhcpState::InformingSent => {
+                    let (addr, mut response) = match self.stream.poll() {
+                        Ok(Async::Ready(Some(data))) => data,
+                        Ok(Async::Ready(None)) => {
+                            warn!("Received an invalid packet");
+                            continue;
+                        }
+                        Ok(Async::NotReady) => {
+                            poll_backoff!(self.state, timer_ack);
+                            self.state.transcend(current, DhcpState::Informing, None);
+                            continue;
+                        }
+                        Err(error) => {
+                            warn!("Socket error: {}", error);
+                            continue;
+                        }
+                    };
+
+                    let dhcp_message_type = validate!(response, addr);
+                    log_receive!(response, addr.ip());
+                    check_xid!(self.state.xid(), response.transaction_id);
+                    check_auth!(self.auth_key, self.server_replay_detections, response, addr);
+
+                    match dhcp_message_type {
+                        MessageType::DhcpAck => {}
+                        _ => {
+                            warn!("Got an unexpected DHCP message type {}", dhcp_message_type);
+                            continue;
+                        }
+                    }
+
+                    // the server does not assign anything, so `yiaddr` is zero
+                    response.your_ip_address = expect!(self.options.address_request);
+                    self.state
+                        .transcend(current, DhcpState::Informed, Some(&response));
+                    return Ok(Async::Ready(Some(self.configure(response))));
+                }
+                DhcpState::Informed => return Ok(Async::Ready(None)),
             }
         }
     }
@@ -620,7 +1152,32 @@ where
         &mut self,
         command: Self::SinkItem,
     ) -> StartSend<Self::SinkItem, Self::SinkError> {
-        let (request, destination) = match command {
+        if let Async::NotReady = self.sink.poll_complete()? {
+            return Ok(AsyncSink::NotReady(command));
+        }
+
+        let (mut request, destination) = match command {
+            Command::Stop { ref message } => {
+                if self.is_stopped {
+                    return Ok(AsyncSink::Ready);
+                }
+                let dhcp_server_id = match self.state.dhcp_server_id() {
+                    Some(dhcp_server_id) if self.state.is_bound() => dhcp_server_id,
+                    _ => {
+                        info!("Stopping the client with nothing to release");
+                        self.is_stopped = true;
+                        return Ok(AsyncSink::Ready);
+                    }
+                };
+                let destination = SocketAddr::new(IpAddr::V4(dhcp_server_id), DHCP_PORT_SERVER);
+                let request = self.builder.release(
+                    self.state.xid(),
+                    self.state.assigned_address(),
+                    dhcp_server_id,
+                    message.to_owned(),
+                );
+                (request, destination)
+            }
             Command::Release { ref message } => {
                 let dhcp_server_id = match self.state.dhcp_server_id() {
                     Some(dhcp_server_id) => dhcp_server_id,
@@ -665,6 +1222,28 @@ where
                 );
                 (request, destination)
             }
+            Command::Renew => {
+                let dhcp_server_id = match (self.state.dhcp_state(), self.state.dhcp_server_id()) {
+                    (DhcpState::Bound, Some(dhcp_server_id)) => dhcp_server_id,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrNotAvailable,
+                            "Nothing to renew",
+                        ))
+                    }
+                };
+                info!("Renewing the lease on demand");
+                self.state.renew_early();
+                let destination = SocketAddr::new(IpAddr::V4(dhcp_server_id), DHCP_PORT_SERVER);
+                let request = self.builder.request_renew(
+                    self.state.xid(),
+                    self.state.seconds(),
+                    self.state.is_broadcast(),
+                    self.state.assigned_address(),
+                    self.options.address_time,
+                );
+                (request, destination)
+            }
             Command::Inform { ref address } => {
                 let dhcp_server_id = match self.state.dhcp_server_id() {
                     Some(dhcp_server_id) => dhcp_server_id,
@@ -673,6 +1252,7 @@ where
                 let destination = SocketAddr::new(IpAddr::V4(dhcp_server_id), DHCP_PORT_SERVER);
                 let request = self.builder.inform(
                     self.state.xid(),
+                    0,
                     self.state.is_broadcast(),
                     address.to_owned(),
                 );
@@ -680,10 +1260,31 @@ where
             }
         };
 
+        if let Some(ref auth_key) = self.auth_key {
+            self.replay_detection += 1;
+            request.set_authentication(auth_key, self.replay_detection);
+        }
         log_send!(request, destination);
         match self.sink.start_send((destination, (request, None))) {
-            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
-            Ok(AsyncSink::NotReady(_item)) => Ok(AsyncSink::NotReady(command)),
+            Ok(AsyncSink::Ready) => {
+                match command {
+                    Command::Stop { .. } => {
+                        info!("Stopping the client");
+                        self.is_stopped = true;
+                    }
+                    Command::Renew => {
+                        self.state
+                            .transcend(DhcpState::Renewing, DhcpState::RenewingSent, None);
+                    }
+                    _ => {}
+                }
+                Ok(AsyncSink::Ready)
+            }
+            // the client is already renewing, so the `Stream` sends the request itself
+            Ok(AsyncSink::NotReady(_item)) => match command {
+                Command::Renew => Ok(AsyncSink::Ready),
+                command => Ok(AsyncSink::NotReady(command)),
+            },
             Err(error) => Err(error),
         }
     }
@@ -698,3 +1299,911 @@ where
         self.poll_complete()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use futures::{stream, sync::mpsc};
+    use tokio::{io, prelude::*, runtime::current_thread::Runtime, timer::Delay};
+
+    use dhcp_protocol::{AuthKey, Message, MessageType};
+
+    use super::Command;
+    use backoff::BackoffConfig;
+    use error::ClientError;
+    use event::ClientEvent;
+    use testing;
+
+    #[test]
+    fn uses_injected_xid_generator() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        let mut sequence = 0u32;
+        client.set_xid_generator(Box::new(move || {
+            sequence += 1;
+            0x002a_0000 | sequence
+        }));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests[0].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        assert_eq!(requests[0].transaction_id, 0x002a_0001);
+        assert_eq!(requests[1].transaction_id, 0x002a_0001);
+    }
+
+    #[test]
+    fn uses_supplied_xid_for_first_session() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        client.set_xid(0x1234_5678);
+        assert_eq!(client.current_xid(), 0x1234_5678);
+
+        let (configuration, client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        assert_eq!(client.current_xid(), 0x1234_5678);
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests[0].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        assert_eq!(requests[0].transaction_id, 0x1234_5678);
+        assert_eq!(requests[1].transaction_id, 0x1234_5678);
+    }
+
+    #[test]
+    fn repeats_discover_seconds_in_selecting_request() {
+        let responder = |request: &Message| {
+            if let Some(MessageType::DhcpDiscover) = request.options.dhcp_message_type {
+                ::std::thread::sleep(Duration::from_millis(1100));
+            }
+            testing::server(request)
+        };
+        let (client, network) = testing::client(Box::new(responder));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests[1].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpRequest as u8
+        );
+        assert_eq!(requests[1].seconds, requests[0].seconds);
+    }
+
+    #[test]
+    fn uses_new_xid_after_restart() {
+        let mut naked = false;
+        let responder = move |request: &Message| match request.options.dhcp_message_type {
+            Some(MessageType::DhcpRequest) if !naked => {
+                naked = true;
+                Some(testing::reply(request, MessageType::DhcpNak))
+            }
+            _ => testing::server(request),
+        };
+        let (client, network) = testing::client(Box::new(responder));
+        let mut runtime = Runtime::new().unwrap();
+
+        let client = match runtime.block_on(client.into_future()) {
+            Err((ClientError::Nak { .. }, client)) => client,
+            _ => panic!("The NAK must be returned"),
+        };
+        let (configuration, _client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests[2].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        assert_eq!(requests[0].transaction_id, requests[1].transaction_id);
+        assert_ne!(requests[0].transaction_id, requests[2].transaction_id);
+        assert_eq!(requests[2].transaction_id, requests[3].transaction_id);
+    }
+
+    /// Runs a rebooting client with the cached gateway either present or absent.
+    ///
+    /// Returns the probed gateway and the type of the first request.
+    fn first_request_after_network_check(is_present: bool) -> (Vec<Ipv4Addr>, MessageType) {
+        let (mut client, network) = testing::rebooting_client(Box::new(testing::server));
+        let probed = Arc::new(Mutex::new(Vec::new()));
+        let probed_by_client = probed.clone();
+        client.set_network_check(
+            Ipv4Addr::new(192, 168, 0, 1),
+            Box::new(move |gateway| {
+                probed_by_client.lock().unwrap().push(gateway);
+                // the reply is awaited like the one of an ARP request
+                Box::new(
+                    Delay::new(Instant::now() + Duration::from_millis(10))
+                        .then(move |_| Ok(is_present)),
+                )
+            }),
+        );
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert_eq!(
+            configuration.unwrap().your_ip_address,
+            Ipv4Addr::from(testing::CLIENT_IP)
+        );
+
+        let request = &network.lock().unwrap().requests[0];
+        let probed = probed.lock().unwrap().to_owned();
+        (probed, request.options.dhcp_message_type.unwrap())
+    }
+
+    #[test]
+    fn reboots_if_cached_gateway_is_present() {
+        let (probed, first) = first_request_after_network_check(true);
+        assert_eq!(probed, vec![Ipv4Addr::new(192, 168, 0, 1)]);
+        assert_eq!(first as u8, MessageType::DhcpRequest as u8);
+    }
+
+    #[test]
+    fn restarts_if_cached_gateway_is_absent() {
+        let (probed, first) = first_request_after_network_check(false);
+        assert_eq!(probed, vec![Ipv4Addr::new(192, 168, 0, 1)]);
+        assert_eq!(first as u8, MessageType::DhcpDiscover as u8);
+    }
+
+    #[test]
+    fn advertises_requested_lease_time() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        client.set_address_time(Some(60));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests[0].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        assert_eq!(
+            requests[1].options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpRequest as u8
+        );
+        assert_eq!(requests[0].options.address_time, Some(60));
+        assert_eq!(requests[1].options.address_time, Some(60));
+    }
+
+    #[test]
+    fn gives_up_acquisition_after_timeout() {
+        let (mut client, network) = testing::client(Box::new(|_| None));
+        client.set_acquisition_timeout(Some(Duration::from_millis(100)));
+
+        let started = Instant::now();
+        let error = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .err()
+            .unwrap();
+
+        match error {
+            ClientError::Timeout => {}
+            error => panic!("Unexpected error: {}", error),
+        }
+        // the first retransmission is not due yet
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(network.lock().unwrap().requests.len(), 1);
+    }
+
+    fn auth_key() -> AuthKey {
+        AuthKey {
+            id: 1,
+            secret: b"shared secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn signs_requests_and_accepts_signed_responses() {
+        let mut replay_detection = 0;
+        let responder = move |request: &Message| {
+            assert!(request.options.authentication.is_some());
+            let mut response = testing::server(request)?;
+            replay_detection += 1;
+            response.set_authentication(&auth_key(), replay_detection);
+            Some(response)
+        };
+        let (mut client, network) = testing::client(Box::new(responder));
+        client.set_auth_key(Some(auth_key()));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        assert_eq!(network.lock().unwrap().requests.len(), 2);
+    }
+
+    #[test]
+    fn counts_replay_detection_per_server() {
+        // the first server sends a NAK, so the client restarts and binds to the second one
+        let mut responses = 0;
+        let responder = move |request: &Message| {
+            responses += 1;
+            let (mut response, replay_detection) = match responses {
+                1 => (testing::reply(request, MessageType::DhcpOffer), 100),
+                2 => (testing::reply(request, MessageType::DhcpNak), 101),
+                _ => (testing::server(request)?, responses - 2),
+            };
+            if responses <= 2 {
+                response.options.dhcp_server_id = Some(Ipv4Addr::new(192, 168, 0, 3));
+            }
+            response.set_authentication(&auth_key(), replay_detection);
+            Some(response)
+        };
+        let (mut client, network) = testing::client(Box::new(responder));
+        client.set_auth_key(Some(auth_key()));
+        client.set_acquisition_timeout(Some(Duration::from_secs(1)));
+
+        let mut runtime = Runtime::new().unwrap();
+        let (error, client) = runtime.block_on(client.into_future()).err().unwrap();
+        match error {
+            ClientError::Nak { .. } => {}
+            error => panic!("Unexpected error: {}", error),
+        }
+        let (configuration, _client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        assert_eq!(network.lock().unwrap().requests.len(), 4);
+    }
+
+    #[test]
+    fn drops_replayed_responses() {
+        let responder = |request: &Message| {
+            let mut response = testing::server(request)?;
+            response.set_authentication(&auth_key(), 1);
+            Some(response)
+        };
+        let (mut client, _network) = testing::client(Box::new(responder));
+        client.set_auth_key(Some(auth_key()));
+        client.set_acquisition_timeout(Some(Duration::from_millis(100)));
+
+        let error = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .err()
+            .unwrap();
+
+        match error {
+            ClientError::Timeout => {}
+            error => panic!("Unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_retransmissions() {
+        let (mut client, network) = testing::client(Box::new(|_| None));
+        client.set_backoff(BackoffConfig {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(64),
+            jitter: Duration::from_millis(0),
+            max_retransmissions: Some(2),
+        });
+
+        let error = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .err()
+            .unwrap();
+
+        match error {
+            ClientError::Timeout => {}
+            error => panic!("Unexpected error: {}", error),
+        }
+        // the original DHCPDISCOVER and two retransmissions
+        assert_eq!(network.lock().unwrap().requests.len(), 3);
+    }
+
+    #[test]
+    fn informs_without_acquiring_lease() {
+        let mut is_lost = true;
+        let (mut client, network) = testing::informing_client(Box::new(move |request| {
+            match request.options.dhcp_message_type {
+                // the first DHCPINFORM is lost, so it is retransmitted
+                Some(MessageType::DhcpInform) if is_lost => {
+                    is_lost = false;
+                    None
+                }
+                Some(MessageType::DhcpInform) => {
+                    let mut ack = testing::reply(request, MessageType::DhcpAck);
+                    ack.your_ip_address = Ipv4Addr::new(0, 0, 0, 0);
+                    ack.options.address_time = None;
+                    Some(ack)
+                }
+                _ => None,
+            }
+        }));
+        client.set_backoff(BackoffConfig {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(64),
+            jitter: Duration::from_millis(0),
+            max_retransmissions: None,
+        });
+
+        let configurations = Runtime::new().unwrap().block_on(client.collect()).unwrap();
+
+        assert_eq!(configurations.len(), 1);
+        assert_eq!(
+            configurations[0].your_ip_address,
+            Ipv4Addr::from(testing::CLIENT_IP)
+        );
+        assert_eq!(
+            configurations[0].subnet_mask,
+            Some(Ipv4Addr::new(255, 255, 255, 0))
+        );
+        let network = network.lock().unwrap();
+        assert_eq!(network.requests.len(), 2);
+        for request in network.requests.iter() {
+            assert_eq!(
+                request.options.dhcp_message_type.unwrap() as u8,
+                MessageType::DhcpInform as u8
+            );
+            assert_eq!(
+                request.client_ip_address,
+                Ipv4Addr::from(testing::CLIENT_IP)
+            );
+            assert_eq!(request.options.address_time, None);
+        }
+        assert_eq!(
+            network.requests[0].transaction_id,
+            network.requests[1].transaction_id
+        );
+    }
+
+    #[test]
+    fn learns_ntp_servers() {
+        let ntp_servers = vec![Ipv4Addr::new(192, 168, 0, 4)];
+        let expected = ntp_servers.to_owned();
+        let (client, _network) = testing::client(Box::new(move |request| {
+            let mut response = testing::server(request)?;
+            response.options.ntp_servers = Some(ntp_servers.to_owned());
+            Some(response)
+        }));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert_eq!(configuration.unwrap().ntp_servers, Some(expected));
+    }
+
+    #[test]
+    fn learns_domain_name_and_search_list() {
+        let (client, _network) = testing::client(Box::new(|request| {
+            let mut response = testing::server(request)?;
+            response.options.domain_name = Some("example.com".to_owned());
+            response.options.domain_search =
+                Some(vec!["eng.example.com".to_owned(), "example.com".to_owned()]);
+            Some(response)
+        }));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        let configuration = configuration.unwrap();
+        assert_eq!(configuration.domain_name, Some("example.com".to_owned()));
+        assert_eq!(
+            configuration.domain_search,
+            Some(vec!["eng.example.com".to_owned(), "example.com".to_owned()])
+        );
+    }
+
+    #[test]
+    fn sends_fqdn_instead_of_hostname() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        client.set_fqdn(Some("host.example.org.".to_owned()));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let request = &network.lock().unwrap().requests[1];
+        assert_eq!(
+            request.options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpRequest as u8
+        );
+        assert_eq!(request.options.hostname, None);
+
+        let expected = b"\x51\x15\x05\xff\xff\x04host\x07example\x03org\x00";
+        let encoded = request.to_vec(None).unwrap();
+        assert!(encoded
+            .windows(expected.len())
+            .any(|option| option == &expected[..]));
+    }
+
+    #[test]
+    fn sends_vendor_class_id() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        client.set_vendor_class_id(Some(b"PXEClient:Arch:00000".to_vec()));
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let discover = &network.lock().unwrap().requests[0];
+        assert_eq!(
+            discover.options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+
+        let expected = b"\x3c\x14PXEClient:Arch:00000";
+        let encoded = discover.to_vec(None).unwrap();
+        assert!(encoded
+            .windows(expected.len())
+            .any(|option| option == &expected[..]));
+    }
+
+    /// Collects the reported events once the client is dropped.
+    fn describe_events(events_rx: mpsc::UnboundedReceiver<ClientEvent>) -> Vec<String> {
+        events_rx
+            .collect()
+            .wait()
+            .unwrap()
+            .into_iter()
+            .map(|event| match event {
+                ClientEvent::StateChanged { from, to } => format!("{} -> {}", from, to),
+                ClientEvent::OfferReceived { .. } => "OFFER".to_owned(),
+                ClientEvent::NakReceived => "NAK".to_owned(),
+                ClientEvent::RetransmitTimeout { .. } => "TIMEOUT".to_owned(),
+                ClientEvent::Bound(_) => "BOUND".to_owned(),
+                ClientEvent::LeaseLost { .. } => "LEASE_LOST".to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_events_of_restarted_acquisition() {
+        let mut naked = false;
+        let responder = move |request: &Message| match request.options.dhcp_message_type {
+            Some(MessageType::DhcpRequest) if !naked => {
+                naked = true;
+                let mut nak = testing::reply(request, MessageType::DhcpNak);
+                nak.options.dhcp_message = Some("Wrong network".to_owned());
+                Some(nak)
+            }
+            _ => testing::server(request),
+        };
+        let (mut client, _network) = testing::client(Box::new(responder));
+        let (events_tx, events_rx) = mpsc::unbounded();
+        client.set_event_sender(events_tx);
+        let mut runtime = Runtime::new().unwrap();
+
+        // the NAK is returned, but the client keeps acquiring a lease if polled further
+        let client = match runtime.block_on(client.into_future()) {
+            Err((ClientError::Nak { message }, client)) => {
+                assert_eq!(message, Some("Wrong network".to_owned()));
+                client
+            }
+            Err((error, _client)) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("The NAK must be returned"),
+        };
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+        drop(client);
+
+        let events = describe_events(events_rx);
+        let acquisition = vec![
+            "INIT -> SELECTING",
+            "SELECTING -> SELECTING_SENT",
+            "OFFER",
+            "SELECTING_SENT -> REQUESTING",
+            "REQUESTING -> REQUESTING_SENT",
+        ];
+        let mut expected = acquisition.to_owned();
+        expected.extend(vec!["NAK", "REQUESTING_SENT -> INIT"]);
+        expected.extend(acquisition);
+        expected.extend(vec!["REQUESTING_SENT -> BOUND", "BOUND"]);
+        assert_eq!(events, expected);
+    }
+
+    /// Binds the client with a lease renewed at once, lets it extend the lease
+    /// answering the extending requests with `responses` and returns the reported events.
+    fn extend_lease(rebinding_time: u32, mut responses: Vec<Option<MessageType>>) -> Vec<String> {
+        let responder = move |request: &Message| {
+            if request.client_ip_address.is_unspecified() {
+                let mut response = testing::server(request)?;
+                response.options.renewal_time = Some(0);
+                response.options.rebinding_time = Some(rebinding_time);
+                return Some(response);
+            }
+            responses
+                .remove(0)
+                .map(|dhcp_message_type| testing::reply(request, dhcp_message_type))
+        };
+        let (mut client, _network) = testing::client(Box::new(responder));
+        let (events_tx, events_rx) = mpsc::unbounded();
+        client.set_event_sender(events_tx);
+        let mut runtime = Runtime::new().unwrap();
+
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+        let mut client = client;
+        loop {
+            client = match runtime.block_on(client.into_future()) {
+                Ok((configuration, _client)) => {
+                    assert!(configuration.is_some());
+                    break;
+                }
+                Err((ClientError::Nak { .. }, client)) => client,
+                Err((error, _client)) => panic!("Unexpected error: {}", error),
+            };
+        }
+
+        describe_events(events_rx)
+    }
+
+    #[test]
+    fn restarts_on_nak_while_renewing() {
+        let events = extend_lease(3600, vec![Some(MessageType::DhcpNak)]);
+
+        let start = events.iter().position(|event| event == "BOUND").unwrap() + 1;
+        assert_eq!(
+            &events[start..start + 5],
+            &[
+                "BOUND -> RENEWING",
+                "RENEWING -> RENEWING_SENT",
+                "NAK",
+                "RENEWING_SENT -> INIT",
+                "INIT -> SELECTING",
+            ]
+        );
+    }
+
+    #[test]
+    fn restarts_on_nak_while_rebinding() {
+        let events = extend_lease(0, vec![None, Some(MessageType::DhcpNak)]);
+
+        let start = events.iter().position(|event| event == "BOUND").unwrap() + 1;
+        assert_eq!(
+            &events[start..start + 8],
+            &[
+                "BOUND -> RENEWING",
+                "RENEWING -> RENEWING_SENT",
+                "TIMEOUT",
+                "RENEWING_SENT -> REBINDING",
+                "REBINDING -> REBINDING_SENT",
+                "NAK",
+                "REBINDING_SENT -> INIT",
+                "INIT -> SELECTING",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_lost_lease_before_restarting() {
+        let mut is_bound = false;
+        let responder = move |request: &Message| {
+            if !request.client_ip_address.is_unspecified() {
+                return None;
+            }
+            let mut response = testing::server(request)?;
+            if !is_bound {
+                response.options.renewal_time = Some(0);
+                response.options.rebinding_time = Some(0);
+                response.options.address_time = Some(0);
+                is_bound = response.options.dhcp_message_type.map(|t| t as u8)
+                    == Some(MessageType::DhcpAck as u8);
+            }
+            Some(response)
+        };
+        let (mut client, network) = testing::client(Box::new(responder));
+        let (events_tx, events_rx) = mpsc::unbounded();
+        client.set_event_sender(events_tx);
+        let mut runtime = Runtime::new().unwrap();
+
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+        let client = match runtime.block_on(client.into_future()) {
+            Err((ClientError::LeaseExpired, client)) => client,
+            Err((error, _client)) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("The lease expiration must be returned"),
+        };
+        let sent = network.lock().unwrap().requests.len();
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+        drop(client);
+
+        let discover = &network.lock().unwrap().requests[sent];
+        assert_eq!(
+            discover.options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        let events = describe_events(events_rx);
+        let start = events
+            .iter()
+            .position(|event| event == "LEASE_LOST")
+            .unwrap();
+        assert_eq!(
+            &events[start - 2..start + 3],
+            &[
+                "REBINDING -> REBINDING_SENT",
+                "TIMEOUT",
+                "LEASE_LOST",
+                "REBINDING_SENT -> INIT",
+                "INIT -> SELECTING",
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_flush_before_sending_next_request() {
+        let (client, network) = testing::slow_client(Box::new(testing::server));
+        let mut runtime = Runtime::new().unwrap();
+
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+
+        let commands = vec![
+            Command::Inform {
+                address: Ipv4Addr::from(testing::CLIENT_IP),
+            },
+            Command::Release { message: None },
+        ];
+        let (_client, _commands) = runtime
+            .block_on(client.send_all(stream::iter_ok::<_, io::Error>(commands)))
+            .unwrap();
+        let types: Vec<u8> = network
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .map(|request| request.options.dhcp_message_type.unwrap() as u8)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                MessageType::DhcpDiscover as u8,
+                MessageType::DhcpRequest as u8,
+                MessageType::DhcpInform as u8,
+                MessageType::DhcpRelease as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn binds_on_rapid_commit_ack() {
+        let responder = |request: &Message| match request.options.dhcp_message_type {
+            Some(MessageType::DhcpDiscover) if request.options.rapid_commit => {
+                let mut ack = testing::reply(request, MessageType::DhcpAck);
+                ack.options.rapid_commit = true;
+                Some(ack)
+            }
+            _ => testing::server(request),
+        };
+        let (mut client, network) = testing::client(Box::new(responder));
+        client.set_rapid_commit(true);
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert_eq!(
+            configuration.unwrap().your_ip_address,
+            Ipv4Addr::from(testing::CLIENT_IP)
+        );
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].options.rapid_commit);
+    }
+
+    #[test]
+    fn falls_back_when_rapid_commit_is_ignored() {
+        let (mut client, network) = testing::client(Box::new(testing::server));
+        client.set_rapid_commit(true);
+
+        let (configuration, _client) = Runtime::new()
+            .unwrap()
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_some());
+        let types: Vec<u8> = network
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .map(|request| request.options.dhcp_message_type.unwrap() as u8)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                MessageType::DhcpDiscover as u8,
+                MessageType::DhcpRequest as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn releases_and_ends_on_stop() {
+        let (client, network) = testing::client(Box::new(testing::server));
+        let mut runtime = Runtime::new().unwrap();
+
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+
+        let client = runtime
+            .block_on(client.send(Command::Stop { message: None }))
+            .unwrap();
+        let (configuration, _client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_none());
+        let requests = &network.lock().unwrap().requests;
+        assert_eq!(
+            requests.last().unwrap().options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpRelease as u8
+        );
+    }
+
+    #[test]
+    fn sets_broadcast_flag_until_bound() {
+        for &is_broadcast in [false, true].iter() {
+            let (mut client, network) = testing::client(Box::new(testing::server));
+            client.set_broadcast(is_broadcast);
+            let mut runtime = Runtime::new().unwrap();
+
+            let (configuration, client) = runtime
+                .block_on(client.into_future())
+                .map_err(|(error, _client)| error)
+                .unwrap();
+            assert!(configuration.is_some());
+            runtime.block_on(client.send(Command::Renew)).unwrap();
+
+            let network = network.lock().unwrap();
+            let flags: Vec<bool> = network
+                .requests
+                .iter()
+                .map(|request| request.is_broadcast)
+                .collect();
+            // DHCPDISCOVER, DHCPREQUEST and the renewal DHCPREQUEST
+            assert_eq!(flags, vec![is_broadcast, is_broadcast, false]);
+        }
+    }
+
+    #[test]
+    fn renews_on_demand() {
+        let (client, network) = testing::client(Box::new(testing::server));
+        let mut runtime = Runtime::new().unwrap();
+
+        let (configuration, client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert!(configuration.is_some());
+
+        let client = runtime.block_on(client.send(Command::Renew)).unwrap();
+        {
+            let network = network.lock().unwrap();
+            let request = network.requests.last().unwrap();
+            assert_eq!(
+                request.options.dhcp_message_type.unwrap() as u8,
+                MessageType::DhcpRequest as u8
+            );
+            assert_eq!(
+                request.client_ip_address,
+                Ipv4Addr::from(testing::CLIENT_IP)
+            );
+            assert_eq!(request.options.dhcp_server_id, None);
+            assert_eq!(
+                network.destinations.last().unwrap().ip(),
+                IpAddr::V4(Ipv4Addr::from(testing::SERVER_IP))
+            );
+        }
+
+        let (configuration, _client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+        assert_eq!(
+            configuration.unwrap().your_ip_address,
+            Ipv4Addr::from(testing::CLIENT_IP)
+        );
+        assert_eq!(network.lock().unwrap().requests.len(), 3);
+    }
+
+    #[test]
+    fn refuses_to_renew_without_lease() {
+        let (client, network) = testing::client(Box::new(testing::server));
+
+        let error = Runtime::new()
+            .unwrap()
+            .block_on(client.send(Command::Renew))
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(network.lock().unwrap().requests.is_empty());
+    }
+
+    #[test]
+    fn ends_on_stop_without_lease() {
+        let (client, network) = testing::client(Box::new(testing::server));
+        let mut runtime = Runtime::new().unwrap();
+
+        let client = runtime
+            .block_on(client.send(Command::Stop { message: None }))
+            .unwrap();
+        let (configuration, _client) = runtime
+            .block_on(client.into_future())
+            .map_err(|(error, _client)| error)
+            .unwrap();
+
+        assert!(configuration.is_none());
+        assert!(network.lock().unwrap().requests.is_empty());
+    }
+}
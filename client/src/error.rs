@@ -0,0 +1,40 @@
+//! The DHCP client error module.
+
+use std::io;
+
+/// Errors returned by the client `Stream`.
+///
+/// `Nak` and `LeaseExpired` are not fatal: the client has already moved to INIT state,
+/// so the `Stream` may be polled further to acquire a new lease.
+#[derive(Fail, Debug)]
+pub enum ClientError {
+    #[fail(display = "Timeout")]
+    Timeout,
+    #[fail(display = "Got DHCPNAK: {:?}", message)]
+    Nak { message: Option<String> },
+    #[fail(display = "Socket error: {}", _0)]
+    Socket(#[cause] io::Error),
+    /// The request has been rejected by the sink, e.g. because it could not be encoded.
+    #[fail(display = "Protocol error: {}", _0)]
+    Protocol(String),
+    #[fail(display = "The lease has expired")]
+    LeaseExpired,
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Socket(error)
+    }
+}
+
+impl From<ClientError> for io::Error {
+    fn from(error: ClientError) -> Self {
+        let kind = match error {
+            ClientError::Socket(error) => return error,
+            ClientError::Timeout => io::ErrorKind::TimedOut,
+            ClientError::Protocol(_) => io::ErrorKind::InvalidData,
+            ClientError::Nak { .. } | ClientError::LeaseExpired => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error.to_string())
+    }
+}
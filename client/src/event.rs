@@ -0,0 +1,26 @@
+//! The client events reported to a user interface.
+
+use std::net::Ipv4Addr;
+
+use client::Configuration;
+use state::DhcpState;
+
+/// The events the `Client` reports through its event channel if it is set.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The client has moved from one state to another.
+    StateChanged { from: DhcpState, to: DhcpState },
+    /// A `DHCPOFFER` has been selected.
+    OfferReceived {
+        address: Ipv4Addr,
+        dhcp_server_id: Ipv4Addr,
+    },
+    /// A `DHCPNAK` has been received, so the client is restarting.
+    NakReceived,
+    /// No responses have been received within the timeout.
+    RetransmitTimeout { seconds: u64 },
+    /// The client has been configured or its lease has been extended.
+    Bound(Configuration),
+    /// The lease has expired, so the address must be removed from the interface.
+    LeaseLost { address: Ipv4Addr },
+}
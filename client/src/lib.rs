@@ -5,8 +5,13 @@ mod macros;
 mod backoff;
 mod builder;
 mod client;
+mod error;
+mod event;
 mod forthon;
+mod oneshot;
 mod state;
+#[cfg(test)]
+mod testing;
 
 #[macro_use]
 extern crate log;
@@ -16,10 +21,20 @@ extern crate futures;
 extern crate bytes;
 extern crate chrono;
 extern crate eui48;
+#[macro_use]
+extern crate failure;
 extern crate hostname;
 extern crate rand;
 
+extern crate dhcp_arp;
 extern crate dhcp_framed;
 extern crate dhcp_protocol;
 
-pub use self::client::{Client, Command, Configuration};
+pub use self::{
+    backoff::BackoffConfig,
+    client::{Client, Command, Configuration, GatewayProbe, XidGenerator},
+    error::ClientError,
+    event::ClientEvent,
+    oneshot::AcquireAndRelease,
+    state::DhcpState,
+};
@@ -23,15 +23,6 @@ macro_rules! log_receive(
     );
 );
 
-/// By design the pending message must be flushed before sending the next one.
-macro_rules! start_send (
-    ($socket:expr, $address:expr, $message:expr) => (
-        if let AsyncSink::NotReady(_) = $socket.start_send(($address, $message))? {
-            panic!("Must wait for poll_complete first");
-        }
-    );
-);
-
 /// Just to move some code from the overwhelmed `poll` method.
 macro_rules! poll_complete (
     ($socket:expr) => (
@@ -70,11 +61,24 @@ macro_rules! check_xid (
 );
 
 /// Just to move some code from the overwhelmed `poll` method.
-macro_rules! check_message_type (
-    ($message:expr, $needed:pat) => (
-        if let $needed = $message {} else {
-            warn!("Got an unexpected DHCP message type {}", $message);
-            continue;
+///
+/// The HMAC has already been verified by the socket, so only the replay detection is checked
+/// against the last value of the server, which is present in every validated response.
+macro_rules! check_auth (
+    ($auth_key:expr, $last_values:expr, $response:expr, $address:expr) => (
+        if $auth_key.is_some() {
+            let last = $last_values
+                .entry(expect!($response.options.dhcp_server_id))
+                .or_insert(0);
+            match $response.options.authentication {
+                Some(ref authentication) if authentication.replay_detection > *last => {
+                    *last = authentication.replay_detection;
+                },
+                _ => {
+                    warn!("Dropping an unauthenticated or replayed response from {}", $address);
+                    continue;
+                },
+            }
         }
     );
 );
@@ -96,13 +100,14 @@ macro_rules! poll_delay (
 
 /// Just to move some code from the overwhelmed `poll` method.
 macro_rules! poll_backoff (
-    ($backoff:expr) => (
-        if let Some(ref mut backoff) = $backoff {
+    ($state:expr, $backoff:ident) => (
+        if let Some(ref mut backoff) = $state.$backoff {
             match backoff.poll() {
                 Ok(Async::Ready(Some((secs, expired)))) => {
                     warn!("No responses after {} seconds", secs);
+                    $state.report(ClientEvent::RetransmitTimeout { seconds: secs });
                     if expired {
-                        return Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout"));
+                        return Err(ClientError::Timeout);
                     }
                 },
                 Ok(Async::Ready(None)) => panic!("Timer returned None"),
@@ -113,11 +118,12 @@ macro_rules! poll_backoff (
             panic!("A bug in the timer setting logic");
         }
     );
-    ($backoff:expr, $revert:expr, $restart:expr) => (
-        if let Some(ref mut backoff) = $backoff {
+    ($state:expr, $backoff:ident, $revert:expr, $restart:expr) => (
+        if let Some(ref mut backoff) = $state.$backoff {
             match backoff.poll() {
                 Ok(Async::Ready(Some((secs, expired)))) => {
                     warn!("No responses after {} seconds", secs);
+                    $state.report(ClientEvent::RetransmitTimeout { seconds: secs });
                     if expired {
                         $restart
                     } else {
@@ -136,11 +142,12 @@ macro_rules! poll_backoff (
 
 /// Just to move some code from the overwhelmed `poll` method.
 macro_rules! poll_forthon (
-    ($forthon:expr, $revert:expr, $restart:expr) => (
-        if let Some(ref mut forthon) = $forthon {
+    ($state:expr, $forthon:ident, $revert:expr, $restart:expr) => (
+        if let Some(ref mut forthon) = $state.$forthon {
             match forthon.poll() {
                 Ok(Async::Ready(Some((secs, expired)))) => {
                     warn!("No responses after {} seconds", secs);
+                    $state.report(ClientEvent::RetransmitTimeout { seconds: secs });
                     if expired {
                         $restart
                     } else {
@@ -160,6 +167,6 @@ macro_rules! poll_forthon (
 /// Panic if there is a bug in the state changing logic.
 macro_rules! panic_state(
     ($from:expr, $to:expr) => (
-        panic!("Invalid state transcension from {} to {}");
+        panic!("Invalid state transcension from {} to {}", $from, $to);
     );
 );
@@ -0,0 +1,162 @@
+//! The one-shot client lifecycle: acquire, hold and release.
+
+use std::time::{Duration, Instant};
+
+use tokio::{io, prelude::*, timer::Delay};
+
+use client::{Client, Command, Configuration, DhcpSinkItem, DhcpStreamItem};
+
+/// The `AcquireAndRelease` stages.
+enum Stage {
+    /// Waiting for the client to get into `BOUND` state.
+    Acquiring,
+    /// The lease is being held and renewed till the deadline.
+    Holding(Delay),
+    /// The `DHCPRELEASE` is being sent.
+    Releasing,
+    /// The `DHCPRELEASE` is being flushed.
+    Flushing,
+}
+
+/// The future returned by `Client::acquire_and_release`.
+///
+/// Resolves to the last received `Configuration` after the lease is released.
+pub struct AcquireAndRelease<I, O>
+where
+    I: Stream<Item = DhcpStreamItem, Error = io::Error> + Send + Sync,
+    O: Sink<SinkItem = DhcpSinkItem, SinkError = io::Error> + Send + Sync,
+{
+    client: Client<I, O>,
+    hold: Option<Duration>,
+    stage: Stage,
+    configuration: Option<Configuration>,
+}
+
+impl<I, O> AcquireAndRelease<I, O>
+where
+    I: Stream<Item = DhcpStreamItem, Error = io::Error> + Send + Sync,
+    O: Sink<SinkItem = DhcpSinkItem, SinkError = io::Error> + Send + Sync,
+{
+    /// Wraps the client which has not been polled yet.
+    pub fn new(client: Client<I, O>, hold: Option<Duration>) -> Self {
+        AcquireAndRelease {
+            client,
+            hold,
+            stage: Stage::Acquiring,
+            configuration: None,
+        }
+    }
+
+    /// Records the configuration yielded by the client.
+    fn record(&mut self, configuration: Option<Configuration>) -> io::Result<()> {
+        match configuration {
+            Some(configuration) => {
+                self.configuration = Some(configuration);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The client has stopped",
+            )),
+        }
+    }
+}
+
+impl<I, O> Future for AcquireAndRelease<I, O>
+where
+    I: Stream<Item = DhcpStreamItem, Error = io::Error> + Send + Sync,
+    O: Sink<SinkItem = DhcpSinkItem, SinkError = io::Error> + Send + Sync,
+{
+    type Item = Configuration;
+    type Error = io::Error;
+
+    #[allow(clippy::io_other_error)]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.stage {
+                Stage::Acquiring => {
+                    let configuration = try_ready!(self.client.poll());
+                    self.record(configuration)?;
+                    match self.hold {
+                        Some(hold) => Stage::Holding(Delay::new(Instant::now() + hold)),
+                        None => Stage::Releasing,
+                    }
+                }
+                Stage::Holding(ref mut deadline) => {
+                    // the client keeps renewing the lease while it is held
+                    if let Async::Ready(configuration) = self.client.poll()? {
+                        self.record(configuration)?;
+                        continue;
+                    }
+                    match deadline.poll() {
+                        Ok(Async::Ready(_)) => Stage::Releasing,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(error) => return Err(io::Error::new(io::ErrorKind::Other, error)),
+                    }
+                }
+                Stage::Releasing => {
+                    let command = Command::Release { message: None };
+                    if let AsyncSink::NotReady(_) = self.client.start_send(command)? {
+                        return Ok(Async::NotReady);
+                    }
+                    Stage::Flushing
+                }
+                Stage::Flushing => {
+                    try_ready!(self.client.poll_complete());
+                    return Ok(Async::Ready(
+                        self.configuration
+                            .take()
+                            .expect("A bug in the configuration recording logic"),
+                    ));
+                }
+            };
+            self.stage = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use dhcp_protocol::MessageType;
+
+    use testing;
+
+    #[test]
+    fn acquires_holds_and_releases() {
+        let (client, network) = testing::client(Box::new(testing::server));
+        let hold = Duration::from_millis(100);
+
+        let started_at = Instant::now();
+        let configuration = Runtime::new()
+            .unwrap()
+            .block_on(client.acquire_and_release(Some(hold)))
+            .unwrap();
+
+        assert!(started_at.elapsed() >= hold);
+        assert_eq!(configuration.your_ip_address, Ipv4Addr::from(testing::CLIENT_IP));
+        let requests = &network.lock().unwrap().requests;
+        let types: Vec<u8> = requests
+            .iter()
+            .map(|request| request.options.dhcp_message_type.unwrap() as u8)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                MessageType::DhcpDiscover as u8,
+                MessageType::DhcpRequest as u8,
+                MessageType::DhcpRelease as u8,
+            ]
+        );
+        assert_eq!(
+            requests.last().unwrap().client_ip_address,
+            Ipv4Addr::from(testing::CLIENT_IP)
+        );
+    }
+}
@@ -1,36 +1,46 @@
 //! The DHCP client state module.
 
 use std::{
+    cmp,
     fmt,
     net::Ipv4Addr,
     time::{Duration, Instant},
 };
 
 use chrono::prelude::*;
+use futures::sync::mpsc;
 use rand;
 use tokio::timer::Delay;
 
 use dhcp_protocol::Message;
 
-use backoff::Backoff;
+use backoff::{Backoff, BackoffConfig};
+use client::XidGenerator;
+use event::ClientEvent;
 use forthon::Forthon;
 
-/// Initial timeout in seconds for the BEB timers.
-const BACKOFF_TIMEOUT_INITIAL: u64 = 4;
-/// Maximum timeout in seconds for the BEB timers.
-const BACKOFF_TIMEOUT_MAXIMUM: u64 = 64;
 /// Minimal stimeout in seconds for the BEF™ timers.
 const FORTHON_TIMEOUT_MINIMAL: u64 = 60;
 /// Is used if a server does not provide the `renewal_time` option.
 const RENEWAL_TIME_FACTOR: f64 = 0.5;
 /// Is used if a server does not provide the `rebinding_time` option.
 const REBINDING_TIME_FACTOR: f64 = 0.875;
+/// RFC 2131 §3.3
+/// The lease time `0xffffffff` is reserved to represent "infinity".
+const INFINITE_LEASE_TIME: u32 = 0xffff_ffff;
+/// The longest timer duration in seconds, since the tokio timer wheel cannot hold
+/// the deadlines much further than two years.
+const TIMER_DURATION_MAXIMAL: u64 = 365 * 24 * 60 * 60;
+/// The largest value of the `secs` field.
+const SECONDS_MAXIMAL: u16 = 0xffff;
 
 /// RFC 2131 DHCP states.
 ///
 /// The ones end with `Sent` are not described in RFC 2131 and
 /// are just substates to tell if the request has been sent or not.
-#[derive(Clone, Copy)]
+/// The ones with `Inform` are not described either and make up
+/// the `DHCPINFORM` exchange of a client configured externally (RFC 2131 §4.4.3).
+#[derive(Debug, Clone, Copy)]
 pub enum DhcpState {
     Init,
     Selecting,
@@ -45,6 +55,10 @@ pub enum DhcpState {
     RenewingSent,
     Rebinding,
     RebindingSent,
+    InitInform,
+    Informing,
+    InformingSent,
+    Informed,
 }
 
 impl fmt::Display for DhcpState {
@@ -64,6 +78,10 @@ impl fmt::Display for DhcpState {
             RenewingSent => write!(f, "RENEWING_SENT"),
             Rebinding => write!(f, "REBINDING"),
             RebindingSent => write!(f, "REBINDING_SENT"),
+            InitInform => write!(f, "INITINFORM"),
+            Informing => write!(f, "INFORMING"),
+            InformingSent => write!(f, "INFORMING_SENT"),
+            Informed => write!(f, "INFORMED"),
         }
     }
 }
@@ -76,6 +94,14 @@ pub struct State {
     is_broadcast: bool,
     /// Generated by the client for each session.
     transaction_id: u32,
+    /// The externally supplied transaction ID used by the first session instead of a generated one.
+    initial_xid: Option<u32>,
+    /// Generates the transaction ID for each session.
+    xid_generator: XidGenerator,
+    /// The channel the client events are reported to.
+    events: Option<mpsc::UnboundedSender<ClientEvent>>,
+    /// The retransmission schedule of the BEB timers.
+    backoff: BackoffConfig,
     /// Recorded by the client from the selected `DHCPOFFER`.
     offered_address: Ipv4Addr,
     /// Recorded by the client from the selected `DHCPOFFER`.
@@ -85,6 +111,10 @@ pub struct State {
     /// Recorded by the client from the `DhcpAck`.
     assigned_address: Ipv4Addr,
 
+    /// The start of the current acquisition or renewal process, reported in `secs`.
+    started_at: Instant,
+    /// The `secs` of the last `DHCPDISCOVER`, which the selecting `DHCPREQUEST` repeats.
+    discover_seconds: u16,
     /// Recorded by the client right before sending the `DhcpRequest`.
     requested_at: i64,
     /// Seconds from `BOUND` till `RENEWING` state.
@@ -93,6 +123,8 @@ pub struct State {
     rebinding_after: u64,
     /// Seconds from `REBINDING` till lease expiration.
     expiration_after: u64,
+    /// Whether the lease never expires.
+    is_lease_infinite: bool,
 
     /// DHCPOFFER receive deadline.
     pub timer_offer: Option<Backoff>,
@@ -117,15 +149,22 @@ impl State {
             dhcp_state,
             is_broadcast,
             transaction_id: rand::random::<u32>(),
+            initial_xid: None,
+            xid_generator: Box::new(rand::random::<u32>),
+            events: None,
+            backoff: BackoffConfig::default(),
             offered_address: Ipv4Addr::new(0, 0, 0, 0),
             offered_time: 0u32,
             dhcp_server_id: server_address,
             assigned_address: Ipv4Addr::new(0, 0, 0, 0),
 
+            started_at: Instant::now(),
+            discover_seconds: 0u16,
             requested_at: 0i64,
             renewal_after: 0u64,
             rebinding_after: 0u64,
             expiration_after: 0u64,
+            is_lease_infinite: false,
 
             timer_offer: None,
             timer_ack: None,
@@ -142,10 +181,13 @@ impl State {
     pub fn transcend(&mut self, from: DhcpState, to: DhcpState, response: Option<&Message>) {
         use self::DhcpState::*;
         trace!("Transcending from {} to {}", from, to);
+        self.report(ClientEvent::StateChanged { from, to });
 
         match from {
             Init => match to {
                 next @ Selecting => {
+                    self.new_transaction();
+                    self.start_process();
                     self.set_dhcp_server_id(None);
                     self.run_timer_offer();
                     self.dhcp_state = next;
@@ -154,6 +196,7 @@ impl State {
             },
             Selecting => match to {
                 next @ SelectingSent => {
+                    self.record_request_time();
                     self.dhcp_state = next;
                 }
                 _ => panic_state!(from, to),
@@ -168,6 +211,18 @@ impl State {
                     self.run_timer_ack();
                     self.dhcp_state = next;
                 }
+                next @ Bound => {
+                    let ack = expect!(response);
+                    self.set_assigned_address(ack.your_ip_address);
+                    self.set_dhcp_server_id(Some(expect!(ack.options.dhcp_server_id)));
+                    self.set_times(
+                        ack.options.renewal_time,
+                        ack.options.rebinding_time,
+                        expect!(ack.options.address_time),
+                    );
+                    self.run_timer_renewal();
+                    self.dhcp_state = next;
+                }
                 _ => panic_state!(from, to),
             },
             Requesting => match to {
@@ -196,9 +251,12 @@ impl State {
 
             InitReboot => match to {
                 next @ Rebooting => {
+                    self.new_transaction();
+                    self.start_process();
                     self.run_timer_ack();
                     self.dhcp_state = next;
                 }
+                next @ Init => self.dhcp_state = next,
                 _ => panic_state!(from, to),
             },
             Rebooting => match to {
@@ -228,6 +286,8 @@ impl State {
 
             Bound => match to {
                 next @ Renewing => {
+                    self.new_transaction();
+                    self.start_process();
                     self.run_timer_rebinding();
                     self.dhcp_state = next;
                 }
@@ -253,8 +313,10 @@ impl State {
                     self.run_timer_renewal();
                     self.dhcp_state = next;
                 }
+                next @ Init => self.dhcp_state = next,
                 next @ Renewing => self.dhcp_state = next,
                 next @ Rebinding => {
+                    self.new_transaction();
                     self.set_dhcp_server_id(None);
                     self.run_timer_expiration();
                     self.dhcp_state = next;
@@ -285,6 +347,33 @@ impl State {
                 next @ Rebinding => self.dhcp_state = next,
                 _ => panic_state!(from, to),
             },
+
+            InitInform => match to {
+                next @ Informing => {
+                    self.new_transaction();
+                    self.start_process();
+                    self.run_timer_ack();
+                    self.dhcp_state = next;
+                }
+                _ => panic_state!(from, to),
+            },
+            Informing => match to {
+                next @ InformingSent => {
+                    self.record_request_time();
+                    self.dhcp_state = next;
+                }
+                _ => panic_state!(from, to),
+            },
+            InformingSent => match to {
+                next @ Informing => self.dhcp_state = next,
+                next @ Informed => {
+                    let ack = expect!(response);
+                    self.set_dhcp_server_id(ack.options.dhcp_server_id);
+                    self.dhcp_state = next;
+                }
+                _ => panic_state!(from, to),
+            },
+            Informed => panic_state!(from, to),
         }
     }
 
@@ -292,14 +381,93 @@ impl State {
         self.dhcp_state
     }
 
+    /// Whether the client holds a lease, i.e. is in `BOUND`, `RENEWING` or `REBINDING` state.
+    #[allow(clippy::match_like_matches_macro)]
+    pub fn is_bound(&self) -> bool {
+        use self::DhcpState::*;
+        match self.dhcp_state {
+            Bound | Renewing | RenewingSent | Rebinding | RebindingSent => true,
+            _ => false,
+        }
+    }
+
+    /// Whether to set the BROADCAST flag of the requests.
+    ///
+    /// The flag is only set until the client has an address to receive unicast at,
+    /// so it is always cleared when renewing, rebinding or informing.
     pub fn is_broadcast(&self) -> bool {
-        self.is_broadcast
+        use self::DhcpState::*;
+        match self.dhcp_state {
+            InitInform | Informing | InformingSent | Informed => false,
+            _ => self.is_broadcast && !self.is_bound(),
+        }
     }
 
     pub fn xid(&self) -> u32 {
         self.transaction_id
     }
 
+    /// Makes the first session use the given transaction ID instead of a generated one.
+    pub fn set_initial_xid(&mut self, xid: u32) {
+        self.transaction_id = xid;
+        self.initial_xid = Some(xid);
+    }
+
+    pub fn set_xid_generator(&mut self, xid_generator: XidGenerator) {
+        self.xid_generator = xid_generator;
+    }
+
+    pub fn set_events(&mut self, events: mpsc::UnboundedSender<ClientEvent>) {
+        self.events = Some(events);
+    }
+
+    pub fn set_backoff(&mut self, backoff: BackoffConfig) {
+        self.backoff = backoff;
+    }
+
+    /// Reports an event if the event channel is set.
+    ///
+    /// The event is discarded if the receiver has been dropped.
+    pub fn report(&self, event: ClientEvent) {
+        if let Some(ref events) = self.events {
+            let _ = events.unbounded_send(event);
+        }
+    }
+
+    /// Starts a new session, which is kept by the retransmissions.
+    fn new_transaction(&mut self) {
+        self.transaction_id = match self.initial_xid.take() {
+            Some(xid) => xid,
+            None => (self.xid_generator)(),
+        };
+    }
+
+    /// Starts a new acquisition or renewal process.
+    ///
+    /// Rebinding continues the renewal process, so it does not start a new one.
+    fn start_process(&mut self) {
+        self.started_at = Instant::now();
+    }
+
+    /// The seconds elapsed since the current process has started (RFC 2131 §2).
+    ///
+    /// Is sent in the `secs` field, so it is capped at `u16::MAX`.
+    pub fn seconds(&self) -> u16 {
+        cmp::min(
+            self.started_at.elapsed().as_secs(),
+            u64::from(SECONDS_MAXIMAL),
+        ) as u16
+    }
+
+    /// The `secs` sent in the last `DHCPDISCOVER`.
+    pub fn discover_seconds(&self) -> u16 {
+        self.discover_seconds
+    }
+
+    pub fn set_discover_seconds(&mut self, discover_seconds: u16) {
+        self.discover_seconds = discover_seconds;
+    }
+
     pub fn offered_address(&self) -> Ipv4Addr {
         self.offered_address.to_owned()
     }
@@ -316,8 +484,7 @@ impl State {
         self.assigned_address.to_owned()
     }
 
-    #[allow(dead_code)]
-    fn set_broadcast(&mut self, value: bool) {
+    pub fn set_broadcast(&mut self, value: bool) {
         self.is_broadcast = value;
     }
 
@@ -341,55 +508,158 @@ impl State {
         self.requested_at = Utc::now().timestamp();
     }
 
+    /// Whether the lease never expires, so it is not renewed at T1.
+    pub fn is_lease_infinite(&self) -> bool {
+        self.is_lease_infinite
+    }
+
+    /// Calculates the intervals between the lease timers.
+    ///
+    /// The times are counted from the request time and clamped to keep
+    /// `T1 <= T2 <= expiration`, so any values sent by a server are safe.
     fn set_times(
         &mut self,
         renewal_time: Option<u32>,
         rebinding_time: Option<u32>,
         expiration_time: u32,
     ) {
-        let renewal_time =
-            renewal_time.unwrap_or(((expiration_time as f64) * RENEWAL_TIME_FACTOR) as u32);
-        let rebinding_time =
-            rebinding_time.unwrap_or(((expiration_time as f64) * REBINDING_TIME_FACTOR) as u32);
-
-        self.renewal_after =
-            ((renewal_time as i64) - (Utc::now().timestamp() - self.requested_at)) as u64;
-        self.rebinding_after = (rebinding_time as u64) - self.renewal_after;
-        self.expiration_after =
-            (expiration_time as u64) - self.renewal_after - self.rebinding_after;
+        self.is_lease_infinite = expiration_time == INFINITE_LEASE_TIME;
+
+        let expiration_time = u64::from(expiration_time);
+        let rebinding_time = rebinding_time
+            .map(u64::from)
+            .unwrap_or(((expiration_time as f64) * REBINDING_TIME_FACTOR) as u64);
+        let rebinding_time = cmp::min(rebinding_time, expiration_time);
+        let renewal_time = renewal_time
+            .map(u64::from)
+            .unwrap_or(((expiration_time as f64) * RENEWAL_TIME_FACTOR) as u64);
+        let renewal_time = cmp::min(renewal_time, rebinding_time);
+
+        let elapsed = cmp::max(Utc::now().timestamp() - self.requested_at, 0) as u64;
+        self.renewal_after = renewal_time.saturating_sub(elapsed);
+        self.rebinding_after = rebinding_time.saturating_sub(elapsed) - self.renewal_after;
+        self.expiration_after = expiration_time.saturating_sub(elapsed)
+            - self.renewal_after
+            - self.rebinding_after;
     }
 
     fn run_timer_offer(&mut self) {
-        self.timer_offer = Some(Backoff::new(
-            Duration::from_secs(BACKOFF_TIMEOUT_INITIAL),
-            Duration::from_secs(BACKOFF_TIMEOUT_MAXIMUM),
-        ));
+        self.timer_offer = Some(Backoff::new(&self.backoff));
     }
 
     fn run_timer_ack(&mut self) {
-        self.timer_ack = Some(Backoff::new(
-            Duration::from_secs(BACKOFF_TIMEOUT_INITIAL),
-            Duration::from_secs(BACKOFF_TIMEOUT_MAXIMUM),
-        ));
+        self.timer_ack = Some(Backoff::new(&self.backoff));
     }
 
-    fn run_timer_renewal(&mut self) {
-        self.timer_renewal = Some(Delay::new(
-            Instant::now() + Duration::from_secs(self.renewal_after),
-        ));
+    /// An infinite lease timer is just rearmed with the maximal duration each time it fires.
+    pub fn run_timer_renewal(&mut self) {
+        let renewal_after = if self.is_lease_infinite {
+            TIMER_DURATION_MAXIMAL
+        } else {
+            self.renewal_after
+        };
+        self.timer_renewal = Some(Delay::new(Instant::now() + Self::timer_duration(renewal_after)));
+    }
+
+    /// Makes the client renew the lease before T1.
+    ///
+    /// The time left until T1 is added to the rebinding timer, so T2 is not moved earlier.
+    pub fn renew_early(&mut self) {
+        if let Some(ref timer_renewal) = self.timer_renewal {
+            let now = Instant::now();
+            if timer_renewal.deadline() > now {
+                self.rebinding_after += (timer_renewal.deadline() - now).as_secs();
+            }
+        }
+        self.transcend(DhcpState::Bound, DhcpState::Renewing, None);
     }
 
     fn run_timer_rebinding(&mut self) {
         self.timer_rebinding = Some(Forthon::new(
-            Duration::from_secs(self.rebinding_after),
+            Self::timer_duration(self.rebinding_after),
             Duration::from_secs(FORTHON_TIMEOUT_MINIMAL),
         ));
     }
 
     fn run_timer_expiration(&mut self) {
         self.timer_expiration = Some(Forthon::new(
-            Duration::from_secs(self.expiration_after),
+            Self::timer_duration(self.expiration_after),
             Duration::from_secs(FORTHON_TIMEOUT_MINIMAL),
         ));
     }
+
+    fn timer_duration(seconds: u64) -> Duration {
+        Duration::from_secs(cmp::min(seconds, TIMER_DURATION_MAXIMAL))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(renewal_time: Option<u32>, rebinding_time: Option<u32>, lease_time: u32) -> State {
+        let mut state = State::new(DhcpState::Init, None, false);
+        // a future request time keeps the elapsed time zero however slow the test is
+        state.requested_at = Utc::now().timestamp() + 60;
+        state.set_times(renewal_time, rebinding_time, lease_time);
+        state.run_timer_renewal();
+        state.run_timer_rebinding();
+        state.run_timer_expiration();
+        state
+    }
+
+    #[test]
+    fn reports_seconds_since_process_start() {
+        let mut state = State::new(DhcpState::Init, None, false);
+        state.transcend(DhcpState::Init, DhcpState::Selecting, None);
+        assert_eq!(state.seconds(), 0);
+
+        // the clock is advanced by moving the start back
+        state.started_at -= Duration::from_secs(5);
+        state.transcend(DhcpState::Selecting, DhcpState::SelectingSent, None);
+        state.transcend(DhcpState::SelectingSent, DhcpState::Selecting, None);
+        assert_eq!(state.seconds(), 5);
+    }
+
+    #[test]
+    fn handles_infinite_lease() {
+        let state = bound(None, None, INFINITE_LEASE_TIME);
+
+        assert!(state.is_lease_infinite());
+        assert_eq!(
+            state.renewal_after + state.rebinding_after + state.expiration_after,
+            u64::from(INFINITE_LEASE_TIME)
+        );
+    }
+
+    #[test]
+    fn handles_huge_lease() {
+        let lease_time = INFINITE_LEASE_TIME / 2;
+        let state = bound(None, None, lease_time);
+
+        assert!(!state.is_lease_infinite());
+        assert_eq!(state.renewal_after, u64::from(lease_time / 2));
+        assert_eq!(
+            state.renewal_after + state.rebinding_after + state.expiration_after,
+            u64::from(lease_time)
+        );
+    }
+
+    #[test]
+    fn handles_zero_lease() {
+        let state = bound(None, None, 0);
+
+        assert_eq!(state.renewal_after, 0);
+        assert_eq!(state.rebinding_after, 0);
+        assert_eq!(state.expiration_after, 0);
+    }
+
+    #[test]
+    fn clamps_inconsistent_server_times() {
+        let state = bound(Some(INFINITE_LEASE_TIME), Some(1000), 600);
+
+        assert_eq!(state.renewal_after, 600);
+        assert_eq!(state.rebinding_after, 0);
+        assert_eq!(state.expiration_after, 0);
+    }
 }
@@ -0,0 +1,238 @@
+//! The in-memory network the client is tested on.
+
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use eui48::MacAddress;
+use futures::{task, StartSend};
+use tokio::{io, prelude::*};
+
+use dhcp_protocol::*;
+
+use client::Client;
+
+pub const SERVER_IP: [u8; 4] = [192, 168, 0, 2];
+pub const CLIENT_IP: [u8; 4] = [192, 168, 0, 100];
+pub const CLIENT_MAC: [u8; 6] = [0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+
+/// Answers a client request like a DHCP server would.
+pub type Responder = Box<FnMut(&Message) -> Option<Message> + Send>;
+
+/// The requests sent by the client and the responses waiting to be received.
+pub struct Network {
+    pub requests: Vec<Message>,
+    /// The destinations of `requests` in the same order.
+    pub destinations: Vec<SocketAddr>,
+    responses: VecDeque<(SocketAddr, Message)>,
+    responder: Responder,
+}
+
+/// The client socket `Stream` part.
+pub struct TestStream(Arc<Mutex<Network>>);
+
+impl Stream for TestStream {
+    type Item = (SocketAddr, Message);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.0.lock().unwrap().responses.pop_front() {
+            Some(item) => Ok(Async::Ready(Some(item))),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// The client socket `Sink` part, which answers the requests at once.
+pub struct TestSink(Arc<Mutex<Network>>);
+
+impl Sink for TestSink {
+    type SinkItem = (SocketAddr, (Message, Option<u16>));
+    type SinkError = io::Error;
+
+    fn start_send(
+        &mut self,
+        item: Self::SinkItem,
+    ) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let (destination, (request, _max_size)) = item;
+        let mut network = self.0.lock().unwrap();
+        if let Some(response) = (network.responder)(&request) {
+            let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::from(SERVER_IP)), DHCP_PORT_SERVER);
+            network.responses.push_back((source, response));
+        }
+        network.requests.push(request);
+        network.destinations.push(destination);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// The client socket `Sink` part, which buffers a request for one extra `poll_complete`
+/// like a congested socket would and forbids sending while a request is pending.
+pub struct SlowSink {
+    inner: TestSink,
+    pending: Option<<TestSink as Sink>::SinkItem>,
+    is_delayed: bool,
+}
+
+impl Sink for SlowSink {
+    type SinkItem = <TestSink as Sink>::SinkItem;
+    type SinkError = io::Error;
+
+    fn start_send(
+        &mut self,
+        item: Self::SinkItem,
+    ) -> StartSend<Self::SinkItem, Self::SinkError> {
+        assert!(
+            self.pending.is_none(),
+            "A request is sent before the previous one is flushed"
+        );
+        self.pending = Some(item);
+        self.is_delayed = true;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        if self.is_delayed {
+            self.is_delayed = false;
+            task::current().notify();
+            return Ok(Async::NotReady);
+        }
+        if let Some(item) = self.pending.take() {
+            self.inner.start_send(item)?;
+        }
+        self.inner.poll_complete()
+    }
+}
+
+/// Creates a client working over a network with the given server.
+pub fn client(responder: Responder) -> (Client<TestStream, TestSink>, Arc<Mutex<Network>>) {
+    let network = network(responder);
+    let sink = TestSink(network.clone());
+    (new_client(&network, sink, None), network)
+}
+
+/// Creates a client starting in INIT-REBOOT state with the cached `CLIENT_IP`.
+pub fn rebooting_client(
+    responder: Responder,
+) -> (Client<TestStream, TestSink>, Arc<Mutex<Network>>) {
+    let network = network(responder);
+    let sink = TestSink(network.clone());
+    let client_address = Some(Ipv4Addr::from(CLIENT_IP));
+    (new_client(&network, sink, client_address), network)
+}
+
+/// Creates a client informing the server of the externally configured `CLIENT_IP`.
+pub fn informing_client(
+    responder: Responder,
+) -> (Client<TestStream, TestSink>, Arc<Mutex<Network>>) {
+    let network = network(responder);
+    let client = Client::new_inform(
+        TestStream(network.clone()),
+        TestSink(network.clone()),
+        MacAddress::new(CLIENT_MAC),
+        None,
+        None,
+        None,
+        Ipv4Addr::from(CLIENT_IP),
+        None,
+        Vec::new(),
+    );
+    (client, network)
+}
+
+/// Creates a client working over a network with the given server and a slow socket.
+pub fn slow_client(responder: Responder) -> (Client<TestStream, SlowSink>, Arc<Mutex<Network>>) {
+    let network = network(responder);
+    let sink = SlowSink {
+        inner: TestSink(network.clone()),
+        pending: None,
+        is_delayed: false,
+    };
+    (new_client(&network, sink, None), network)
+}
+
+fn network(responder: Responder) -> Arc<Mutex<Network>> {
+    Arc::new(Mutex::new(Network {
+        requests: Vec::new(),
+        destinations: Vec::new(),
+        responses: VecDeque::new(),
+        responder,
+    }))
+}
+
+fn new_client<O>(
+    network: &Arc<Mutex<Network>>,
+    sink: O,
+    client_address: Option<Ipv4Addr>,
+) -> Client<TestStream, O>
+where
+    O: Sink<SinkItem = <TestSink as Sink>::SinkItem, SinkError = io::Error> + Send + Sync,
+{
+    Client::new(
+        TestStream(network.clone()),
+        sink,
+        MacAddress::new(CLIENT_MAC),
+        None,
+        None,
+        None,
+        client_address,
+        None,
+        None,
+        None,
+        Vec::new(),
+    )
+}
+
+/// Leases `CLIENT_IP` to every client for an hour.
+pub fn server(request: &Message) -> Option<Message> {
+    match request.options.dhcp_message_type {
+        Some(MessageType::DhcpDiscover) => Some(reply(request, MessageType::DhcpOffer)),
+        Some(MessageType::DhcpRequest) => Some(reply(request, MessageType::DhcpAck)),
+        _ => None,
+    }
+}
+
+/// Creates a server response to the request.
+pub fn reply(request: &Message, dhcp_message_type: MessageType) -> Message {
+    let mut options = Options {
+        dhcp_message_type: Some(dhcp_message_type),
+        dhcp_server_id: Some(Ipv4Addr::from(SERVER_IP)),
+        ..Default::default()
+    };
+    let your_ip_address = match dhcp_message_type {
+        MessageType::DhcpNak => Ipv4Addr::new(0, 0, 0, 0),
+        _ => {
+            options.address_time = Some(3600);
+            options.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+            Ipv4Addr::from(CLIENT_IP)
+        }
+    };
+
+    Message {
+        operation_code: OperationCode::BootReply,
+        hardware_type: HardwareType::Ethernet,
+        hardware_address_length: request.hardware_address_length,
+        hardware_options: Default::default(),
+
+        transaction_id: request.transaction_id,
+        seconds: Default::default(),
+        is_broadcast: request.is_broadcast,
+
+        client_ip_address: request.client_ip_address,
+        your_ip_address,
+        server_ip_address: Ipv4Addr::from(SERVER_IP),
+        gateway_ip_address: request.gateway_ip_address,
+
+        client_hardware_address: request.client_hardware_address,
+        server_name: Default::default(),
+        boot_filename: Default::default(),
+
+        options,
+    }
+}
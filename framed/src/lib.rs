@@ -6,6 +6,8 @@ mod socket;
 extern crate tokio;
 #[macro_use]
 extern crate futures;
+#[macro_use]
+extern crate log;
 extern crate net2;
 
 extern crate dhcp_protocol;
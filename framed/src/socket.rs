@@ -24,6 +24,10 @@ pub struct DhcpFramed {
     buf_write: Vec<u8>,
     /// Stores the destination address and the number of bytes to send.
     pending: Option<(SocketAddr, usize)>,
+    /// Whether to skip the fixed-length options with wrong lengths instead of the whole message.
+    is_lenient: bool,
+    /// The key the received messages are verified and the sent ones are signed with.
+    auth_key: Option<AuthKey>,
 }
 
 pub type DhcpStreamItem = (SocketAddr, Message);
@@ -41,8 +45,65 @@ impl DhcpFramed {
             buf_read: vec![0u8; BUFFER_READ_CAPACITY],
             buf_write: vec![0u8; BUFFER_WRITE_CAPACITY],
             pending: None,
+            is_lenient: true,
+            auth_key: None,
         })
     }
+
+    /// Sets the key of the RFC 3118 delayed authentication.
+    ///
+    /// The received messages without a valid HMAC-MD5 are dropped. The sent messages
+    /// with the delayed authentication option set by `Message::set_authentication`
+    /// are signed after being trimmed and padded, so the HMAC covers the datagram as sent.
+    pub fn set_auth_key(&mut self, auth_key: Option<AuthKey>) {
+        self.auth_key = auth_key;
+    }
+
+    /// Sets whether to skip the fixed-length options with wrong lengths
+    /// instead of dropping the whole message.
+    ///
+    /// The socket is lenient by default, since some clients are known to send
+    /// such options. The skipped options are logged.
+    pub fn set_lenient(&mut self, is_lenient: bool) {
+        self.is_lenient = is_lenient;
+    }
+
+    /// Returns the local address the socket is bound to.
+    ///
+    /// # Errors
+    /// `io::Error` on a socket error.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Encodes the message fitting it into `max_size` if it is specified.
+    ///
+    /// The least important options are dropped one by one until the message fits.
+    ///
+    /// # Errors
+    /// `io::Error` if the message does not fit even without the optional options.
+    fn encode(dst: &mut [u8], message: &mut Message, max_size: Option<u16>) -> io::Result<usize> {
+        loop {
+            match message.to_bytes(dst, max_size) {
+                Ok(amount) => return Ok(amount),
+                Err(error) => {
+                    let max_size = match max_size {
+                        Some(max_size) => max_size,
+                        None => return Err(error),
+                    };
+                    if message.options.drop_least_important().is_none() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "The message does not fit into the maximal size of {} bytes",
+                                max_size,
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl Stream for DhcpFramed {
@@ -57,7 +118,26 @@ impl Stream for DhcpFramed {
     /// `io::Error` on a socket error.
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         let (amount, addr) = try_ready!(self.socket.poll_recv_from(&mut self.buf_read));
-        match Message::from_bytes(&self.buf_read[..amount]) {
+        if let Some(ref auth_key) = self.auth_key {
+            if !Message::verify_datagram(&self.buf_read[..amount], auth_key) {
+                warn!("Dropping an unauthenticated message from {}", addr);
+                return Ok(Async::Ready(None));
+            }
+        }
+        let result = if self.is_lenient {
+            Message::from_bytes_lenient(&self.buf_read[..amount]).map(|(frame, anomalies)| {
+                for anomaly in anomalies {
+                    warn!(
+                        "Skipped the option {:?} from {} with length {} instead of {}",
+                        anomaly.tag, addr, anomaly.length, anomaly.expected,
+                    );
+                }
+                frame
+            })
+        } else {
+            Message::from_bytes(&self.buf_read[..amount])
+        };
+        match result {
             Ok(frame) => Ok(Async::Ready(Some((addr, frame)))),
             Err(_) => Ok(Async::Ready(None)),
         }
@@ -79,8 +159,20 @@ impl Sink for DhcpFramed {
             return Ok(AsyncSink::NotReady(item));
         }
 
-        let (addr, (message, max_size)) = item;
-        let amount = message.to_bytes(&mut self.buf_write, max_size)?;
+        let (addr, (mut message, max_size)) = item;
+        let mut amount = Self::encode(&mut self.buf_write, &mut message, max_size)?;
+        if amount < SIZE_BOOTP_MINIMAL {
+            // the buffer may still contain a previous longer message
+            for byte in self.buf_write[amount..SIZE_BOOTP_MINIMAL].iter_mut() {
+                *byte = 0;
+            }
+            amount = SIZE_BOOTP_MINIMAL;
+        }
+        if let Some(ref auth_key) = self.auth_key {
+            if message.options.authentication.is_some() {
+                Message::sign_datagram(&mut self.buf_write[..amount], auth_key)?;
+            }
+        }
         self.pending = Some((addr, amount));
 
         Ok(AsyncSink::Ready)
@@ -117,3 +209,190 @@ impl Sink for DhcpFramed {
         self.poll_complete()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+
+    fn message(options: Options) -> Message {
+        Message {
+            operation_code: OperationCode::BootReply,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: 0,
+            transaction_id: 0x1234_5678,
+            seconds: 0,
+            is_broadcast: false,
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(192, 168, 0, 100),
+            server_ip_address: Ipv4Addr::new(192, 168, 0, 2),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            client_hardware_address: Default::default(),
+            server_name: Vec::new(),
+            boot_filename: Vec::new(),
+            options,
+        }
+    }
+
+    fn servers(amount: u8) -> Option<Vec<Ipv4Addr>> {
+        Some(
+            (0..amount)
+                .map(|host| Ipv4Addr::new(10, 0, 0, host))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn trims_messages_to_max_size() {
+        let mut message = message(Options {
+            dhcp_message_type: Some(MessageType::DhcpOffer),
+            subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+            ntp_servers: servers(63),
+            smtp_servers: servers(63),
+            www_servers: servers(63),
+            ..Default::default()
+        });
+        let mut dst = vec![0u8; BUFFER_WRITE_CAPACITY];
+        let max_size = SIZE_MESSAGE_MINIMAL as u16;
+
+        let amount = DhcpFramed::encode(&mut dst, &mut message, Some(max_size)).unwrap();
+        assert!(amount <= SIZE_MESSAGE_MINIMAL - SIZE_HEADER_IP - SIZE_HEADER_UDP);
+
+        let decoded = Message::from_bytes(&dst[..amount]).unwrap();
+        assert_eq!(
+            decoded.options.subnet_mask,
+            Some(Ipv4Addr::new(255, 255, 255, 0))
+        );
+        assert_eq!(decoded.options.ntp_servers, servers(63));
+        assert_eq!(decoded.options.smtp_servers, None);
+        assert_eq!(decoded.options.www_servers, None);
+    }
+
+    #[test]
+    fn refuses_messages_exceeding_max_size() {
+        let mut message = message(Options {
+            dhcp_message_type: Some(MessageType::DhcpOffer),
+            client_id: Some(vec![0xff; 200]),
+            ..Default::default()
+        });
+        let mut dst = vec![0u8; BUFFER_WRITE_CAPACITY];
+
+        let error = DhcpFramed::encode(&mut dst, &mut message, Some(300)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn pads_short_messages_to_bootp_minimum() {
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        let receiver = ::std::net::UdpSocket::bind(&localhost).unwrap();
+        let destination = receiver.local_addr().unwrap();
+        let socket = UdpSocket::bind(&localhost).unwrap();
+
+        let message = message(Options {
+            dhcp_message_type: Some(MessageType::DhcpOffer),
+            ..Default::default()
+        });
+        let framed = DhcpFramed::new(socket).unwrap();
+        Runtime::new()
+            .unwrap()
+            .block_on(framed.send((destination, (message, None))))
+            .unwrap();
+
+        let mut datagram = vec![0u8; BUFFER_READ_CAPACITY];
+        let amount = receiver.recv(&mut datagram).unwrap();
+        assert_eq!(amount, SIZE_BOOTP_MINIMAL);
+        let decoded = Message::from_bytes(&datagram[..amount]).unwrap();
+        assert_eq!(decoded.transaction_id, 0x1234_5678);
+    }
+
+    /// Receives a message with a 3-byte subnet mask option.
+    fn receive_malformed(is_lenient: bool) -> Option<Message> {
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        let sender = ::std::net::UdpSocket::bind(&localhost).unwrap();
+        let socket = UdpSocket::bind(&localhost).unwrap();
+        let destination = socket.local_addr().unwrap();
+        let mut framed = DhcpFramed::new(socket).unwrap();
+        framed.set_lenient(is_lenient);
+
+        let mut datagram = vec![0u8; BUFFER_WRITE_CAPACITY];
+        message(Options::default())
+            .to_bytes(&mut datagram, None)
+            .unwrap();
+        datagram.truncate(OFFSET_OPTIONS);
+        datagram.extend_from_slice(&[OptionTag::SubnetMask.code(), 3, 255, 255, 255]);
+        datagram.extend_from_slice(&[OptionTag::DhcpMessageType.code(), 1, 2]);
+        datagram.push(OptionTag::End.code());
+        sender.send_to(&datagram, &destination).unwrap();
+
+        let (item, _framed) = Runtime::new()
+            .unwrap()
+            .block_on(framed.into_future())
+            .map_err(|(error, _framed)| error)
+            .unwrap();
+        item.map(|(_addr, message)| message)
+    }
+
+    #[test]
+    fn signs_padded_messages_and_verifies_received_ones() {
+        let key = AuthKey {
+            id: 1,
+            secret: b"shared secret".to_vec(),
+        };
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        let mut sender = DhcpFramed::new(UdpSocket::bind(&localhost).unwrap()).unwrap();
+        sender.set_auth_key(Some(key.to_owned()));
+        let mut receiver = DhcpFramed::new(UdpSocket::bind(&localhost).unwrap()).unwrap();
+        receiver.set_auth_key(Some(key.to_owned()));
+        let destination = receiver.local_addr().unwrap();
+        let unsigned = ::std::net::UdpSocket::bind(&localhost).unwrap();
+
+        let unsigned_message = message(Options {
+            dhcp_message_type: Some(MessageType::DhcpOffer),
+            ..Default::default()
+        });
+        let mut datagram = vec![0u8; BUFFER_WRITE_CAPACITY];
+        let amount = unsigned_message.to_bytes(&mut datagram, None).unwrap();
+        unsigned.send_to(&datagram[..amount], &destination).unwrap();
+        let mut signed_message = message(Options {
+            dhcp_message_type: Some(MessageType::DhcpOffer),
+            ..Default::default()
+        });
+        signed_message.set_authentication(&key, 1);
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime
+            .block_on(sender.send((destination, (signed_message, None))))
+            .unwrap();
+        let (item, receiver) = runtime
+            .block_on(receiver.into_future())
+            .map_err(|(error, _receiver)| error)
+            .unwrap();
+        assert!(item.is_none());
+        let (item, _receiver) = runtime
+            .block_on(receiver.into_future())
+            .map_err(|(error, _receiver)| error)
+            .unwrap();
+        let (_addr, received) = item.unwrap();
+        let authentication = received.options.authentication.unwrap();
+        assert_eq!(authentication.replay_detection, 1);
+    }
+
+    #[test]
+    fn skips_malformed_options_by_default() {
+        let message = receive_malformed(true).unwrap();
+        assert!(message.options.subnet_mask.is_none());
+        assert_eq!(
+            message.options.dhcp_message_type.unwrap() as u8,
+            MessageType::DhcpOffer as u8
+        );
+    }
+
+    #[test]
+    fn drops_malformed_messages_if_strict() {
+        assert!(receive_malformed(false).is_none());
+    }
+}
@@ -4,15 +4,24 @@ extern crate bytes;
 extern crate eui48;
 #[macro_use]
 extern crate failure;
+extern crate md5;
 
 mod v4;
 
 pub use self::v4::{
     constants::*,
-    options::{MessageType, OptionTag, Options, Overload},
+    options::{
+        Authentication, AutoConfigure, ClientFqdn, MessageType, OptionTag, Options, Overload,
+    },
+    AuthKey,
     HardwareType,
+    LengthAnomaly,
     Message,
+    MessageAddresses,
     OperationCode,
+    ParseError,
+    ValidationError,
+    parse,
 };
 
 pub const DHCP_PORT_SERVER: u16 = 67;
@@ -0,0 +1,101 @@
+//! DHCP message address fields module.
+
+use std::net::Ipv4Addr;
+
+use eui48::MacAddress;
+
+use super::Message;
+
+/// The address fields of a DHCP message.
+///
+/// Is returned by `Message::addresses`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageAddresses {
+    /// `ciaddr`, the client address if it is already configured.
+    pub client_ip_address: Ipv4Addr,
+    /// `yiaddr`, the address offered or assigned to the client.
+    pub your_ip_address: Ipv4Addr,
+    /// `siaddr`, the next server to use in bootstrap.
+    pub server_ip_address: Ipv4Addr,
+    /// `giaddr`, the relay agent address.
+    pub gateway_ip_address: Ipv4Addr,
+    /// `chaddr`, the client hardware address.
+    pub client_hardware_address: MacAddress,
+}
+
+impl MessageAddresses {
+    /// Whether the message has been forwarded by a relay agent (`giaddr` is set).
+    pub fn is_relayed(&self) -> bool {
+        !self.gateway_ip_address.is_unspecified()
+    }
+
+    /// Whether the client already has a configured address (`ciaddr` is set),
+    /// e.g. while renewing, rebinding or sending `DHCPINFORM`.
+    pub fn is_from_configured_client(&self) -> bool {
+        !self.client_ip_address.is_unspecified()
+    }
+}
+
+impl Message {
+    /// Groups the address fields of the message.
+    pub fn addresses(&self) -> MessageAddresses {
+        MessageAddresses {
+            client_ip_address: self.client_ip_address,
+            your_ip_address: self.your_ip_address,
+            server_ip_address: self.server_ip_address,
+            gateway_ip_address: self.gateway_ip_address,
+            client_hardware_address: self.client_hardware_address,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v4::{options::Options, HardwareType, OperationCode};
+
+    fn message(client_ip_address: Ipv4Addr, gateway_ip_address: Ipv4Addr) -> Message {
+        Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: false,
+
+            client_ip_address,
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address,
+
+            client_hardware_address: MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options: Options::default(),
+        }
+    }
+
+    #[test]
+    fn tells_relayed_and_configured_clients() {
+        let unspecified = Ipv4Addr::new(0, 0, 0, 0);
+        let client = Ipv4Addr::new(192, 168, 0, 100);
+        let relay_agent = Ipv4Addr::new(10, 0, 0, 1);
+
+        let cases = [
+            (unspecified, unspecified, false, false),
+            (client, unspecified, false, true),
+            (unspecified, relay_agent, true, false),
+            (client, relay_agent, true, true),
+        ];
+        for &(ciaddr, giaddr, is_relayed, is_configured) in cases.iter() {
+            let addresses = message(ciaddr, giaddr).addresses();
+            assert_eq!(addresses.client_ip_address, ciaddr);
+            assert_eq!(addresses.gateway_ip_address, giaddr);
+            assert_eq!(addresses.is_relayed(), is_relayed);
+            assert_eq!(addresses.is_from_configured_client(), is_configured);
+        }
+    }
+}
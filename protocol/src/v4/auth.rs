@@ -0,0 +1,307 @@
+//! DHCP delayed authentication module (RFC 3118 §5).
+
+use std::{io, mem, ops::Range};
+
+use md5;
+
+use super::{
+    constants::*,
+    options::{Authentication, OptionTag},
+    Message,
+};
+
+/// The HMAC-MD5 block size.
+const HMAC_BLOCK_SIZE: usize = 64;
+/// The HMAC-MD5 digest size.
+const HMAC_SIZE: usize = 16;
+/// The secret ID size.
+const SECRET_ID_SIZE: usize = 4;
+/// The offset of the `hops` field, which is zeroed before computing the HMAC.
+const OFFSET_HOPS: usize = 3;
+/// The offset of the `giaddr` field, which is zeroed before computing the HMAC.
+const OFFSET_GATEWAY_IP_ADDRESS: usize = 24;
+
+/// A secret shared by a client and a server.
+#[derive(Debug, Clone)]
+pub struct AuthKey {
+    /// Identifies the secret among the ones known to the peer.
+    pub id: u32,
+    pub secret: Vec<u8>,
+}
+
+impl Message {
+    /// Sets the delayed authentication option with a zeroed HMAC-MD5.
+    ///
+    /// The HMAC is computed by `sign_datagram` over the encoded message,
+    /// so the options dropped to fit the message and the padding are covered as sent.
+    pub fn set_authentication(&mut self, key: &AuthKey, replay_detection: u64) {
+        let mut information = vec![0u8; SECRET_ID_SIZE + HMAC_SIZE];
+        information[..SECRET_ID_SIZE].copy_from_slice(&to_be_bytes(key.id));
+        self.options.authentication = Some(Authentication {
+            protocol: Authentication::PROTOCOL_DELAYED,
+            algorithm: Authentication::ALGORITHM_HMAC_MD5,
+            rdm: Authentication::RDM_MONOTONIC,
+            replay_detection,
+            information,
+        });
+    }
+
+    /// Fills the HMAC of the delayed authentication option in an encoded message.
+    ///
+    /// Must be called on the datagram exactly as it is sent, since any later change
+    /// breaks the HMAC.
+    ///
+    /// # Errors
+    /// `io::Error` if the datagram has no delayed authentication option set with `key`.
+    pub fn sign_datagram(datagram: &mut [u8], key: &AuthKey) -> io::Result<()> {
+        let hmac = match find_hmac(datagram, key) {
+            Some(hmac) => hmac,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Authentication option not found",
+                ))
+            }
+        };
+        let digest = hmac_md5(&key.secret, &authenticated_data(datagram, &hmac));
+        datagram[hmac].copy_from_slice(&digest);
+        Ok(())
+    }
+
+    /// Checks the delayed authentication option of a received datagram against the key.
+    ///
+    /// The HMAC is computed over the datagram as received, so the option order
+    /// and the padding of the peer do not matter. The replay detection is not checked.
+    pub fn verify_datagram(datagram: &[u8], key: &AuthKey) -> bool {
+        let hmac = match find_hmac(datagram, key) {
+            Some(hmac) => hmac,
+            None => return false,
+        };
+        let expected = hmac_md5(&key.secret, &authenticated_data(datagram, &hmac));
+        // compared in constant time not to reveal the matching prefix length
+        expected
+            .iter()
+            .zip(datagram[hmac].iter())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+/// Copies the datagram with the `hops` and `giaddr` fields and the HMAC zeroed,
+/// since relay agents may change the former.
+fn authenticated_data(datagram: &[u8], hmac: &Range<usize>) -> Vec<u8> {
+    let mut data = datagram.to_vec();
+    data[OFFSET_HOPS] = 0;
+    let giaddr = OFFSET_GATEWAY_IP_ADDRESS..OFFSET_GATEWAY_IP_ADDRESS + mem::size_of::<u32>();
+    for byte in data[giaddr].iter_mut() {
+        *byte = 0;
+    }
+    for byte in data[hmac.to_owned()].iter_mut() {
+        *byte = 0;
+    }
+    data
+}
+
+/// Finds the HMAC of the delayed authentication option set with the key.
+///
+/// The option is searched in the main area and then in the overloaded ones.
+fn find_hmac(datagram: &[u8], key: &AuthKey) -> Option<Range<usize>> {
+    if datagram.len() < OFFSET_OPTIONS {
+        return None;
+    }
+    let mut overload = None;
+    let option = find_option(datagram, OFFSET_OPTIONS..datagram.len(), &mut overload)
+        .or_else(|| match overload {
+            Some(1) | Some(3) => find_option(
+                datagram,
+                OFFSET_BOOT_FILENAME..OFFSET_MAGIC_COOKIE,
+                &mut None,
+            ),
+            _ => None,
+        })
+        .or_else(|| match overload {
+            Some(2) | Some(3) => find_option(
+                datagram,
+                OFFSET_SERVER_NAME..OFFSET_BOOT_FILENAME,
+                &mut None,
+            ),
+            _ => None,
+        })?;
+
+    let authentication = Authentication::from_bytes(&datagram[option.to_owned()])?;
+    if authentication.protocol != Authentication::PROTOCOL_DELAYED
+        || authentication.algorithm != Authentication::ALGORITHM_HMAC_MD5
+        || authentication.information.len() != SECRET_ID_SIZE + HMAC_SIZE
+        || authentication.information[..SECRET_ID_SIZE] != to_be_bytes(key.id)
+    {
+        return None;
+    }
+    Some(option.end - HMAC_SIZE..option.end)
+}
+
+/// Walks the options in the area and returns the data range of the authentication option.
+///
+/// Records the overload option value on the way.
+fn find_option(
+    datagram: &[u8],
+    area: Range<usize>,
+    overload: &mut Option<u8>,
+) -> Option<Range<usize>> {
+    let mut i = area.start;
+    while i < area.end {
+        let tag = OptionTag::from(datagram[i]);
+        match tag {
+            OptionTag::Pad => {
+                i += 1;
+                continue;
+            }
+            OptionTag::End => return None,
+            _ => {}
+        }
+        let length = *datagram.get(i + 1)? as usize;
+        let data = i + 2..i + 2 + length;
+        if data.end > area.end {
+            return None;
+        }
+        match tag {
+            OptionTag::Authentication => return Some(data),
+            OptionTag::Overload if length == 1 => *overload = Some(datagram[data.start]),
+            _ => {}
+        }
+        i = data.end;
+    }
+    None
+}
+
+fn to_be_bytes(value: u32) -> [u8; 4] {
+    [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+/// HMAC-MD5 (RFC 2104).
+fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; HMAC_SIZE] {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..HMAC_SIZE].copy_from_slice(&md5::compute(key)[..]);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = md5::Context::new();
+    inner.consume(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+    inner.consume(data);
+    let mut outer = md5::Context::new();
+    outer.consume(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+    outer.consume(&inner.compute()[..]);
+    outer.compute().0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use eui48::MacAddress;
+
+    use super::*;
+    use v4::{
+        options::{MessageType, Options},
+        HardwareType, OperationCode,
+    };
+
+    fn request() -> Message {
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpRequest);
+        options.address_request = Some(Ipv4Addr::new(192, 168, 0, 100));
+        options.hostname = Some("host".to_owned());
+        Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: true,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+
+            client_hardware_address: MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options,
+        }
+    }
+
+    fn key() -> AuthKey {
+        AuthKey {
+            id: 7,
+            secret: b"shared secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn computes_rfc2104_test_vector() {
+        assert_eq!(
+            hmac_md5(&[0x0b; 16], b"Hi There"),
+            [
+                0x92, 0x94, 0x72, 0x7a, 0x36, 0x38, 0xbb, 0x1c, 0x13, 0xf4, 0x8e, 0xf8, 0x15, 0x8b,
+                0xfc, 0x9d,
+            ]
+        );
+    }
+
+    fn signed(message: &mut Message, replay_detection: u64) -> Vec<u8> {
+        message.set_authentication(&key(), replay_detection);
+        let mut datagram = message.to_vec(None).unwrap();
+        Message::sign_datagram(&mut datagram, &key()).unwrap();
+        datagram
+    }
+
+    #[test]
+    fn verifies_signed_datagram_after_relaying() {
+        let mut datagram = signed(&mut request(), 1);
+        datagram[OFFSET_HOPS] = 1;
+        datagram[OFFSET_GATEWAY_IP_ADDRESS] = 10;
+        assert!(Message::verify_datagram(&datagram, &key()));
+
+        let message = Message::from_bytes(&datagram).unwrap();
+        assert_eq!(message.options.authentication.unwrap().replay_detection, 1);
+    }
+
+    #[test]
+    fn covers_padding_and_peer_option_order() {
+        let mut datagram = signed(&mut request(), 1);
+        datagram.resize(SIZE_BOOTP_MINIMAL, 0);
+        Message::sign_datagram(&mut datagram, &key()).unwrap();
+        assert!(Message::verify_datagram(&datagram, &key()));
+
+        datagram[SIZE_BOOTP_MINIMAL - 1] = 0xff;
+        assert!(!Message::verify_datagram(&datagram, &key()));
+    }
+
+    #[test]
+    fn rejects_tampered_and_unsigned_datagrams() {
+        let mut datagram = signed(&mut request(), 1);
+        let other = AuthKey {
+            id: 7,
+            secret: b"other secret".to_vec(),
+        };
+        assert!(!Message::verify_datagram(&datagram, &other));
+
+        // the transaction ID
+        datagram[4] ^= 1;
+        assert!(!Message::verify_datagram(&datagram, &key()));
+
+        let unsigned = request().to_vec(None).unwrap();
+        assert!(!Message::verify_datagram(&unsigned, &key()));
+        assert!(!Message::verify_datagram(&unsigned[..10], &key()));
+    }
+}
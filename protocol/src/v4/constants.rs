@@ -35,3 +35,11 @@ pub const SIZE_HEADER_UDP: usize = 8;
 
 /// The minimal message size the client MUST be able to accept.
 pub const SIZE_MESSAGE_MINIMAL: usize = 576;
+
+/// The minimal BOOTP message size some relay agents require (RFC 1542 §3.2.1).
+///
+/// Shorter messages are padded with zeros after the `end` option.
+pub const SIZE_BOOTP_MINIMAL: usize = 300;
+
+/// The maximal message size limited by the UDP datagram size.
+pub const SIZE_MESSAGE_MAXIMAL: usize = 65535 - SIZE_HEADER_IP - SIZE_HEADER_UDP;
@@ -7,8 +7,8 @@ use eui48::{EUI48LEN, MacAddress};
 
 use super::{
     constants::*,
-    options::{OptionTag::*, Options, Overload},
-    Message,
+    options::{self, OptionTag, OptionTag::*, Options, Overload},
+    Message, OperationCode,
 };
 
 /// Checks if there is enough space in buffer to get a value.
@@ -53,12 +53,36 @@ macro_rules! distance(
     );
 );
 
+/// A fixed-length option with a wrong length octet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthAnomaly {
+    pub tag: OptionTag,
+    pub length: usize,
+    pub expected: usize,
+}
+
 impl Message {
     /// DHCP message deserialization.
     ///
     /// # Errors
     /// `io::Error` if the packet is abrupted, too small or contains invalid length octets.
     pub fn from_bytes(src: &[u8]) -> io::Result<Self> {
+        Self::deserialize(src, false).map(|(message, _)| message)
+    }
+
+    /// DHCP message deserialization skipping the fixed-length options with wrong lengths.
+    ///
+    /// The skipped options are returned along with the message, so they may be logged
+    /// to diagnose the peer instead of dropping the whole message.
+    ///
+    /// # Errors
+    /// `io::Error` if the packet is abrupted, too small or contains other invalid length octets.
+    pub fn from_bytes_lenient(src: &[u8]) -> io::Result<(Self, Vec<LengthAnomaly>)> {
+        Self::deserialize(src, true)
+    }
+
+    fn deserialize(src: &[u8], is_lenient: bool) -> io::Result<(Self, Vec<LengthAnomaly>)> {
+        let mut anomalies = Vec::new();
         let mut cursor = ::std::io::Cursor::new(src.as_ref());
         check_remaining!(cursor, OFFSET_OPTIONS);
 
@@ -96,40 +120,110 @@ impl Message {
         };
 
         if cursor.get_u32_be() != MAGIC_COOKIE {
+            // the BOOTP 'vend' field is optional (RFC 951 §3), so a legacy client
+            // may leave it zeroed or fill it with its own data instead of the options
+            if let OperationCode::BootRequest = message.operation_code {
+                return Ok((message, anomalies));
+            }
             return Err(io::Error::new(io::ErrorKind::InvalidData, "MAGIC_COOKIE"));
         }
 
-        Self::append_options(&mut cursor, &mut message.options)?;
+        // the domain search list is decoded after all the areas are concatenated
+        let mut domain_search = None;
+        Self::append_options(
+            &mut cursor,
+            &mut message.options,
+            &mut domain_search,
+            is_lenient,
+            &mut anomalies,
+        )?;
         match message.options.overload {
             Some(Overload::File) => {
                 let mut cursor =
                     ::std::io::Cursor::new(&src[OFFSET_BOOT_FILENAME..OFFSET_MAGIC_COOKIE]);
-                Self::append_options(&mut cursor, &mut message.options)?;
+                Self::append_options(
+                    &mut cursor,
+                    &mut message.options,
+                    &mut domain_search,
+                    is_lenient,
+                    &mut anomalies,
+                )?;
             }
             Some(Overload::Sname) => {
                 let mut cursor =
                     ::std::io::Cursor::new(&src[OFFSET_SERVER_NAME..OFFSET_BOOT_FILENAME]);
-                Self::append_options(&mut cursor, &mut message.options)?;
+                Self::append_options(
+                    &mut cursor,
+                    &mut message.options,
+                    &mut domain_search,
+                    is_lenient,
+                    &mut anomalies,
+                )?;
             }
             Some(Overload::Both) => {
                 let mut cursor =
                     ::std::io::Cursor::new(&src[OFFSET_BOOT_FILENAME..OFFSET_MAGIC_COOKIE]);
-                Self::append_options(&mut cursor, &mut message.options)?;
+                Self::append_options(
+                    &mut cursor,
+                    &mut message.options,
+                    &mut domain_search,
+                    is_lenient,
+                    &mut anomalies,
+                )?;
                 let mut cursor =
                     ::std::io::Cursor::new(&src[OFFSET_SERVER_NAME..OFFSET_BOOT_FILENAME]);
-                Self::append_options(&mut cursor, &mut message.options)?;
+                Self::append_options(
+                    &mut cursor,
+                    &mut message.options,
+                    &mut domain_search,
+                    is_lenient,
+                    &mut anomalies,
+                )?;
             }
             _ => {}
         }
+        message.options.domain_search =
+            domain_search.and_then(|data| options::domain_search::decode(&data));
 
-        Ok(message)
+        Ok((message, anomalies))
     }
 
-    fn append_options(mut cursor: &mut io::Cursor<&[u8]>, options: &mut Options) -> io::Result<()> {
+    #[allow(clippy::needless_borrow)]
+    fn append_options(
+        mut cursor: &mut io::Cursor<&[u8]>,
+        options: &mut Options,
+        domain_search: &mut Option<Vec<u8>>,
+        is_lenient: bool,
+        anomalies: &mut Vec<LengthAnomaly>,
+    ) -> io::Result<()> {
         while cursor.remaining() > 0 {
             check_remaining!(cursor, mem::size_of::<u8>());
-            let tag = cursor.get_u8();
-            match tag.into() {
+            let tag = OptionTag::from(cursor.get_u8());
+            if let Some(expected) = tag.fixed_length() {
+                check_remaining!(cursor, mem::size_of::<u8>());
+                let length = cursor.bytes()[0] as usize;
+                if length != expected {
+                    if !is_lenient {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Option {:?} has length {} instead of {}",
+                                tag, length, expected
+                            ),
+                        ));
+                    }
+                    anomalies.push(LengthAnomaly {
+                        tag,
+                        length,
+                        expected,
+                    });
+                    cursor.advance(mem::size_of::<u8>());
+                    check_remaining!(cursor, length);
+                    cursor.advance(length);
+                    continue;
+                }
+            }
+            match tag {
                 // unsplittable options
                 TimeOffset => options.time_offset = Some(Self::get_opt_u32(&mut cursor)?),
                 SubnetMask => options.subnet_mask = Some(Self::get_opt_ipv4(&mut cursor)?),
@@ -180,6 +274,13 @@ impl Message {
                 }
                 RenewalTime => options.renewal_time = Some(Self::get_opt_u32(&mut cursor)?),
                 RebindingTime => options.rebinding_time = Some(Self::get_opt_u32(&mut cursor)?),
+                AutoConfigure => {
+                    options.auto_configure = Some(Self::get_opt_u8(&mut cursor)?.into())
+                }
+                RapidCommit => {
+                    Self::get_opt_empty(&mut cursor)?;
+                    options.rapid_commit = true;
+                }
 
                 // splittable options
                 Routers => {
@@ -433,6 +534,22 @@ impl Message {
                         &mut options.stda_servers,
                     )?)
                 }
+                ClientFqdn => {
+                    let mut data = None;
+                    let data = Self::get_opt_vec(&mut cursor, &mut data)?;
+                    options.client_fqdn = options::ClientFqdn::from_bytes(&data);
+                }
+                Authentication => {
+                    let mut data = None;
+                    let data = Self::get_opt_vec(&mut cursor, &mut data)?;
+                    options.authentication = options::Authentication::from_bytes(&data);
+                }
+                RelayAgentInformation => {
+                    options.relay_agent_info = Some(Self::get_opt_vec(
+                        &mut cursor,
+                        &mut options.relay_agent_info,
+                    )?)
+                }
                 ClasslessStaticRoutes => {
                     options.classless_static_routes = Some(Self::get_opt_classless_static_routes(
                         &mut cursor,
@@ -440,14 +557,26 @@ impl Message {
                     )?)
                 }
 
+                DomainSearch => {
+                    *domain_search = Some(Self::get_opt_vec(&mut cursor, domain_search)?)
+                }
+
                 End => break,
                 Pad => continue,
-                Unknown => Self::skip(&mut cursor)?,
+                Unknown(_) => Self::skip(&mut cursor)?,
             }
         }
         Ok(())
     }
 
+    /// Has no data at all.
+    fn get_opt_empty(cursor: &mut io::Cursor<&[u8]>) -> io::Result<()> {
+        check_remaining!(cursor, mem::size_of::<u8>());
+        let len = cursor.get_u8() as usize;
+        check_length!(len, 0);
+        Ok(())
+    }
+
     /// Cannot be splitted so reassembling not required.
     fn get_opt_u8(cursor: &mut io::Cursor<&[u8]>) -> io::Result<u8> {
         check_remaining!(cursor, mem::size_of::<u8>());
@@ -621,16 +750,21 @@ impl Message {
         let mut value = Vec::with_capacity(len / MIN_ELEMENT_SIZE);
         while len > 0 {
             let subnet_mask_len = cursor.get_u8() as usize;
+            // the significant octets of the subnet number follow the prefix length (RFC 3442 §3)
+            #[allow(clippy::manual_div_ceil)]
+            let subnet_number_len = (subnet_mask_len + BITS_IN_BYTE - 1) / BITS_IN_BYTE;
+            if subnet_mask_len > IPV4_BITSIZE || len < MIN_ELEMENT_SIZE + subnet_number_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid classless static route descriptor",
+                ));
+            }
             let subnet_mask_i =
                 (<u32>::max_value() as u64 + 1) - 2u64.pow((IPV4_BITSIZE - subnet_mask_len) as u32);
 
-            let mut subnet_number_len = 0;
             let mut subnet_number_a: [u8; IPV4_BYTESIZE] = [0u8; IPV4_BYTESIZE];
-            for i in 0..4 {
-                if subnet_mask_len > i * BITS_IN_BYTE {
-                    subnet_number_len += 1;
-                    subnet_number_a[i] = cursor.get_u8();
-                }
+            for octet in subnet_number_a.iter_mut().take(subnet_number_len) {
+                *octet = cursor.get_u8();
             }
             len -= MIN_ELEMENT_SIZE + subnet_number_len;
 
@@ -656,3 +790,104 @@ impl Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a message with an empty header and the raw options.
+    fn message_with_options(options: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0u8; OFFSET_OPTIONS];
+        bytes[OFFSET_MAGIC_COOKIE..OFFSET_OPTIONS].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        bytes.extend_from_slice(options);
+        bytes.push(End.code());
+        bytes
+    }
+
+    #[test]
+    fn parses_bootp_request_without_magic_cookie() {
+        let mut bytes = vec![0u8; SIZE_BOOTP_MINIMAL];
+        bytes[..8].copy_from_slice(&[1, 1, 6, 0, 0x12, 0x34, 0x56, 0x78]);
+
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.transaction_id, 0x1234_5678);
+        assert!(message.options.dhcp_message_type.is_none());
+        assert!(message.validate_bootp().is_ok());
+
+        // a reply is always sent by a DHCP-aware server, so it must have the cookie
+        bytes[0] = 2;
+        assert!(Message::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_classless_static_routes() {
+        let tag = ClasslessStaticRoutes.code();
+        let routes = [
+            // the prefix is longer than 32 bits
+            vec![tag, 9, 33, 10, 0, 0, 1, 10, 0, 0, 1],
+            // the descriptor overruns the option
+            vec![tag, 5, 24, 10, 0, 0, 10],
+        ];
+        for option in routes.iter() {
+            let bytes = message_with_options(option);
+            assert!(Message::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn reassembles_split_domain_search() {
+        // the pointers in the second instance refer to the data of the first one
+        let tag = DomainSearch.code();
+        let options = [
+            &[tag, 8, 3][..],
+            b"eng",
+            &[7],
+            b"exa",
+            &[tag, 13],
+            b"mple",
+            &[0, 0xc0, 4, 3],
+            b"www",
+            &[0xc0, 4],
+        ].concat();
+        let message = Message::from_bytes(&message_with_options(&options)).unwrap();
+        assert_eq!(
+            message.options.domain_search,
+            Some(vec!["eng.example".to_owned(), "example".to_owned(), "www.example".to_owned()])
+        );
+    }
+
+    #[test]
+    fn detects_fixed_length_anomalies() {
+        let mut options = vec![SubnetMask.code(), 3, 255, 255, 255];
+        options.extend_from_slice(&[DhcpMessageType.code(), 2, 1, 0]);
+        options.extend_from_slice(&[Hostname.code(), 4]);
+        options.extend_from_slice(b"host");
+        let bytes = message_with_options(&options);
+
+        match Message::from_bytes(&bytes) {
+            Err(ref error) if error.kind() == io::ErrorKind::InvalidData => {}
+            Err(error) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("The anomaly has not been detected"),
+        }
+
+        let (message, anomalies) = Message::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(
+            anomalies,
+            vec![
+                LengthAnomaly {
+                    tag: SubnetMask,
+                    length: 3,
+                    expected: 4,
+                },
+                LengthAnomaly {
+                    tag: DhcpMessageType,
+                    length: 2,
+                    expected: 1,
+                },
+            ]
+        );
+        assert!(message.options.subnet_mask.is_none());
+        assert!(message.options.dhcp_message_type.is_none());
+        assert_eq!(message.options.hostname, Some("host".to_owned()));
+    }
+}
@@ -0,0 +1,68 @@
+//! DHCP authentication option module.
+
+/// The authentication option (RFC 3118).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authentication {
+    pub protocol: u8,
+    pub algorithm: u8,
+    /// The replay detection method.
+    pub rdm: u8,
+    pub replay_detection: u64,
+    /// The protocol-specific data, e.g. the secret ID followed by the HMAC.
+    pub information: Vec<u8>,
+}
+
+impl Authentication {
+    /// The delayed authentication protocol (RFC 3118 §5).
+    pub const PROTOCOL_DELAYED: u8 = 2;
+    /// The HMAC-MD5 algorithm of the delayed authentication protocol.
+    pub const ALGORITHM_HMAC_MD5: u8 = 1;
+    /// The replay detection field is a monotonically increasing counter.
+    pub const RDM_MONOTONIC: u8 = 0;
+
+    /// Decodes the option data.
+    ///
+    /// Returns `None` if the data is too short.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 11 {
+            return None;
+        }
+        let mut replay_detection = 0u64;
+        for byte in data[3..11].iter() {
+            replay_detection = (replay_detection << 8) | u64::from(*byte);
+        }
+        Some(Authentication {
+            protocol: data[0],
+            algorithm: data[1],
+            rdm: data[2],
+            replay_detection,
+            information: data[11..].to_vec(),
+        })
+    }
+
+    /// Encodes the option data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![self.protocol, self.algorithm, self.rdm];
+        for i in (0..8).rev() {
+            data.push((self.replay_detection >> (i * 8)) as u8);
+        }
+        data.extend_from_slice(&self.information);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_option_data() {
+        let data = b"\x02\x01\x00\x00\x00\x00\x00\x00\x00\x01\x2a\x00\x00\x00\x07";
+        let authentication = Authentication::from_bytes(&data[..]).unwrap();
+        assert_eq!(authentication.protocol, Authentication::PROTOCOL_DELAYED);
+        assert_eq!(authentication.replay_detection, 0x012a);
+        assert_eq!(authentication.information, vec![0, 0, 0, 7]);
+        assert_eq!(authentication.to_bytes(), data.to_vec());
+        assert_eq!(Authentication::from_bytes(&data[..10]), None);
+    }
+}
@@ -0,0 +1,35 @@
+//! DHCP auto-configure option module.
+
+use std::fmt;
+
+/// DHCP auto-configure values (RFC 2563).
+#[derive(Debug, Clone, Copy)]
+pub enum AutoConfigure {
+    DoNotAutoConfigure = 0,
+    AutoConfigure,
+    Undefined,
+}
+
+impl fmt::Display for AutoConfigure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AutoConfigure::*;
+        match self {
+            DoNotAutoConfigure => write!(f, "DO_NOT_AUTO_CONFIGURE"),
+            AutoConfigure => write!(f, "AUTO_CONFIGURE"),
+
+            Undefined => write!(f, "UNDEFINED"),
+        }
+    }
+}
+
+impl From<u8> for AutoConfigure {
+    fn from(value: u8) -> Self {
+        use self::AutoConfigure::*;
+        match value {
+            0 => DoNotAutoConfigure,
+            1 => AutoConfigure,
+
+            _ => Undefined,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! DHCP client FQDN option module.
+
+/// The client FQDN option (RFC 4702).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFqdn {
+    /// The `N`, `E`, `O` and `S` bits.
+    pub flags: u8,
+    /// The domain name without the trailing dot.
+    pub name: String,
+}
+
+impl ClientFqdn {
+    /// The server should perform the A RR updates.
+    pub const FLAG_S: u8 = 0x01;
+    /// The server has overridden the client preference for the `S` bit.
+    pub const FLAG_O: u8 = 0x02;
+    /// The name is encoded in the canonical wire format.
+    pub const FLAG_E: u8 = 0x04;
+    /// The server should not perform any DNS updates.
+    pub const FLAG_N: u8 = 0x08;
+
+    /// Decodes the option data.
+    ///
+    /// The name may be either in the deprecated ASCII or in the canonical wire format.
+    /// Returns `None` if the data is malformed.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 3 {
+            return None;
+        }
+        let flags = data[0];
+        let data = &data[3..];
+
+        let name = if flags & Self::FLAG_E != 0 {
+            let mut labels = Vec::new();
+            let mut i = 0;
+            while i < data.len() {
+                let len = data[i] as usize;
+                if len == 0 {
+                    break;
+                }
+                let label = data.get(i + 1..i + 1 + len)?;
+                labels.push(String::from_utf8(label.to_vec()).ok()?);
+                i += 1 + len;
+            }
+            labels.join(".")
+        } else {
+            let name = String::from_utf8(data.to_vec()).ok()?;
+            name.trim_right_matches(|c| c == '.' || c == '\0').to_owned()
+        };
+
+        Some(ClientFqdn { flags, name })
+    }
+
+    /// Encodes the option data, always in the canonical wire format.
+    ///
+    /// The deprecated `RCODE1` and `RCODE2` fields are set to 255 as RFC 4702 §2.2 requires.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![self.flags | Self::FLAG_E, 255, 255];
+        for label in self.name.split('.').filter(|label| !label.is_empty()) {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_both_name_formats() {
+        let ascii = b"\x01\x00\x00host.example.org.";
+        let wire = b"\x05\xff\xff\x04host\x07example\x03org\x00";
+
+        for data in [&ascii[..], &wire[..]].iter() {
+            let fqdn = ClientFqdn::from_bytes(data).unwrap();
+            assert_eq!(fqdn.name, "host.example.org");
+            assert!(fqdn.flags & ClientFqdn::FLAG_S != 0);
+        }
+        assert_eq!(ClientFqdn::from_bytes(&wire[..]).unwrap().to_bytes(), wire.to_vec());
+    }
+}
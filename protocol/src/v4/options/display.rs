@@ -0,0 +1,294 @@
+//! DHCP options printing module.
+
+use std::{fmt, net::Ipv4Addr};
+
+use super::{Authentication, AutoConfigure, ClientFqdn, MessageType, OptionTag, Options, Overload};
+
+/// Prints an option value decoded for a human reader.
+trait OptionValue {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// Prints the items separated with commas.
+fn fmt_list<T, F>(f: &mut fmt::Formatter, items: &[T], fmt_item: F) -> fmt::Result
+where
+    F: Fn(&mut fmt::Formatter, &T) -> fmt::Result,
+{
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        fmt_item(f, item)?;
+    }
+    Ok(())
+}
+
+impl OptionValue for u8 {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl OptionValue for u16 {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// All the 32-bit integer options are times in seconds.
+impl OptionValue for u32 {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}s", self)
+    }
+}
+
+impl OptionValue for String {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl OptionValue for Ipv4Addr {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// The opaque data is printed as hexadecimal bytes.
+impl OptionValue for Vec<u8> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl OptionValue for Vec<String> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, name| write!(f, "{:?}", name))
+    }
+}
+
+impl OptionValue for Vec<u16> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, value| write!(f, "{}", value))
+    }
+}
+
+impl OptionValue for Vec<Ipv4Addr> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, address| write!(f, "{}", address))
+    }
+}
+
+impl OptionValue for Vec<(Ipv4Addr, Ipv4Addr)> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, &(first, second)| {
+            write!(f, "{}/{}", first, second)
+        })
+    }
+}
+
+/// The routes are printed as `destination/mask>router`.
+impl OptionValue for Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)> {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_list(f, self, |f, &(destination, mask, router)| {
+            write!(f, "{}/{}>{}", destination, mask, router)
+        })
+    }
+}
+
+impl OptionValue for Overload {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl OptionValue for MessageType {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl OptionValue for AutoConfigure {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl OptionValue for ClientFqdn {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} (flags {:#04x})", self.name, self.flags)
+    }
+}
+
+impl OptionValue for Authentication {
+    fn fmt_value(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "protocol {}, algorithm {}, replay detection {}",
+            self.protocol, self.algorithm, self.replay_detection
+        )
+    }
+}
+
+/// Prints the options which are set as `[code] Tag=value` lines.
+macro_rules! fmt_opts(
+    ($f:expr, $options:expr, $(($field:ident, $tag:ident)),* $(,)*) => (
+        $(
+            if let Some(ref value) = $options.$field {
+                write!($f, "[{:03}] {:?}=", OptionTag::$tag.code(), OptionTag::$tag)?;
+                value.fmt_value($f)?;
+                writeln!($f)?;
+            }
+        )*
+    )
+);
+
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_opts!(
+            f,
+            self,
+            (subnet_mask, SubnetMask),
+            (time_offset, TimeOffset),
+            (routers, Routers),
+            (time_servers, TimeServers),
+            (name_servers, NameServers),
+            (domain_name_servers, DomainNameServers),
+            (log_servers, LogServers),
+            (quotes_servers, QuotesServers),
+            (lpr_servers, LprServers),
+            (impress_servers, ImpressServers),
+            (rlp_servers, RlpServers),
+            (hostname, Hostname),
+            (boot_file_size, BootFileSize),
+            (merit_dump_file, MeritDumpFile),
+            (domain_name, DomainName),
+            (swap_server, SwapServer),
+            (root_path, RootPath),
+            (extensions_path, ExtensionsPath),
+            (forward_on_off, ForwardOnOff),
+            (non_local_source_route_on_off, NonLocalSourceRouteOnOff),
+            (policy_filters, PolicyFilters),
+            (max_datagram_reassembly_size, MaxDatagramReassemblySize),
+            (default_ip_ttl, DefaultIpTtl),
+            (mtu_timeout, MtuTimeout),
+            (mtu_plateau, MtuPlateau),
+            (mtu_interface, MtuInterface),
+            (mtu_subnet, MtuSubnet),
+            (broadcast_address, BroadcastAddress),
+            (mask_recovery, MaskRecovery),
+            (mask_supplier, MaskSupplier),
+            (perform_router_discovery, PerformRouterDiscovery),
+            (router_solicitation_address, RouterSolicitationAddress),
+            (static_routes, StaticRoutes),
+            (trailer_encapsulation, TrailerEncapsulation),
+            (arp_timeout, ArpTimeout),
+            (ethernet_encapsulation, EthernetEncapsulation),
+            (default_tcp_ttl, DefaultTcpTtl),
+            (keepalive_time, KeepaliveTime),
+            (keepalive_data, KeepaliveData),
+            (nis_domain, NisDomain),
+            (nis_servers, NisServers),
+            (ntp_servers, NtpServers),
+            (vendor_specific, VendorSpecific),
+            (netbios_name_servers, NetbiosNameServers),
+            (netbios_distribution_servers, NetbiosDistributionServers),
+            (netbios_node_type, NetbiosNodeType),
+            (netbios_scope, NetbiosScope),
+            (x_window_font_servers, XWindowFontServers),
+            (x_window_manager_servers, XWindowManagerServers),
+            (address_request, AddressRequest),
+            (address_time, AddressTime),
+            (overload, Overload),
+            (dhcp_message_type, DhcpMessageType),
+            (dhcp_server_id, DhcpServerId),
+        );
+
+        if let Some(ref parameter_list) = self.parameter_list {
+            write!(
+                f,
+                "[{:03}] {:?}=",
+                OptionTag::ParameterList.code(),
+                OptionTag::ParameterList
+            )?;
+            fmt_list(f, parameter_list, |f, &tag| match OptionTag::from(tag) {
+                OptionTag::Unknown(code) => write!(f, "{}", code),
+                known => write!(f, "{:?}", known),
+            })?;
+            writeln!(f)?;
+        }
+
+        fmt_opts!(
+            f,
+            self,
+            (dhcp_message, DhcpMessage),
+            (dhcp_max_message_size, DhcpMaxMessageSize),
+            (renewal_time, RenewalTime),
+            (rebinding_time, RebindingTime),
+            (class_id, ClassId),
+            (client_id, ClientId),
+            (netware_ip_domain, NetwareIpDomain),
+            (netware_ip_option, NetwareIpOption),
+            (nis_v3_domain_name, NisDomainName),
+            (nis_v3_servers, NisServerAddress),
+            (server_name, ServerName),
+            (bootfile_name, BootfileName),
+            (home_agent_addresses, HomeAgentAddresses),
+            (smtp_servers, SmtpServers),
+            (pop3_servers, Pop3Servers),
+            (nntp_servers, NntpServers),
+            (www_servers, WwwServers),
+            (finger_servers, FingerServers),
+            (irc_servers, IrcServers),
+            (street_talk_servers, StreetTalkServers),
+            (stda_servers, StdaServers),
+        );
+
+        if self.rapid_commit {
+            writeln!(
+                f,
+                "[{:03}] {:?}",
+                OptionTag::RapidCommit.code(),
+                OptionTag::RapidCommit
+            )?;
+        }
+
+        fmt_opts!(
+            f,
+            self,
+            (client_fqdn, ClientFqdn),
+            (relay_agent_info, RelayAgentInformation),
+            (authentication, Authentication),
+            (auto_configure, AutoConfigure),
+            (classless_static_routes, ClasslessStaticRoutes),
+            (domain_search, DomainSearch),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_options_by_tag() {
+        let mut options = Options::default();
+        options.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+        options.routers = Some(vec![
+            Ipv4Addr::new(192, 168, 0, 1),
+            Ipv4Addr::new(192, 168, 0, 2),
+        ]);
+        options.dhcp_message_type = Some(MessageType::DhcpOffer);
+        options.address_time = Some(3600);
+        options.parameter_list = Some(vec![1, 3]);
+
+        let output = options.to_string();
+        assert!(output.contains("SubnetMask=255.255.255.0"));
+        assert!(output.contains("Routers=192.168.0.1,192.168.0.2"));
+        assert!(output.contains("MessageType=DHCPOFFER"));
+        assert!(output.contains("AddressTime=3600s"));
+        assert!(output.contains("ParameterList=SubnetMask,Routers"));
+        assert!(!output.contains("Hostname"));
+    }
+}
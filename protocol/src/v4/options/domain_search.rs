@@ -0,0 +1,109 @@
+//! DHCP domain search option module.
+
+use std::collections::HashMap;
+
+/// The two high bits of a compression pointer.
+const POINTER_MASK: u8 = 0xc0;
+/// The maximal offset a compression pointer can refer to.
+const POINTER_OFFSET_MAX: usize = 0x3fff;
+/// The maximal length of a label.
+const LABEL_LENGTH_MAX: usize = 63;
+
+/// Decodes the domain search list (RFC 3397).
+///
+/// The data must be already concatenated from all the option instances,
+/// since the compression pointers refer to offsets within the whole list.
+/// Only the backward pointers are accepted, which makes loops impossible.
+/// Returns `None` if the data is malformed.
+pub fn decode(data: &[u8]) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (name, next) = decode_name(data, i)?;
+        names.push(name);
+        i = next;
+    }
+    Some(names)
+}
+
+/// Encodes the domain search list (RFC 3397), compressing the repeated suffixes.
+pub fn encode(names: &[String]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut suffixes: HashMap<Vec<&str>, usize> = HashMap::new();
+    for name in names.iter() {
+        let labels: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+        let mut is_compressed = false;
+        for i in 0..labels.len() {
+            if let Some(&offset) = suffixes.get(&labels[i..]) {
+                data.push(POINTER_MASK | (offset >> 8) as u8);
+                data.push(offset as u8);
+                is_compressed = true;
+                break;
+            }
+            if data.len() <= POINTER_OFFSET_MAX {
+                suffixes.insert(labels[i..].to_vec(), data.len());
+            }
+            data.push(labels[i].len() as u8);
+            data.extend_from_slice(labels[i].as_bytes());
+        }
+        if !is_compressed {
+            data.push(0);
+        }
+    }
+    data
+}
+
+/// Decodes a name starting at `start` and returns it with the offset right after it.
+fn decode_name(data: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut i = start;
+    let mut next = None;
+    // the lowest offset visited, which every pointer must precede
+    let mut limit = start;
+    loop {
+        let len = *data.get(i)? as usize;
+        if len == 0 {
+            break;
+        }
+        if len as u8 & POINTER_MASK == POINTER_MASK {
+            let offset = ((len & (POINTER_OFFSET_MAX >> 8)) << 8) | *data.get(i + 1)? as usize;
+            if offset >= limit {
+                return None;
+            }
+            if next.is_none() {
+                next = Some(i + 2);
+            }
+            limit = offset;
+            i = offset;
+            continue;
+        }
+        if len > LABEL_LENGTH_MAX {
+            return None;
+        }
+        let label = data.get(i + 1..i + 1 + len)?;
+        labels.push(String::from_utf8(label.to_vec()).ok()?);
+        i += 1 + len;
+    }
+    Some((labels.join("."), next.unwrap_or(i + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rfc3397_example() {
+        // RFC 3397 §2: "eng.apple.com." and "marketing.apple.com."
+        let data = b"\x03eng\x05apple\x03com\x00\x09marketing\xc0\x04";
+        let names = decode(&data[..]).unwrap();
+        assert_eq!(names, vec!["eng.apple.com", "marketing.apple.com"]);
+        assert_eq!(encode(&names), data.to_vec());
+    }
+
+    #[test]
+    fn rejects_forward_and_looping_pointers() {
+        assert_eq!(decode(b"\xc0\x00"), None);
+        assert_eq!(decode(b"\x03com\x00\xc0\x05"), None);
+        assert_eq!(decode(b"\x03com"), None);
+    }
+}
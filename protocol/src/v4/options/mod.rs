@@ -1,10 +1,18 @@
 //! DHCP options module.
 
+mod authentication;
+mod auto_configure;
+mod client_fqdn;
+mod display;
+pub(crate) mod domain_search;
 mod message_type;
 mod option_tag;
 mod overload;
 
-pub use self::{message_type::MessageType, option_tag::OptionTag, overload::Overload};
+pub use self::{
+    authentication::Authentication, auto_configure::AutoConfigure, client_fqdn::ClientFqdn, message_type::MessageType,
+    option_tag::OptionTag, overload::Overload,
+};
 
 use std::net::Ipv4Addr;
 
@@ -13,7 +21,12 @@ use std::net::Ipv4Addr;
 /// Implemented completely with `Option` for better flexibility and polymorphism.
 ///
 /// [RFC 2132](https://tools.ietf.org/html/rfc2132)
+/// [RFC 2563](https://tools.ietf.org/html/rfc2563)
+/// [RFC 3046](https://tools.ietf.org/html/rfc3046)
+/// [RFC 3118](https://tools.ietf.org/html/rfc3118)
+/// [RFC 3397](https://tools.ietf.org/html/rfc3397)
 /// [RFC 3442](https://tools.ietf.org/html/rfc3442)
+/// [RFC 4702](https://tools.ietf.org/html/rfc4702)
 #[derive(Default)]
 pub struct Options {
     /*
@@ -112,8 +125,132 @@ pub struct Options {
     pub street_talk_servers: Option<Vec<Ipv4Addr>>,
     pub stda_servers: Option<Vec<Ipv4Addr>>,
 
+    /*
+    RFC 4039 (Rapid Commit Option)
+    */
+    /// The option has no data, so it is just present or absent.
+    pub rapid_commit: bool,
+
+    /*
+    RFC 4702 (The Client FQDN Option)
+    */
+    pub client_fqdn: Option<ClientFqdn>,
+
+    /*
+    RFC 3046 (DHCP Relay Agent Information Option)
+    */
+    /// The raw sub-options, which the server must echo unchanged.
+    pub relay_agent_info: Option<Vec<u8>>,
+
+    /*
+    RFC 3118 (Authentication for DHCP Messages)
+    */
+    pub authentication: Option<Authentication>,
+
+    /*
+    RFC 2563 (Auto-Configure Option)
+    */
+    pub auto_configure: Option<AutoConfigure>,
+
     /*
     RFC 3442 (The Classless Static Route Option)
     */
     pub classless_static_routes: Option<Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>>,
+
+    /*
+    RFC 3397 (Domain Search Option)
+    */
+    /// The domain names without the trailing dots.
+    pub domain_search: Option<Vec<String>>,
+}
+
+/// Clears the first option set among the listed ones and returns its tag.
+macro_rules! take_first(
+    ($options:expr, $(($field:ident, $tag:ident)),* $(,)*) => (
+        $(
+            if $options.$field.take().is_some() {
+                return Some(OptionTag::$tag);
+            }
+        )*
+    )
+);
+
+impl Options {
+    /// Clears the least important option which is set and returns its tag.
+    ///
+    /// Used to trim a message not fitting into the maximal message size of its recipient.
+    /// The unimplemented options are dropped first in the reverse encoding order,
+    /// followed by the human-readable DHCP message.
+    /// The options required by the protocol and the implemented network configuration
+    /// are never dropped.
+    ///
+    /// Returns `None` if there is nothing left to drop.
+    pub fn drop_least_important(&mut self) -> Option<OptionTag> {
+        take_first!(
+            self,
+            (domain_search, DomainSearch),
+            (stda_servers, StdaServers),
+            (street_talk_servers, StreetTalkServers),
+            (irc_servers, IrcServers),
+            (finger_servers, FingerServers),
+            (www_servers, WwwServers),
+            (nntp_servers, NntpServers),
+            (pop3_servers, Pop3Servers),
+            (smtp_servers, SmtpServers),
+            (home_agent_addresses, HomeAgentAddresses),
+            (bootfile_name, BootfileName),
+            (server_name, ServerName),
+            (nis_v3_servers, NisServerAddress),
+            (nis_v3_domain_name, NisDomainName),
+            (netware_ip_option, NetwareIpOption),
+            (netware_ip_domain, NetwareIpDomain),
+            (class_id, ClassId),
+            (x_window_manager_servers, XWindowManagerServers),
+            (x_window_font_servers, XWindowFontServers),
+            (netbios_scope, NetbiosScope),
+            (netbios_node_type, NetbiosNodeType),
+            (netbios_distribution_servers, NetbiosDistributionServers),
+            (netbios_name_servers, NetbiosNameServers),
+            (vendor_specific, VendorSpecific),
+            (ntp_servers, NtpServers),
+            (nis_servers, NisServers),
+            (nis_domain, NisDomain),
+            (keepalive_data, KeepaliveData),
+            (keepalive_time, KeepaliveTime),
+            (default_tcp_ttl, DefaultTcpTtl),
+            (ethernet_encapsulation, EthernetEncapsulation),
+            (arp_timeout, ArpTimeout),
+            (trailer_encapsulation, TrailerEncapsulation),
+            (router_solicitation_address, RouterSolicitationAddress),
+            (perform_router_discovery, PerformRouterDiscovery),
+            (mask_supplier, MaskSupplier),
+            (mask_recovery, MaskRecovery),
+            (broadcast_address, BroadcastAddress),
+            (mtu_subnet, MtuSubnet),
+            (mtu_interface, MtuInterface),
+            (mtu_plateau, MtuPlateau),
+            (mtu_timeout, MtuTimeout),
+            (default_ip_ttl, DefaultIpTtl),
+            (max_datagram_reassembly_size, MaxDatagramReassemblySize),
+            (policy_filters, PolicyFilters),
+            (non_local_source_route_on_off, NonLocalSourceRouteOnOff),
+            (forward_on_off, ForwardOnOff),
+            (extensions_path, ExtensionsPath),
+            (root_path, RootPath),
+            (swap_server, SwapServer),
+            (domain_name, DomainName),
+            (merit_dump_file, MeritDumpFile),
+            (boot_file_size, BootFileSize),
+            (rlp_servers, RlpServers),
+            (impress_servers, ImpressServers),
+            (lpr_servers, LprServers),
+            (quotes_servers, QuotesServers),
+            (log_servers, LogServers),
+            (name_servers, NameServers),
+            (time_servers, TimeServers),
+            (time_offset, TimeOffset),
+            (dhcp_message, DhcpMessage),
+        );
+        None
+    }
 }
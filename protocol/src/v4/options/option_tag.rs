@@ -1,10 +1,9 @@
 //! DHCP option tags module.
 
 /// DHCP options codes.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OptionTag {
-    Unknown = -1,
-    Pad = 0,
+    Pad,
 
     /*
     RFC 2132
@@ -102,12 +101,186 @@ pub enum OptionTag {
     StreetTalkServers,
     StdaServers,
 
+    /*
+    RFC 4039 (Rapid Commit Option)
+    */
+    RapidCommit,
+
+    /*
+    RFC 4702 (The Client FQDN Option)
+    */
+    ClientFqdn,
+
+    /*
+    RFC 3046 (DHCP Relay Agent Information Option)
+    */
+    RelayAgentInformation,
+
+    /*
+    RFC 3118 (Authentication for DHCP Messages)
+    */
+    Authentication,
+
+    /*
+    RFC 2563 (Auto-Configure Option)
+    */
+    AutoConfigure,
+
+    /*
+    RFC 3397 (Domain Search Option)
+    */
+    DomainSearch,
+
     /*
     RFC 3442 (The Classless Static Route Option)
     */
-    ClasslessStaticRoutes = 121,
+    ClasslessStaticRoutes,
+
+    End,
+
+    /// A tag the crate does not know. The raw code is kept, so the option is skipped correctly.
+    Unknown(u8),
+}
+
+impl OptionTag {
+    /// The data length of the fixed-length options or `None` for the variable-length ones.
+    pub fn fixed_length(self) -> Option<usize> {
+        use self::OptionTag::*;
+        match self {
+            RapidCommit => Some(0),
+            ForwardOnOff
+            | NonLocalSourceRouteOnOff
+            | DefaultIpTtl
+            | MtuSubnet
+            | MaskRecovery
+            | MaskSupplier
+            | PerformRouterDiscovery
+            | TrailerEncapsulation
+            | EthernetEncapsulation
+            | DefaultTcpTtl
+            | KeepaliveData
+            | NetbiosNodeType
+            | Overload
+            | DhcpMessageType
+            | AutoConfigure => Some(1),
+            BootFileSize | MaxDatagramReassemblySize | MtuInterface | DhcpMaxMessageSize => Some(2),
+            SubnetMask
+            | TimeOffset
+            | SwapServer
+            | MtuTimeout
+            | BroadcastAddress
+            | RouterSolicitationAddress
+            | ArpTimeout
+            | KeepaliveTime
+            | AddressRequest
+            | AddressTime
+            | DhcpServerId
+            | RenewalTime
+            | RebindingTime => Some(4),
+            _ => None,
+        }
+    }
 
-    End = 255,
+    /// The code of the tag, which is the raw code for the unknown ones.
+    pub fn code(self) -> u8 {
+        use self::OptionTag::*;
+        match self {
+            Pad => 0,
+            SubnetMask => 1,
+            TimeOffset => 2,
+            Routers => 3,
+            TimeServers => 4,
+            NameServers => 5,
+            DomainNameServers => 6,
+            LogServers => 7,
+            QuotesServers => 8,
+            LprServers => 9,
+            ImpressServers => 10,
+            RlpServers => 11,
+            Hostname => 12,
+            BootFileSize => 13,
+            MeritDumpFile => 14,
+            DomainName => 15,
+            SwapServer => 16,
+            RootPath => 17,
+            ExtensionsPath => 18,
+            ForwardOnOff => 19,
+            NonLocalSourceRouteOnOff => 20,
+            PolicyFilters => 21,
+            MaxDatagramReassemblySize => 22,
+            DefaultIpTtl => 23,
+            MtuTimeout => 24,
+            MtuPlateau => 25,
+            MtuInterface => 26,
+            MtuSubnet => 27,
+            BroadcastAddress => 28,
+            MaskRecovery => 29,
+            MaskSupplier => 30,
+            PerformRouterDiscovery => 31,
+            RouterSolicitationAddress => 32,
+            StaticRoutes => 33,
+            TrailerEncapsulation => 34,
+            ArpTimeout => 35,
+            EthernetEncapsulation => 36,
+            DefaultTcpTtl => 37,
+            KeepaliveTime => 38,
+            KeepaliveData => 39,
+            NisDomain => 40,
+            NisServers => 41,
+            NtpServers => 42,
+            VendorSpecific => 43,
+            NetbiosNameServers => 44,
+            NetbiosDistributionServers => 45,
+            NetbiosNodeType => 46,
+            NetbiosScope => 47,
+            XWindowFontServers => 48,
+            XWindowManagerServers => 49,
+            AddressRequest => 50,
+            AddressTime => 51,
+            Overload => 52,
+            DhcpMessageType => 53,
+            DhcpServerId => 54,
+            ParameterList => 55,
+            DhcpMessage => 56,
+            DhcpMaxMessageSize => 57,
+            RenewalTime => 58,
+            RebindingTime => 59,
+            ClassId => 60,
+            ClientId => 61,
+            NetwareIpDomain => 62,
+            NetwareIpOption => 63,
+            NisDomainName => 64,
+            NisServerAddress => 65,
+            ServerName => 66,
+            BootfileName => 67,
+            HomeAgentAddresses => 68,
+            SmtpServers => 69,
+            Pop3Servers => 70,
+            NntpServers => 71,
+            WwwServers => 72,
+            FingerServers => 73,
+            IrcServers => 74,
+            StreetTalkServers => 75,
+            StdaServers => 76,
+
+            RapidCommit => 80,
+
+            ClientFqdn => 81,
+
+            RelayAgentInformation => 82,
+
+            Authentication => 90,
+
+            AutoConfigure => 116,
+
+            DomainSearch => 119,
+
+            ClasslessStaticRoutes => 121,
+
+            End => 255,
+            Unknown(tag) => tag,
+        }
+    }
 }
 
 impl From<u8> for OptionTag {
@@ -192,10 +365,65 @@ impl From<u8> for OptionTag {
             75 => StreetTalkServers,
             76 => StdaServers,
 
+            80 => RapidCommit,
+
+            81 => ClientFqdn,
+
+            82 => RelayAgentInformation,
+
+            90 => Authentication,
+
+            116 => AutoConfigure,
+
+            119 => DomainSearch,
+
             121 => ClasslessStaticRoutes,
 
             255 => End,
-            _ => Unknown,
+            tag => Unknown(tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_every_tag_back_and_forth() {
+        for tag in 0..=255u8 {
+            assert_eq!(OptionTag::from(tag).code(), tag);
+        }
+    }
+
+    #[test]
+    fn recognizes_standard_tags() {
+        let known = [
+            (19, OptionTag::ForwardOnOff),
+            (28, OptionTag::BroadcastAddress),
+            (42, OptionTag::NtpServers),
+            (43, OptionTag::VendorSpecific),
+            (44, OptionTag::NetbiosNameServers),
+            (50, OptionTag::AddressRequest),
+            (52, OptionTag::Overload),
+            (54, OptionTag::DhcpServerId),
+            (55, OptionTag::ParameterList),
+            (56, OptionTag::DhcpMessage),
+            (57, OptionTag::DhcpMaxMessageSize),
+            (58, OptionTag::RenewalTime),
+            (59, OptionTag::RebindingTime),
+            (60, OptionTag::ClassId),
+            (61, OptionTag::ClientId),
+            (80, OptionTag::RapidCommit),
+            (81, OptionTag::ClientFqdn),
+            (82, OptionTag::RelayAgentInformation),
+            (90, OptionTag::Authentication),
+            (119, OptionTag::DomainSearch),
+            (121, OptionTag::ClasslessStaticRoutes),
+        ];
+        for &(tag, variant) in known.iter() {
+            assert_eq!(OptionTag::from(tag), variant);
         }
+        assert_eq!(OptionTag::from(224), OptionTag::Unknown(224));
     }
 }
@@ -0,0 +1,114 @@
+//! DHCP packet parsing module.
+
+use std::io;
+
+use super::{
+    constants::{MAGIC_COOKIE, OFFSET_MAGIC_COOKIE, OFFSET_OPTIONS},
+    Message,
+};
+
+/// The error type returned by `parse`.
+#[derive(Fail, Debug)]
+pub enum ParseError {
+    #[fail(display = "Parse error: the packet of {} bytes is too short", _0)]
+    TooShort(usize),
+    #[fail(display = "Parse error: invalid magic cookie {:#010x}", _0)]
+    InvalidMagicCookie(u32),
+    #[fail(display = "Parse error: invalid options: {}", _0)]
+    InvalidOptions(#[cause] io::Error),
+}
+
+/// Decodes a raw DHCP packet, e.g. the UDP payload of a captured frame.
+///
+/// The message is not validated, so `Message::validate` may be called afterwards.
+/// `Message::to_vec` is the encoding counterpart, which reproduces the packet
+/// if its options are encoded in the canonical order of this crate without padding.
+///
+/// # Errors
+/// `ParseError` if the header is truncated or the options are malformed.
+pub fn parse(bytes: &[u8]) -> Result<Message, ParseError> {
+    if bytes.len() < OFFSET_OPTIONS {
+        return Err(ParseError::TooShort(bytes.len()));
+    }
+    let magic_cookie = bytes[OFFSET_MAGIC_COOKIE..OFFSET_OPTIONS]
+        .iter()
+        .fold(0u32, |cookie, byte| (cookie << 8) | u32::from(*byte));
+    if magic_cookie != MAGIC_COOKIE {
+        return Err(ParseError::InvalidMagicCookie(magic_cookie));
+    }
+    Message::from_bytes(bytes).map_err(ParseError::InvalidOptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use v4::options::MessageType;
+
+    /// A `DHCPDISCOVER` captured from the client of this crate.
+    fn captured_discover() -> Vec<u8> {
+        let mut packet = vec![
+            0x01, 0x01, 0x06, 0x00, // op, htype, hlen, hops
+            0x3d, 0x1d, 0x9a, 0x5e, // xid
+            0x00, 0x04, 0x00, 0x00, // secs, flags
+        ];
+        packet.extend_from_slice(&[0; 16]); // ciaddr, yiaddr, siaddr, giaddr
+        packet.extend_from_slice(&[0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]); // chaddr
+        packet.resize(OFFSET_MAGIC_COOKIE, 0);
+        packet.extend_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        packet.extend_from_slice(&[
+            53, 1, 1, // DHCPDISCOVER
+            57, 2, 0x05, 0xdc, // the maximal message size
+            50, 4, 192, 168, 0, 100, // the requested address
+            55, 4, 1, 3, 6, 121, // the parameter list
+            61, 7, 1, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37, // the client ID
+            12, 4, b'h', b'o', b's', b't', // the hostname
+            255,
+        ]);
+        packet
+    }
+
+    #[test]
+    fn decodes_and_reencodes_captured_discover() {
+        let packet = captured_discover();
+        let message = parse(&packet).unwrap();
+
+        assert_eq!(
+            message.validate().unwrap() as u8,
+            MessageType::DhcpDiscover as u8
+        );
+        assert_eq!(message.transaction_id, 0x3d1d_9a5e);
+        assert_eq!(message.seconds, 4);
+        assert_eq!(message.options.dhcp_max_message_size, Some(1500));
+        assert_eq!(
+            message.options.address_request,
+            Some(Ipv4Addr::new(192, 168, 0, 100))
+        );
+        assert_eq!(message.options.hostname, Some("host".to_owned()));
+        assert_eq!(message.to_vec(None).unwrap(), packet);
+    }
+
+    #[test]
+    fn reports_typed_errors() {
+        let mut packet = captured_discover();
+        match parse(&packet[..OFFSET_MAGIC_COOKIE]) {
+            Err(ParseError::TooShort(length)) => assert_eq!(length, OFFSET_MAGIC_COOKIE),
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        packet[OFFSET_MAGIC_COOKIE] = 0;
+        match parse(&packet) {
+            Err(ParseError::InvalidMagicCookie(0x0082_5363)) => {}
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        let mut packet = captured_discover();
+        let hostname_length = packet.len() - 6;
+        packet[hostname_length] = 40;
+        match parse(&packet) {
+            Err(ParseError::InvalidOptions(_)) => {}
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+}
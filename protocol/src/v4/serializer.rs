@@ -1,12 +1,12 @@
 //! DHCP message serialization module.
 
-use std::{io, mem, net::Ipv4Addr};
+use std::{cmp, io, mem, net::Ipv4Addr};
 
 use bytes::{Buf, BufMut};
 
 use super::{
     constants::*,
-    options::{OptionTag, Overload as OverloadEnum},
+    options::{domain_search, OptionTag, Overload as OverloadEnum},
     Message,
 };
 
@@ -26,10 +26,8 @@ const SIZE_OPTION_SUFFIX: usize = 1;
 /// Both of the above.
 const SIZE_OPTION_AFFIXES: usize = SIZE_OPTION_PREFIX + SIZE_OPTION_SUFFIX;
 
-/// The overload option which is written last by the main cursor.
+/// The overload option which is written last after the main cursor.
 const SIZE_OPTION_OVERLOAD: usize = mem::size_of::<u8>() * 3;
-/// The above and the required space for the `overload` option, which is written last.
-const SIZE_OPTION_MAIN_AFFIXES: usize = SIZE_OPTION_AFFIXES + SIZE_OPTION_OVERLOAD;
 
 /// The maximal option size.
 const SIZE_OPTION_MAX: usize = 255;
@@ -52,7 +50,12 @@ impl Message {
     /// and are written to the packet only if there is enough space left.
     /// The order of options and behavior of the encoder may be changed in the future.
     ///
+    /// The options which do not fit into the main options area are written to
+    /// the empty `boot_filename` and `server_name` fields, which is signaled
+    /// with the `overload` option (RFC 2131 §4.1).
+    ///
     /// If `max_size` is specified, `dst` is truncated to it.
+    /// Use `Options::drop_least_important` to trim a message which does not fit.
     ///
     /// # Errors
     /// `io::Error` if the buffer is too small.
@@ -61,20 +64,43 @@ impl Message {
 
         // the slice is truncated to the maximal client message size
         let dst = if let Some(max_size) = max_size {
-            &mut dst[..((max_size as usize) - SIZE_HEADER_IP - SIZE_HEADER_UDP)]
+            let size = (max_size as usize).saturating_sub(SIZE_HEADER_IP + SIZE_HEADER_UDP);
+            let size = cmp::min(size, dst.len());
+            &mut dst[..size]
         } else {
             dst
         };
+        // the overloaded fields are sliced below, so the header must fit
+        if dst.len() < OFFSET_OPTIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "No more space left",
+            ));
+        }
+
+        // the overloaded fields are only used if they are empty
+        let file_end = if self.boot_filename.is_empty() {
+            OFFSET_MAGIC_COOKIE
+        } else {
+            OFFSET_BOOT_FILENAME
+        };
+        let sname_end = if self.server_name.is_empty() {
+            OFFSET_BOOT_FILENAME
+        } else {
+            OFFSET_SERVER_NAME
+        };
+        // the main cursor leaves space for the 'overload' option, which is written last
+        let main_end = dst.len().saturating_sub(SIZE_OPTION_OVERLOAD);
 
         // cursors are initialized in the way they must be filled
         let mut cursors: [io::Cursor<&mut [u8]>; CURSOR_INDEX_TOTAL] = [
             io::Cursor::new(unsafe {
-                &mut *(&mut dst[OFFSET_BOOT_FILENAME..OFFSET_MAGIC_COOKIE] as *mut [u8])
+                &mut *(&mut dst[OFFSET_BOOT_FILENAME..file_end] as *mut [u8])
             }),
             io::Cursor::new(unsafe {
-                &mut *(&mut dst[OFFSET_SERVER_NAME..OFFSET_BOOT_FILENAME] as *mut [u8])
+                &mut *(&mut dst[OFFSET_SERVER_NAME..sname_end] as *mut [u8])
             }),
-            io::Cursor::new(unsafe { &mut *(dst as *mut [u8]) }),
+            io::Cursor::new(unsafe { &mut *(&mut dst[..main_end] as *mut [u8]) }),
         ];
 
         check_remaining!(cursors[CURSOR_INDEX_MAIN], OFFSET_OPTIONS);
@@ -137,37 +163,295 @@ impl Message {
             ClientId,
             &self.options.client_id,
         )?;
-
-        // the mandatory implemented network configuration options are encoded next
-        Self::put_opt_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            SubnetMask,
-            &self.options.subnet_mask,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            DomainNameServers,
-            &self.options.domain_name_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            Routers,
-            &self.options.routers,
-        )?;
-        Self::put_opt_vec_ipv4_pairs(
+        Self::put_opt_vec(
             &mut cursors[CURSOR_INDEX_MAIN],
-            StaticRoutes,
-            &self.options.static_routes,
+            Authentication,
+            &self
+                .options
+                .authentication
+                .as_ref()
+                .map(|authentication| authentication.to_bytes()),
         )?;
 
-        // the splittable options are encoded after, leaving space for the 'overload' option
+        // the mandatory implemented network configuration options are encoded next
+        // and the rest of options are spilled to the overloaded fields if needed
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_ipv4(cursor, SubnetMask, &self.options.subnet_mask)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, DomainNameServers, &self.options.domain_name_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, Routers, &self.options.routers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4_pairs(cursor, StaticRoutes, &self.options.static_routes)
+        })?;
+
+        // the splittable options are encoded after
         Self::put_opt_classless_static_routes(
             &mut cursors,
             ClasslessStaticRoutes,
             &self.options.classless_static_routes,
         )?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_splitted(
+                cursor,
+                DomainSearch,
+                &self
+                    .options
+                    .domain_search
+                    .as_ref()
+                    .map(|names| domain_search::encode(names)),
+            )
+        })?;
+
+        // some helpful and optional options are encoded next
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, RenewalTime, &self.options.renewal_time)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, RebindingTime, &self.options.rebinding_time)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, Hostname, &self.options.hostname)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, DhcpMessage, &self.options.dhcp_message)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(
+                cursor,
+                AutoConfigure,
+                &self.options.auto_configure.to_owned().map(|v| v as u8),
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_empty(cursor, RapidCommit, self.options.rapid_commit)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(
+                cursor,
+                ClientFqdn,
+                &self.options.client_fqdn.as_ref().map(|fqdn| fqdn.to_bytes()),
+            )
+        })?;
 
-        // the overload options is written last by the main cursor
+        // unimplemented options are encoded next
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, TimeOffset, &self.options.time_offset)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, TimeServers, &self.options.time_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NameServers, &self.options.name_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, LogServers, &self.options.log_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, QuotesServers, &self.options.quotes_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, LprServers, &self.options.lpr_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, ImpressServers, &self.options.impress_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, RlpServers, &self.options.rlp_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u16(cursor, BootFileSize, &self.options.boot_file_size)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, MeritDumpFile, &self.options.merit_dump_file)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, DomainName, &self.options.domain_name)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_ipv4(cursor, SwapServer, &self.options.swap_server)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, RootPath, &self.options.root_path)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, ExtensionsPath, &self.options.extensions_path)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, ForwardOnOff, &self.options.forward_on_off)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(
+                cursor,
+                NonLocalSourceRouteOnOff,
+                &self.options.non_local_source_route_on_off,
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4_pairs(cursor, PolicyFilters, &self.options.policy_filters)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u16(
+                cursor,
+                MaxDatagramReassemblySize,
+                &self.options.max_datagram_reassembly_size,
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, DefaultIpTtl, &self.options.default_ip_ttl)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, MtuTimeout, &self.options.mtu_timeout)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_u16(cursor, MtuPlateau, &self.options.mtu_plateau)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u16(cursor, MtuInterface, &self.options.mtu_interface)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, MtuSubnet, &self.options.mtu_subnet)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_ipv4(cursor, BroadcastAddress, &self.options.broadcast_address)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, MaskRecovery, &self.options.mask_recovery)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, MaskSupplier, &self.options.mask_supplier)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, PerformRouterDiscovery, &self.options.perform_router_discovery)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_ipv4(
+                cursor,
+                RouterSolicitationAddress,
+                &self.options.router_solicitation_address,
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, TrailerEncapsulation, &self.options.trailer_encapsulation)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, ArpTimeout, &self.options.arp_timeout)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, EthernetEncapsulation, &self.options.ethernet_encapsulation)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, DefaultTcpTtl, &self.options.default_tcp_ttl)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u32(cursor, KeepaliveTime, &self.options.keepalive_time)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, KeepaliveData, &self.options.keepalive_data)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, NisDomain, &self.options.nis_domain)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NisServers, &self.options.nis_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NtpServers, &self.options.ntp_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(cursor, VendorSpecific, &self.options.vendor_specific)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NetbiosNameServers, &self.options.netbios_name_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(
+                cursor,
+                NetbiosDistributionServers,
+                &self.options.netbios_distribution_servers,
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_u8(cursor, NetbiosNodeType, &self.options.netbios_node_type)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, NetbiosScope, &self.options.netbios_scope)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, XWindowFontServers, &self.options.x_window_font_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(
+                cursor,
+                XWindowManagerServers,
+                &self.options.x_window_manager_servers,
+            )
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(cursor, ClassId, &self.options.class_id)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(cursor, NetwareIpDomain, &self.options.netware_ip_domain)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(cursor, NetwareIpOption, &self.options.netware_ip_option)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, NisDomainName, &self.options.nis_v3_domain_name)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NisServerAddress, &self.options.nis_v3_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, ServerName, &self.options.server_name)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_string(cursor, BootfileName, &self.options.bootfile_name)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, HomeAgentAddresses, &self.options.home_agent_addresses)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, SmtpServers, &self.options.smtp_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, Pop3Servers, &self.options.pop3_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, NntpServers, &self.options.nntp_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, WwwServers, &self.options.www_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, FingerServers, &self.options.finger_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, IrcServers, &self.options.irc_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, StreetTalkServers, &self.options.street_talk_servers)
+        })?;
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec_ipv4(cursor, StdaServers, &self.options.stda_servers)
+        })?;
+
+        // RFC 3046 §2.2: the relay agent information is copied as the last option
+        Self::put_opt_spilled(&mut cursors, |cursor| {
+            Self::put_opt_vec(cursor, RelayAgentInformation, &self.options.relay_agent_info)
+        })?;
+
+        if cursors[CURSOR_INDEX_FILE].position() > 0 {
+            cursors[CURSOR_INDEX_FILE].put_u8(End.code());
+        }
+        if cursors[CURSOR_INDEX_SNAME].position() > 0 {
+            cursors[CURSOR_INDEX_SNAME].put_u8(End.code());
+        }
+
+        // the overload option is written last into the space left by the main cursor
         let overload = if cursors[CURSOR_INDEX_FILE].position() > 0
             && cursors[CURSOR_INDEX_SNAME].position() > 0
         {
@@ -179,345 +463,63 @@ impl Message {
         } else {
             None
         };
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            Overload,
-            &overload.map(|v| v as u8),
-        )?;
+        let mut cursor = io::Cursor::new(dst);
+        cursor.set_position(cursors[CURSOR_INDEX_MAIN].position());
+        Self::put_opt_u8(&mut cursor, Overload, &overload.map(|v| v as u8))?;
 
-        // some helpful and optional options are encoded next
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            RenewalTime,
-            &self.options.renewal_time,
-        )?;
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            RebindingTime,
-            &self.options.rebinding_time,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            Hostname,
-            &self.options.hostname,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            DhcpMessage,
-            &self.options.dhcp_message,
-        )?;
+        check_remaining!(cursor, mem::size_of::<u8>());
+        cursor.put_u8(End.code());
+        Ok(cursor.position() as usize)
+    }
 
-        // unimplemented options are encoded next
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            TimeOffset,
-            &self.options.time_offset,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            TimeServers,
-            &self.options.time_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NameServers,
-            &self.options.name_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            LogServers,
-            &self.options.log_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            QuotesServers,
-            &self.options.quotes_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            LprServers,
-            &self.options.lpr_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ImpressServers,
-            &self.options.impress_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            RlpServers,
-            &self.options.rlp_servers,
-        )?;
-        Self::put_opt_u16(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            BootFileSize,
-            &self.options.boot_file_size,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MeritDumpFile,
-            &self.options.merit_dump_file,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            DomainName,
-            &self.options.domain_name,
-        )?;
-        Self::put_opt_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            SwapServer,
-            &self.options.swap_server,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            RootPath,
-            &self.options.root_path,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ExtensionsPath,
-            &self.options.extensions_path,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ForwardOnOff,
-            &self.options.forward_on_off,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NonLocalSourceRouteOnOff,
-            &self.options.non_local_source_route_on_off,
-        )?;
-        Self::put_opt_vec_ipv4_pairs(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            PolicyFilters,
-            &self.options.policy_filters,
-        )?;
-        Self::put_opt_u16(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MaxDatagramReassemblySize,
-            &self.options.max_datagram_reassembly_size,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            DefaultIpTtl,
-            &self.options.default_ip_ttl,
-        )?;
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MtuTimeout,
-            &self.options.mtu_timeout,
-        )?;
-        Self::put_opt_vec_u16(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MtuPlateau,
-            &self.options.mtu_plateau,
-        )?;
-        Self::put_opt_u16(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MtuInterface,
-            &self.options.mtu_interface,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MtuSubnet,
-            &self.options.mtu_subnet,
-        )?;
-        Self::put_opt_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            BroadcastAddress,
-            &self.options.broadcast_address,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MaskRecovery,
-            &self.options.mask_recovery,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            MaskSupplier,
-            &self.options.mask_supplier,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            PerformRouterDiscovery,
-            &self.options.perform_router_discovery,
-        )?;
-        Self::put_opt_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            RouterSolicitationAddress,
-            &self.options.router_solicitation_address,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            TrailerEncapsulation,
-            &self.options.trailer_encapsulation,
-        )?;
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ArpTimeout,
-            &self.options.arp_timeout,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            EthernetEncapsulation,
-            &self.options.ethernet_encapsulation,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            DefaultTcpTtl,
-            &self.options.default_tcp_ttl,
-        )?;
-        Self::put_opt_u32(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            KeepaliveTime,
-            &self.options.keepalive_time,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            KeepaliveData,
-            &self.options.keepalive_data,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NisDomain,
-            &self.options.nis_domain,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NisServers,
-            &self.options.nis_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NtpServers,
-            &self.options.ntp_servers,
-        )?;
-        Self::put_opt_vec(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            VendorSpecific,
-            &self.options.vendor_specific,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetbiosNameServers,
-            &self.options.netbios_name_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetbiosDistributionServers,
-            &self.options.netbios_distribution_servers,
-        )?;
-        Self::put_opt_u8(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetbiosNodeType,
-            &self.options.netbios_node_type,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetbiosScope,
-            &self.options.netbios_scope,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            XWindowFontServers,
-            &self.options.x_window_font_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            XWindowManagerServers,
-            &self.options.x_window_manager_servers,
-        )?;
-        Self::put_opt_vec(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ClassId,
-            &self.options.class_id,
-        )?;
-        Self::put_opt_vec(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetwareIpDomain,
-            &self.options.netware_ip_domain,
-        )?;
-        Self::put_opt_vec(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NetwareIpOption,
-            &self.options.netware_ip_option,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NisDomainName,
-            &self.options.nis_v3_domain_name,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NisServerAddress,
-            &self.options.nis_v3_servers,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            ServerName,
-            &self.options.server_name,
-        )?;
-        Self::put_opt_string(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            BootfileName,
-            &self.options.bootfile_name,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            HomeAgentAddresses,
-            &self.options.home_agent_addresses,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            SmtpServers,
-            &self.options.smtp_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            Pop3Servers,
-            &self.options.pop3_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            NntpServers,
-            &self.options.nntp_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            WwwServers,
-            &self.options.www_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            FingerServers,
-            &self.options.finger_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            IrcServers,
-            &self.options.irc_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            StreetTalkServers,
-            &self.options.street_talk_servers,
-        )?;
-        Self::put_opt_vec_ipv4(
-            &mut cursors[CURSOR_INDEX_MAIN],
-            StdaServers,
-            &self.options.stda_servers,
-        )?;
+    /// DHCP message serialization into a new vector.
+    ///
+    /// Is a convenience wrapper around `to_bytes` for the cases
+    /// where a pre-allocated buffer is not available.
+    ///
+    /// # Errors
+    /// `io::Error` if the message does not fit into `max_size`.
+    pub fn to_vec(&self, max_size: Option<u16>) -> io::Result<Vec<u8>> {
+        let mut dst = vec![0u8; SIZE_MESSAGE_MAXIMAL];
+        let size = self.to_bytes(&mut dst, max_size)?;
+        dst.truncate(size);
+        Ok(dst)
+    }
 
-        check_remaining!(cursors[CURSOR_INDEX_MAIN], mem::size_of::<u8>());
-        cursors[CURSOR_INDEX_MAIN].put_u8(End as u8);
-        if cursors[CURSOR_INDEX_FILE].position() > 0 {
-            cursors[CURSOR_INDEX_FILE].put_u8(End as u8);
+    /// Writes an option by the first cursor having enough space left.
+    ///
+    /// The main cursor is tried first, then the `boot_filename` and `server_name` ones.
+    fn put_opt_spilled<F>(
+        cursors: &mut [io::Cursor<&mut [u8]>; CURSOR_INDEX_TOTAL],
+        put: F,
+    ) -> io::Result<()>
+    where
+        F: Fn(&mut io::Cursor<&mut [u8]>) -> io::Result<()>,
+    {
+        for index in &[CURSOR_INDEX_MAIN, CURSOR_INDEX_FILE, CURSOR_INDEX_SNAME] {
+            // nothing is written on an unsuccessful attempt
+            if put(&mut cursors[*index]).is_ok() {
+                return Ok(());
+            }
         }
-        if cursors[CURSOR_INDEX_SNAME].position() > 0 {
-            cursors[CURSOR_INDEX_SNAME].put_u8(End as u8);
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "No more space left",
+        ))
+    }
+
+    /// Has no data at all.
+    fn put_opt_empty(
+        cursor: &mut io::Cursor<&mut [u8]>,
+        tag: OptionTag,
+        value: bool,
+    ) -> io::Result<()> {
+        if value {
+            check_remaining!(cursor, SIZE_OPTION_AFFIXES);
+            cursor.put_u8(tag.code());
+            cursor.put_u8(0);
         }
-        Ok(cursors[CURSOR_INDEX_MAIN].position() as usize)
+        Ok(())
     }
 
     /// Cannot be splitted.
@@ -529,7 +531,7 @@ impl Message {
         if let Some(ref value) = value {
             let size = mem::size_of::<u8>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put_u8(*value);
         }
@@ -545,7 +547,7 @@ impl Message {
         if let Some(ref value) = value {
             let size = mem::size_of::<u16>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put_u16_be(*value);
         }
@@ -561,7 +563,7 @@ impl Message {
         if let Some(ref value) = value {
             let size = mem::size_of::<u32>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put_u32_be(*value);
         }
@@ -577,7 +579,7 @@ impl Message {
         if let Some(ref value) = value {
             let size = mem::size_of::<u32>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put_u32_be(u32::from(*value));
         }
@@ -596,7 +598,7 @@ impl Message {
             }
             let size = value.len();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put(value);
         }
@@ -615,13 +617,35 @@ impl Message {
             }
             let size = value.len();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             cursor.put(value);
         }
         Ok(())
     }
 
+    /// Can be splitted, so the data longer than 255 bytes is written
+    /// as several consecutive instances (RFC 3396).
+    fn put_opt_vec_splitted(
+        cursor: &mut io::Cursor<&mut [u8]>,
+        tag: OptionTag,
+        value: &Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        if let Some(ref value) = value {
+            if value.is_empty() {
+                return Ok(());
+            }
+            let chunks = value.chunks(SIZE_OPTION_MAX);
+            check_remaining!(cursor, SIZE_OPTION_AFFIXES * chunks.len() + value.len());
+            for chunk in chunks {
+                cursor.put_u8(tag.code());
+                cursor.put_u8(chunk.len() as u8);
+                cursor.put(chunk);
+            }
+        }
+        Ok(())
+    }
+
     /// Can be splitted.
     fn put_opt_vec_u16(
         cursor: &mut io::Cursor<&mut [u8]>,
@@ -634,7 +658,7 @@ impl Message {
             }
             let size = value.len() * mem::size_of::<u16>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             for element in value.iter() {
                 cursor.put_u16_be(*element);
@@ -655,7 +679,7 @@ impl Message {
             }
             let size = value.len() * mem::size_of::<u32>();
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             for element in value.iter() {
                 cursor.put_u32_be(u32::from(element.to_owned()));
@@ -676,7 +700,7 @@ impl Message {
             }
             let size = value.len() * mem::size_of::<u32>() * 2;
             check_remaining!(cursor, SIZE_OPTION_AFFIXES + size);
-            cursor.put_u8(tag as u8);
+            cursor.put_u8(tag.code());
             cursor.put_u8(size as u8);
             for element in value.iter() {
                 cursor.put_u32_be(u32::from(element.0.to_owned()));
@@ -729,11 +753,7 @@ impl Message {
             let (mut i, mut j, mut c) = (0, 0, 0); // iterators
             while c < cursors.len() {
                 let mut cursor = &mut cursors[c];
-                let affix_len = if c != CURSOR_INDEX_MAIN {
-                    SIZE_OPTION_AFFIXES // only the tag, the length and the END
-                } else {
-                    SIZE_OPTION_MAIN_AFFIXES // also some space for the 'overload' option
-                };
+                let affix_len = SIZE_OPTION_AFFIXES; // the tag, the length and the END
 
                 let mut len: usize = 0; // the length to be written by each cursor
                 let mut repeat = false;
@@ -752,16 +772,16 @@ impl Message {
                 }
 
                 if len > 0 {
-                    cursor.put_u8(tag as u8);
+                    cursor.put_u8(tag.code());
                     cursor.put_u8(len as u8);
                     for k in i..j {
                         cursor.put(descriptors.get(k).unwrap());
                         cursor.put_u32_be(u32::from(value.get(k).unwrap().2.to_owned()));
                     }
                     i = j;
-                    if !repeat {
-                        c += 1;
-                    }
+                }
+                if len == 0 || !repeat {
+                    c += 1;
                 }
 
                 if j >= descriptors.len() {
@@ -778,3 +798,176 @@ impl Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use eui48::MacAddress;
+
+    use super::*;
+    use v4::{
+        options::{MessageType, Options},
+        HardwareType, OperationCode,
+    };
+
+    fn discover(options: Options) -> Message {
+        Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: true,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+
+            client_hardware_address: MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options,
+        }
+    }
+
+    #[test]
+    fn serializes_to_vec_like_to_buffer() {
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.hostname = Some("client".to_owned());
+        let message = discover(options);
+
+        for max_size in &[None, Some(SIZE_MESSAGE_MINIMAL as u16)] {
+            let mut buffer = vec![0u8; SIZE_MESSAGE_MAXIMAL];
+            let size = message.to_bytes(&mut buffer, *max_size).unwrap();
+            assert_eq!(message.to_vec(*max_size).unwrap(), &buffer[..size]);
+        }
+    }
+
+    #[test]
+    fn round_trips_options_overloaded_into_file() {
+        let servers = |amount: u8| -> Option<Vec<Ipv4Addr>> {
+            Some(
+                (0..amount)
+                    .map(|host| Ipv4Addr::new(10, 0, 0, host))
+                    .collect(),
+            )
+        };
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.ntp_servers = servers(63);
+        options.www_servers = servers(20);
+        let bytes = discover(options)
+            .to_vec(Some(SIZE_MESSAGE_MINIMAL as u16))
+            .unwrap();
+
+        assert_eq!(bytes[OFFSET_BOOT_FILENAME], OptionTag::WwwServers.code());
+        let message = Message::from_bytes(&bytes).unwrap();
+        match message.options.overload {
+            Some(OverloadEnum::File) => {}
+            _ => panic!("Only the file field must be overloaded"),
+        }
+        assert_eq!(message.options.ntp_servers, servers(63));
+        assert_eq!(message.options.www_servers, servers(20));
+    }
+
+    #[test]
+    fn round_trips_ntp_servers() {
+        let ntp_servers = vec![Ipv4Addr::new(192, 168, 0, 4), Ipv4Addr::new(10, 0, 0, 5)];
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.ntp_servers = Some(ntp_servers.to_owned());
+        let bytes = discover(options).to_vec(None).unwrap();
+
+        let expected = [
+            &[OptionTag::NtpServers.code(), 8][..],
+            &[192, 168, 0, 4, 10, 0, 0, 5],
+        ].concat();
+        assert!(bytes
+            .windows(expected.len())
+            .any(|option| option == &expected[..]));
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.options.ntp_servers, Some(ntp_servers));
+    }
+
+    #[test]
+    fn round_trips_domain_search() {
+        let domain_search = vec!["eng.example.com".to_owned(), "example.com".to_owned()];
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.domain_search = Some(domain_search.to_owned());
+        let bytes = discover(options).to_vec(None).unwrap();
+
+        // the second name is a pointer to the suffix of the first one
+        let expected = [
+            &[OptionTag::DomainSearch.code(), 19, 3][..],
+            b"eng",
+            &[7],
+            b"example",
+            &[3],
+            b"com",
+            &[0, 0xc0, 4],
+        ].concat();
+        assert!(bytes
+            .windows(expected.len())
+            .any(|option| option == &expected[..]));
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.options.domain_search, Some(domain_search));
+    }
+
+    #[test]
+    fn round_trips_classless_static_routes() {
+        let router = Ipv4Addr::new(10, 1, 2, 1);
+        let routes = vec![
+            (
+                Ipv4Addr::new(0, 0, 0, 0),
+                Ipv4Addr::new(0, 0, 0, 0),
+                Ipv4Addr::new(192, 168, 0, 1),
+            ),
+            (
+                Ipv4Addr::new(10, 1, 2, 0),
+                Ipv4Addr::new(255, 255, 255, 0),
+                router,
+            ),
+            (
+                Ipv4Addr::new(10, 1, 2, 3),
+                Ipv4Addr::new(255, 255, 255, 255),
+                router,
+            ),
+        ];
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.classless_static_routes = Some(routes.to_owned());
+        let bytes = discover(options).to_vec(None).unwrap();
+
+        let expected = [
+            &[OptionTag::ClasslessStaticRoutes.code(), 22][..],
+            &[0, 192, 168, 0, 1],
+            &[24, 10, 1, 2, 10, 1, 2, 1],
+            &[32, 10, 1, 2, 3, 10, 1, 2, 1],
+        ].concat();
+        assert!(bytes
+            .windows(expected.len())
+            .any(|option| option == &expected[..]));
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(message.options.classless_static_routes, Some(routes));
+    }
+
+    #[test]
+    fn serializes_rapid_commit_without_data() {
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpDiscover);
+        options.rapid_commit = true;
+        let bytes = discover(options).to_vec(None).unwrap();
+
+        let tag = OptionTag::RapidCommit.code();
+        assert!(bytes.windows(2).any(|option| option == [tag, 0]));
+        assert!(Message::from_bytes(&bytes).unwrap().options.rapid_commit);
+
+        let bytes = discover(Options::default()).to_vec(None).unwrap();
+        assert!(!Message::from_bytes(&bytes).unwrap().options.rapid_commit);
+    }
+}
@@ -1,19 +1,34 @@
 //! DHCP message validation module.
 
-use super::{constants::SIZE_MESSAGE_MINIMAL, options::MessageType, Message};
+use eui48::EUI48LEN;
+
+use super::{
+    constants::SIZE_MESSAGE_MINIMAL,
+    options::{MessageType, OptionTag},
+    HardwareType, Message, OperationCode,
+};
 
 /// The error type returned by `Message::validate`.
 #[derive(Fail, Debug)]
 pub enum Error {
-    #[fail(display = "Validation error: {}", _0)]
-    Validation(&'static str),
+    #[fail(display = "Validation error: required option {:?} is missing", _0)]
+    MissingOption(OptionTag),
+    #[fail(display = "Validation error: option {:?} has an invalid value", _0)]
+    InvalidOption(OptionTag),
+    #[fail(display = "Validation error: inconsistent fields: {}", _0)]
+    Inconsistent(&'static str),
+    #[fail(
+        display = "Validation error: hardware address length {} does not match the hardware type",
+        _0
+    )]
+    InvalidHardwareAddressLength(u8),
 }
 
 /// Checks if required options are present for each message type.
 macro_rules! must_set_option (
-    ($name:expr) => (
+    ($name:expr, $tag:expr) => (
         if $name.is_none() {
-            return Err(Error::Validation(stringify!($name)));
+            return Err(Error::MissingOption($tag));
         }
     );
 );
@@ -23,21 +38,36 @@ impl Message {
     ///
     /// Returns the DHCP message type on successful validation.
     ///
+    /// `DHCPINFORM` and `DHCPRELEASE` messages with zero `ciaddr` are rejected,
+    /// since the client must put its address there (RFC 2131 §4.4.3, §4.4.6).
+    ///
     /// # Errors
-    /// Returns `Error::Validation` if any option is invalid.
+    /// `Error::MissingOption` if a required option is absent.
+    /// `Error::InvalidOption` if an option has an invalid value.
+    /// `Error::Inconsistent` if the message fields contradict its type.
+    /// `Error::InvalidHardwareAddressLength` if `hlen` does not match `htype`.
     pub fn validate(&self) -> Result<MessageType, Error> {
-        let message = self; // for the stringify! macro above
+        let message = self;
 
         let dhcp_message_type = match message.options.dhcp_message_type {
-            None | Some(MessageType::Undefined) => {
-                return Err(Error::Validation("DHCP message type is absent or zero"));
+            None => return Err(Error::MissingOption(OptionTag::DhcpMessageType)),
+            Some(MessageType::Undefined) => {
+                return Err(Error::InvalidOption(OptionTag::DhcpMessageType));
             }
             Some(dhcp_message_type) => dhcp_message_type,
         };
 
+        if let HardwareType::Ethernet = message.hardware_type {
+            if message.hardware_address_length as usize != EUI48LEN {
+                return Err(Error::InvalidHardwareAddressLength(
+                    message.hardware_address_length,
+                ));
+            }
+        }
+
         if let Some(dhcp_max_message_size) = message.options.dhcp_max_message_size {
             if (dhcp_max_message_size as usize) < SIZE_MESSAGE_MINIMAL {
-                return Err(Error::Validation("DHCP maximal message size is too low"));
+                return Err(Error::InvalidOption(OptionTag::DhcpMaxMessageSize));
             }
         }
 
@@ -47,33 +77,209 @@ impl Message {
             MessageType::DhcpRequest => if message.client_ip_address.is_unspecified()
                 || message.options.dhcp_server_id.is_some()
             {
-                must_set_option!(message.options.address_request);
+                must_set_option!(message.options.address_request, OptionTag::AddressRequest);
+            },
+            MessageType::DhcpInform => if message.client_ip_address.is_unspecified() {
+                return Err(Error::Inconsistent("DHCPINFORM without 'ciaddr'"));
             },
-            MessageType::DhcpInform => {}
             MessageType::DhcpRelease => {
-                must_set_option!(message.options.dhcp_server_id);
+                must_set_option!(message.options.dhcp_server_id, OptionTag::DhcpServerId);
+                if message.client_ip_address.is_unspecified() {
+                    return Err(Error::Inconsistent("DHCPRELEASE without 'ciaddr'"));
+                }
             }
             MessageType::DhcpDecline => {
-                must_set_option!(message.options.address_request);
-                must_set_option!(message.options.dhcp_server_id);
+                must_set_option!(message.options.address_request, OptionTag::AddressRequest);
+                must_set_option!(message.options.dhcp_server_id, OptionTag::DhcpServerId);
             }
 
             // server generated packets section
             MessageType::DhcpOffer => {
-                must_set_option!(message.options.address_time);
-                must_set_option!(message.options.dhcp_server_id);
+                /*
+                RFC 2563 §2.3
+                A server which does not want the client to autoconfigure sends
+                a DHCPOFFER with 'yiaddr' set to zero and the Auto-Configure
+                option set to DoNotAutoConfigure, so no lease is offered.
+                */
+                if !message.your_ip_address.is_unspecified()
+                    || message.options.auto_configure.is_none()
+                {
+                    must_set_option!(message.options.address_time, OptionTag::AddressTime);
+                }
+                must_set_option!(message.options.dhcp_server_id, OptionTag::DhcpServerId);
             }
             MessageType::DhcpAck => {
-                must_set_option!(message.options.address_time);
-                must_set_option!(message.options.dhcp_server_id);
+                /*
+                RFC 2131 §4.3.5
+                The server MUST NOT send a lease expiration time to the client
+                and SHOULD NOT fill in 'yiaddr' in response to a DHCPINFORM.
+                */
+                if !message.your_ip_address.is_unspecified() {
+                    must_set_option!(message.options.address_time, OptionTag::AddressTime);
+                }
+                must_set_option!(message.options.dhcp_server_id, OptionTag::DhcpServerId);
             }
             MessageType::DhcpNak => {
-                must_set_option!(message.options.dhcp_server_id);
+                must_set_option!(message.options.dhcp_server_id, OptionTag::DhcpServerId);
             }
 
-            _ => return Err(Error::Validation("Unknown DHCP message type")),
+            _ => return Err(Error::InvalidOption(OptionTag::DhcpMessageType)),
         }
 
         Ok(dhcp_message_type)
     }
+
+    /// BOOTP request validation (RFC 951).
+    ///
+    /// Legacy BOOTP clients send a `BOOTREQUEST` without the DHCP message type.
+    ///
+    /// # Errors
+    /// `Error::Inconsistent` if the message is not a `BOOTREQUEST` or has the DHCP message type.
+    /// `Error::InvalidHardwareAddressLength` if `hlen` does not match `htype`.
+    pub fn validate_bootp(&self) -> Result<(), Error> {
+        match self.operation_code {
+            OperationCode::BootRequest => {}
+            _ => return Err(Error::Inconsistent("BOOTP message is not a BOOTREQUEST")),
+        }
+        if self.options.dhcp_message_type.is_some() {
+            return Err(Error::Inconsistent("DHCP message type in a BOOTP request"));
+        }
+        if let HardwareType::Ethernet = self.hardware_type {
+            if self.hardware_address_length as usize != EUI48LEN {
+                return Err(Error::InvalidHardwareAddressLength(
+                    self.hardware_address_length,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use eui48::MacAddress;
+
+    use super::*;
+    use v4::{options::Options, HardwareType, OperationCode};
+
+    fn message(dhcp_message_type: Option<MessageType>) -> Message {
+        let mut options = Options::default();
+        options.dhcp_message_type = dhcp_message_type;
+
+        Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: false,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+
+            client_hardware_address: MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options,
+        }
+    }
+
+    #[test]
+    fn reports_missing_message_type() {
+        match message(None).validate() {
+            Err(Error::MissingOption(OptionTag::DhcpMessageType)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_invalid_message_type() {
+        match message(Some(MessageType::Undefined)).validate() {
+            Err(Error::InvalidOption(OptionTag::DhcpMessageType)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_invalid_max_message_size() {
+        let mut message = message(Some(MessageType::DhcpDiscover));
+        message.options.dhcp_max_message_size = Some((SIZE_MESSAGE_MINIMAL - 1) as u16);
+        match message.validate() {
+            Err(Error::InvalidOption(OptionTag::DhcpMaxMessageSize)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn accepts_ethernet_hardware_address_length() {
+        match message(Some(MessageType::DhcpDiscover)).validate() {
+            Ok(MessageType::DhcpDiscover) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_invalid_hardware_address_length() {
+        for &length in [0, 16].iter() {
+            let mut message = message(Some(MessageType::DhcpDiscover));
+            message.hardware_address_length = length;
+            match message.validate() {
+                Err(Error::InvalidHardwareAddressLength(value)) if value == length => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_bootp_request() {
+        assert!(message(None).validate_bootp().is_ok());
+        match message(Some(MessageType::DhcpDiscover)).validate_bootp() {
+            Err(Error::Inconsistent(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let mut reply = message(None);
+        reply.operation_code = OperationCode::BootReply;
+        match reply.validate_bootp() {
+            Err(Error::Inconsistent(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_missing_required_option() {
+        let mut message = message(Some(MessageType::DhcpDecline));
+        message.options.address_request = Some(Ipv4Addr::new(192, 168, 0, 100));
+        match message.validate() {
+            Err(Error::MissingOption(OptionTag::DhcpServerId)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_inconsistent_fields() {
+        let mut message = message(Some(MessageType::DhcpRelease));
+        message.options.dhcp_server_id = Some(Ipv4Addr::new(192, 168, 0, 2));
+        match message.validate() {
+            Err(Error::Inconsistent(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_inform_without_client_address() {
+        let mut message = message(Some(MessageType::DhcpInform));
+        match message.validate() {
+            Err(Error::Inconsistent(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        message.client_ip_address = Ipv4Addr::new(192, 168, 0, 100);
+        assert!(message.validate().is_ok());
+    }
 }
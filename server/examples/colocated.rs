@@ -0,0 +1,94 @@
+//! Run this with administrator privileges where it is required
+//! in order to bind the DHCP server and client sockets to their ports 67 and 68.
+//!
+//! An all-in-one appliance serving the downstream network on one interface
+//! while acquiring its own upstream address on another one.
+//!
+//! The server and the client share no global state, so they only need distinct sockets.
+//! Without binding the client socket to its interface, it may also see the server
+//! broadcasts to the downstream clients, but they are dropped by the `xid` check.
+
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate eui48;
+extern crate tokio;
+
+extern crate dhcp_client;
+extern crate dhcp_framed;
+extern crate dhcp_protocol;
+extern crate dhcp_server;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use tokio::{net::UdpSocket, prelude::*};
+
+use dhcp_client::Client;
+use dhcp_framed::DhcpFramed;
+use dhcp_protocol::{DHCP_PORT_CLIENT, SIZE_MESSAGE_MINIMAL};
+
+fn main() {
+    std::env::set_var("RUST_BACKTRACE", "full");
+    std::env::set_var(
+        "RUST_LOG",
+        "colocated=trace,dhcp_server=trace,dhcp_client=trace",
+    );
+    env_logger::init();
+
+    let upstream_iface_name = "eth0";
+    let downstream_iface_name = "eth1".to_string();
+    let server_ip_address = Ipv4Addr::new(192, 168, 0, 1);
+
+    let server = dhcp_server::ServerBuilder::new(
+        server_ip_address,
+        downstream_iface_name,
+        (
+            Ipv4Addr::new(192, 168, 0, 50),
+            Ipv4Addr::new(192, 168, 0, 99),
+        ),
+        (
+            Ipv4Addr::new(192, 168, 0, 100),
+            Ipv4Addr::new(192, 168, 0, 199),
+        ),
+        dhcp_server::RamStorage::new(),
+        Ipv4Addr::new(255, 255, 255, 0),
+        vec![server_ip_address],
+        vec![server_ip_address],
+        vec![],
+        vec![],
+    ).finish()
+        .expect("Server creating error");
+
+    let socket = UdpSocket::bind(&SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        DHCP_PORT_CLIENT,
+    )).expect("Client socket binding error");
+    socket.set_broadcast(true).expect("Client socket error");
+    let (sink, stream) = DhcpFramed::new(socket)
+        .expect("Client socket error")
+        .split();
+    let client = Client::new_on_interface(
+        stream,
+        sink,
+        upstream_iface_name,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Some(SIZE_MESSAGE_MINIMAL as u16),
+        Vec::new(),
+    ).expect("Hardware address resolving error");
+
+    let server = server.map_err(|error| error!("Server error: {}", error));
+    let client = client
+        .for_each(|configuration| {
+            info!("Upstream configuration: {:?}", configuration);
+            Ok(())
+        })
+        .map_err(|error| error!("Client error: {}", error));
+
+    info!("DHCP server and client started");
+    tokio::run(server.join(client).map(|_| ()));
+}
@@ -0,0 +1,17 @@
+//! The audit trail of the server messages.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use dhcp_protocol::Message;
+
+/// Records every message the server receives and sends for forensic logging.
+///
+/// Unlike the logs, the sink gets the whole messages, so it may store them in any format.
+/// Both methods do nothing by default, so only the needed one may be implemented.
+pub trait AuditSink: Send {
+    /// Is called for every received message before it is validated.
+    fn on_recv(&mut self, _message: &Message, _source: SocketAddr) {}
+
+    /// Is called for every message right before it is sent.
+    fn on_send(&mut self, _message: &Message, _destination: Ipv4Addr) {}
+}
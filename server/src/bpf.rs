@@ -24,16 +24,23 @@ pub struct BpfData {
     cpu_pool: CpuPool,
     /// The interface MAC address.
     iface_hw_addr: MacAddress,
+    /// The IP TTL of the sent packets.
+    ip_ttl: u8,
 }
 
 impl BpfData {
     /// Constructs a new BPF object on the specified interface with a CPU pool.
     ///
-    /// The CPU pool size is defaulted to `DEFAULT_BPF_NUM_THREADS_SIZE` if not specified.
+    /// The CPU pool size is defaulted to `DEFAULT_BPF_NUM_THREADS_SIZE`
+    /// and the IP TTL is defaulted to `DEFAULT_IP_TTL` if not specified.
     ///
     /// # Errors
     /// `io::Error` if there is something wrong with the interface.
-    pub fn new(iface_name: &str, bpf_num_threads_size: Option<usize>) -> io::Result<Self> {
+    pub fn new(
+        iface_name: &str,
+        bpf_num_threads_size: Option<usize>,
+        ip_ttl: Option<u8>,
+    ) -> io::Result<Self> {
         Ok(BpfData {
             bpf: Bpf::new(iface_name)?,
             cpu_pool: CpuPool::new(bpf_num_threads_size.unwrap_or(DEFAULT_BPF_NUM_THREADS_SIZE)),
@@ -68,6 +75,7 @@ impl BpfData {
                     "No hardware address on the interface",
                 ))?
             },
+            ip_ttl: ip_ttl.unwrap_or(DEFAULT_IP_TTL),
         })
     }
 
@@ -92,6 +100,7 @@ impl BpfData {
             message.client_hardware_address.to_owned(),
             source.to_owned(),
             destination.to_owned(),
+            self.ip_ttl,
             &payload[..amount],
         )?;
 
@@ -117,6 +126,7 @@ impl BpfData {
         dst_mac: MacAddress,
         src_ip: Ipv4Addr,
         dst_ip: Ipv4Addr,
+        ip_ttl: u8,
         payload: &[u8],
     ) -> io::Result<Vec<u8>> {
         use etherparse::{PacketBuilder, WriteError};
@@ -124,7 +134,7 @@ impl BpfData {
         let builder = PacketBuilder::ethernet2(
             *array_ref!(src_mac.as_bytes(), 0, EUI48LEN),
             *array_ref!(dst_mac.as_bytes(), 0, EUI48LEN),
-        ).ipv4(src_ip.octets(), dst_ip.octets(), DEFAULT_IP_TTL)
+        ).ipv4(src_ip.octets(), dst_ip.octets(), ip_ttl)
             .udp(DHCP_PORT_SERVER, DHCP_PORT_CLIENT);
 
         let mut result = Vec::<u8>::with_capacity(builder.size(payload.len()));
@@ -137,3 +147,27 @@ impl BpfData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use etherparse::{IpHeader, PacketHeaders};
+
+    use super::*;
+
+    #[test]
+    fn builds_packet_with_given_ttl() {
+        let packet = BpfData::ethernet_packet(
+            MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x01]),
+            MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            Ipv4Addr::new(192, 168, 0, 2),
+            Ipv4Addr::new(192, 168, 0, 100),
+            1,
+            &[0u8; 300],
+        ).unwrap();
+
+        match PacketHeaders::from_ethernet_slice(&packet).unwrap().ip {
+            Some(IpHeader::Version4(header)) => assert_eq!(header.time_to_live, 1),
+            _ => panic!("No IPv4 header in the packet"),
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! The server reply broadcasting policy.
+
+/// Decides whether the replies to clients without an address are broadcast.
+///
+/// Some clients set the `BROADCAST` flag incorrectly, so the flag may be overridden.
+/// Replies to clients with a nonzero `ciaddr` are always unicast to that address.
+#[derive(Debug, Clone, Copy)]
+pub enum BroadcastPolicy {
+    /// Broadcasts if the client has set the `BROADCAST` flag (RFC 2131 §4.1).
+    Honor,
+    /// Broadcasts regardless of the `BROADCAST` flag.
+    AlwaysBroadcast,
+    /// Unicasts to the offered address regardless of the `BROADCAST` flag.
+    AlwaysUnicastWhenPossible,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for BroadcastPolicy {
+    fn default() -> Self {
+        BroadcastPolicy::Honor
+    }
+}
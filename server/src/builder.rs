@@ -7,6 +7,7 @@ use dhcp_protocol::*;
 use database::{Ack, Error, Offer};
 
 /// Builds common server messages with some parameters.
+#[derive(Clone)]
 pub struct MessageBuilder {
     /// Sent to clients in `server_ip_address` field.
     server_ip_address: Ipv4Addr,
@@ -22,10 +23,27 @@ pub struct MessageBuilder {
     static_routes: Vec<(Ipv4Addr, Ipv4Addr)>,
     /// Sent to clients in options.
     classless_static_routes: Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>,
+    /// The only options sent in response to `DHCPINFORM` if set.
+    inform_options: Option<Vec<OptionTag>>,
+    /// Sent to clients in options if set.
+    tftp_server_name: Option<String>,
+    /// Sent to clients in options if set.
+    bootfile_name: Option<String>,
+    /// Sent to clients in `server_ip_address` field instead of the server address if set.
+    next_server: Option<Ipv4Addr>,
+    /// Sent to clients in `boot_filename` field if set.
+    boot_file: Option<String>,
+    /// Sent to clients in options.
+    ntp_servers: Vec<Ipv4Addr>,
+    /// Sent to clients in options.
+    domain_name: Option<String>,
+    /// Sent to clients in options.
+    domain_search: Vec<String>,
 }
 
 impl MessageBuilder {
     /// Creates a builder with message parameters which will not be changed.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         server_ip_address: Ipv4Addr,
         hostname: Option<String>,
@@ -35,6 +53,7 @@ impl MessageBuilder {
         domain_name_servers: Vec<Ipv4Addr>,
         static_routes: Vec<(Ipv4Addr, Ipv4Addr)>,
         classless_static_routes: Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>,
+        inform_options: Option<Vec<OptionTag>>,
     ) -> Self {
         MessageBuilder {
             server_ip_address,
@@ -45,17 +64,57 @@ impl MessageBuilder {
             domain_name_servers,
             static_routes,
             classless_static_routes,
+            inform_options,
+            tftp_server_name: None,
+            bootfile_name: None,
+            next_server: None,
+            boot_file: None,
+            ntp_servers: Vec::new(),
+            domain_name: None,
+            domain_search: Vec::new(),
         }
     }
 
+    /// Sets the boot options sent with the leases, e.g. to PXE clients.
+    pub fn set_boot_options(
+        &mut self,
+        tftp_server_name: Option<String>,
+        bootfile_name: Option<String>,
+    ) {
+        self.tftp_server_name = tftp_server_name;
+        self.bootfile_name = bootfile_name;
+    }
+
+    /// Sets the BOOTP `siaddr` and `file` fields sent with the leases.
+    ///
+    /// The values are also sent in the options 66 and 67 if the client requests them
+    /// and they are not set with `set_boot_options`.
+    pub fn set_next_server(&mut self, next_server: Option<Ipv4Addr>, boot_file: Option<String>) {
+        self.next_server = next_server;
+        self.boot_file = boot_file;
+    }
+
+    /// Sets the NTP servers sent to the clients requesting them.
+    pub fn set_ntp_servers(&mut self, ntp_servers: Vec<Ipv4Addr>) {
+        self.ntp_servers = ntp_servers;
+    }
+
+    /// Sets the domain name and the domain search list sent to the clients requesting them.
+    pub fn set_domain(&mut self, domain_name: Option<String>, domain_search: Vec<String>) {
+        self.domain_name = domain_name;
+        self.domain_search = domain_search;
+    }
+
     /// Creates a `DHCPOFFER` message from a `DHCPDISCOVER` message.
     pub fn dhcp_discover_to_offer(&self, discover: &Message, offer: &Offer) -> Message {
         let mut options = Options::default();
-        self.append_default_options(&mut options);
+        self.append_default_options(&mut options, discover);
         if let Some(ref parameter_list) = discover.options.parameter_list {
             self.append_requested_options(&mut options, parameter_list);
         }
 
+        self.append_boot_options(&mut options, discover);
+
         options.dhcp_message_type = Some(MessageType::DhcpOffer);
         options.dhcp_message = Some(offer.message.to_owned());
         options.address_time = Some(offer.lease_time);
@@ -72,6 +131,40 @@ impl MessageBuilder {
 
             client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
             your_ip_address: offer.address,
+            server_ip_address: self.next_server.unwrap_or(Ipv4Addr::new(0, 0, 0, 0)),
+            gateway_ip_address: discover.gateway_ip_address,
+
+            client_hardware_address: discover.client_hardware_address,
+            server_name: Default::default(),
+            boot_filename: self.boot_filename(),
+
+            options,
+        }
+    }
+
+    /// Creates a no-lease `DHCPOFFER` message from a `DHCPDISCOVER` message.
+    ///
+    /// The offer carries no address and tells the client not to autoconfigure (RFC 2563).
+    pub fn dhcp_discover_to_no_lease(&self, discover: &Message, error: &Error) -> Message {
+        let mut options = Options::default();
+        self.append_default_options(&mut options, discover);
+
+        options.dhcp_message_type = Some(MessageType::DhcpOffer);
+        options.dhcp_message = Some(error.to_string());
+        options.auto_configure = Some(AutoConfigure::DoNotAutoConfigure);
+
+        Message {
+            operation_code: OperationCode::BootReply,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: discover.hardware_address_length,
+            hardware_options: Default::default(),
+
+            transaction_id: discover.transaction_id,
+            seconds: Default::default(),
+            is_broadcast: discover.is_broadcast,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
             server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
             gateway_ip_address: discover.gateway_ip_address,
 
@@ -83,14 +176,25 @@ impl MessageBuilder {
         }
     }
 
+    /// Creates a `DHCPACK` message from a `DHCPDISCOVER` message with the Rapid Commit option.
+    ///
+    /// The option is echoed to complete the two-message exchange (RFC 4039 §4).
+    pub fn dhcp_discover_to_ack(&self, discover: &Message, ack: &Ack) -> Message {
+        let mut response = self.dhcp_request_to_ack(discover, ack);
+        response.options.rapid_commit = true;
+        response
+    }
+
     /// Creates a `DHCPACK` message from a `DHCPREQUEST` message.
     pub fn dhcp_request_to_ack(&self, request: &Message, ack: &Ack) -> Message {
         let mut options = Options::default();
-        self.append_default_options(&mut options);
+        self.append_default_options(&mut options, request);
         if let Some(ref parameter_list) = request.options.parameter_list {
             self.append_requested_options(&mut options, parameter_list);
         }
 
+        self.append_boot_options(&mut options, request);
+
         options.dhcp_message_type = Some(MessageType::DhcpAck);
         options.dhcp_message = Some(ack.message.to_owned());
         options.address_time = Some(ack.lease_time);
@@ -109,23 +213,75 @@ impl MessageBuilder {
 
             client_ip_address: request.client_ip_address,
             your_ip_address: ack.address,
-            server_ip_address: self.server_ip_address,
+            server_ip_address: self.next_server.unwrap_or(self.server_ip_address),
             gateway_ip_address: request.gateway_ip_address,
 
             client_hardware_address: request.client_hardware_address,
             server_name: Default::default(),
-            boot_filename: Default::default(),
+            boot_filename: self.boot_filename(),
+
+            options,
+        }
+    }
+
+    /// Creates a `BOOTREPLY` message from a BOOTP `BOOTREQUEST` message (RFC 1534).
+    ///
+    /// BOOTP clients do not request options, so the basic network configuration
+    /// is sent in the vendor extensions and the DHCP options are omitted.
+    pub fn bootp_request_to_reply(&self, request: &Message, ack: &Ack) -> Message {
+        let mut options = Options {
+            hostname: self.hostname.to_owned(),
+            ..Default::default()
+        };
+        self.append_requested_options(
+            &mut options,
+            &[
+                OptionTag::SubnetMask.code(),
+                OptionTag::Routers.code(),
+                OptionTag::DomainNameServers.code(),
+            ],
+        );
+
+        Message {
+            operation_code: OperationCode::BootReply,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: request.hardware_address_length,
+            hardware_options: Default::default(),
+
+            transaction_id: request.transaction_id,
+            seconds: Default::default(),
+            is_broadcast: request.is_broadcast,
+
+            client_ip_address: request.client_ip_address,
+            your_ip_address: ack.address,
+            server_ip_address: self.next_server.unwrap_or(self.server_ip_address),
+            gateway_ip_address: request.gateway_ip_address,
+
+            client_hardware_address: request.client_hardware_address,
+            server_name: Default::default(),
+            boot_filename: self.boot_filename(),
 
             options,
         }
     }
 
     /// Creates a `DHCPACK` message from a `DHCPINFORM` message.
+    ///
+    /// The lease time options are never sent and the requested options are
+    /// restricted to the configured subset if it is set.
     pub fn dhcp_inform_to_ack(&self, inform: &Message, message: &str) -> Message {
         let mut options = Options::default();
-        self.append_default_options(&mut options);
+        self.append_default_options(&mut options, inform);
         if let Some(ref parameter_list) = inform.options.parameter_list {
-            self.append_requested_options(&mut options, parameter_list);
+            let parameter_list: Vec<u8> = match self.inform_options {
+                Some(ref inform_options) => parameter_list
+                    .iter()
+                    .filter(|tag| inform_options.contains(&OptionTag::from(**tag)))
+                    .cloned()
+                    .collect(),
+                None => parameter_list.to_owned(),
+            };
+            self.append_requested_options(&mut options, &parameter_list);
         }
 
         options.dhcp_message_type = Some(MessageType::DhcpAck);
@@ -157,11 +313,21 @@ impl MessageBuilder {
     /// Creates a `DHCPNAK` message from a `DHCPREQUEST` message.
     pub fn dhcp_request_to_nak(&self, request: &Message, error: &Error) -> Message {
         let mut options = Options::default();
-        self.append_default_options(&mut options);
+        self.append_default_options(&mut options, request);
 
         options.dhcp_message_type = Some(MessageType::DhcpNak);
         options.dhcp_message = Some(error.to_string());
 
+        /*
+        RFC 2131 §4.3.2
+        If 'giaddr' is set in the DHCPREQUEST message, the client is on a
+        different subnet.  The server MUST set the broadcast bit in the
+        DHCPNAK, so that the relay agent will broadcast the DHCPNAK to the
+        client, because the client may not have a correct network address
+        or subnet mask, and the client may not be answering ARP requests.
+        */
+        let is_broadcast = request.is_broadcast || request.addresses().is_relayed();
+
         Message {
             operation_code: OperationCode::BootReply,
             hardware_type: HardwareType::Ethernet,
@@ -170,7 +336,7 @@ impl MessageBuilder {
 
             transaction_id: request.transaction_id,
             seconds: Default::default(),
-            is_broadcast: request.is_broadcast,
+            is_broadcast,
 
             client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
             your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
@@ -185,9 +351,43 @@ impl MessageBuilder {
         }
     }
 
-    fn append_default_options(&self, options: &mut Options) {
+    fn append_default_options(&self, options: &mut Options, request: &Message) {
         options.hostname = self.hostname.to_owned();
         options.dhcp_server_id = Some(self.server_ip_address);
+
+        /*
+        RFC 3046 §2.2
+        DHCP servers claiming to support the Relay Agent Information option
+        SHALL echo the entire contents of the Relay Agent Information option
+        in all replies.
+        */
+        options.relay_agent_info = request.options.relay_agent_info.to_owned();
+    }
+
+    fn append_boot_options(&self, options: &mut Options, request: &Message) {
+        options.server_name = self.tftp_server_name.to_owned();
+        options.bootfile_name = self.bootfile_name.to_owned();
+
+        // PXE clients may look for the boot parameters in the options only
+        if let Some(ref parameter_list) = request.options.parameter_list {
+            if options.server_name.is_none()
+                && parameter_list.contains(&OptionTag::ServerName.code())
+            {
+                options.server_name = self.next_server.map(|address| address.to_string());
+            }
+            if options.bootfile_name.is_none()
+                && parameter_list.contains(&OptionTag::BootfileName.code())
+            {
+                options.bootfile_name = self.boot_file.to_owned();
+            }
+        }
+    }
+
+    fn boot_filename(&self) -> Vec<u8> {
+        match self.boot_file {
+            Some(ref boot_file) => boot_file.as_bytes().to_vec(),
+            None => Vec::new(),
+        }
     }
 
     fn append_requested_options(&self, options: &mut Options, parameter_list: &[u8]) {
@@ -197,6 +397,17 @@ impl MessageBuilder {
                 OptionTag::DomainNameServers => if self.domain_name_servers.len() > 0 {
                     options.domain_name_servers = Some(self.domain_name_servers.to_owned());
                 },
+                OptionTag::NtpServers => if !self.ntp_servers.is_empty() {
+                    options.ntp_servers = Some(self.ntp_servers.to_owned());
+                },
+                OptionTag::DomainName => options.domain_name = self.domain_name.to_owned(),
+
+                /*
+                RFC 3397
+                */
+                OptionTag::DomainSearch => if !self.domain_search.is_empty() {
+                    options.domain_search = Some(self.domain_search.to_owned());
+                },
 
                 /*
                 RFC 3442
@@ -216,14 +427,14 @@ impl MessageBuilder {
                     options.classless_static_routes = Some(self.classless_static_routes.to_owned())
                 },
                 OptionTag::Routers => if (!parameter_list
-                    .contains(&(OptionTag::ClasslessStaticRoutes as u8))
+                    .contains(&OptionTag::ClasslessStaticRoutes.code())
                     || self.classless_static_routes.len() == 0)
                     && self.routers.len() > 0
                 {
                     options.routers = Some(self.routers.to_owned());
                 },
                 OptionTag::StaticRoutes => if (!parameter_list
-                    .contains(&(OptionTag::ClasslessStaticRoutes as u8))
+                    .contains(&OptionTag::ClasslessStaticRoutes.code())
                     || self.classless_static_routes.len() == 0)
                     && self.static_routes.len() > 0
                 {
@@ -235,3 +446,75 @@ impl MessageBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use eui48::MacAddress;
+
+    use super::*;
+
+    #[test]
+    fn overloads_options_exceeding_the_main_area() {
+        let routers: Vec<Ipv4Addr> = (1..21).map(|i| Ipv4Addr::new(192, 168, 0, i)).collect();
+        let domain_name_servers: Vec<Ipv4Addr> =
+            (1..61).map(|i| Ipv4Addr::new(192, 168, 1, i)).collect();
+        let builder = MessageBuilder::new(
+            Ipv4Addr::new(192, 168, 0, 2),
+            Some("server".to_owned()),
+            Ipv4Addr::new(255, 255, 255, 0),
+            routers.to_owned(),
+            domain_name_servers.to_owned(),
+            vec![],
+            vec![],
+            None,
+        );
+
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(MessageType::DhcpRequest);
+        options.parameter_list = Some(vec![
+            OptionTag::SubnetMask.code(),
+            OptionTag::Routers.code(),
+            OptionTag::DomainNameServers.code(),
+        ]);
+        let request = Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: true,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+
+            client_hardware_address: MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options,
+        };
+        let ack = Ack {
+            address: Ipv4Addr::new(192, 168, 0, 100),
+            lease_time: 1000,
+            renewal_time: 500,
+            rebinding_time: 875,
+            message: "Successfully assigned".to_owned(),
+        };
+
+        let response = builder.dhcp_request_to_ack(&request, &ack);
+        let mut buffer = vec![0u8; SIZE_MESSAGE_MINIMAL];
+        let size = response
+            .to_bytes(&mut buffer, Some(SIZE_MESSAGE_MINIMAL as u16))
+            .unwrap();
+        let response = Message::from_bytes(&buffer[..size]).unwrap();
+
+        assert!(size < SIZE_MESSAGE_MINIMAL);
+        assert!(response.options.overload.is_some());
+        assert_eq!(response.options.routers, Some(routers));
+        assert_eq!(response.options.domain_name_servers, Some(domain_name_servers));
+    }
+}
@@ -0,0 +1,20 @@
+//! The commands a running server accepts through its control channel.
+
+use futures::sync::mpsc;
+
+use config::ServerConfig;
+
+/// Is sent to the server through the channel set with `ServerBuilder::with_commands`.
+#[derive(Debug, Clone)]
+pub enum ServerCommand {
+    /// Replaces the address pools, exclusions, reservations and client configuration data.
+    ///
+    /// Subsequent responses use the new configuration at once.
+    /// Existing leases are preserved while their addresses stay within the new pools,
+    /// and the other ones are deallocated. An invalid configuration is ignored.
+    Reconfigure(ServerConfig),
+    /// Sends the current lease table in the ISC `dhcpd.leases` format to the channel.
+    ///
+    /// Lets the tools understanding that format monitor the server.
+    ExportIscLeases(mpsc::UnboundedSender<String>),
+}
@@ -0,0 +1,137 @@
+//! The server configuration which may be replaced at runtime.
+
+use std::{io, net::Ipv4Addr};
+
+use eui48::MacAddress;
+
+use netmath;
+
+/// The address pools and client configuration data of the server.
+///
+/// Is used to reconfigure a running server with `ServerCommand::Reconfigure`.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// An inclusive IPv4 address range.
+    pub static_address_range: (Ipv4Addr, Ipv4Addr),
+    /// An inclusive IPv4 address range.
+    pub dynamic_address_range: (Ipv4Addr, Ipv4Addr),
+    /// Static data for client configuration.
+    pub subnet_mask: Ipv4Addr,
+    /// Static data for client configuration.
+    pub routers: Vec<Ipv4Addr>,
+    /// Static data for client configuration.
+    pub domain_name_servers: Vec<Ipv4Addr>,
+    /// Static data for client configuration.
+    pub static_routes: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// Static data for client configuration.
+    pub classless_static_routes: Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>,
+    /// The inclusive address ranges which are never allocated unless reserved.
+    pub excluded_address_ranges: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// The client identifiers with their reserved addresses and lease times.
+    pub reservations: Vec<(Vec<u8>, Ipv4Addr, Option<u32>)>,
+    /// The client hardware addresses with their reserved addresses.
+    pub hardware_reservations: Vec<(MacAddress, Ipv4Addr)>,
+}
+
+impl ServerConfig {
+    /// The subnet mask prefix length or `None` if the mask is not contiguous.
+    pub fn prefix_len(&self) -> Option<u8> {
+        netmath::prefix_len(&self.subnet_mask)
+    }
+
+    /// Checks the configuration the way the server building does.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` on a non-contiguous subnet mask or an empty excluded range.
+    pub fn validate(&self) -> io::Result<()> {
+        validate_subnet_mask(&self.subnet_mask)?;
+        validate_excluded_ranges(&self.excluded_address_ranges)
+    }
+}
+
+/// Fails if the subnet mask is not contiguous.
+pub(crate) fn validate_subnet_mask(mask: &Ipv4Addr) -> io::Result<()> {
+    match netmath::prefix_len(mask) {
+        Some(_) => Ok(()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The subnet mask {} is not contiguous", mask),
+        )),
+    }
+}
+
+/// Fails if any of the excluded address ranges is empty.
+pub(crate) fn validate_excluded_ranges(ranges: &[(Ipv4Addr, Ipv4Addr)]) -> io::Result<()> {
+    for &(first, last) in ranges {
+        if first > last {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The excluded address range {} - {} is empty", first, last),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The restricted pool the unknown clients are served from.
+///
+/// The clients get only an address, the subnet mask, the gateway and the DNS server,
+/// which is usually a captive portal one.
+#[derive(Debug, Clone)]
+pub struct GuestPool {
+    /// An inclusive IPv4 address range.
+    pub address_range: (Ipv4Addr, Ipv4Addr),
+    /// Static data for guest configuration.
+    pub subnet_mask: Ipv4Addr,
+    /// Static data for guest configuration.
+    pub router: Ipv4Addr,
+    /// Static data for guest configuration.
+    pub domain_name_server: Ipv4Addr,
+}
+
+/// The configuration of a subnet served through relay agents.
+///
+/// The subnet is chosen for a request if its relay agent address (`giaddr`) belongs to it.
+#[derive(Debug, Clone)]
+pub struct SubnetConfig {
+    /// The subnet network address.
+    pub network: Ipv4Addr,
+    /// Static data for client configuration. Defines the subnet with `network`.
+    pub subnet_mask: Ipv4Addr,
+    /// An inclusive IPv4 address range.
+    pub dynamic_address_range: (Ipv4Addr, Ipv4Addr),
+    /// Static data for client configuration.
+    pub routers: Vec<Ipv4Addr>,
+    /// Static data for client configuration.
+    pub domain_name_servers: Vec<Ipv4Addr>,
+}
+
+impl SubnetConfig {
+    /// Whether the address belongs to the subnet.
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        netmath::is_in_subnet(address, &self.network, &self.subnet_mask)
+    }
+}
+
+/// The configuration of the clients of a vendor class, e.g. the PXE ones.
+///
+/// The class is chosen for a directly connected client if its vendor class identifier
+/// (RFC 2132 §9.13) starts with `prefix`.
+#[derive(Debug, Clone)]
+pub struct VendorClassConfig {
+    /// The vendor class identifier prefix, e.g. `PXEClient`.
+    pub prefix: Vec<u8>,
+    /// An inclusive IPv4 address range.
+    pub dynamic_address_range: (Ipv4Addr, Ipv4Addr),
+    /// Static data for client configuration. Is the TFTP server for PXE clients.
+    pub tftp_server_name: Option<String>,
+    /// Static data for client configuration.
+    pub bootfile_name: Option<String>,
+}
+
+impl VendorClassConfig {
+    /// Whether the vendor class identifier belongs to the class.
+    pub fn matches(&self, class_id: &[u8]) -> bool {
+        class_id.starts_with(&self.prefix)
+    }
+}
@@ -0,0 +1,325 @@
+//! The address conflict detection.
+//!
+//! RFC 2131 §4.3.1
+//! As a consistency check, the allocating server SHOULD probe the reused address
+//! before allocating the address, e.g., with an ICMP echo request.
+//!
+//! Where ICMP is filtered, the address may be probed with ARP instead (RFC 5227).
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::{net, os::unix::io::FromRawFd};
+
+use futures::{task, Async, Future, Poll};
+#[cfg(target_os = "linux")]
+use libc;
+#[cfg(target_os = "linux")]
+use tokio::reactor::Handle;
+use tokio::{net::UdpSocket, timer::Delay};
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+use dhcp_arp;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+)))]
+use futures::future;
+
+/// Checks whether an address is already used by some host.
+///
+/// The probe is polled like a future, so the server keeps the `DHCPDISCOVER`
+/// until the probe completes instead of blocking the reactor.
+pub trait ConflictProber: Send {
+    /// Returns `Async::Ready(true)` if the address has answered the probe.
+    ///
+    /// The probe is started on the first call for the address and polled on the next ones.
+    /// The probes of different addresses may be in progress at the same time.
+    /// If `Async::NotReady` is returned, the current task must be notified
+    /// when the probe completes, like with any other future.
+    fn poll_in_use(&mut self, address: &Ipv4Addr) -> Poll<bool, io::Error>;
+}
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const SIZE_ICMP_ECHO: usize = 16;
+const SIZE_BUFFER: usize = 1500;
+
+/// Probes addresses with ICMP echo requests over a raw socket.
+pub struct IcmpProber {
+    /// The raw socket is only used with `send_to` and `recv_from`, which work the same way.
+    socket: UdpSocket,
+    timeout: Duration,
+    identifier: u16,
+    sequence: u16,
+    /// The probes in progress, keyed by the probed address.
+    probes: HashMap<Ipv4Addr, IcmpProbe>,
+}
+
+/// The echo request waiting for its reply.
+struct IcmpProbe {
+    sequence: u16,
+    is_sent: bool,
+    is_answered: bool,
+    deadline: Delay,
+}
+
+impl IcmpProber {
+    /// Opens the raw ICMP socket.
+    ///
+    /// # Errors
+    /// `io::Error` if the process lacks the privileges to open raw sockets
+    /// or the platform is not supported.
+    #[cfg(target_os = "linux")]
+    pub fn new(timeout: Duration) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_ICMP) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { net::UdpSocket::from_raw_fd(fd) };
+        let socket = UdpSocket::from_std(socket, &Handle::default())?;
+
+        Ok(IcmpProber {
+            socket,
+            timeout,
+            identifier: ::std::process::id() as u16,
+            sequence: 0,
+            probes: HashMap::new(),
+        })
+    }
+
+    /// Opens the raw ICMP socket.
+    ///
+    /// # Errors
+    /// `io::Error` if the process lacks the privileges to open raw sockets
+    /// or the platform is not supported.
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_timeout: Duration) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "ICMP conflict detection is not supported on this platform",
+        ))
+    }
+
+    /// Sends an echo request and polls the matching reply until the timeout.
+    #[allow(clippy::io_other_error)]
+    fn poll_probe(&mut self, address: &Ipv4Addr) -> Poll<bool, io::Error> {
+        self.poll_replies(address)?;
+
+        let probe = match self.probes.get_mut(address) {
+            Some(probe) => probe,
+            None => return Ok(Async::Ready(false)),
+        };
+        if probe.is_answered {
+            return Ok(Async::Ready(true));
+        }
+        if !probe.is_sent {
+            let request = echo_request(self.identifier, probe.sequence);
+            let destination = SocketAddr::new(IpAddr::V4(*address), 0);
+            try_ready!(self.socket.poll_send_to(&request, &destination));
+            probe.is_sent = true;
+        }
+
+        match probe.deadline.poll() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(false)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(error) => Err(io::Error::new(io::ErrorKind::Other, error)),
+        }
+    }
+
+    /// Reads every reply received so far and marks the probes they answer.
+    ///
+    /// The socket is shared by the probes, so the task is notified if another probe
+    /// is answered, because its own poll might have already returned `Async::NotReady`.
+    fn poll_replies(&mut self, address: &Ipv4Addr) -> io::Result<()> {
+        let mut buffer = [0u8; SIZE_BUFFER];
+        loop {
+            let (size, source) = match self.socket.poll_recv_from(&mut buffer)? {
+                Async::Ready(result) => result,
+                Async::NotReady => return Ok(()),
+            };
+            let source = match source.ip() {
+                IpAddr::V4(source) => source,
+                IpAddr::V6(_) => continue,
+            };
+            if let Some(probe) = self.probes.get_mut(&source) {
+                if is_echo_reply(&buffer[..size], self.identifier, probe.sequence) {
+                    probe.is_answered = true;
+                    if source != *address {
+                        task::current().notify();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ConflictProber for IcmpProber {
+    fn poll_in_use(&mut self, address: &Ipv4Addr) -> Poll<bool, io::Error> {
+        if !self.probes.contains_key(address) {
+            self.sequence = self.sequence.wrapping_add(1);
+            let probe = IcmpProbe {
+                sequence: self.sequence,
+                is_sent: false,
+                is_answered: false,
+                deadline: Delay::new(Instant::now() + self.timeout),
+            };
+            self.probes.insert(*address, probe);
+        }
+
+        let result = self.poll_probe(address);
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.probes.remove(address);
+        result
+    }
+}
+
+/// Probes addresses with ARP requests on the server interface.
+///
+/// Unlike ICMP, ARP cannot be filtered by the probed host firewall,
+/// but only the hosts on the same link are found.
+pub struct ArpProber {
+    iface: String,
+    timeout: Duration,
+    /// The probes in progress, keyed by the probed address.
+    probes: HashMap<Ipv4Addr, ArpProbe>,
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+type ArpProbe = dhcp_arp::Probe;
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+)))]
+type ArpProbe = future::Empty<bool, io::Error>;
+
+impl ArpProber {
+    /// Creates a prober of the interface the server is bound to.
+    pub fn new(iface: String, timeout: Duration) -> Self {
+        ArpProber {
+            iface,
+            timeout,
+            probes: HashMap::new(),
+        }
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "freebsd",
+        target_os = "macos"
+    ))]
+    fn start(&self, address: &Ipv4Addr) -> io::Result<ArpProbe> {
+        dhcp_arp::probe(*address, &self.iface, self.timeout)
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "freebsd",
+        target_os = "macos"
+    )))]
+    fn start(&self, _address: &Ipv4Addr) -> io::Result<ArpProbe> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "ARP conflict detection is not supported on this platform",
+        ))
+    }
+}
+
+impl ConflictProber for ArpProber {
+    fn poll_in_use(&mut self, address: &Ipv4Addr) -> Poll<bool, io::Error> {
+        if !self.probes.contains_key(address) {
+            let probe = self.start(address)?;
+            self.probes.insert(*address, probe);
+        }
+
+        let result = match self.probes.get_mut(address) {
+            Some(probe) => probe.poll(),
+            None => Ok(Async::Ready(false)),
+        };
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.probes.remove(address);
+        result
+    }
+}
+
+/// Builds an ICMP echo request with an empty payload.
+fn echo_request(identifier: u16, sequence: u16) -> [u8; SIZE_ICMP_ECHO] {
+    let mut packet = [0u8; SIZE_ICMP_ECHO];
+    packet[0] = ICMP_ECHO_REQUEST;
+    packet[4..6].copy_from_slice(&[(identifier >> 8) as u8, identifier as u8]);
+    packet[6..8].copy_from_slice(&[(sequence >> 8) as u8, sequence as u8]);
+    let checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&[(checksum >> 8) as u8, checksum as u8]);
+    packet
+}
+
+/// Checks whether the IP packet received by a raw socket is the expected echo reply.
+fn is_echo_reply(packet: &[u8], identifier: u16, sequence: u16) -> bool {
+    if packet.is_empty() {
+        return false;
+    }
+    let header_size = ((packet[0] & 0x0f) as usize) * 4;
+    if packet.len() < header_size + 8 {
+        return false;
+    }
+    let icmp = &packet[header_size..];
+    icmp[0] == ICMP_ECHO_REPLY
+        && (u16::from(icmp[4]) << 8 | u16::from(icmp[5])) == identifier
+        && (u16::from(icmp[6]) << 8 | u16::from(icmp[7])) == sequence
+}
+
+/// The Internet checksum (RFC 1071).
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u32::from(chunk[0]) << 8 | u32::from(chunk[1])
+        } else {
+            u32::from(chunk[0]) << 8
+        };
+        sum = sum.wrapping_add(word);
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_echo_request_with_valid_checksum() {
+        let request = echo_request(0x1234, 7);
+
+        assert_eq!(request[0], ICMP_ECHO_REQUEST);
+        assert_eq!(checksum(&request), 0);
+    }
+}
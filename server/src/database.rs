@@ -1,9 +1,20 @@
 //! The DHCP database implementation with address allocating algorithms.
 
-use std::{cmp, net::Ipv4Addr};
+use std::{
+    cmp,
+    collections::HashMap,
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
 
-use lease::Lease;
+use eui48::MacAddress;
+use futures::{Async, Poll};
+
+use config::ServerConfig;
+use lease::{Lease, INFINITE_LEASE_TIME};
 use storage::{self, Storage};
+use storage_journal::StorageJournal;
+use storage_overlay::StorageOverlay;
 
 /// T1 RFC 2131 suggestion.
 const RENEWAL_TIME_FACTOR: f64 = 0.5;
@@ -13,14 +24,26 @@ const REBINDING_TIME_FACTOR: f64 = 0.875;
 const DEFAULT_LEASE_TIME: u32 = 60 * 60 * 24;
 /// 1 week.
 const MAX_LEASE_TIME: u32 = 60 * 60 * 24 * 7;
+/// No lower limit by default.
+const MIN_LEASE_TIME: u32 = 0;
+/// The FNV-1a 32-bit offset basis.
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+/// The FNV-1a 32-bit prime.
+const FNV_PRIME: u32 = 0x0100_0193;
 
 /// Errors generated by `Database` methods.
 #[derive(Fail, Debug)]
 pub enum Error {
-    #[fail(display = "Database error")]
-    Storage(storage::Error),
-    #[fail(display = "The dynamic pool has been exhausted")]
-    DynamicPoolExhausted,
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(#[cause] storage::Error),
+    #[fail(display = "The address pool has been exhausted")]
+    PoolExhausted,
+    #[fail(display = "The client is not allowed to be served")]
+    NotAllowed,
+    #[fail(display = "The address {} is out of the served ranges", _0)]
+    AddressOutOfRange(Ipv4Addr),
+    #[fail(display = "The address {} is in use", _0)]
+    AddressInUse(Ipv4Addr),
 
     #[fail(display = "The requested address is not offered")]
     OfferNotFound,
@@ -73,7 +96,131 @@ where
     /// The inclusive dynamic address range.
     dynamic_address_range: (Ipv4Addr, Ipv4Addr),
     /// A user defined persistent DHCP database.
-    storage: S,
+    storage: StorageJournal<StorageOverlay<S>>,
+    /// Whether new addresses are derived from client hardware addresses.
+    deterministic_allocation: bool,
+    /// The client identifier and the hardware address of the client being served.
+    client_hardware_address: Option<(Vec<u8>, MacAddress)>,
+    /// The addresses reserved for particular clients.
+    reservations: HashMap<Vec<u8>, Reservation>,
+    /// The addresses reserved for particular client hardware addresses
+    /// whatever client identifiers they send.
+    hardware_reservations: HashMap<MacAddress, Reservation>,
+    /// The inclusive address range of the unknown clients if set.
+    guest_address_range: Option<(Ipv4Addr, Ipv4Addr)>,
+    /// The inclusive dynamic address ranges of the relayed subnets.
+    subnet_address_ranges: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// The inclusive address ranges which are never allocated unless reserved.
+    excluded_address_ranges: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// The shortest lease time granted.
+    min_lease_time: u32,
+    /// The longest lease time granted.
+    max_lease_time: u32,
+    /// The lease time granted if a client does not request one.
+    default_lease_time: u32,
+    /// The time after which a frozen address becomes allocatable again.
+    decline_cooldown: Option<Duration>,
+    /// The freeze times of the frozen addresses if there is a cooldown.
+    frozen_at: HashMap<Ipv4Addr, Instant>,
+    /// Whether the addresses frozen before the start have been found.
+    is_frozen_scanned: bool,
+    /// The sweep in progress.
+    sweeping: Option<Walk>,
+    /// The reclaiming of the addresses managed before reconfiguration in progress.
+    reclaiming: Option<Walk>,
+}
+
+/// The addresses walked by a sweep or a reclaiming.
+///
+/// Keeps the progress while the storage is not ready, so the walk is resumed
+/// at the address it has stopped at.
+struct Walk {
+    /// The addresses left to check.
+    pending: Addresses,
+    /// The client identifiers and the addresses deallocated so far.
+    deallocated: Vec<(Vec<u8>, Ipv4Addr)>,
+}
+
+impl Walk {
+    fn new(addresses: Addresses) -> Self {
+        Walk {
+            pending: addresses,
+            deallocated: Vec::new(),
+        }
+    }
+
+    fn next(&self) -> Option<Ipv4Addr> {
+        self.pending.peek()
+    }
+
+    /// Moves to the next address recording the client of the current one if it is deallocated.
+    fn advance(&mut self, deallocated: Option<Vec<u8>>) {
+        if let Some(address) = self.pending.next() {
+            if let Some(client_id) = deallocated {
+                self.deallocated.push((client_id, address));
+            }
+        }
+    }
+}
+
+/// The addresses of several ranges in ascending order without duplicates.
+///
+/// Only the ranges are kept, so a large pool is walked without listing its addresses.
+struct Addresses {
+    /// The disjoint inclusive ranges left to walk, the next one last.
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Addresses {
+    fn new(mut ranges: Vec<(u32, u32)>) -> Self {
+        ranges.retain(|&(first, last)| first <= last);
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            if let Some(previous) = merged.last_mut() {
+                // the ranges are sorted, so `first` is never less than the previous one
+                if first <= previous.1 || first - previous.1 == 1 {
+                    previous.1 = previous.1.max(last);
+                    continue;
+                }
+            }
+            merged.push((first, last));
+        }
+        merged.reverse();
+        Addresses { ranges: merged }
+    }
+
+    fn peek(&self) -> Option<Ipv4Addr> {
+        self.ranges.last().map(|&(first, _)| Ipv4Addr::from(first))
+    }
+
+    /// Adds the addresses of `other` to the ones left to walk.
+    fn extend(&mut self, other: Addresses) {
+        let ranges = self.ranges.drain(..).chain(other.ranges).collect();
+        *self = Addresses::new(ranges);
+    }
+}
+
+impl Iterator for Addresses {
+    type Item = Ipv4Addr;
+
+    fn next(&mut self) -> Option<Ipv4Addr> {
+        let (first, last) = self.ranges.last().cloned()?;
+        if first == last {
+            self.ranges.pop();
+        } else if let Some(range) = self.ranges.last_mut() {
+            range.0 = first + 1;
+        }
+        Some(Ipv4Addr::from(first))
+    }
+}
+
+/// An address reserved for a particular client.
+#[derive(Clone)]
+struct Reservation {
+    address: Ipv4Addr,
+    /// Overrides the requested and default lease times if set.
+    lease_time: Option<u32>,
 }
 
 impl<S> Database<S>
@@ -89,8 +236,236 @@ where
         Database {
             static_address_range,
             dynamic_address_range,
-            storage,
+            storage: StorageJournal::new(StorageOverlay::new(storage)),
+            deterministic_allocation: false,
+            client_hardware_address: None,
+            reservations: HashMap::new(),
+            hardware_reservations: HashMap::new(),
+            guest_address_range: None,
+            subnet_address_ranges: Vec::new(),
+            excluded_address_ranges: Vec::new(),
+            min_lease_time: MIN_LEASE_TIME,
+            max_lease_time: MAX_LEASE_TIME,
+            default_lease_time: DEFAULT_LEASE_TIME,
+            decline_cooldown: None,
+            frozen_at: HashMap::new(),
+            is_frozen_scanned: false,
+            sweeping: None,
+            reclaiming: None,
+        }
+    }
+
+    /// Polls the storage readiness to process the next request.
+    pub fn poll_ready(&mut self) -> Poll<(), storage::Error> {
+        self.storage.poll_ready()
+    }
+
+    /// Keeps the further changes in RAM, so the storage is only read.
+    pub fn set_dry_run(&mut self) {
+        self.storage.storage_mut().set_dry_run();
+    }
+
+    /// Starts journaling the storage operations of a request or a command.
+    ///
+    /// The server processes it again from the start while the storage is not ready,
+    /// and the operations completed before are replayed from the journal then.
+    pub fn begin_journal(&mut self) {
+        self.storage.begin();
+    }
+
+    /// Replays the journal from the start.
+    pub fn rewind_journal(&mut self) {
+        self.storage.rewind();
+    }
+
+    /// Drops the journal once the request or the command has been processed.
+    pub fn end_journal(&mut self) {
+        self.storage.end();
+    }
+
+    /// Makes the dynamic pool addresses be derived from client hardware addresses.
+    ///
+    /// The address is chosen by the hash of the hardware address adopted with
+    /// `adopt_client_hardware_address` or of the client identifier if there is none.
+    /// On collision, the pool is
+    /// probed linearly from that address, so every client gets the same address
+    /// as long as the same clients compete for the pool, even if the storage is lost.
+    /// The requested addresses are ignored in this mode.
+    pub fn set_deterministic_allocation(&mut self, deterministic_allocation: bool) {
+        self.deterministic_allocation = deterministic_allocation;
+    }
+
+    /// Replaces the lease time bounds.
+    ///
+    /// The lease times requested by clients are clamped to `[min, max]`,
+    /// and `default` is granted if a client does not request a lease time.
+    pub fn set_lease_bounds(&mut self, min: u32, max: u32, default: u32) {
+        self.min_lease_time = min;
+        self.max_lease_time = max;
+        self.default_lease_time = default;
+    }
+
+    /// Makes the frozen addresses allocatable again after the cooldown.
+    ///
+    /// If `None`, the frozen addresses are never allocated again.
+    pub fn set_decline_cooldown(&mut self, decline_cooldown: Option<Duration>) {
+        self.decline_cooldown = decline_cooldown;
+    }
+
+    /// Reserves an address for the client.
+    ///
+    /// The reserved address is never allocated to other clients. If `lease_time` is set,
+    /// it overrides the requested and default lease times for the client and is not
+    /// limited by the maximal lease time, so `0xffff_ffff` makes the lease infinite.
+    pub fn reserve(&mut self, client_id: Vec<u8>, address: Ipv4Addr, lease_time: Option<u32>) {
+        self.reservations.insert(
+            client_id,
+            Reservation {
+                address,
+                lease_time,
+            },
+        );
+    }
+
+    /// Reserves an address for the client with the hardware address.
+    ///
+    /// Works like `reserve`, but the reservation is extended with `adopt_client_hardware_address`
+    /// to any client identifier the client sends, like a DUID or its hardware address.
+    pub fn reserve_hardware_address(
+        &mut self,
+        client_hardware_address: MacAddress,
+        address: Ipv4Addr,
+        lease_time: Option<u32>,
+    ) {
+        self.hardware_reservations.insert(
+            client_hardware_address,
+            Reservation {
+                address,
+                lease_time,
+            },
+        );
+    }
+
+    /// Associates the client hardware address with the client identifier.
+    ///
+    /// Must be called before processing each request, so the reservation of the hardware
+    /// address is found by the client identifier the rest of the database is keyed by
+    /// and the deterministic allocation hashes the hardware address.
+    pub fn adopt_client_hardware_address(
+        &mut self,
+        client_id: &[u8],
+        client_hardware_address: &MacAddress,
+    ) {
+        self.client_hardware_address = Some((client_id.to_vec(), *client_hardware_address));
+        if self.reservations.contains_key(client_id) {
+            return;
+        }
+        if let Some(reservation) = self.hardware_reservations.get(client_hardware_address) {
+            self.reservations
+                .insert(client_id.to_vec(), reservation.to_owned());
+        }
+    }
+
+    /// Sets the guest pool the unknown clients are served from.
+    ///
+    /// The guest addresses are allocated with `allocate_guest` only.
+    pub fn set_guest_pool(&mut self, guest_address_range: (Ipv4Addr, Ipv4Addr)) {
+        self.guest_address_range = Some(guest_address_range);
+    }
+
+    /// Adds the dynamic pool of a relayed subnet.
+    ///
+    /// The subnet addresses are allocated with `allocate_in_subnet` only.
+    pub fn add_subnet_pool(&mut self, dynamic_address_range: (Ipv4Addr, Ipv4Addr)) {
+        self.subnet_address_ranges.push(dynamic_address_range);
+    }
+
+    /// Excludes an address range from all the pools.
+    ///
+    /// The excluded addresses are never allocated, even after being thawed,
+    /// and the leases of such addresses are not renewed.
+    /// The reserved addresses are still allocated to their clients.
+    pub fn exclude(&mut self, excluded_address_range: (Ipv4Addr, Ipv4Addr)) {
+        self.excluded_address_ranges.push(excluded_address_range);
+    }
+
+    /// Whether an address is reserved for the client.
+    pub fn is_reserved(&self, client_id: &[u8]) -> bool {
+        self.reservations.contains_key(client_id)
+    }
+
+    /// Replaces the static and dynamic address pools, the exclusions and the reservations.
+    ///
+    /// The addresses which have left the pools or have been reserved for other clients
+    /// are deallocated afterwards with `reclaim`.
+    pub fn reconfigure(&mut self, config: &ServerConfig) {
+        let previous_addresses = self.managed_addresses();
+        match self.reclaiming {
+            Some(ref mut walk) => walk.pending.extend(previous_addresses),
+            None => self.reclaiming = Some(Walk::new(previous_addresses)),
+        }
+
+        self.static_address_range = config.static_address_range;
+        self.dynamic_address_range = config.dynamic_address_range;
+        self.excluded_address_ranges = config.excluded_address_ranges.to_owned();
+        self.reservations.clear();
+        for &(ref client_id, address, lease_time) in &config.reservations {
+            self.reserve(client_id.to_owned(), address, lease_time);
+        }
+        self.hardware_reservations.clear();
+        for &(client_hardware_address, address) in &config.hardware_reservations {
+            self.reserve_hardware_address(client_hardware_address, address, None);
+        }
+    }
+
+    /// Deallocates the addresses which have left the pools on reconfiguration.
+    ///
+    /// The leases are kept in `Released` state like in `sweep`.
+    /// Returns the client identifiers and the deallocated addresses,
+    /// which are empty if there has been no reconfiguration since the last call.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error. The reclaiming is abandoned then.
+    pub fn reclaim(&mut self) -> Poll<Vec<(Vec<u8>, Ipv4Addr)>, Error> {
+        while let Some(address) = self.reclaiming.as_ref().and_then(Walk::next) {
+            let deallocated = match self.reclaim_address(&address) {
+                Ok(Async::Ready(deallocated)) => deallocated,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => {
+                    self.reclaiming = None;
+                    return Err(error);
+                }
+            };
+            if let Some(ref mut walk) = self.reclaiming {
+                walk.advance(deallocated);
+            }
+        }
+        let reclaimed = self.reclaiming.take().map(|walk| walk.deallocated);
+        Ok(Async::Ready(reclaimed.unwrap_or_default()))
+    }
+
+    /// Deallocates the address if it is not managed anymore or is reserved for another client.
+    ///
+    /// Returns the client identifier if the address has been deallocated.
+    fn reclaim_address(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
+        let client_id = match try_ready!(self.storage.get_client(address)) {
+            Some(client_id) => client_id,
+            None => return Ok(Async::Ready(None)),
+        };
+        let is_reserved_for_other = self
+            .reservations
+            .iter()
+            .any(|(id, reservation)| reservation.address == *address && *id != client_id);
+        if self.is_address_in_pools(address) && !is_reserved_for_other {
+            return Ok(Async::Ready(None));
+        }
+        match try_ready!(self.storage.get_lease(&client_id)) {
+            Some(ref lease) if lease.address() == *address => {
+                try_ready!(self.deallocate(&client_id, address));
+            }
+            _ => try_ready!(self.storage.delete_client(address)),
         }
+        Ok(Async::Ready(Some(client_id)))
     }
 
     /// Allocates an address.
@@ -137,19 +512,63 @@ where
         client_id: &[u8],
         lease_time: Option<u32>,
         requested_address: Option<Ipv4Addr>,
-    ) -> Result<Offer, Error> {
+    ) -> Poll<Offer, Error> {
+        self.allocate_in(client_id, lease_time, requested_address, None)
+    }
+
+    /// Allocates an address in a relayed subnet added with `add_subnet_pool`.
+    ///
+    /// Follows the `allocate` algorithm, but the addresses outside the subnet
+    /// dynamic range are never offered except the reserved ones.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    /// `self::Error` on subnet pool exhaustion.
+    pub fn allocate_in_subnet(
+        &mut self,
+        client_id: &[u8],
+        lease_time: Option<u32>,
+        requested_address: Option<Ipv4Addr>,
+        dynamic_address_range: (Ipv4Addr, Ipv4Addr),
+    ) -> Poll<Offer, Error> {
+        self.allocate_in(
+            client_id,
+            lease_time,
+            requested_address,
+            Some(dynamic_address_range),
+        )
+    }
+
+    /// Allocates an address in the subnet with the dynamic range or in the direct one if `None`.
+    fn allocate_in(
+        &mut self,
+        client_id: &[u8],
+        lease_time: Option<u32>,
+        requested_address: Option<Ipv4Addr>,
+        subnet: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> Poll<Offer, Error> {
+        try_ready!(self.thaw());
+
+        if let Some(reservation) = self.reservations.get(client_id).cloned() {
+            return self.allocate_reserved(client_id, lease_time, reservation);
+        }
+
         // for lease time case 1
         let reuse_lease_time = lease_time.is_none();
         // lease time case 2 or 3
-        let lease_time = cmp::min(lease_time.unwrap_or(DEFAULT_LEASE_TIME), MAX_LEASE_TIME);
+        let lease_time = self.bounded_lease_time(lease_time);
 
         // address allocation case 1
-        if let Some(address) = self.client_current_address(client_id)? {
-            if self.is_address_allocated_by(&address, client_id)?
-                && !self.is_address_frozen(&address)?
+        if let Some(address) = try_ready!(self.client_current_address(client_id)) {
+            if try_ready!(self.is_address_allocated_by(&address, client_id))
+                && !try_ready!(self.is_address_frozen(&address))
+                && ((self.is_address_in_subnet_pools(&address, subnet)
+                    && !self.is_address_excluded(&address))
+                    || self.is_address_reserved(&address))
             {
                 // lease time case 1
-                let lease_time = self.offer(&address, client_id, lease_time, reuse_lease_time)?;
+                let lease_time =
+                    try_ready!(self.offer(&address, client_id, lease_time, reuse_lease_time));
                 let offer = Offer {
                     address,
                     lease_time,
@@ -160,7 +579,7 @@ where
                     client_id,
                     offer.address
                 );
-                return Ok(offer);
+                return Ok(Async::Ready(offer));
             } else {
                 trace!("Client {:?} has no current address", client_id);
             }
@@ -169,9 +588,9 @@ where
         }
 
         // address allocation case 2
-        if let Some(address) = self.client_last_address(client_id)? {
-            if self.is_address_available(&address)? {
-                let lease_time = self.offer(&address, client_id, lease_time, false)?;
+        if let Some(address) = try_ready!(self.client_last_address(client_id)) {
+            if try_ready!(self.is_address_available(&address, subnet)) {
+                let lease_time = try_ready!(self.offer(&address, client_id, lease_time, false));
                 let offer = Offer {
                     address,
                     lease_time,
@@ -182,7 +601,7 @@ where
                     client_id,
                     offer.address
                 );
-                return Ok(offer);
+                return Ok(Async::Ready(offer));
             } else {
                 trace!("The previous address {} is not available", address);
             }
@@ -190,10 +609,15 @@ where
             trace!("Client {:?} has never had an address", client_id);
         }
 
-        // address allocation case 3
+        // address allocation case 3, skipped to keep the addresses predictable
+        let requested_address = if self.deterministic_allocation {
+            None
+        } else {
+            requested_address
+        };
         if let Some(address) = requested_address {
-            if self.is_address_available(&address)? {
-                let lease_time = self.offer(&address, client_id, lease_time, false)?;
+            if try_ready!(self.is_address_available(&address, subnet)) {
+                let lease_time = try_ready!(self.offer(&address, client_id, lease_time, false));
                 let offer = Offer {
                     address,
                     lease_time,
@@ -204,7 +628,7 @@ where
                     client_id,
                     offer.address
                 );
-                return Ok(offer);
+                return Ok(Async::Ready(offer));
             } else {
                 trace!("The requested address {} is not available", address);
             }
@@ -213,9 +637,9 @@ where
         }
 
         // address allocation case 4, giaddr stuff not implemented
-        let address = self.get_dynamic_available()?
-            .ok_or(Error::DynamicPoolExhausted)?;
-        let lease_time = self.offer(&address, client_id, lease_time, false)?;
+        let address = try_ready!(self.get_dynamic_available(client_id, subnet))
+            .ok_or(Error::PoolExhausted)?;
+        let lease_time = try_ready!(self.offer(&address, client_id, lease_time, false));
         let offer = Offer {
             address,
             lease_time,
@@ -226,7 +650,58 @@ where
             client_id,
             offer.address
         );
-        Ok(offer)
+        Ok(Async::Ready(offer))
+    }
+
+    /// Allocates an address from the guest pool for an unknown client.
+    ///
+    /// The client gets its current or previous guest address if it is still available,
+    /// otherwise the first available one. The requested address is ignored.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    /// `self::Error` on guest pool exhaustion or if there is no guest pool.
+    pub fn allocate_guest(
+        &mut self,
+        client_id: &[u8],
+        lease_time: Option<u32>,
+    ) -> Poll<Offer, Error> {
+        let (first, last) = self.guest_address_range.ok_or(Error::NotAllowed)?;
+        try_ready!(self.thaw());
+        let lease_time = self.bounded_lease_time(lease_time);
+
+        let mut address = None;
+        if let Some(last_address) = try_ready!(self.client_last_address(client_id)) {
+            let is_current = try_ready!(self.is_address_allocated_by(&last_address, client_id))
+                && !try_ready!(self.is_address_frozen(&last_address))
+                && self.is_address_in_guest_pool(&last_address)
+                && !self.is_address_excluded(&last_address);
+            if is_current || try_ready!(self.is_guest_address_available(&last_address)) {
+                address = Some(last_address);
+            }
+        }
+        if address.is_none() && first <= last {
+            for candidate in (u32::from(first)..=u32::from(last)).map(Ipv4Addr::from) {
+                if try_ready!(self.is_guest_address_available(&candidate)) {
+                    address = Some(candidate);
+                    break;
+                }
+            }
+        }
+        let address = address.ok_or(Error::PoolExhausted)?;
+
+        let lease_time = try_ready!(self.offer(&address, client_id, lease_time, false));
+        let offer = Offer {
+            address,
+            lease_time,
+            message: "Offering an address from the guest pool".to_owned(),
+        };
+        trace!(
+            "Offering to the client {:?} the address {} from the guest pool",
+            client_id,
+            offer.address
+        );
+        Ok(Async::Ready(offer))
     }
 
     /// Assigns a previously offered address.
@@ -239,8 +714,8 @@ where
         client_id: &[u8],
         address: &Ipv4Addr,
         lease_time: Option<u32>,
-    ) -> Result<Ack, Error> {
-        if let Some(lease) = self.storage.get_lease(&client_id)? {
+    ) -> Poll<Ack, Error> {
+        if let Some(lease) = try_ready!(self.storage.get_lease(client_id)) {
             if lease.is_offered() {
                 if lease.address() != *address {
                     return Err(Error::OfferInvalid);
@@ -248,15 +723,21 @@ where
                 if lease.is_offer_expired() {
                     return Err(Error::OfferExpired);
                 }
-                let lease_time =
-                    cmp::min(lease_time.unwrap_or(lease.lease_time()), lease.lease_time());
-                self.storage
-                    .update_lease(client_id, &mut |lease: &mut Lease| lease.assign(lease_time))?;
+                let lease_time = cmp::min(
+                    cmp::max(
+                        lease_time.unwrap_or(lease.lease_time()),
+                        self.min_lease_time,
+                    ),
+                    lease.lease_time(),
+                );
+                try_ready!(self
+                    .storage
+                    .update_lease(client_id, &mut |lease: &mut Lease| lease.assign(lease_time)));
                 let ack = Ack {
                     address: Ipv4Addr::from(lease.address()),
-                    lease_time: lease.lease_time(),
-                    renewal_time: ((lease.lease_time() as f64) * RENEWAL_TIME_FACTOR) as u32,
-                    rebinding_time: ((lease.lease_time() as f64) * REBINDING_TIME_FACTOR) as u32,
+                    lease_time,
+                    renewal_time: ((lease_time as f64) * RENEWAL_TIME_FACTOR) as u32,
+                    rebinding_time: ((lease_time as f64) * REBINDING_TIME_FACTOR) as u32,
                     message: "Successfully assigned".to_owned(),
                 };
                 trace!(
@@ -264,7 +745,7 @@ where
                     ack.address,
                     client_id
                 );
-                return Ok(ack);
+                return Ok(Async::Ready(ack));
             } else {
                 return Err(Error::OfferNotFound);
             }
@@ -272,6 +753,28 @@ where
         Err(Error::OfferNotFound)
     }
 
+    /// Assigns a previously offered address for good.
+    ///
+    /// BOOTP clients never renew their addresses (RFC 1534), so the lease is infinite
+    /// regardless of the lease time bounds.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    /// `self::Error` on address assignment error.
+    pub fn bind(&mut self, client_id: &[u8], address: &Ipv4Addr) -> Poll<Ack, Error> {
+        let mut ack = try_ready!(self.assign(client_id, address, None));
+        try_ready!(self
+            .storage
+            .update_lease(client_id, &mut |lease: &mut Lease| {
+                lease.assign(INFINITE_LEASE_TIME)
+            }));
+        ack.lease_time = INFINITE_LEASE_TIME;
+        ack.renewal_time = INFINITE_LEASE_TIME;
+        ack.rebinding_time = INFINITE_LEASE_TIME;
+        ack.message = "Bound for good".to_owned();
+        Ok(Async::Ready(ack))
+    }
+
     /// Renewes a previously assigned address.
     ///
     /// # Errors
@@ -282,17 +785,24 @@ where
         client_id: &[u8],
         address: &Ipv4Addr,
         lease_time: Option<u32>,
-    ) -> Result<Ack, Error> {
-        let lease_time = cmp::min(lease_time.unwrap_or(DEFAULT_LEASE_TIME), MAX_LEASE_TIME);
-        if let Some(lease) = self.storage.get_lease(&client_id)? {
-            if lease.address() == *address {
-                self.storage
-                    .update_lease(client_id, &mut |lease: &mut Lease| lease.renew(lease_time))?;
+    ) -> Poll<Ack, Error> {
+        let lease_time = match self.reservations.get(client_id) {
+            Some(&Reservation {
+                lease_time: Some(lease_time),
+                ..
+            }) => lease_time,
+            _ => self.bounded_lease_time(lease_time),
+        };
+        if let Some(lease) = try_ready!(self.storage.get_lease(client_id)) {
+            if lease.address() == *address && self.is_address_in_pools(address) {
+                try_ready!(self
+                    .storage
+                    .update_lease(client_id, &mut |lease: &mut Lease| lease.renew(lease_time)));
                 let ack = Ack {
                     address: Ipv4Addr::from(lease.address()),
-                    lease_time: lease.lease_time(),
-                    renewal_time: ((lease.lease_time() as f64) * RENEWAL_TIME_FACTOR) as u32,
-                    rebinding_time: ((lease.lease_time() as f64) * REBINDING_TIME_FACTOR) as u32,
+                    lease_time,
+                    renewal_time: ((lease_time as f64) * RENEWAL_TIME_FACTOR) as u32,
+                    rebinding_time: ((lease_time as f64) * REBINDING_TIME_FACTOR) as u32,
                     message: "Your lease has been renewed".to_owned(),
                 };
                 trace!(
@@ -300,9 +810,9 @@ where
                     ack.address,
                     client_id
                 );
-                return Ok(ack);
+                Ok(Async::Ready(ack))
             } else {
-                Err(Error::LeaseInvalid)
+                Err(try_ready!(self.lease_error(client_id, address)))
             }
         } else {
             Err(Error::LeaseNotFound)
@@ -314,142 +824,534 @@ where
     ///
     /// # Errors
     /// `self::Error` on internal storage error.
-    pub fn deallocate(&mut self, client_id: &[u8], address: &Ipv4Addr) -> Result<(), Error> {
-        self.storage.delete_client(address)?;
-        self.storage
-            .update_lease(client_id, &mut |lease: &mut Lease| lease.release())?;
-        Ok(())
+    pub fn deallocate(&mut self, client_id: &[u8], address: &Ipv4Addr) -> Poll<(), Error> {
+        try_ready!(self.storage.delete_client(address));
+        try_ready!(self
+            .storage
+            .update_lease(client_id, &mut |lease: &mut Lease| lease.release()));
+        Ok(Async::Ready(()))
     }
 
-    /// Freezes an address due to a `DHCPDECLINE` message.
+    /// Deallocates the addresses of the expired leases and the expired offers.
     ///
-    /// # Errors
-    /// `self::Error` on internal storage error.
-    pub fn freeze(&mut self, address: &Ipv4Addr) -> Result<(), Error> {
-        self.storage.add_frozen(address)?;
-        Ok(())
-    }
-
-    /// Checks the address of a client in the `INIT-REBOOT` state.
+    /// Walks the pools and the reservations, so the storage does not have to be iterable.
+    /// The leases are kept in `Released` state to let the clients get their previous
+    /// addresses back. Returns the client identifiers and the deallocated addresses.
     ///
     /// # Errors
-    /// `self::Error` on internal storage error.
-    /// `self::Error` if the address is not leased to the client.
-    pub fn check(&self, client_id: &[u8], address: &Ipv4Addr) -> Result<Ack, Error> {
-        if let Some(lease) = self.storage.get_lease(&client_id)? {
-            if lease.address() == *address && !lease.is_expired() && !lease.is_released() {
-                Ok(Ack {
-                    address: Ipv4Addr::from(lease.address()),
-                    lease_time: lease.lease_time(),
-                    renewal_time: ((lease.lease_time() as f64) * RENEWAL_TIME_FACTOR) as u32,
-                    rebinding_time: ((lease.lease_time() as f64) * REBINDING_TIME_FACTOR) as u32,
-                    message: "Your lease is active".to_owned(),
-                })
-            } else {
-                Err(Error::LeaseInvalid)
+    /// `self::Error` on internal storage error. The sweep is abandoned then.
+    pub fn sweep(&mut self) -> Poll<Vec<(Vec<u8>, Ipv4Addr)>, Error> {
+        if self.sweeping.is_none() {
+            self.sweeping = Some(Walk::new(self.managed_addresses()));
+        }
+        while let Some(address) = self.sweeping.as_ref().and_then(Walk::next) {
+            let deallocated = match self.sweep_address(&address) {
+                Ok(Async::Ready(deallocated)) => deallocated,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => {
+                    self.sweeping = None;
+                    return Err(error);
+                }
+            };
+            if let Some(ref mut walk) = self.sweeping {
+                walk.advance(deallocated);
             }
-        } else {
-            Err(Error::LeaseNotFound)
         }
+        let swept = self.sweeping.take().map(|walk| walk.deallocated);
+        Ok(Async::Ready(swept.unwrap_or_default()))
     }
 
-    fn offer(
-        &mut self,
-        address: &Ipv4Addr,
-        client_id: &[u8],
-        lease_time: u32,
-        reuse_lease_time: bool,
-    ) -> Result<u32, Error> {
-        self.storage.add_client(address, client_id)?;
-
-        let mut lease_time = lease_time;
-        if reuse_lease_time {
-            self.storage.get_lease(client_id)?.map(|lease| {
-                if lease.is_active() {
-                    lease_time = lease.expires_after();
-                }
-            });
+    /// Deallocates the address if its lease or offer has expired.
+    ///
+    /// Returns the client identifier if the address has been deallocated.
+    fn sweep_address(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
+        let client_id = match try_ready!(self.storage.get_client(address)) {
+            Some(client_id) => client_id,
+            None => return Ok(Async::Ready(None)),
+        };
+        match try_ready!(self.storage.get_lease(&client_id)) {
+            Some(ref lease) if lease.address() == *address && lease.is_allocated() => {
+                return Ok(Async::Ready(None));
+            }
+            Some(ref lease) if lease.address() == *address => {
+                try_ready!(self.deallocate(&client_id, address));
+            }
+            _ => try_ready!(self.storage.delete_client(address)),
         }
-
-        self.storage
-            .add_lease(client_id, Lease::new(address.to_owned(), lease_time))?;
-        Ok(lease_time)
+        Ok(Async::Ready(Some(client_id)))
     }
 
-    fn client_current_address(&self, client_id: &[u8]) -> Result<Option<Ipv4Addr>, Error> {
-        if let Some(lease) = self.storage.get_lease(client_id)? {
-            if lease.is_allocated() {
-                return Ok(Some(lease.address()));
+    /// Counts the assigned leases which have not expired.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    pub fn count_active_leases(&mut self) -> Poll<usize, Error> {
+        let mut active_leases = 0;
+        for address in self.managed_addresses() {
+            let client_id = match try_ready!(self.storage.get_client(&address)) {
+                Some(client_id) => client_id,
+                None => continue,
+            };
+            match try_ready!(self.storage.get_lease(&client_id)) {
+                Some(ref lease) if lease.address() == address && lease.is_active() => {
+                    active_leases += 1;
+                }
+                _ => {}
             }
         }
-        Ok(None)
+        Ok(Async::Ready(active_leases))
     }
 
-    fn client_last_address(&self, client_id: &[u8]) -> Result<Option<Ipv4Addr>, Error> {
-        if let Some(lease) = self.storage.get_lease(client_id)? {
-            return Ok(Some(lease.address()));
+    /// Writes the lease table in the ISC `dhcpd.leases` format.
+    ///
+    /// The frozen addresses are written as abandoned leases.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    pub fn export_isc(&mut self) -> Poll<String, Error> {
+        let mut leases = String::new();
+        for address in self.managed_addresses() {
+            if try_ready!(self.storage.check_frozen(&address)) {
+                leases.push_str(&format!(
+                    "lease {} {{\n  binding state abandoned;\n}}\n",
+                    address
+                ));
+                continue;
+            }
+            let client_id = match try_ready!(self.storage.get_client(&address)) {
+                Some(client_id) => client_id,
+                None => continue,
+            };
+            match try_ready!(self.storage.get_lease(&client_id)) {
+                Some(ref lease) if lease.address() == address => {
+                    if let Some(block) = lease.to_isc(&client_id) {
+                        leases.push_str(&block);
+                    }
+                }
+                _ => {}
+            }
         }
-        Ok(None)
+        Ok(Async::Ready(leases))
     }
 
-    fn is_address_available(&self, address: &Ipv4Addr) -> Result<bool, Error> {
-        Ok(
-            !self.is_address_allocated(address)? && !self.is_address_frozen(address)?
-                && (self.is_address_in_static_pool(address)
-                    || self.is_address_in_dynamic_pool(address)),
-        )
+    /// All the pool and reserved addresses in ascending order.
+    fn managed_addresses(&self) -> Addresses {
+        let pools = [
+            Some(self.static_address_range),
+            Some(self.dynamic_address_range),
+            self.guest_address_range,
+        ];
+        let subnets = self.subnet_address_ranges.iter();
+        let mut ranges: Vec<(u32, u32)> = pools
+            .iter()
+            .flat_map(Option::iter)
+            .chain(subnets)
+            .map(|&(first, last)| (u32::from(first), u32::from(last)))
+            .collect();
+        let reservations = self
+            .reservations
+            .values()
+            .chain(self.hardware_reservations.values());
+        ranges.extend(reservations.map(|reservation| {
+            let address = u32::from(reservation.address);
+            (address, address)
+        }));
+        Addresses::new(ranges)
     }
 
-    fn is_address_allocated(&self, address: &Ipv4Addr) -> Result<bool, Error> {
-        if let Some(client_id) = self.storage.get_client(address)? {
-            if let Some(lease) = self.storage.get_lease(&client_id)? {
-                return Ok(lease.is_allocated());
-            }
+    /// Freezes an address due to a `DHCPDECLINE` message.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    pub fn freeze(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        try_ready!(self.storage.add_frozen(address));
+        if self.decline_cooldown.is_some() {
+            self.frozen_at.insert(address.to_owned(), Instant::now());
         }
-        Ok(false)
+        Ok(Async::Ready(()))
     }
 
-    fn is_address_allocated_by(&self, address: &Ipv4Addr, cid: &[u8]) -> Result<bool, Error> {
-        if let Some(client_id) = self.storage.get_client(address)? {
-            if cid.to_vec() == client_id {
-                if let Some(lease) = self.storage.get_lease(&client_id)? {
-                    return Ok(lease.is_allocated());
+    /// Unfreezes the addresses whose decline cooldown has elapsed.
+    ///
+    /// The addresses frozen before the start are found on the first call
+    /// and their cooldown is started anew, since the freeze times are not stored.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    fn thaw(&mut self) -> Poll<(), Error> {
+        let decline_cooldown = match self.decline_cooldown {
+            Some(decline_cooldown) => decline_cooldown,
+            None => return Ok(Async::Ready(())),
+        };
+
+        if !self.is_frozen_scanned {
+            let now = Instant::now();
+            for address in self.managed_addresses() {
+                if try_ready!(self.storage.check_frozen(&address)) {
+                    self.frozen_at.entry(address).or_insert(now);
                 }
             }
+            self.is_frozen_scanned = true;
         }
-        Ok(false)
+
+        let now = Instant::now();
+        let thawed: Vec<Ipv4Addr> = self
+            .frozen_at
+            .iter()
+            .filter(|&(_, &frozen_at)| now.duration_since(frozen_at) >= decline_cooldown)
+            .map(|(&address, _)| address)
+            .collect();
+        for address in thawed {
+            try_ready!(self.storage.delete_frozen(&address));
+            self.frozen_at.remove(&address);
+            trace!("The address {} is not frozen anymore", address);
+        }
+        Ok(Async::Ready(()))
     }
 
-    fn is_address_frozen(&self, address: &Ipv4Addr) -> Result<bool, Error> {
-        Ok(self.storage.check_frozen(address)?)
+    /// Records the hostname the client has been named with in its lease.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    pub fn name(&mut self, client_id: &[u8], hostname: Option<String>) -> Poll<(), Error> {
+        try_ready!(self
+            .storage
+            .update_lease(client_id, &mut |lease: &mut Lease| {
+                lease.set_hostname(hostname.to_owned())
+            }));
+        Ok(Async::Ready(()))
     }
 
-    fn get_dynamic_available(&self) -> Result<Option<Ipv4Addr>, Error> {
-        for address in
-            u32::from(self.dynamic_address_range.0)..=u32::from(self.dynamic_address_range.1)
-        {
-            let address = Ipv4Addr::from(address);
-            if self.is_address_available(&address)? {
-                return Ok(Some(address));
-            }
-        }
-        Ok(None)
+    /// Returns the lease of the client if it exists.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    #[allow(dead_code)]
+    pub fn lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error> {
+        Ok(self.storage.get_lease(client_id)?)
     }
 
-    fn is_address_in_static_pool(&self, address: &Ipv4Addr) -> bool {
-        self.static_address_range.0 <= *address && *address <= self.static_address_range.1
+    /// Checks the address of a client in the `INIT-REBOOT` state.
+    ///
+    /// The active lease is extended like on renewal, so the granted times are counted
+    /// from now and the requested lease time is clamped to the bounds.
+    ///
+    /// # Errors
+    /// `self::Error` on internal storage error.
+    /// `self::Error` if the address is not leased to the client.
+    pub fn check(
+        &mut self,
+        client_id: &[u8],
+        address: &Ipv4Addr,
+        lease_time: Option<u32>,
+    ) -> Poll<Ack, Error> {
+        match try_ready!(self.storage.get_lease(client_id)) {
+            Some(ref lease)
+                if lease.address() == *address
+                    && !lease.is_expired()
+                    && !lease.is_released()
+                    && self.is_address_in_pools(address) => {}
+            Some(_) => return Err(try_ready!(self.lease_error(client_id, address))),
+            None => return Err(Error::LeaseNotFound),
+        }
+
+        let mut ack = try_ready!(self.renew(client_id, address, lease_time));
+        ack.message = "Your lease is active".to_owned();
+        Ok(Async::Ready(ack))
     }
 
-    fn is_address_in_dynamic_pool(&self, address: &Ipv4Addr) -> bool {
-        self.dynamic_address_range.0 <= *address && *address <= self.dynamic_address_range.1
+    /// Explains why the existing lease of the client is not valid for the address.
+    fn lease_error(&mut self, client_id: &[u8], address: &Ipv4Addr) -> Poll<Error, Error> {
+        if !self.is_address_in_pools(address) {
+            return Ok(Async::Ready(Error::AddressOutOfRange(*address)));
+        }
+        if try_ready!(self.is_address_allocated(address))
+            && !try_ready!(self.is_address_allocated_by(address, client_id))
+        {
+            return Ok(Async::Ready(Error::AddressInUse(*address)));
+        }
+        Ok(Async::Ready(Error::LeaseInvalid))
     }
-}
+
+    /// Allocates the address reserved for the client.
+    ///
+    /// The reserved lease time replaces the requested one if set.
+    fn allocate_reserved(
+        &mut self,
+        client_id: &[u8],
+        lease_time: Option<u32>,
+        reservation: Reservation,
+    ) -> Poll<Offer, Error> {
+        let address = reservation.address;
+        if try_ready!(self.is_address_frozen(&address))
+            || (try_ready!(self.is_address_allocated(&address))
+                && !try_ready!(self.is_address_allocated_by(&address, client_id)))
+        {
+            return Err(Error::AddressInUse(address));
+        }
+
+        let (lease_time, reuse_lease_time) = match reservation.lease_time {
+            Some(lease_time) => (lease_time, false),
+            None => (
+                self.bounded_lease_time(lease_time),
+                lease_time.is_none()
+                    && try_ready!(self.is_address_allocated_by(&address, client_id)),
+            ),
+        };
+        let lease_time = try_ready!(self.offer(&address, client_id, lease_time, reuse_lease_time));
+        let offer = Offer {
+            address,
+            lease_time,
+            message: "Offering the reserved address".to_owned(),
+        };
+        trace!(
+            "Offering to the client {:?} the reserved address {}",
+            client_id,
+            offer.address
+        );
+        Ok(Async::Ready(offer))
+    }
+
+    /// The requested lease time clamped to the bounds or the default one if not requested.
+    fn bounded_lease_time(&self, lease_time: Option<u32>) -> u32 {
+        cmp::min(
+            cmp::max(
+                lease_time.unwrap_or(self.default_lease_time),
+                self.min_lease_time,
+            ),
+            self.max_lease_time,
+        )
+    }
+
+    fn offer(
+        &mut self,
+        address: &Ipv4Addr,
+        client_id: &[u8],
+        lease_time: u32,
+        reuse_lease_time: bool,
+    ) -> Poll<u32, Error> {
+        try_ready!(self.storage.add_client(address, client_id));
+
+        let mut lease_time = lease_time;
+        if reuse_lease_time {
+            try_ready!(self.storage.get_lease(client_id)).map(|lease| {
+                if lease.is_active() {
+                    lease_time = lease.expires_after();
+                }
+            });
+        }
+
+        try_ready!(self
+            .storage
+            .add_lease(client_id, Lease::new(address.to_owned(), lease_time)));
+        Ok(Async::Ready(lease_time))
+    }
+
+    fn client_current_address(&mut self, client_id: &[u8]) -> Poll<Option<Ipv4Addr>, Error> {
+        if let Some(lease) = try_ready!(self.storage.get_lease(client_id)) {
+            if lease.is_allocated() {
+                return Ok(Async::Ready(Some(lease.address())));
+            }
+        }
+        Ok(Async::Ready(None))
+    }
+
+    fn client_last_address(&mut self, client_id: &[u8]) -> Poll<Option<Ipv4Addr>, Error> {
+        if let Some(lease) = try_ready!(self.storage.get_lease(client_id)) {
+            return Ok(Async::Ready(Some(lease.address())));
+        }
+        Ok(Async::Ready(None))
+    }
+
+    fn is_address_available(
+        &mut self,
+        address: &Ipv4Addr,
+        subnet: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> Poll<bool, Error> {
+        let is_available = !try_ready!(self.is_address_allocated(address))
+            && !try_ready!(self.is_address_frozen(address))
+            && self.is_address_in_subnet_pools(address, subnet)
+            && !self.is_address_excluded(address)
+            && !self.is_address_reserved(address);
+        Ok(Async::Ready(is_available))
+    }
+
+    fn is_guest_address_available(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        let is_available = !try_ready!(self.is_address_allocated(address))
+            && !try_ready!(self.is_address_frozen(address))
+            && self.is_address_in_guest_pool(address)
+            && !self.is_address_excluded(address)
+            && !self.is_address_reserved(address);
+        Ok(Async::Ready(is_available))
+    }
+
+    fn is_address_allocated(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        if let Some(client_id) = try_ready!(self.storage.get_client(address)) {
+            if let Some(lease) = try_ready!(self.storage.get_lease(&client_id)) {
+                return Ok(Async::Ready(lease.is_allocated()));
+            }
+        }
+        Ok(Async::Ready(false))
+    }
+
+    fn is_address_allocated_by(&mut self, address: &Ipv4Addr, cid: &[u8]) -> Poll<bool, Error> {
+        if let Some(client_id) = try_ready!(self.storage.get_client(address)) {
+            if cid.to_vec() == client_id {
+                if let Some(lease) = try_ready!(self.storage.get_lease(&client_id)) {
+                    return Ok(Async::Ready(lease.is_allocated()));
+                }
+            }
+        }
+        Ok(Async::Ready(false))
+    }
+
+    fn is_address_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        Ok(self.storage.check_frozen(address)?)
+    }
+
+    fn get_dynamic_available(
+        &mut self,
+        client_id: &[u8],
+        subnet: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> Poll<Option<Ipv4Addr>, Error> {
+        let (first, last) = subnet.unwrap_or(self.dynamic_address_range);
+        let first = u32::from(first);
+        let last = u32::from(last);
+        if first > last {
+            return Ok(Async::Ready(None));
+        }
+        let size = u64::from(last - first) + 1;
+        let start = if self.deterministic_allocation {
+            let key = match self.client_hardware_address {
+                Some((ref id, ref client_hardware_address)) if id.as_slice() == client_id => {
+                    client_hardware_address.as_bytes()
+                }
+                _ => client_id,
+            };
+            u64::from(Self::hash(key)) % size
+        } else {
+            0
+        };
+
+        for offset in 0..size {
+            let address = Ipv4Addr::from(first + ((start + offset) % size) as u32);
+            if try_ready!(self.is_address_available(&address, subnet)) {
+                return Ok(Async::Ready(Some(address)));
+            }
+        }
+        Ok(Async::Ready(None))
+    }
+
+    /// The FNV-1a hash, which is stable across builds and platforms.
+    fn hash(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    /// Whether the address belongs to any pool and is not excluded, or is reserved.
+    pub fn is_address_in_pools(&self, address: &Ipv4Addr) -> bool {
+        ((self.is_address_in_static_pool(address) || self.is_address_in_dynamic_pool(address)
+            || self.is_address_in_guest_pool(address)
+            || self.is_address_in_relayed_pool(address))
+            && !self.is_address_excluded(address))
+            || self.is_address_reserved(address)
+    }
+
+    /// Whether the address may be allocated in the subnet with the dynamic range
+    /// or in the direct one if `None`.
+    fn is_address_in_subnet_pools(
+        &self,
+        address: &Ipv4Addr,
+        subnet: Option<(Ipv4Addr, Ipv4Addr)>,
+    ) -> bool {
+        match subnet {
+            Some((first, last)) => first <= *address && *address <= last,
+            None => {
+                (self.is_address_in_static_pool(address)
+                    || self.is_address_in_dynamic_pool(address))
+                    && !self.is_address_in_guest_pool(address)
+                    && !self.is_address_in_relayed_pool(address)
+            }
+        }
+    }
+
+    fn is_address_reserved(&self, address: &Ipv4Addr) -> bool {
+        self.reservations
+            .values()
+            .chain(self.hardware_reservations.values())
+            .any(|reservation| reservation.address == *address)
+    }
+
+    fn is_address_excluded(&self, address: &Ipv4Addr) -> bool {
+        self.excluded_address_ranges
+            .iter()
+            .any(|&(first, last)| first <= *address && *address <= last)
+    }
+
+    fn is_address_in_static_pool(&self, address: &Ipv4Addr) -> bool {
+        self.static_address_range.0 <= *address && *address <= self.static_address_range.1
+    }
+
+    fn is_address_in_dynamic_pool(&self, address: &Ipv4Addr) -> bool {
+        self.dynamic_address_range.0 <= *address && *address <= self.dynamic_address_range.1
+    }
+
+    fn is_address_in_relayed_pool(&self, address: &Ipv4Addr) -> bool {
+        self.subnet_address_ranges
+            .iter()
+            .any(|&(first, last)| first <= *address && *address <= last)
+    }
+
+    fn is_address_in_guest_pool(&self, address: &Ipv4Addr) -> bool {
+        match self.guest_address_range {
+            Some((first, last)) => first <= *address && *address <= last,
+            None => false,
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use storage::Ready;
     use storage_ram::RamStorage;
 
+    fn config(
+        static_address_range: (Ipv4Addr, Ipv4Addr),
+        dynamic_address_range: (Ipv4Addr, Ipv4Addr),
+    ) -> ServerConfig {
+        ServerConfig {
+            static_address_range,
+            dynamic_address_range,
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            routers: vec![],
+            domain_name_servers: vec![],
+            static_routes: vec![],
+            classless_static_routes: vec![],
+            excluded_address_ranges: vec![],
+            reservations: vec![],
+            hardware_reservations: vec![],
+        }
+    }
+
+    #[test]
+    fn walks_merged_address_ranges() {
+        let mut addresses = Addresses::new(vec![(10, 12), (2, 3), (11, 15), (4, 4), (20, 19)]);
+        assert_eq!(addresses.next(), Some(Ipv4Addr::from(2)));
+        addresses.extend(Addresses::new(vec![
+            (1, 1),
+            (16, 16),
+            (u32::max_value(), u32::max_value()),
+        ]));
+
+        let walked: Vec<u32> = addresses.map(u32::from).collect();
+        assert_eq!(
+            walked,
+            vec![1, 3, 4, 10, 11, 12, 13, 14, 15, 16, u32::max_value()]
+        );
+    }
+
     #[test]
     fn reuses_client_current_address() {
         let mut storage = Database::new(
@@ -471,9 +1373,11 @@ mod tests {
                 Some(1000),
                 Some(Ipv4Addr::new(192, 168, 0, 11)),
             )
+            .ready()
             .unwrap();
         let ack1 = storage
             .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+            .ready()
             .unwrap();
 
         let offer2 = storage
@@ -482,9 +1386,11 @@ mod tests {
                 Some(1000),
                 Some(Ipv4Addr::new(192, 168, 0, 12)),
             )
+            .ready()
             .unwrap();
         let ack2 = storage
             .assign(client_id.as_ref(), &offer2.address, Some(offer2.lease_time))
+            .ready()
             .unwrap();
 
         assert_eq!(ack1.address, ack2.address);
@@ -507,12 +1413,15 @@ mod tests {
 
         let offer1 = storage
             .allocate(client_id.as_ref(), Some(1000), None)
+            .ready()
             .unwrap();
         let ack1 = storage
             .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+            .ready()
             .unwrap();
         storage
             .deallocate(client_id.as_ref(), &ack1.address)
+            .ready()
             .unwrap();
 
         let offer2 = storage
@@ -521,9 +1430,11 @@ mod tests {
                 Some(1000),
                 Some(Ipv4Addr::new(192, 168, 0, 166)),
             )
+            .ready()
             .unwrap();
         let ack2 = storage
             .assign(client_id.as_ref(), &offer2.address, Some(offer2.lease_time))
+            .ready()
             .unwrap();
 
         assert_eq!(ack1.address, ack2.address);
@@ -549,16 +1460,20 @@ mod tests {
 
         let offer1 = storage
             .allocate(client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let ack1 = storage
             .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+            .ready()
             .unwrap();
         storage
             .deallocate(client_id.as_ref(), &ack1.address)
+            .ready()
             .unwrap();
 
         let offer2 = storage
             .allocate(another_client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let ack2 = storage
             .assign(
@@ -566,6 +1481,7 @@ mod tests {
                 &offer2.address,
                 Some(offer2.lease_time),
             )
+            .ready()
             .unwrap();
 
         assert_eq!(ack1.address, ack2.address);
@@ -592,16 +1508,20 @@ mod tests {
 
         let offer1 = storage
             .allocate(client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let ack1 = storage
             .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+            .ready()
             .unwrap();
         storage
             .deallocate(client_id.as_ref(), &ack1.address)
+            .ready()
             .unwrap();
 
         let offer2 = storage
             .allocate(another_client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let _ack2 = storage
             .assign(
@@ -609,13 +1529,16 @@ mod tests {
                 &offer2.address,
                 Some(offer2.lease_time),
             )
+            .ready()
             .unwrap();
 
         let offer3 = storage
             .allocate(client_id.as_ref(), Some(1000), Some(requested))
+            .ready()
             .unwrap();
         let ack3 = storage
             .assign(client_id.as_ref(), &offer3.address, Some(offer3.lease_time))
+            .ready()
             .unwrap();
 
         assert_eq!(ack3.address, requested);
@@ -643,16 +1566,20 @@ mod tests {
 
         let offer1 = storage
             .allocate(client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let ack1 = storage
             .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+            .ready()
             .unwrap();
         storage
             .deallocate(client_id.as_ref(), &ack1.address)
+            .ready()
             .unwrap();
 
         let offer2 = storage
             .allocate(another_client_id.as_ref(), Some(1000), Some(current))
+            .ready()
             .unwrap();
         let _ack2 = storage
             .assign(
@@ -660,10 +1587,12 @@ mod tests {
                 &offer2.address,
                 Some(offer2.lease_time),
             )
+            .ready()
             .unwrap();
 
         let offer3 = storage
             .allocate(yet_another_client_id.as_ref(), Some(1000), Some(requested))
+            .ready()
             .unwrap();
         let _ack3 = storage
             .assign(
@@ -671,15 +1600,550 @@ mod tests {
                 &offer3.address,
                 Some(offer3.lease_time),
             )
+            .ready()
             .unwrap();
 
         let offer4 = storage
             .allocate(client_id.as_ref(), Some(1000), Some(requested))
+            .ready()
             .unwrap();
         let ack4 = storage
             .assign(client_id.as_ref(), &offer4.address, Some(offer4.lease_time))
+            .ready()
             .unwrap();
 
         assert_ne!(ack4.address, requested);
     }
+
+    #[test]
+    fn reclaims_leases_outside_reconfigured_pools() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 101),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 200),
+            ),
+            RamStorage::new(),
+        );
+        let client_id = vec![1u8];
+        let another_client_id = vec![2u8];
+
+        let kept = Ipv4Addr::new(192, 168, 0, 66);
+        let dropped = Ipv4Addr::new(192, 168, 0, 166);
+
+        for &(ref client_id, address) in &[(&client_id, kept), (&another_client_id, dropped)] {
+            let offer = storage
+                .allocate(client_id.as_ref(), Some(1000), Some(address))
+                .ready()
+                .unwrap();
+            storage
+                .assign(client_id.as_ref(), &offer.address, Some(offer.lease_time))
+                .ready()
+                .unwrap();
+        }
+
+        storage.reconfigure(&config(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 101),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 201),
+                Ipv4Addr::new(192, 168, 0, 250),
+            ),
+        ));
+        let reclaimed = storage.reclaim().ready().unwrap();
+
+        assert_eq!(reclaimed, vec![(another_client_id.to_owned(), dropped)]);
+        assert!(storage
+            .storage
+            .get_client(&dropped)
+            .ready()
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .renew(client_id.as_ref(), &kept, Some(1000))
+            .ready()
+            .is_ok());
+        match storage
+            .renew(another_client_id.as_ref(), &dropped, Some(1000))
+            .ready()
+        {
+            Err(Error::AddressOutOfRange(address)) => assert_eq!(address, dropped),
+            _ => panic!("The lease outside the pools must be invalid"),
+        }
+        let offer = storage
+            .allocate(another_client_id.as_ref(), Some(1000), None)
+            .ready()
+            .unwrap();
+        assert_eq!(offer.address, Ipv4Addr::new(192, 168, 0, 201));
+    }
+
+    #[test]
+    fn reconciles_exclusions_and_reservations_on_reconfiguration() {
+        let static_address_range = (
+            Ipv4Addr::new(192, 168, 0, 2),
+            Ipv4Addr::new(192, 168, 0, 100),
+        );
+        let dynamic_address_range = (
+            Ipv4Addr::new(192, 168, 0, 101),
+            Ipv4Addr::new(192, 168, 0, 105),
+        );
+        let mut storage = Database::new(
+            static_address_range,
+            dynamic_address_range,
+            RamStorage::new(),
+        );
+        storage.reserve(vec![0xff], Ipv4Addr::new(192, 168, 0, 50), None);
+
+        let excluded = Ipv4Addr::new(192, 168, 0, 101);
+        let reserved = Ipv4Addr::new(192, 168, 0, 102);
+        let kept = Ipv4Addr::new(192, 168, 0, 103);
+        for &(client_id, address) in &[(1u8, excluded), (2u8, reserved), (3u8, kept)] {
+            let offer = storage
+                .allocate(&[client_id], None, Some(address))
+                .ready()
+                .unwrap();
+            assert_eq!(offer.address, address);
+            storage
+                .assign(&[client_id], &address, None)
+                .ready()
+                .unwrap();
+        }
+
+        let mut config = config(static_address_range, dynamic_address_range);
+        config.excluded_address_ranges = vec![(excluded, excluded)];
+        config.reservations = vec![(vec![0xfe], reserved, None)];
+        storage.reconfigure(&config);
+        let reclaimed = storage.reclaim().ready().unwrap();
+
+        assert_eq!(reclaimed, vec![(vec![1u8], excluded), (vec![2u8], reserved)]);
+        assert!(storage.renew(&[3u8], &kept, None).ready().is_ok());
+        assert!(!storage.is_reserved(&[0xff]));
+        let offer = storage.allocate(&[0xfe], None, None).ready().unwrap();
+        assert_eq!(offer.address, reserved);
+    }
+
+    #[test]
+    fn derives_addresses_from_client_hardware_addresses() {
+        let database = || {
+            let mut database = Database::new(
+                (
+                    Ipv4Addr::new(192, 168, 0, 2),
+                    Ipv4Addr::new(192, 168, 0, 9),
+                ),
+                (
+                    Ipv4Addr::new(192, 168, 0, 10),
+                    Ipv4Addr::new(192, 168, 0, 13),
+                ),
+                RamStorage::new(),
+            );
+            database.set_deterministic_allocation(true);
+            database
+        };
+        let client_id = vec![1u8];
+        let client_hardware_address = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]);
+        let slot = |mac: &MacAddress| Database::<RamStorage>::hash(mac.as_bytes()) % 4;
+        let colliding_client_id = vec![2u8];
+        let colliding_client_hardware_address = (0u8..)
+            .map(|i| MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0f, i]))
+            .find(|mac| slot(mac) == slot(&client_hardware_address))
+            .unwrap();
+
+        let mut addresses = Vec::new();
+        for _ in 0..2 {
+            // a fresh database imitates a storage loss
+            let mut storage = database();
+            storage.adopt_client_hardware_address(&client_id, &client_hardware_address);
+            let offer1 = storage
+                .allocate(
+                    client_id.as_ref(),
+                    Some(1000),
+                    Some(Ipv4Addr::new(192, 168, 0, 13)),
+                )
+                .ready()
+                .unwrap();
+            storage
+                .assign(client_id.as_ref(), &offer1.address, Some(offer1.lease_time))
+                .ready()
+                .unwrap();
+            storage.adopt_client_hardware_address(
+                &colliding_client_id,
+                &colliding_client_hardware_address,
+            );
+            let offer2 = storage
+                .allocate(colliding_client_id.as_ref(), Some(1000), None)
+                .ready()
+                .unwrap();
+            addresses.push((offer1.address, offer2.address));
+        }
+
+        let expected = u32::from(Ipv4Addr::new(192, 168, 0, 10)) + slot(&client_hardware_address);
+        assert_eq!(addresses[0].0, Ipv4Addr::from(expected));
+        assert_ne!(addresses[0].0, addresses[0].1);
+        assert_eq!(addresses[0], addresses[1]);
+    }
+
+    #[test]
+    fn sweeps_expired_leases() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 101),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 200),
+            ),
+            RamStorage::new(),
+        );
+        let expired_client_id = vec![1u8];
+        let active_client_id = vec![2u8];
+
+        let mut addresses = Vec::new();
+        for client_id in &[&expired_client_id, &active_client_id] {
+            let offer = storage
+                .allocate(client_id.as_ref(), Some(1000), None)
+                .ready()
+                .unwrap();
+            storage
+                .assign(client_id.as_ref(), &offer.address, Some(offer.lease_time))
+                .ready()
+                .unwrap();
+            addresses.push(offer.address);
+        }
+        storage
+            .storage
+            .update_lease(&expired_client_id, &mut |lease: &mut Lease| lease.assign(0))
+            .ready()
+            .unwrap();
+
+        assert_eq!(
+            storage.sweep().ready().unwrap(),
+            vec![(expired_client_id.to_owned(), addresses[0])]
+        );
+        assert_eq!(
+            storage.storage.get_client(&addresses[0]).ready().unwrap(),
+            None
+        );
+        assert!(storage
+            .lease(&expired_client_id)
+            .ready()
+            .unwrap()
+            .unwrap()
+            .is_released());
+        assert_eq!(
+            storage.storage.get_client(&addresses[1]).ready().unwrap(),
+            Some(active_client_id)
+        );
+        assert!(storage.sweep().ready().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exports_isc_leases() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 99),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 100),
+                Ipv4Addr::new(192, 168, 0, 199),
+            ),
+            RamStorage::new(),
+        );
+        let records = [
+            (
+                vec![0x01, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37],
+                "192.168.0.100 assigned 86400 1893456000 1893456000 0 0 1893542400 686f7374",
+            ),
+            (
+                vec![0x00, 0x0c, 0x29, 0x13, 0x0e, 0x38],
+                "192.168.0.101 released 3600 1538560800 1538560800 1538564400 1538568000 1538568000 -",
+            ),
+        ];
+        for &(ref client_id, record) in records.iter() {
+            let lease = Lease::from_record(record).unwrap();
+            storage
+                .storage
+                .add_client(&lease.address(), client_id)
+                .ready()
+                .unwrap();
+            storage.storage.add_lease(client_id, lease).ready().unwrap();
+        }
+        storage
+            .freeze(&Ipv4Addr::new(192, 168, 0, 102))
+            .ready()
+            .unwrap();
+
+        let expected = "\
+lease 192.168.0.100 {
+  starts 2 2030/01/01 00:00:00;
+  ends 3 2030/01/02 00:00:00;
+  binding state active;
+  hardware ethernet 00:0c:29:13:0e:37;
+  uid 01:00:0c:29:13:0e:37;
+  client-hostname \"host\";
+}
+lease 192.168.0.101 {
+  starts 3 2018/10/03 11:00:00;
+  ends 3 2018/10/03 12:00:00;
+  binding state released;
+  hardware ethernet 00:0c:29:13:0e:38;
+  uid 00:0c:29:13:0e:38;
+}
+lease 192.168.0.102 {
+  binding state abandoned;
+}
+";
+        assert_eq!(storage.export_isc().ready().unwrap(), expected);
+    }
+
+    #[test]
+    fn clamps_lease_time_to_bounds() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 101),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 200),
+            ),
+            RamStorage::new(),
+        );
+        storage.set_lease_bounds(600, 7200, 3600);
+
+        // too short, too long and absent
+        let cases = [(Some(60), 600), (Some(86400), 7200), (None, 3600)];
+        for (i, &(requested, granted)) in cases.iter().enumerate() {
+            let client_id = vec![i as u8];
+            let offer = storage
+                .allocate(&client_id, requested, None)
+                .ready()
+                .unwrap();
+            assert_eq!(offer.lease_time, granted);
+
+            let ack = storage
+                .assign(&client_id, &offer.address, requested)
+                .ready()
+                .unwrap();
+            assert_eq!(ack.lease_time, granted);
+            assert_eq!(ack.renewal_time, granted / 2);
+            assert_eq!(ack.rebinding_time, granted / 8 * 7);
+
+            let ack = storage
+                .renew(&client_id, &offer.address, requested)
+                .ready()
+                .unwrap();
+            assert_eq!(ack.lease_time, granted);
+        }
+    }
+
+    #[test]
+    fn thaws_declined_address_after_cooldown() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 100),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 102),
+            ),
+            RamStorage::new(),
+        );
+        let decline_cooldown = Duration::from_secs(60);
+        storage.set_decline_cooldown(Some(decline_cooldown));
+        let declined = Ipv4Addr::new(192, 168, 0, 101);
+        storage.freeze(&declined).ready().unwrap();
+
+        let offer = storage
+            .allocate(&[1u8], None, Some(declined))
+            .ready()
+            .unwrap();
+        assert_ne!(offer.address, declined);
+
+        // the clock is advanced by moving the freeze time back
+        *storage.frozen_at.get_mut(&declined).unwrap() -= decline_cooldown;
+        let offer = storage
+            .allocate(&[2u8], None, Some(declined))
+            .ready()
+            .unwrap();
+        assert_eq!(offer.address, declined);
+        assert!(!storage.storage.check_frozen(&declined).ready().unwrap());
+    }
+
+    #[test]
+    fn never_allocates_excluded_addresses() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 100),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 105),
+            ),
+            RamStorage::new(),
+        );
+        let decline_cooldown = Duration::from_secs(60);
+        storage.set_decline_cooldown(Some(decline_cooldown));
+        storage.exclude((
+            Ipv4Addr::new(192, 168, 0, 102),
+            Ipv4Addr::new(192, 168, 0, 104),
+        ));
+        let printer = Ipv4Addr::new(192, 168, 0, 103);
+        storage.reserve(vec![0xff], printer, None);
+        let declined = Ipv4Addr::new(192, 168, 0, 102);
+        storage.freeze(&declined).ready().unwrap();
+        *storage.frozen_at.get_mut(&declined).unwrap() -= decline_cooldown;
+
+        let first = storage
+            .allocate(&[1u8], None, Some(declined))
+            .ready()
+            .unwrap();
+        let second = storage
+            .allocate(&[2u8], None, Some(printer))
+            .ready()
+            .unwrap();
+        assert_eq!(first.address, Ipv4Addr::new(192, 168, 0, 101));
+        assert_eq!(second.address, Ipv4Addr::new(192, 168, 0, 105));
+        match storage.allocate(&[3u8], None, None).ready() {
+            Err(Error::PoolExhausted) => {}
+            _ => panic!("An excluded address has been allocated"),
+        }
+        assert!(!storage.storage.check_frozen(&declined).ready().unwrap());
+
+        let offer = storage.allocate(&[0xff], None, None).ready().unwrap();
+        assert_eq!(offer.address, printer);
+        storage.assign(&[0xff], &printer, None).ready().unwrap();
+        assert!(storage.renew(&[0xff], &printer, None).ready().is_ok());
+    }
+
+    #[test]
+    fn explains_allocation_failures() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 100),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 102),
+            ),
+            RamStorage::new(),
+        );
+        let foreign = Ipv4Addr::new(10, 0, 0, 1);
+        let first = storage.allocate(&[1u8], None, None).ready().unwrap();
+        storage
+            .assign(&[1u8], &first.address, None)
+            .ready()
+            .unwrap();
+        let second = storage.allocate(&[2u8], None, None).ready().unwrap();
+        storage
+            .assign(&[2u8], &second.address, None)
+            .ready()
+            .unwrap();
+        // the address has been leased before it is reserved
+        let reserved = second.address;
+        storage.reserve(vec![0xff], reserved, None);
+
+        match storage.allocate(&[3u8], None, None).ready() {
+            Err(Error::PoolExhausted) => {}
+            _ => panic!("The pool must be exhausted"),
+        }
+        match storage.allocate(&[0xff], None, None).ready() {
+            Err(Error::AddressInUse(address)) => assert_eq!(address, reserved),
+            _ => panic!("The reserved address must be in use"),
+        }
+        match storage.check(&[1u8], &foreign, None).ready() {
+            Err(Error::AddressOutOfRange(address)) => assert_eq!(address, foreign),
+            _ => panic!("The foreign address must be out of range"),
+        }
+        match storage.check(&[1u8], &second.address, None).ready() {
+            Err(Error::AddressInUse(address)) => assert_eq!(address, reserved),
+            _ => panic!("The address of another client must be in use"),
+        }
+        storage.deallocate(&[1u8], &first.address).ready().unwrap();
+        match storage.check(&[1u8], &first.address, None).ready() {
+            Err(Error::LeaseInvalid) => {}
+            _ => panic!("The released lease must be invalid"),
+        }
+    }
+
+    /// A storage failing every operation, like one with a lost database connection.
+    struct BrokenStorage;
+
+    impl Storage for BrokenStorage {
+        fn get_client(&mut self, _address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, storage::Error> {
+            Err(storage::Error::GetClient("Connection lost".to_owned()))
+        }
+
+        fn add_client(
+            &mut self,
+            _address: &Ipv4Addr,
+            _client_id: &[u8],
+        ) -> Poll<(), storage::Error> {
+            Err(storage::Error::AddClient("Connection lost".to_owned()))
+        }
+
+        fn delete_client(&mut self, _address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            Err(storage::Error::DeleteClient("Connection lost".to_owned()))
+        }
+
+        fn get_lease(&mut self, _client_id: &[u8]) -> Poll<Option<Lease>, storage::Error> {
+            Err(storage::Error::GetLease("Connection lost".to_owned()))
+        }
+
+        fn add_lease(&mut self, _client_id: &[u8], _lease: Lease) -> Poll<(), storage::Error> {
+            Err(storage::Error::AddLease("Connection lost".to_owned()))
+        }
+
+        fn update_lease(
+            &mut self,
+            _client_id: &[u8],
+            _action: &mut FnMut(&mut Lease) -> (),
+        ) -> Poll<(), storage::Error> {
+            Err(storage::Error::UpdateLease("Connection lost".to_owned()))
+        }
+
+        fn check_frozen(&mut self, _address: &Ipv4Addr) -> Poll<bool, storage::Error> {
+            Err(storage::Error::CheckFrozen("Connection lost".to_owned()))
+        }
+
+        fn add_frozen(&mut self, _address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            Err(storage::Error::AddFrozen("Connection lost".to_owned()))
+        }
+
+        fn delete_frozen(&mut self, _address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            Err(storage::Error::DeleteFrozen("Connection lost".to_owned()))
+        }
+    }
+
+    #[test]
+    fn explains_storage_failures() {
+        let mut storage = Database::new(
+            (
+                Ipv4Addr::new(192, 168, 0, 2),
+                Ipv4Addr::new(192, 168, 0, 100),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 101),
+                Ipv4Addr::new(192, 168, 0, 200),
+            ),
+            BrokenStorage,
+        );
+
+        match storage.allocate(&[1u8], None, None).ready() {
+            Err(error @ Error::Storage(_)) => assert_eq!(
+                error.to_string(),
+                "Storage error: Lease getting error: Connection lost"
+            ),
+            _ => panic!("The storage error must be reported"),
+        }
+    }
 }
@@ -0,0 +1,74 @@
+//! The interface-scoped server socket.
+//!
+//! A socket bound to `0.0.0.0:67` receives the requests from every interface
+//! and the kernel routes the responses by the destination address only,
+//! so a server on a multi-homed host may answer on the wrong interface.
+//! `SO_BINDTODEVICE` scopes both directions to a single interface.
+
+use std::{io, mem, net, net::SocketAddrV4, os::unix::io::FromRawFd};
+
+use libc;
+
+/// Binds a UDP socket to `addr` receiving and sending only via the `iface_name` interface.
+///
+/// `SO_REUSEADDR` is set, so a server may be run per interface on the same port.
+///
+/// # Errors
+/// `io::Error` if the interface does not exist or the process lacks the privileges.
+pub fn bind_to_device(addr: &SocketAddrV4, iface_name: &str) -> io::Result<net::UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the socket is closed on the early returns
+    let socket = unsafe { net::UdpSocket::from_raw_fd(fd) };
+
+    let enable: libc::c_int = 1;
+    set_option(
+        fd,
+        libc::SO_REUSEADDR,
+        &enable as *const _ as *const libc::c_void,
+        mem::size_of::<libc::c_int>(),
+    )?;
+    set_option(
+        fd,
+        libc::SO_BINDTODEVICE,
+        iface_name.as_ptr() as *const libc::c_void,
+        iface_name.len(),
+    )?;
+
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    let result = unsafe {
+        libc::bind(
+            fd,
+            &sockaddr as *const _ as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+fn set_option(
+    fd: libc::c_int,
+    option: libc::c_int,
+    value: *const libc::c_void,
+    size: usize,
+) -> io::Result<()> {
+    let result =
+        unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, option, value, size as libc::socklen_t) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! The DHCP server error module.
+
+use std::io;
+
+use storage;
+
+/// Errors terminating the server future.
+///
+/// The errors related to a single request are only logged, so the server keeps working.
+#[derive(Fail, Debug)]
+pub enum ServerError {
+    #[fail(display = "Socket error: {}", _0)]
+    Socket(#[cause] io::Error),
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(#[cause] storage::Error),
+    #[fail(display = "BPF error: {}", _0)]
+    Bpf(#[cause] io::Error),
+}
+
+impl From<io::Error> for ServerError {
+    fn from(error: io::Error) -> Self {
+        ServerError::Socket(error)
+    }
+}
+
+impl From<ServerError> for io::Error {
+    #[allow(clippy::io_other_error)]
+    fn from(error: ServerError) -> Self {
+        match error {
+            ServerError::Socket(error) | ServerError::Bpf(error) => error,
+            error => io::Error::new(io::ErrorKind::Other, error.to_string()),
+        }
+    }
+}
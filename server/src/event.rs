@@ -0,0 +1,41 @@
+//! The lease events reported to monitoring and DDNS integrations.
+
+use std::net::Ipv4Addr;
+
+use eui48::MacAddress;
+
+/// Handles the lease events of the server.
+///
+/// Is called synchronously from the server future, so it must not block.
+pub type LeaseEventHandler = Box<FnMut(LeaseEvent) + Send>;
+
+/// What has happened to a lease.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseEventKind {
+    /// The address has been offered with `DHCPOFFER`.
+    Offered,
+    /// The address has been assigned or confirmed with `DHCPACK`.
+    Acked,
+    /// The lease has been extended by a renewing or rebinding client.
+    Renewed,
+    /// The client has released the address with `DHCPRELEASE`.
+    Released,
+    /// The client has declined the address with `DHCPDECLINE`.
+    Declined,
+    /// The lease has expired and its address has been deallocated by sweeping.
+    Expired,
+    /// The address has left the pools on reconfiguration and has been deallocated.
+    Reclaimed,
+}
+
+/// A lease event reported to the `LeaseEventHandler` if it is set.
+#[derive(Debug, Clone)]
+pub struct LeaseEvent {
+    pub kind: LeaseEventKind,
+    pub client_id: Vec<u8>,
+    /// Is unknown for the expired and reclaimed leases.
+    pub client_hardware_address: Option<MacAddress>,
+    pub address: Ipv4Addr,
+    /// Is only known for the offered, acknowledged and renewed leases.
+    pub lease_time: Option<u32>,
+}
@@ -1,8 +1,11 @@
 //! Address lease implementation.
 
-use std::net::Ipv4Addr;
+use std::{cmp, net::Ipv4Addr, str::FromStr};
 
 use chrono::prelude::*;
+use eui48::EUI48LEN;
+
+use dhcp_protocol::HardwareType;
 
 /// The state of the `Lease`.
 #[derive(Clone)]
@@ -15,6 +18,10 @@ enum State {
 /// A client has only `OFFER_TIMEOUT` seconds to accept a `DHCPOFFER`.
 const OFFER_TIMEOUT: u32 = 60;
 
+/// RFC 2131 §3.3
+/// The lease time `0xffffffff` is reserved to represent "infinity".
+pub(crate) const INFINITE_LEASE_TIME: u32 = 0xffff_ffff;
+
 /// A lease record of the DHCP server lease database.
 #[derive(Clone)]
 pub struct Lease {
@@ -26,6 +33,7 @@ pub struct Lease {
     renewed_at: u32,
     released_at: u32,
     expires_at: u32,
+    hostname: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -43,6 +51,7 @@ impl Lease {
             renewed_at: 0,
             released_at: 0,
             expires_at: 0,
+            hostname: None,
         }
     }
 
@@ -56,6 +65,17 @@ impl Lease {
         self.lease_time
     }
 
+    /// The hostname the client has been named with.
+    #[allow(clippy::option_as_ref_deref)]
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_ref().map(|hostname| hostname.as_str())
+    }
+
+    /// Records the hostname the client has been named with.
+    pub fn set_hostname(&mut self, hostname: Option<String>) {
+        self.hostname = hostname;
+    }
+
     /// Moves the lease from `Offered` to the `Assigned` state.
     ///
     /// Records the assignment time and calculates the expiration time.
@@ -63,7 +83,7 @@ impl Lease {
         self.state = State::Assigned;
         self.assigned_at = Utc::now().timestamp() as u32;
         self.lease_time = lease_time;
-        self.expires_at = self.assigned_at + self.lease_time;
+        self.expires_at = self.assigned_at.saturating_add(self.lease_time);
     }
 
     /// Renewes the expiration time if the lease is in the `Assigned` state.
@@ -72,7 +92,7 @@ impl Lease {
     pub fn renew(&mut self, lease_time: u32) {
         self.lease_time = lease_time;
         self.renewed_at = Utc::now().timestamp() as u32;
-        self.expires_at = self.renewed_at + self.lease_time;
+        self.expires_at = self.renewed_at.saturating_add(self.lease_time);
     }
 
     /// Releases the address and moves the lease to `Released` state.
@@ -154,9 +174,153 @@ impl Lease {
 
     /// Check whether the lease is expired.
     pub fn is_expired(&self) -> bool {
-        if self.expires_at == 0 {
+        if self.expires_at == 0 || self.lease_time == INFINITE_LEASE_TIME {
             return false;
         }
         (Utc::now().timestamp() as u32) >= self.expires_at
     }
+
+    /// Writes the lease as a line of space-separated fields.
+    ///
+    /// The hostname is hex-encoded or written as `-` if it is not set.
+    pub(crate) fn to_record(&self) -> String {
+        let state = match self.state {
+            State::Offered => "offered",
+            State::Assigned => "assigned",
+            State::Released => "released",
+        };
+        let hostname = match self.hostname {
+            Some(ref hostname) => to_hex(hostname.as_bytes()),
+            None => "-".to_owned(),
+        };
+        format!(
+            "{} {} {} {} {} {} {} {} {}",
+            self.address,
+            state,
+            self.lease_time,
+            self.offered_at,
+            self.assigned_at,
+            self.renewed_at,
+            self.released_at,
+            self.expires_at,
+            hostname,
+        )
+    }
+
+    /// Writes the lease as an ISC `dhcpd.leases` block.
+    ///
+    /// The hardware address is only written if the client ID is a bare or typed Ethernet address.
+    /// Returns `None` for offered leases, since ISC dhcpd does not record offers either.
+    pub(crate) fn to_isc(&self, client_id: &[u8]) -> Option<String> {
+        let (starts, ends, binding_state) = match self.state {
+            State::Offered => return None,
+            State::Assigned => {
+                let starts = cmp::max(self.assigned_at, self.renewed_at);
+                let state = if self.is_expired() {
+                    "expired"
+                } else {
+                    "active"
+                };
+                (starts, self.expires_at, state)
+            }
+            State::Released => {
+                let starts = cmp::max(self.assigned_at, self.renewed_at);
+                (starts, self.released_at, "released")
+            }
+        };
+        let ends = if self.lease_time == INFINITE_LEASE_TIME && !self.is_released() {
+            "never".to_owned()
+        } else {
+            to_isc_time(ends)
+        };
+
+        let mut block = format!("lease {} {{\n", self.address);
+        block.push_str(&format!("  starts {};\n", to_isc_time(starts)));
+        block.push_str(&format!("  ends {};\n", ends));
+        block.push_str(&format!("  binding state {};\n", binding_state));
+        let hardware_address = match client_id.len() {
+            EUI48LEN => Some(client_id),
+            len if len == EUI48LEN + 1 && client_id[0] == HardwareType::Ethernet as u8 => {
+                Some(&client_id[1..])
+            }
+            _ => None,
+        };
+        if let Some(hardware_address) = hardware_address {
+            block.push_str(&format!(
+                "  hardware ethernet {};\n",
+                to_colon_hex(hardware_address)
+            ));
+        }
+        block.push_str(&format!("  uid {};\n", to_colon_hex(client_id)));
+        if let Some(ref hostname) = self.hostname {
+            block.push_str(&format!("  client-hostname \"{}\";\n", hostname));
+        }
+        block.push_str("}\n");
+        Some(block)
+    }
+
+    /// Reads the lease written by `to_record`.
+    ///
+    /// Returns `None` if the record is malformed.
+    pub(crate) fn from_record(record: &str) -> Option<Self> {
+        let fields: Vec<&str> = record.split_whitespace().collect();
+        if fields.len() != 9 {
+            return None;
+        }
+        let state = match fields[1] {
+            "offered" => State::Offered,
+            "assigned" => State::Assigned,
+            "released" => State::Released,
+            _ => return None,
+        };
+        let hostname = match fields[8] {
+            "-" => None,
+            hex => Some(String::from_utf8(from_hex(hex)?).ok()?),
+        };
+
+        Some(Lease {
+            address: Ipv4Addr::from_str(fields[0]).ok()?,
+            state,
+            lease_time: fields[2].parse().ok()?,
+            offered_at: fields[3].parse().ok()?,
+            assigned_at: fields[4].parse().ok()?,
+            renewed_at: fields[5].parse().ok()?,
+            released_at: fields[6].parse().ok()?,
+            expires_at: fields[7].parse().ok()?,
+            hostname,
+        })
+    }
+}
+
+/// Formats a UNIX timestamp like ISC dhcpd does: the weekday number and the UTC date and time.
+pub(crate) fn to_isc_time(timestamp: u32) -> String {
+    Utc.timestamp(i64::from(timestamp), 0)
+        .format("%w %Y/%m/%d %H:%M:%S")
+        .to_string()
+}
+
+/// Encodes bytes as colon-separated lowercase hex octets.
+fn to_colon_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Encodes bytes as a lowercase hex string.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a hex string. Returns `None` if the string is not valid hex.
+#[allow(clippy::manual_is_multiple_of)]
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
 }
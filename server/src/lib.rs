@@ -2,19 +2,39 @@
 
 #[macro_use]
 mod macros;
+mod audit;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 mod bpf;
+mod broadcast;
 mod builder;
+mod command;
+mod config;
+mod conflict;
 mod database;
+#[cfg(target_os = "linux")]
+mod device;
+mod error;
+mod event;
 mod lease;
+mod metrics;
+mod naming;
+mod netmath;
+mod rate_limit;
 mod server;
 mod storage;
+mod storage_file;
+mod storage_journal;
+mod storage_overlay;
 mod storage_ram;
+#[cfg(all(target_os = "linux", feature = "systemd"))]
+mod systemd;
 
 #[macro_use]
 extern crate log;
 extern crate bytes;
 extern crate chrono;
+extern crate eui48;
+#[macro_use]
 extern crate futures;
 extern crate hostname;
 extern crate tokio;
@@ -23,9 +43,9 @@ extern crate failure;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 extern crate etherparse;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
-extern crate eui48;
-#[cfg(any(target_os = "freebsd", target_os = "macos"))]
 extern crate futures_cpupool;
+#[cfg(target_os = "linux")]
+extern crate libc;
 #[cfg(target_os = "windows")]
 extern crate tokio_process;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
@@ -36,13 +56,27 @@ extern crate ifcontrol;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 extern crate netif_bpf;
 
-#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
 extern crate dhcp_arp;
 extern crate dhcp_framed;
 extern crate dhcp_protocol;
 
 pub use self::{
-    server::{Server, ServerBuilder},
+    audit::AuditSink,
+    broadcast::BroadcastPolicy,
+    command::ServerCommand,
+    config::{GuestPool, ServerConfig, SubnetConfig, VendorClassConfig},
+    error::ServerError,
+    event::{LeaseEvent, LeaseEventHandler, LeaseEventKind},
+    metrics::{MetricsSnapshot, ServerMetrics},
+    naming::NamePolicy,
+    server::{HostnameGenerator, Server, ServerBuilder},
     storage::Storage,
+    storage_file::FileStorage,
     storage_ram::RamStorage,
 };
@@ -1,16 +1,13 @@
 //! Macro functions used in the `Server:poll` method.
 
-/// A panic indicates a bug in the application logic.
-macro_rules! expect (
-    ($option:expr) => (
-        $option.expect("A bug in DHCP message validation")
-    );
-);
-
 /// Just to move some code from the overwhelmed `poll` method.
 macro_rules! log_receive(
     ($message:expr, $source:expr) => (
-        info!("Received {} from {}", expect!($message.options.dhcp_message_type), $source);
+        // the message is not validated yet, so the type may be missing
+        match $message.options.dhcp_message_type {
+            Some(dhcp_message_type) => info!("Received {} from {}", dhcp_message_type, $source),
+            None => info!("Received an untyped message from {}", $source),
+        }
         debug!("{}", $message);
     );
 );
@@ -18,7 +15,10 @@ macro_rules! log_receive(
 /// Just to move some code from the overwhelmed `poll` method.
 macro_rules! log_send(
     ($message:expr, $destination:expr) => (
-        info!("Sending {} to {}", expect!($message.options.dhcp_message_type), $destination);
+        match $message.options.dhcp_message_type {
+            Some(dhcp_message_type) => info!("Sending {} to {}", dhcp_message_type, $destination),
+            None => info!("Sending a BOOTREPLY to {}", $destination),
+        }
         debug!("{}", $message);
     );
 );
@@ -35,7 +35,7 @@ macro_rules! poll (
             Ok(Async::NotReady) => return Ok(Async::NotReady),
             Err(error) => {
                 warn!("Socket error: {}", error);
-                return Err(error);
+                return Err(From::from(error));
             },
         };
     );
@@ -64,7 +64,7 @@ macro_rules! start_send (
             },
             Err(error) => {
                 warn!("Socket error: {}", error);
-                return Err(error);
+                return Err(From::from(error));
             },
         }
     );
@@ -78,55 +78,37 @@ macro_rules! poll_complete (
             Ok(Async::NotReady) => return Ok(Async::NotReady),
             Err(error) => {
                 warn!("Socket error: {}", error);
-                return Err(error);
+                return Err(From::from(error));
             },
         }
     );
 );
 
 /// Just to move some code from the overwhelmed `poll` method.
+///
+/// Runs the `netsh` processes of the handles, which are kept until their entries are removed.
 #[cfg(target_os = "windows")]
 macro_rules! poll_arp (
     ($arp:expr) => (
-        let mut ready = false;
-        if let Some(ref mut arp) = $arp {
-            if let Some(ref mut delete) = arp.0 {
-                match delete.poll() {
-                    Ok(Async::Ready(_)) => {
-                        trace!("The netsh delete process finished.");
-                    },
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    Err(error) => {
-                        warn!("netsh delete process future error: {}", error);
-                        continue;
-                    },
+        for (address, arp) in $arp.iter_mut() {
+            loop {
+                match arp.poll() {
+                    Ok(_) => break,
+                    // the handle may still have processes to run
+                    Err(error) => error!("ARP process error for {}: {:?}", address, error),
                 }
             }
-            arp.0 = None;
-            if let Some(ref mut add) = arp.1 {
-                let output = match add.poll() {
-                    Ok(Async::Ready(output)) => {
-                        trace!("The netsh add process finished.");
-                        ready = true;
-                        output
-                    },
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    Err(error) => {
-                        warn!("netsh add process future error: {}", error);
-                        continue;
-                    },
-                };
-                if !output.status.success() {
-                    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
-                    if stdout != "The object already exists." {
-                        error!("ARP process error: {}", stdout);
-                    }
-                }
-            }
-            arp.1 = None;
         }
-        if ready {
-            $arp = None;
+    );
+);
+
+/// Returns from the request processing until the storage is ready.
+macro_rules! ready (
+    ($poll:expr) => (
+        match $poll {
+            Ok(Async::Ready(item)) => Ok(item),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(error) => Err(error),
         }
     );
 );
@@ -0,0 +1,119 @@
+//! The server metrics shared with external exporters.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dhcp_protocol::MessageType;
+
+/// The message and lease counters updated by the server future.
+///
+/// Is shared via `Arc`, so an exporter may take snapshots from another thread
+/// while the server is running.
+#[derive(Default)]
+pub struct ServerMetrics {
+    discovers: AtomicUsize,
+    requests: AtomicUsize,
+    declines: AtomicUsize,
+    releases: AtomicUsize,
+    informs: AtomicUsize,
+    offers: AtomicUsize,
+    acks: AtomicUsize,
+    naks: AtomicUsize,
+    allocation_failures: AtomicUsize,
+    rate_limited: AtomicUsize,
+    client_rate_limited: AtomicUsize,
+    active_leases: AtomicUsize,
+}
+
+/// A point-in-time copy of the `ServerMetrics` counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// `DHCPDISCOVER` messages received.
+    pub discovers: usize,
+    /// `DHCPREQUEST` messages received.
+    pub requests: usize,
+    /// `DHCPDECLINE` messages received.
+    pub declines: usize,
+    /// `DHCPRELEASE` messages received.
+    pub releases: usize,
+    /// `DHCPINFORM` messages received.
+    pub informs: usize,
+    /// `DHCPOFFER` messages sent.
+    pub offers: usize,
+    /// `DHCPACK` messages sent.
+    pub acks: usize,
+    /// `DHCPNAK` messages sent.
+    pub naks: usize,
+    /// `DHCPDISCOVER` messages no address could be allocated for.
+    pub allocation_failures: usize,
+    /// Messages dropped by the global or the per-client rate limiter.
+    pub rate_limited: usize,
+    /// Messages dropped by the per-client rate limiter, which are also counted in `rate_limited`.
+    pub client_rate_limited: usize,
+    /// The assigned leases which have not expired as of the last lease change or sweep.
+    ///
+    /// A lease expiring between them is counted until the next sweep or lease change.
+    pub active_leases: usize,
+}
+
+impl ServerMetrics {
+    /// Copies the current counter values.
+    ///
+    /// The counters are read one by one, so they may be slightly inconsistent with each other.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            discovers: self.discovers.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            declines: self.declines.load(Ordering::Relaxed),
+            releases: self.releases.load(Ordering::Relaxed),
+            informs: self.informs.load(Ordering::Relaxed),
+            offers: self.offers.load(Ordering::Relaxed),
+            acks: self.acks.load(Ordering::Relaxed),
+            naks: self.naks.load(Ordering::Relaxed),
+            allocation_failures: self.allocation_failures.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            client_rate_limited: self.client_rate_limited.load(Ordering::Relaxed),
+            active_leases: self.active_leases.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counts a valid received message.
+    pub(crate) fn on_recv(&self, dhcp_message_type: MessageType) {
+        let counter = match dhcp_message_type {
+            MessageType::DhcpDiscover => &self.discovers,
+            MessageType::DhcpRequest => &self.requests,
+            MessageType::DhcpDecline => &self.declines,
+            MessageType::DhcpRelease => &self.releases,
+            MessageType::DhcpInform => &self.informs,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a sent response.
+    pub(crate) fn on_send(&self, dhcp_message_type: MessageType) {
+        let counter = match dhcp_message_type {
+            MessageType::DhcpOffer => &self.offers,
+            MessageType::DhcpAck => &self.acks,
+            MessageType::DhcpNak => &self.naks,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_allocation_failure(&self) {
+        self.allocation_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_client_rate_limited(&self) {
+        self.on_rate_limited();
+        self.client_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_active_leases(&self, active_leases: usize) {
+        self.active_leases.store(active_leases, Ordering::Relaxed);
+    }
+}
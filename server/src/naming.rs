@@ -0,0 +1,45 @@
+//! The client-requested name policy.
+
+use dhcp_protocol::Message;
+
+/// Decides which option the client-requested name is taken from
+/// if the request carries both the Hostname (12) and the Client FQDN (81) options.
+///
+/// If only one of them is present, it is used regardless of the policy.
+#[derive(Debug, Clone, Copy)]
+pub enum NamePolicy {
+    /// Prefers the Hostname option.
+    PreferHostname,
+    /// Prefers the Client FQDN option as RFC 4702 §3.1 recommends.
+    PreferClientFqdn,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for NamePolicy {
+    fn default() -> Self {
+        NamePolicy::PreferClientFqdn
+    }
+}
+
+impl NamePolicy {
+    /// Extracts the client-requested name from the request.
+    pub(crate) fn requested_name(self, request: &Message) -> Option<String> {
+        let hostname = request
+            .options
+            .hostname
+            .as_ref()
+            .filter(|hostname| !hostname.is_empty())
+            .cloned();
+        let fqdn = request
+            .options
+            .client_fqdn
+            .as_ref()
+            .filter(|fqdn| !fqdn.name.is_empty())
+            .map(|fqdn| fqdn.name.to_owned());
+
+        match self {
+            NamePolicy::PreferHostname => hostname.or(fqdn),
+            NamePolicy::PreferClientFqdn => fqdn.or(hostname),
+        }
+    }
+}
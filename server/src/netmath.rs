@@ -0,0 +1,49 @@
+//! IPv4 address arithmetic helpers.
+
+use std::net::Ipv4Addr;
+
+/// Returns the prefix length of a subnet mask.
+///
+/// Returns `None` if the mask is not contiguous, e.g. `255.255.0.255`.
+pub fn prefix_len(subnet_mask: &Ipv4Addr) -> Option<u8> {
+    let mask = u32::from(*subnet_mask);
+    let prefix_len = (!mask).leading_zeros();
+    if mask.checked_shl(prefix_len).unwrap_or(0) != 0 {
+        return None;
+    }
+    Some(prefix_len as u8)
+}
+
+/// Whether the address belongs to the network with the subnet mask.
+pub fn is_in_subnet(address: &Ipv4Addr, network: &Ipv4Addr, subnet_mask: &Ipv4Addr) -> bool {
+    let mask = u32::from(*subnet_mask);
+    u32::from(*address) & mask == u32::from(*network) & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_prefix_len_of_contiguous_masks() {
+        let masks = [
+            ([0, 0, 0, 0], 0),
+            ([255, 0, 0, 0], 8),
+            ([255, 255, 240, 0], 20),
+            ([255, 255, 255, 0], 24),
+            ([255, 255, 255, 252], 30),
+            ([255, 255, 255, 255], 32),
+        ];
+        for &(mask, expected) in masks.iter() {
+            assert_eq!(prefix_len(&Ipv4Addr::from(mask)), Some(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_non_contiguous_masks() {
+        let masks = [[255, 255, 0, 255], [0, 255, 255, 255], [255, 255, 255, 253]];
+        for &mask in masks.iter() {
+            assert_eq!(prefix_len(&Ipv4Addr::from(mask)), None);
+        }
+    }
+}
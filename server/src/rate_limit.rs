@@ -0,0 +1,249 @@
+//! The incoming message rate limiters.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+use eui48::MacAddress;
+
+/// The number of client buckets which triggers the first sweep of the idle ones.
+const CLIENT_BUCKETS_SWEEP_MIN: usize = 4096;
+/// The number of client buckets beyond which the least recently used one is evicted.
+const CLIENT_BUCKETS_MAX: usize = 65536;
+
+/// A token bucket refilled at a constant rate.
+///
+/// Protects the server and its storage from broadcast storms.
+/// Unlike per-client deduplication, it limits all the incoming messages together.
+pub struct RateLimiter {
+    /// The tokens added per second.
+    rate: f64,
+    /// The bucket capacity.
+    burst: f64,
+    /// The tokens currently available.
+    tokens: f64,
+    /// The last refill time.
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket.
+    pub fn new(requests_per_second: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimiter {
+            rate: f64::from(requests_per_second),
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one.
+    ///
+    /// Returns `false` if the message must be dropped.
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.refilled_at {
+            let elapsed = now - self.refilled_at;
+            let elapsed =
+                elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+            self.refilled_at = now;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.burst
+    }
+}
+
+/// A token bucket per client hardware address.
+///
+/// Keeps a flooding client from exhausting the pool or the server time of the others.
+/// The renewals of a client have their own bucket, so its discovery flood
+/// does not prevent it from keeping the lease it already has.
+pub struct ClientRateLimiter {
+    /// The tokens added per second to each bucket.
+    requests_per_second: u32,
+    /// The capacity of each bucket.
+    burst: u32,
+    /// The buckets keyed by the client hardware address and whether they are for renewals.
+    buckets: HashMap<(MacAddress, bool), ClientBucket>,
+    /// The bucket keys ordered by their last use, the least recent first.
+    usage: BTreeMap<u64, (MacAddress, bool)>,
+    /// The use counter ordering the buckets.
+    uses: u64,
+    /// The number of buckets which triggers the next sweep.
+    sweep_at: usize,
+    /// The number of buckets beyond which the least recently used one is evicted.
+    max_buckets: usize,
+}
+
+/// A client token bucket with its last use number.
+struct ClientBucket {
+    limiter: RateLimiter,
+    used: u64,
+}
+
+impl ClientRateLimiter {
+    /// Creates a limiter with every client bucket full.
+    pub fn new(requests_per_second: u32, burst: u32) -> Self {
+        ClientRateLimiter {
+            requests_per_second,
+            burst,
+            buckets: HashMap::new(),
+            usage: BTreeMap::new(),
+            uses: 0,
+            sweep_at: CLIENT_BUCKETS_SWEEP_MIN,
+            max_buckets: CLIENT_BUCKETS_MAX,
+        }
+    }
+
+    /// Takes a token from the client bucket if there is one.
+    ///
+    /// Returns `false` if the message must be dropped.
+    pub fn allow(&mut self, client_hardware_address: MacAddress, is_renewal: bool) -> bool {
+        self.allow_at(client_hardware_address, is_renewal, Instant::now())
+    }
+
+    fn allow_at(
+        &mut self,
+        client_hardware_address: MacAddress,
+        is_renewal: bool,
+        now: Instant,
+    ) -> bool {
+        let key = (client_hardware_address, is_renewal);
+        if !self.buckets.contains_key(&key) {
+            if self.buckets.len() >= self.sweep_at {
+                self.sweep(now);
+            }
+            if self.buckets.len() >= self.max_buckets {
+                self.evict();
+            }
+        }
+
+        self.uses += 1;
+        let (requests_per_second, burst, used) = (self.requests_per_second, self.burst, self.uses);
+        let bucket = self.buckets.entry(key).or_insert_with(|| ClientBucket {
+            limiter: RateLimiter::new(requests_per_second, burst),
+            used,
+        });
+        self.usage.remove(&bucket.used);
+        bucket.used = used;
+        self.usage.insert(used, key);
+        bucket.limiter.allow_at(now)
+    }
+
+    /// Drops the full buckets, which are equivalent to the new ones.
+    ///
+    /// The next sweep is triggered once the remaining buckets double,
+    /// so a flood of distinct clients does not make every message scan the map.
+    fn sweep(&mut self, now: Instant) {
+        let usage = &mut self.usage;
+        self.buckets.retain(|_, bucket| {
+            bucket.limiter.refill(now);
+            let is_idle = bucket.limiter.is_full();
+            if is_idle {
+                usage.remove(&bucket.used);
+            }
+            !is_idle
+        });
+        self.sweep_at = (self.buckets.len() * 2).max(CLIENT_BUCKETS_SWEEP_MIN);
+    }
+
+    /// Drops the least recently used bucket.
+    fn evict(&mut self) {
+        let used = match self.usage.keys().next() {
+            Some(&used) => used,
+            None => return,
+        };
+        if let Some(key) = self.usage.remove(&used) {
+            self.buckets.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn refills_at_configured_rate() {
+        let mut limiter = RateLimiter::new(10, 5);
+        let start = limiter.refilled_at;
+
+        assert_eq!((0..8).filter(|_| limiter.allow_at(start)).count(), 5);
+        let later = start + Duration::from_millis(300);
+        assert_eq!((0..8).filter(|_| limiter.allow_at(later)).count(), 3);
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..8).filter(|_| limiter.allow_at(much_later)).count(), 5);
+    }
+
+    #[test]
+    fn limits_each_client_separately() {
+        let mut limiter = ClientRateLimiter::new(1, 2);
+        let now = Instant::now();
+        let flooding = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x10, 0x01]);
+        let quiet = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x10, 0x02]);
+
+        let allowed = (0..8).filter(|_| limiter.allow_at(flooding, false, now));
+        assert_eq!(allowed.count(), 2);
+        assert!(limiter.allow_at(flooding, true, now));
+        assert!(limiter.allow_at(quiet, false, now));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_client_at_capacity() {
+        let mut limiter = ClientRateLimiter::new(1, 1);
+        limiter.max_buckets = 2;
+        let now = Instant::now();
+        let first = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x10, 0x01]);
+        let second = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x10, 0x02]);
+        let third = MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x10, 0x03]);
+
+        assert!(limiter.allow_at(first, false, now));
+        assert!(limiter.allow_at(second, false, now));
+        assert!(!limiter.allow_at(first, false, now));
+        assert!(limiter.allow_at(third, false, now));
+
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(!limiter.allow_at(first, false, now));
+        assert!(limiter.allow_at(second, false, now));
+    }
+
+    #[test]
+    fn sweeps_idle_clients_when_buckets_double() {
+        let mut limiter = ClientRateLimiter::new(1, 1);
+        let now = Instant::now();
+        let client = |i: usize| {
+            MacAddress::new([0x00, 0x0c, 0x29, (i >> 16) as u8, (i >> 8) as u8, i as u8])
+        };
+
+        for i in 0..CLIENT_BUCKETS_SWEEP_MIN {
+            limiter.allow_at(client(i), false, now);
+        }
+        assert_eq!(limiter.buckets.len(), CLIENT_BUCKETS_SWEEP_MIN);
+
+        let later = now + Duration::from_secs(60);
+        limiter.allow_at(client(CLIENT_BUCKETS_SWEEP_MIN), false, later);
+        assert_eq!(limiter.buckets.len(), 1);
+        assert_eq!(limiter.usage.len(), 1);
+        assert_eq!(limiter.sweep_at, CLIENT_BUCKETS_SWEEP_MIN);
+    }
+}
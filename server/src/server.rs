@@ -1,21 +1,66 @@
 //! The main DHCP server module.
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(target_os = "windows")]
+use std::collections::hash_map::Entry;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use eui48::MacAddress;
+use futures::sync::{mpsc, oneshot};
 use hostname;
-use tokio::{io, prelude::*};
+use tokio::{io, prelude::*, timer::Interval};
 
-#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(any(
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
 use dhcp_arp;
-use dhcp_framed::DhcpFramed;
-use dhcp_protocol::{Message, MessageType, DHCP_PORT_CLIENT, DHCP_PORT_SERVER};
+use dhcp_framed::{DhcpFramed, DhcpSinkItem, DhcpStreamItem};
+use dhcp_protocol::{
+    AuthKey, Message, MessageType, OptionTag, DHCP_PORT_CLIENT, DHCP_PORT_SERVER,
+    SIZE_BOOT_FILENAME,
+};
 
+use audit::AuditSink;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 use bpf::BpfData;
+use broadcast::BroadcastPolicy;
 use builder::MessageBuilder;
-use database::{Database, Error::LeaseInvalid};
+use command::ServerCommand;
+use config::{self, GuestPool, ServerConfig, SubnetConfig, VendorClassConfig};
+use conflict::{ArpProber, ConflictProber, IcmpProber};
+use database::{
+    Database, Error,
+    Error::{
+        AddressInUse, AddressOutOfRange, LeaseInvalid, LeaseNotFound, NotAllowed, PoolExhausted,
+    },
+    Offer,
+};
+#[cfg(target_os = "linux")]
+use device;
+use error::ServerError;
+use event::{LeaseEvent, LeaseEventHandler, LeaseEventKind};
+use metrics::ServerMetrics;
+use naming::NamePolicy;
+use rate_limit::{ClientRateLimiter, RateLimiter};
 use storage::Storage;
+#[cfg(all(target_os = "linux", feature = "systemd"))]
+use systemd;
 use tokio::net::UdpSocket;
+#[cfg(target_os = "linux")]
+use tokio::reactor::Handle;
+
+/// Generates the client hostname from its request, the address leased to it
+/// and the client-requested name chosen according to the `NamePolicy`.
+///
+/// Returning `None` omits the hostname option.
+pub type HostnameGenerator = Box<FnMut(&Message, Ipv4Addr, Option<&str>) -> Option<String> + Send>;
 
 /// Some options like `cpu_pool_size` are OS-specific, so the builder pattern is required.
 pub struct ServerBuilder<S>
@@ -32,8 +77,49 @@ where
     domain_name_servers: Vec<Ipv4Addr>,
     static_routes: Vec<(Ipv4Addr, Ipv4Addr)>,
     classless_static_routes: Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>,
+    allowed_clients: Option<Vec<MacAddress>>,
+    signal_no_lease: bool,
+    commands: Option<mpsc::UnboundedReceiver<ServerCommand>>,
+    hostname_generator: Option<HostnameGenerator>,
+    name_policy: NamePolicy,
+    deterministic_allocation: bool,
+    reservations: Vec<(Vec<u8>, Ipv4Addr, Option<u32>)>,
+    hardware_reservations: Vec<(MacAddress, Ipv4Addr)>,
+    excluded_address_ranges: Vec<(Ipv4Addr, Ipv4Addr)>,
+    source_port_check: Option<bool>,
+    inform_options: Option<Vec<OptionTag>>,
+    broadcast_policy: BroadcastPolicy,
+    audit_sink: Option<Box<AuditSink>>,
+    allow_rapid_commit: bool,
+    allow_bootp: bool,
+    is_authoritative: bool,
+    conflict_detection: Option<Duration>,
+    arp_probe: Option<Duration>,
+    rate_limit: Option<(u32, u32)>,
+    client_rate_limit: Option<(u32, u32)>,
+    ready_signal: Option<oneshot::Sender<()>>,
+    sweep_interval: Option<Duration>,
+    guest_pool: Option<GuestPool>,
+    subnets: Vec<SubnetConfig>,
+    vendor_classes: Vec<VendorClassConfig>,
+    next_server: Option<Ipv4Addr>,
+    boot_file: Option<String>,
+    lease_bounds: Option<(u32, u32, u32)>,
+    decline_cooldown: Option<Duration>,
+    ntp_servers: Vec<Ipv4Addr>,
+    domain_name: Option<String>,
+    domain_search: Vec<String>,
+    lease_event_handler: Option<LeaseEventHandler>,
+    metrics: Arc<ServerMetrics>,
+    auth_key: Option<AuthKey>,
+    dry_run: bool,
+    bind_port: Option<u16>,
+    #[allow(unused)]
+    bind_to_device: bool,
     #[allow(unused)]
     bpf_num_threads_size: Option<usize>,
+    #[allow(unused)]
+    bpf_ip_ttl: Option<u8>,
 }
 
 impl<S> ServerBuilder<S>
@@ -51,16 +137,16 @@ where
     /// Something like `ens33` on Linux or like `Ethernet` on Windows.
     ///
     /// * `static_address_range`
-    /// An inclusive IPv4 address range. Gaps may be implemented later.
+    /// An inclusive IPv4 address range. Gaps are made with `exclude_range`.
     ///
     /// * `dynamic_address_range`
-    /// An inclusive IPv4 address range. Gaps may be implemented later.
+    /// An inclusive IPv4 address range. Gaps are made with `exclude_range`.
     ///
     /// * `storage`
     /// The `Storage` trait object. The trait must be implemented by a crate user.
     ///
     /// * `subnet_mask`
-    /// Static data for client configuration.
+    /// Static data for client configuration. Must be contiguous, or building fails.
     ///
     /// * `routers`
     /// Static data for client configuration.
@@ -97,10 +183,440 @@ where
             domain_name_servers,
             static_routes,
             classless_static_routes,
+            allowed_clients: None,
+            signal_no_lease: false,
+            commands: None,
+            hostname_generator: None,
+            name_policy: NamePolicy::default(),
+            deterministic_allocation: false,
+            reservations: Vec::new(),
+            hardware_reservations: Vec::new(),
+            excluded_address_ranges: Vec::new(),
+            source_port_check: None,
+            inform_options: None,
+            broadcast_policy: BroadcastPolicy::default(),
+            audit_sink: None,
+            allow_rapid_commit: false,
+            allow_bootp: false,
+            is_authoritative: false,
+            conflict_detection: None,
+            arp_probe: None,
+            rate_limit: None,
+            client_rate_limit: None,
+            ready_signal: None,
+            sweep_interval: None,
+            guest_pool: None,
+            subnets: Vec::new(),
+            vendor_classes: Vec::new(),
+            next_server: None,
+            boot_file: None,
+            lease_bounds: None,
+            decline_cooldown: None,
+            ntp_servers: Vec::new(),
+            domain_name: None,
+            domain_search: Vec::new(),
+            lease_event_handler: None,
+            metrics: Arc::new(ServerMetrics::default()),
+            auth_key: None,
+            dry_run: false,
+            bind_port: None,
+            bind_to_device: false,
             bpf_num_threads_size: None,
+            bpf_ip_ttl: None,
         }
     }
 
+    /// Restricts the server to the clients with the specified hardware addresses.
+    ///
+    /// If not called during building, every client is served.
+    pub fn with_allowed_clients(&mut self, allowed_clients: Vec<MacAddress>) -> &mut Self {
+        self.allowed_clients = Some(allowed_clients);
+        self
+    }
+
+    /// Makes the server answer a `DHCPDISCOVER` it cannot serve with a no-lease `DHCPOFFER`.
+    ///
+    /// The `DHCPOFFER` carries no address and the Auto-Configure option set
+    /// to `DoNotAutoConfigure` (RFC 2563), so the client stops waiting for this server.
+    /// Only the clients which sent the Auto-Configure option receive it.
+    /// If not called during building, such `DHCPDISCOVER` messages are silently ignored.
+    pub fn with_no_lease_signal(&mut self) -> &mut Self {
+        self.signal_no_lease = true;
+        self
+    }
+
+    /// Sets the control channel the running server receives its commands from.
+    ///
+    /// If not called during building, the server cannot be controlled at runtime.
+    pub fn with_commands(
+        &mut self,
+        commands: mpsc::UnboundedReceiver<ServerCommand>,
+    ) -> &mut Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Sets the callback naming the clients in `DHCPOFFER` and `DHCPACK` messages.
+    ///
+    /// The generated hostname replaces the server one and is recorded in the client lease.
+    /// If not called during building, the clients receive the server hostname.
+    pub fn with_hostname_generator(&mut self, hostname_generator: HostnameGenerator) -> &mut Self {
+        self.hostname_generator = Some(hostname_generator);
+        self
+    }
+
+    /// Sets which option the client-requested name passed to the hostname generator
+    /// is taken from if the request carries both the Hostname and the Client FQDN options.
+    ///
+    /// If not called during building, the Client FQDN option is preferred.
+    pub fn with_name_policy(&mut self, name_policy: NamePolicy) -> &mut Self {
+        self.name_policy = name_policy;
+        self
+    }
+
+    /// Makes the dynamic pool addresses be derived from client hardware addresses.
+    ///
+    /// Each client gets a stable and predictable address without any reservations.
+    /// Collisions are resolved by probing the pool linearly.
+    /// If not called during building, the first available address is allocated.
+    pub fn with_deterministic_allocation(&mut self) -> &mut Self {
+        self.deterministic_allocation = true;
+        self
+    }
+
+    /// Reserves an address for the client with the specified identifier.
+    ///
+    /// The client identifier is the `client_id` option value or, if the client
+    /// does not send it, the client hardware address bytes.
+    /// If `lease_time` is set, it overrides the requested and default lease times,
+    /// and `Some(0xffff_ffff)` makes the lease infinite.
+    /// May be called several times to reserve addresses for several clients.
+    pub fn with_reservation(
+        &mut self,
+        client_id: Vec<u8>,
+        address: Ipv4Addr,
+        lease_time: Option<u32>,
+    ) -> &mut Self {
+        self.reservations.push((client_id, address, lease_time));
+        self
+    }
+
+    /// Reserves an address for the client with the specified hardware address.
+    ///
+    /// The reservation is matched against the `chaddr` field, so it applies whatever
+    /// `client_id` option the client sends, like its hardware type and address (RFC 2132 §9.14)
+    /// or a DUID (RFC 4361).
+    /// The reserved address is excluded from the dynamic pool if it belongs to it.
+    /// May be called several times to reserve addresses for several clients.
+    pub fn with_mac_reservation(&mut self, mac: MacAddress, address: Ipv4Addr) -> &mut Self {
+        self.hardware_reservations.push((mac, address));
+        self
+    }
+
+    /// Excludes an address from all the pools.
+    ///
+    /// Used for the addresses assigned statically, like the ones of gateways and printers.
+    /// May be called several times to exclude several addresses.
+    pub fn exclude(&mut self, address: Ipv4Addr) -> &mut Self {
+        self.exclude_range(address, address)
+    }
+
+    /// Excludes an inclusive address range from all the pools.
+    ///
+    /// The excluded addresses are never offered, even after a decline cooldown,
+    /// and their existing leases are not renewed. The reservations take precedence,
+    /// so a reserved address is still given to its client if it is excluded.
+    /// May be called several times to exclude several ranges.
+    pub fn exclude_range(&mut self, first: Ipv4Addr, last: Ipv4Addr) -> &mut Self {
+        self.excluded_address_ranges.push((first, last));
+        self
+    }
+
+    /// Makes the server check the UDP source port of requests.
+    ///
+    /// Clients send requests from port 68 and relay agents (with nonzero `giaddr`)
+    /// send them from port 67. Requests from other ports are logged and, if `drop`
+    /// is set, ignored, since they may indicate a misconfigured relay or an attack.
+    /// If not called during building, the source port is not checked.
+    pub fn with_source_port_check(&mut self, drop: bool) -> &mut Self {
+        self.source_port_check = Some(drop);
+        self
+    }
+
+    /// Restricts the options sent in response to `DHCPINFORM` to the specified ones.
+    ///
+    /// The client still receives only the options it has requested.
+    /// If not called during building, every requested option is sent.
+    pub fn with_inform_options(&mut self, inform_options: Vec<OptionTag>) -> &mut Self {
+        self.inform_options = Some(inform_options);
+        self
+    }
+
+    /// Overrides the client `BROADCAST` flag when choosing the reply destination.
+    ///
+    /// If not called during building, the flag is honored.
+    pub fn with_broadcast_policy(&mut self, broadcast_policy: BroadcastPolicy) -> &mut Self {
+        self.broadcast_policy = broadcast_policy;
+        self
+    }
+
+    /// Makes the server answer a `DHCPDISCOVER` with the Rapid Commit option
+    /// with a `DHCPACK` at once (RFC 4039).
+    ///
+    /// The address is assigned without waiting for a `DHCPREQUEST`.
+    /// If not called during building, the option is ignored and a `DHCPOFFER` is sent.
+    pub fn with_rapid_commit(&mut self) -> &mut Self {
+        self.allow_rapid_commit = true;
+        self
+    }
+
+    /// Makes the server answer the legacy BOOTP clients (RFC 1534).
+    ///
+    /// A `BOOTREQUEST` without the DHCP message type gets an address bound for good
+    /// and a `BOOTREPLY` with the basic network configuration.
+    /// If not called during building, such requests are dropped as invalid.
+    pub fn with_bootp(&mut self) -> &mut Self {
+        self.allow_bootp = true;
+        self
+    }
+
+    /// Makes the server the authority on the network (the only DHCP server on it).
+    ///
+    /// A client in INIT-REBOOT state requesting an address outside the server pools
+    /// gets a `DHCPNAK`, so it restarts at once instead of waiting for a timeout.
+    /// If not called during building, the server remains silent as RFC 2131 §4.3.2
+    /// requires for a server with no record of the client.
+    pub fn with_authoritative_mode(&mut self) -> &mut Self {
+        self.is_authoritative = true;
+        self
+    }
+
+    /// Makes the server ping every allocated address before offering it (RFC 2131 §4.3.1).
+    ///
+    /// An address answering within `timeout` is frozen and the next one is tried.
+    /// The `DHCPDISCOVER` is kept until the reply or the timeout, which delays the offer,
+    /// but the requests of the other clients are processed in the meantime.
+    /// Raw ICMP sockets require privileges, so building fails if the socket cannot be opened.
+    /// If not called during building, addresses are offered without probing.
+    pub fn with_conflict_detection(&mut self, timeout: Duration) -> &mut Self {
+        self.conflict_detection = Some(timeout);
+        self
+    }
+
+    /// Makes the server probe every allocated address with ARP before offering it.
+    ///
+    /// Is an alternative to `with_conflict_detection` for the networks filtering ICMP.
+    /// The ARP request is sent on the server interface and an address answering
+    /// within `timeout` is frozen and the next one is tried.
+    /// Each probe has its own packet socket polled by the reactor, so the `DHCPDISCOVER`
+    /// waits for the reply or the timeout while the other requests are processed.
+    /// The probing errors, e.g. the lack of privileges, are logged and do not stop offering.
+    /// Building fails if both probes are enabled.
+    /// If not called during building, addresses are offered without ARP probing.
+    pub fn with_arp_probe(&mut self, timeout: Duration) -> &mut Self {
+        self.arp_probe = Some(timeout);
+        self
+    }
+
+    /// Limits the rate of all the incoming messages together with a token bucket.
+    ///
+    /// Up to `burst` messages are processed at once and the bucket is refilled
+    /// with `requests_per_second` tokens per second. The excess messages are dropped.
+    /// If not called during building, every message is processed.
+    pub fn with_rate_limit(&mut self, requests_per_second: u32, burst: u32) -> &mut Self {
+        self.rate_limit = Some((requests_per_second, burst));
+        self
+    }
+
+    /// Limits the rate of the incoming messages of each client with a token bucket.
+    ///
+    /// Works like `with_rate_limit`, but the buckets are kept per client hardware address,
+    /// so a flooding client does not affect the others. The renewals have separate buckets,
+    /// so a client flooding with `DHCPDISCOVER` may still renew its lease.
+    /// If not called during building, the clients are not limited.
+    pub fn with_client_rate_limit(&mut self, requests_per_second: u32, burst: u32) -> &mut Self {
+        self.client_rate_limit = Some((requests_per_second, burst));
+        self
+    }
+
+    /// Sets the channel signaled once the storage has become ready and the server
+    /// starts processing requests.
+    ///
+    /// No request is processed before the storage is loaded, so addresses in use
+    /// are not handed out during the load window anyway. The signal lets the caller
+    /// await the moment, e.g. to report the service readiness.
+    pub fn with_ready_signal(&mut self, ready_signal: oneshot::Sender<()>) -> &mut Self {
+        self.ready_signal = Some(ready_signal);
+        self
+    }
+
+    /// Makes the server deallocate the addresses of expired leases periodically.
+    ///
+    /// Expired leases do not hold their addresses anyway, but sweeping keeps the storage
+    /// from growing with stale entries on a long-running server.
+    /// If not called during building, the leases are only checked when an address is requested.
+    pub fn with_sweep_interval(&mut self, sweep_interval: Duration) -> &mut Self {
+        self.sweep_interval = Some(sweep_interval);
+        self
+    }
+
+    /// Serves the clients without reservations from the guest pool.
+    ///
+    /// Such clients get only the minimal guest configuration. The clients allowed with
+    /// `with_allowed_clients` are known as well and get the full configuration,
+    /// while the others are served from the guest pool instead of being refused.
+    /// If not called during building, every client gets the full configuration.
+    pub fn with_guest_pool(&mut self, guest_pool: GuestPool) -> &mut Self {
+        self.guest_pool = Some(guest_pool);
+        self
+    }
+
+    /// Adds a subnet served through relay agents.
+    ///
+    /// The requests relayed from the subnet are served from its dynamic pool and get
+    /// its configuration. The requests with zero `giaddr` and the ones relayed from
+    /// the subnets not added are served from the directly connected subnet.
+    pub fn with_subnet(&mut self, subnet: SubnetConfig) -> &mut Self {
+        self.subnets.push(subnet);
+        self
+    }
+
+    /// Adds a vendor class served from its own dynamic pool, e.g. to boot PXE clients.
+    ///
+    /// The directly connected clients with matching vendor class identifiers get
+    /// the addresses from the class pool and the class boot options along with
+    /// the default configuration. The classes are matched in the order they are added.
+    pub fn with_vendor_class(&mut self, vendor_class: VendorClassConfig) -> &mut Self {
+        self.vendor_classes.push(vendor_class);
+        self
+    }
+
+    /// Sets the boot file name sent in the BOOTP `file` field for network booting.
+    ///
+    /// It is also sent in the option 67 to the clients requesting it.
+    /// If not called during building, the field is left empty.
+    pub fn with_boot_file(&mut self, boot_file: String) -> &mut Self {
+        self.boot_file = Some(boot_file);
+        self
+    }
+
+    /// Sets the boot server address sent in the BOOTP `siaddr` field for network booting.
+    ///
+    /// It is also sent in the option 66 to the clients requesting it.
+    /// If not called during building, the server address is sent in `DHCPACK` only.
+    pub fn with_next_server(&mut self, next_server: Ipv4Addr) -> &mut Self {
+        self.next_server = Some(next_server);
+        self
+    }
+
+    /// Sets the bounds of the lease times granted to clients in seconds.
+    ///
+    /// The requested lease times are clamped to `[min, max]`, and `default` is granted
+    /// to the clients not requesting a lease time. Must satisfy `min <= default <= max`,
+    /// or building fails. The reserved lease times are not limited.
+    /// If not called during building, the lease time is 24 hours by default
+    /// and at most one week.
+    pub fn with_lease_bounds(&mut self, min: u32, max: u32, default: u32) -> &mut Self {
+        self.lease_bounds = Some((min, max, default));
+        self
+    }
+
+    /// Makes the declined addresses allocatable again after the cooldown.
+    ///
+    /// The addresses found in use by the conflict detection are frozen the same way.
+    /// The cooldown of the addresses frozen before a restart is started anew.
+    /// If not called during building, the declined addresses are frozen permanently.
+    pub fn with_decline_cooldown(&mut self, decline_cooldown: Duration) -> &mut Self {
+        self.decline_cooldown = Some(decline_cooldown);
+        self
+    }
+
+    /// Sets the NTP servers sent in the option 42 to the clients requesting it.
+    ///
+    /// The servers are sent to the clients of the relayed subnets as well.
+    /// If not called during building, the option is not sent.
+    pub fn with_ntp_servers(&mut self, ntp_servers: Vec<Ipv4Addr>) -> &mut Self {
+        self.ntp_servers = ntp_servers;
+        self
+    }
+
+    /// Sets the domain name sent in the option 15 to the clients requesting it.
+    ///
+    /// The name is sent to the clients of the relayed subnets as well.
+    /// If not called during building, the option is not sent.
+    pub fn with_domain_name(&mut self, domain_name: String) -> &mut Self {
+        self.domain_name = Some(domain_name);
+        self
+    }
+
+    /// Sets the domain search list sent in the option 119 to the clients requesting it.
+    ///
+    /// The list is sent to the clients of the relayed subnets as well.
+    /// If not called during building, the option is not sent.
+    pub fn with_domain_search(&mut self, domain_search: Vec<String>) -> &mut Self {
+        self.domain_search = domain_search;
+        self
+    }
+
+    /// Sets the handler notified when an address is offered, assigned, renewed,
+    /// released, declined or deallocated on expiration.
+    ///
+    /// If not called during building, the lease events are not reported.
+    pub fn with_lease_event_handler(
+        &mut self,
+        lease_event_handler: LeaseEventHandler,
+    ) -> &mut Self {
+        self.lease_event_handler = Some(lease_event_handler);
+        self
+    }
+
+    /// Returns the metrics the built server will update.
+    ///
+    /// May be called any number of times, e.g. to pass the handle to a metrics exporter
+    /// reading the snapshots while the server is running.
+    pub fn metrics_handle(&self) -> Arc<ServerMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Sets the key of the RFC 3118 delayed authentication.
+    ///
+    /// The requests without a valid HMAC-MD5 signature or with a replay detection value
+    /// not greater than the previous one of the client are dropped, and every response is signed.
+    /// The HMAC is verified and computed by the `DhcpFramed` socket, so a socket passed
+    /// to `finish_with_socket` must be given the key with `DhcpFramed::set_auth_key`.
+    /// If not called during building, the messages are not authenticated.
+    pub fn with_auth_key(&mut self, auth_key: AuthKey) -> &mut Self {
+        self.auth_key = Some(auth_key);
+        self
+    }
+
+    /// Sets the sink recording every received and sent message.
+    ///
+    /// If not called during building, the messages are only logged.
+    pub fn with_audit_sink(&mut self, audit_sink: Box<AuditSink>) -> &mut Self {
+        self.audit_sink = Some(audit_sink);
+        self
+    }
+
+    /// Makes the server process the requests without acting on them.
+    ///
+    /// The decisions are logged, audited and reported as lease events, but no responses
+    /// are sent, no ARP entries are changed and the changes are kept in RAM, so the storage
+    /// is only read. The conflict probes are still sent if conflict detection is enabled.
+    pub fn with_dry_run(&mut self) -> &mut Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Sets the UDP port `finish` binds the server socket to.
+    ///
+    /// The port 0 makes the OS choose an ephemeral one, which is read back with `local_addr`.
+    /// If not called during building, the port is 67.
+    pub fn with_bind_port(&mut self, bind_port: u16) -> &mut Self {
+        self.bind_port = Some(bind_port);
+        self
+    }
+
     /// Sets the CPU pool size used for BPF communication.
     ///
     /// If not called during building, the BPF object will use its default pool size.
@@ -110,75 +626,321 @@ where
         self
     }
 
+    /// Sets the IP TTL of the hardware unicasts sent via BPF.
+    ///
+    /// Some equipment and test setups need a specific TTL, e.g. 1 to keep the packets on the link.
+    /// If not called during building, the TTL is 64.
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    pub fn with_bpf_ip_ttl(&mut self, bpf_ip_ttl: u8) -> &mut Self {
+        self.bpf_ip_ttl = Some(bpf_ip_ttl);
+        self
+    }
+
+    /// Scopes the server socket to the `iface_name` interface with `SO_BINDTODEVICE`.
+    ///
+    /// The requests from other interfaces are not received and the responses
+    /// are always sent via `iface_name`, so a server may be run per interface
+    /// on a multi-homed host. If not called during building, every interface is served.
+    #[cfg(target_os = "linux")]
+    pub fn with_device_binding(&mut self) -> &mut Self {
+        self.bind_to_device = true;
+        self
+    }
+
     /// Consumes the builder and returns the built server.
+    ///
+    /// The server binds its UDP socket to `0.0.0.0:67` or to the port set with `with_bind_port`.
     pub fn finish(self) -> io::Result<Server<S>> {
-        Server::new(
-            self.server_ip_address,
-            self.iface_name,
-            self.static_address_range,
-            self.dynamic_address_range,
-            self.storage,
-            self.subnet_mask,
-            self.routers,
-            self.domain_name_servers,
-            self.static_routes,
-            self.classless_static_routes,
-            self.bpf_num_threads_size,
-        )
+        let port = self.bind_port.unwrap_or(DHCP_PORT_SERVER);
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+        #[cfg(target_os = "linux")]
+        let socket = if self.bind_to_device {
+            let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+            let socket = device::bind_to_device(&addr, &self.iface_name)?;
+            UdpSocket::from_std(socket, &Handle::default())?
+        } else {
+            UdpSocket::bind(&addr)?
+        };
+        #[cfg(not(target_os = "linux"))]
+        let socket = UdpSocket::bind(&addr)?;
+        socket.set_broadcast(true)?;
+        let mut socket = DhcpFramed::new(socket)?;
+        socket.set_auth_key(self.auth_key.to_owned());
+
+        self.finish_with_socket(socket)
+    }
+
+    /// Consumes the builder and returns the server working over the socket
+    /// passed by systemd (`LISTEN_FDS` socket activation).
+    ///
+    /// The service manager owns the socket, so the server does not need the privileges
+    /// to bind to `0.0.0.0:67`. The socket must be bound with the `Broadcast=yes` option.
+    #[cfg(all(target_os = "linux", feature = "systemd"))]
+    pub fn finish_with_activated_socket(self) -> io::Result<Server<S>> {
+        let socket = systemd::activated_socket()?;
+        let socket = UdpSocket::from_std(socket, &Handle::default())?;
+        socket.set_broadcast(true)?;
+        let mut socket = DhcpFramed::new(socket)?;
+        socket.set_auth_key(self.auth_key.to_owned());
+
+        self.finish_with_socket(socket)
+    }
+
+    /// Consumes the builder and returns the server working over the given DHCP socket.
+    ///
+    /// Any `Stream + Sink` of DHCP messages may be used instead of the default `DhcpFramed`.
+    pub fn finish_with_socket<T>(self, socket: T) -> io::Result<Server<S, T>>
+    where
+        T: Stream<Item = DhcpStreamItem, Error = io::Error>
+            + Sink<SinkItem = DhcpSinkItem, SinkError = io::Error>,
+    {
+        Server::new(socket, self)
+    }
+}
+
+/// A relayed subnet with its message building helper.
+struct Subnet {
+    config: SubnetConfig,
+    builder: MessageBuilder,
+}
+
+/// A vendor class with its message building helper.
+struct VendorClass {
+    config: VendorClassConfig,
+    builder: MessageBuilder,
+}
+
+impl VendorClass {
+    /// Extends the directly connected subnet configuration with the class boot options.
+    fn new(config: VendorClassConfig, builder: &MessageBuilder) -> Self {
+        let mut builder = builder.to_owned();
+        builder.set_boot_options(
+            config.tftp_server_name.to_owned(),
+            config.bootfile_name.to_owned(),
+        );
+        VendorClass { config, builder }
     }
 }
 
+/// A request kept until the probe of the address allocated for it completes.
+struct ProbingRequest {
+    addr: SocketAddr,
+    request: Message,
+    /// The address being probed.
+    address: Ipv4Addr,
+    /// The completed probes of the request.
+    probed: Vec<(Ipv4Addr, bool)>,
+}
+
 /// The struct implementing the `Future` trait.
-pub struct Server<S>
+pub struct Server<S, T = DhcpFramed>
 where
     S: Storage,
+    T: Stream<Item = DhcpStreamItem, Error = io::Error>
+        + Sink<SinkItem = DhcpSinkItem, SinkError = io::Error>,
 {
     /// The server UDP socket.
-    socket: DhcpFramed,
+    socket: T,
     /// The IP address the server is hosted on.
     server_ip_address: Ipv4Addr,
     /// The interface the server works on.
-    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "macos"
+    ))]
     iface_name: String,
     /// The DHCP message building helper.
     builder: MessageBuilder,
+    /// The DHCP message building helper for the guest pool clients if it is set.
+    guest_builder: Option<MessageBuilder>,
+    /// The subnets served through relay agents.
+    subnets: Vec<Subnet>,
+    /// The vendor classes of the directly connected clients.
+    vendor_classes: Vec<VendorClass>,
+    /// The network boot server address.
+    next_server: Option<Ipv4Addr>,
+    /// The network boot file name.
+    boot_file: Option<String>,
+    /// The NTP servers reapplied on reconfiguration.
+    ntp_servers: Vec<Ipv4Addr>,
+    /// The domain name reapplied on reconfiguration.
+    domain_name: Option<String>,
+    /// The domain search list reapplied on reconfiguration.
+    domain_search: Vec<String>,
+    /// The handler of the lease events.
+    lease_event_handler: Option<LeaseEventHandler>,
+    /// The counters shared with the `ServerBuilder::metrics_handle` holders.
+    metrics: Arc<ServerMetrics>,
+    /// The key the requests are verified and the responses are signed with.
+    auth_key: Option<AuthKey>,
+    /// The replay detection counter of the signed responses.
+    replay_detection: u64,
+    /// The last replay detection values of the authenticated clients, keyed by the client ID.
+    ///
+    /// The value is forgotten once the client lease is released or deallocated.
+    client_replay_detections: HashMap<Vec<u8>, u64>,
     /// The DHCP database using a persistent storage object.
     database: Database<S>,
-    /// The asynchronous `netsh` processes used to work with ARP entries.
+    /// Whether to process the requests without sending responses or changing ARP entries.
+    dry_run: bool,
+    /// The hardware addresses of the only clients to be served if set.
+    allowed_clients: Option<Vec<MacAddress>>,
+    /// Whether to answer unserved `DHCPDISCOVER` messages with a no-lease `DHCPOFFER`.
+    signal_no_lease: bool,
+    /// The control channel receiving commands at runtime.
+    commands: Option<mpsc::UnboundedReceiver<ServerCommand>>,
+    /// The callback naming the clients.
+    hostname_generator: Option<HostnameGenerator>,
+    /// The option the client-requested name is taken from.
+    name_policy: NamePolicy,
+    /// Whether to drop requests from unexpected source ports if they are checked.
+    source_port_check: Option<bool>,
+    /// The only options sent in response to `DHCPINFORM` if set.
+    inform_options: Option<Vec<OptionTag>>,
+    /// Whether to honor or override the client `BROADCAST` flag.
+    broadcast_policy: BroadcastPolicy,
+    /// The sink recording every received and sent message.
+    audit_sink: Option<Box<AuditSink>>,
+    /// Whether to answer `DHCPDISCOVER` with the Rapid Commit option with `DHCPACK`.
+    allow_rapid_commit: bool,
+    /// Whether to answer the BOOTP requests without the DHCP message type.
+    allow_bootp: bool,
+    /// Whether to send `DHCPNAK` to INIT-REBOOT clients requesting foreign addresses.
+    is_authoritative: bool,
+    /// The address prober used before offering if conflict detection is enabled.
+    prober: Option<Box<ConflictProber>>,
+    /// The completed probes of the request being processed, so they are not repeated
+    /// when the request is processed again.
+    probed: Vec<(Ipv4Addr, bool)>,
+    /// The address the request being processed waits for the probe of.
+    probing_address: Option<Ipv4Addr>,
+    /// The requests waiting for their probes, keyed by the transaction ID and
+    /// the client hardware address, while the other requests are processed.
+    probing_requests: HashMap<(u32, MacAddress), ProbingRequest>,
+    /// The global incoming message rate limiter.
+    rate_limiter: Option<RateLimiter>,
+    /// The per-client incoming message rate limiter.
+    client_rate_limiter: Option<ClientRateLimiter>,
+    /// The channel signaled once the storage is ready for the first time.
+    ready_signal: Option<oneshot::Sender<()>>,
+    /// The timer of the periodic expired lease sweeping.
+    sweep_timer: Option<Interval>,
+    /// Whether the sweep started by the timer waits for the storage.
+    is_sweep_pending: bool,
+    /// Whether the active lease counting waits for the storage.
+    is_count_pending: bool,
+    /// Whether the leases have changed since the last active lease counting.
+    is_count_requested: bool,
+    /// The channel of the lease export waiting for the storage.
+    pending_export: Option<mpsc::UnboundedSender<String>>,
+    /// The request and its source waiting for the storage.
+    pending_request: Option<(SocketAddr, Message)>,
+    /// The handles of the injected ARP entries, keyed by the address.
+    ///
+    /// A handle runs the asynchronous `netsh` processes and deletes its entry once dropped.
     #[cfg(target_os = "windows")]
-    arp: Option<dhcp_arp::Arp>,
+    arp: HashMap<Ipv4Addr, dhcp_arp::Arp>,
     /// The object encapsulating BPF functionality.
     #[cfg(any(target_os = "freebsd", target_os = "macos"))]
     bpf_data: BpfData,
 }
 
-impl<S> Server<S>
+impl<S, T> Server<S, T>
 where
     S: Storage,
+    T: Stream<Item = DhcpStreamItem, Error = io::Error>
+        + Sink<SinkItem = DhcpSinkItem, SinkError = io::Error>,
 {
     /// Creates a server future.
-    #[allow(unused_variables)]
-    fn new(
-        server_ip_address: Ipv4Addr,
-        iface_name: String,
-        static_address_range: (Ipv4Addr, Ipv4Addr),
-        dynamic_address_range: (Ipv4Addr, Ipv4Addr),
-        storage: S,
-        subnet_mask: Ipv4Addr,
-        routers: Vec<Ipv4Addr>,
-        domain_name_servers: Vec<Ipv4Addr>,
-        static_routes: Vec<(Ipv4Addr, Ipv4Addr)>,
-        classless_static_routes: Vec<(Ipv4Addr, Ipv4Addr, Ipv4Addr)>,
-        bpf_num_threads_size: Option<usize>,
-    ) -> io::Result<Self> {
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), DHCP_PORT_SERVER);
-        let socket = UdpSocket::bind(&addr)?;
-        socket.set_broadcast(true)?;
+    fn new(socket: T, builder: ServerBuilder<S>) -> io::Result<Self> {
+        #[allow(unused_variables)]
+        let ServerBuilder {
+            server_ip_address,
+            iface_name,
+            static_address_range,
+            dynamic_address_range,
+            storage,
+            subnet_mask,
+            routers,
+            domain_name_servers,
+            static_routes,
+            classless_static_routes,
+            allowed_clients,
+            signal_no_lease,
+            commands,
+            hostname_generator,
+            name_policy,
+            deterministic_allocation,
+            reservations,
+            hardware_reservations,
+            excluded_address_ranges,
+            source_port_check,
+            inform_options,
+            broadcast_policy,
+            audit_sink,
+            allow_rapid_commit,
+            allow_bootp,
+            is_authoritative,
+            conflict_detection,
+            arp_probe,
+            rate_limit,
+            client_rate_limit,
+            ready_signal,
+            sweep_interval,
+            guest_pool,
+            subnets,
+            vendor_classes,
+            next_server,
+            boot_file,
+            lease_bounds,
+            decline_cooldown,
+            ntp_servers,
+            domain_name,
+            domain_search,
+            lease_event_handler,
+            metrics,
+            auth_key,
+            dry_run,
+            bpf_num_threads_size,
+            bpf_ip_ttl,
+            ..
+        } = builder;
+
+        let masks = guest_pool
+            .iter()
+            .map(|guest_pool| guest_pool.subnet_mask)
+            .chain(subnets.iter().map(|subnet| subnet.subnet_mask));
+        for mask in Some(subnet_mask).into_iter().chain(masks) {
+            config::validate_subnet_mask(&mask)?;
+        }
+        if let Some(ref boot_file) = boot_file {
+            if boot_file.len() >= SIZE_BOOT_FILENAME {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The boot file name {} is too long", boot_file),
+                ));
+            }
+        }
+
+        config::validate_excluded_ranges(&excluded_address_ranges)?;
+
+        if let Some((min, max, default)) = lease_bounds {
+            if min > default || default > max {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The default lease time {} is not within [{}, {}]",
+                        default, min, max
+                    ),
+                ));
+            }
+        }
 
-        let socket = DhcpFramed::new(socket)?;
         let hostname = hostname::get_hostname();
 
-        let builder = MessageBuilder::new(
+        let mut builder = MessageBuilder::new(
             server_ip_address,
             hostname,
             subnet_mask,
@@ -186,42 +948,649 @@ where
             domain_name_servers,
             static_routes,
             classless_static_routes,
+            inform_options.to_owned(),
         );
+        builder.set_next_server(next_server, boot_file.to_owned());
+        builder.set_ntp_servers(ntp_servers.to_owned());
+        builder.set_domain(domain_name.to_owned(), domain_search.to_owned());
+        let guest_builder = guest_pool.as_ref().map(|guest_pool| {
+            MessageBuilder::new(
+                server_ip_address,
+                hostname::get_hostname(),
+                guest_pool.subnet_mask,
+                vec![guest_pool.router],
+                vec![guest_pool.domain_name_server],
+                Vec::new(),
+                Vec::new(),
+                inform_options.to_owned(),
+            )
+        });
+        let subnets: Vec<Subnet> = subnets
+            .into_iter()
+            .map(|config| {
+                let mut builder = MessageBuilder::new(
+                    server_ip_address,
+                    hostname::get_hostname(),
+                    config.subnet_mask,
+                    config.routers.to_owned(),
+                    config.domain_name_servers.to_owned(),
+                    Vec::new(),
+                    Vec::new(),
+                    inform_options.to_owned(),
+                );
+                builder.set_next_server(next_server, boot_file.to_owned());
+                builder.set_ntp_servers(ntp_servers.to_owned());
+                builder.set_domain(domain_name.to_owned(), domain_search.to_owned());
+                Subnet { config, builder }
+            })
+            .collect();
+        let vendor_classes: Vec<VendorClass> = vendor_classes
+            .into_iter()
+            .map(|config| VendorClass::new(config, &builder))
+            .collect();
+
+        let prober: Option<Box<ConflictProber>> = match (conflict_detection, arp_probe) {
+            (Some(_), Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The ICMP and ARP conflict detection cannot be enabled together",
+                ))
+            }
+            (Some(timeout), None) => Some(Box::new(IcmpProber::new(timeout).map_err(|error| {
+                io::Error::new(
+                    error.kind(),
+                    format!("Conflict detection socket error: {}", error),
+                )
+            })?)),
+            (None, Some(timeout)) => Some(Box::new(ArpProber::new(iface_name.to_owned(), timeout))),
+            (None, None) => None,
+        };
+        let rate_limiter = rate_limit
+            .map(|(requests_per_second, burst)| RateLimiter::new(requests_per_second, burst));
+        let client_rate_limiter = client_rate_limit
+            .map(|(requests_per_second, burst)| ClientRateLimiter::new(requests_per_second, burst));
+        let sweep_timer =
+            sweep_interval.map(|interval| Interval::new(Instant::now() + interval, interval));
 
-        let database = Database::new(static_address_range, dynamic_address_range, storage);
+        let mut database = Database::new(static_address_range, dynamic_address_range, storage);
+        database.set_deterministic_allocation(deterministic_allocation);
+        if let Some((min, max, default)) = lease_bounds {
+            database.set_lease_bounds(min, max, default);
+        }
+        database.set_decline_cooldown(decline_cooldown);
+        if dry_run {
+            database.set_dry_run();
+        }
+        for (client_id, address, lease_time) in reservations {
+            database.reserve(client_id, address, lease_time);
+        }
+        for (client_hardware_address, address) in hardware_reservations {
+            database.reserve_hardware_address(client_hardware_address, address, None);
+        }
+        for excluded_address_range in excluded_address_ranges {
+            database.exclude(excluded_address_range);
+        }
+        if let Some(guest_pool) = guest_pool {
+            database.set_guest_pool(guest_pool.address_range);
+        }
+        for subnet in &subnets {
+            database.add_subnet_pool(subnet.config.dynamic_address_range);
+        }
+        for vendor_class in &vendor_classes {
+            database.add_subnet_pool(vendor_class.config.dynamic_address_range);
+        }
 
         Ok(Server {
             socket,
             server_ip_address,
-            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            #[cfg(any(
+                target_os = "windows",
+                target_os = "linux",
+                target_os = "freebsd",
+                target_os = "macos"
+            ))]
             iface_name: iface_name.to_owned(),
             builder,
+            guest_builder,
+            subnets,
+            vendor_classes,
+            next_server,
+            boot_file,
+            ntp_servers,
+            domain_name,
+            domain_search,
+            lease_event_handler,
+            metrics,
+            auth_key,
+            // the counter must keep increasing after restarts, so it starts from the current time
+            replay_detection: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs() << 32)
+                .unwrap_or_default(),
+            client_replay_detections: HashMap::new(),
             database,
+            dry_run,
+            allowed_clients,
+            signal_no_lease,
+            commands,
+            hostname_generator,
+            name_policy,
+            source_port_check,
+            inform_options,
+            broadcast_policy,
+            audit_sink,
+            allow_rapid_commit,
+            allow_bootp,
+            is_authoritative,
+            prober,
+            probed: Vec::new(),
+            probing_address: None,
+            probing_requests: HashMap::new(),
+            rate_limiter,
+            client_rate_limiter,
+            ready_signal,
+            sweep_timer,
+            is_sweep_pending: false,
+            is_count_pending: false,
+            is_count_requested: true,
+            pending_export: None,
+            pending_request: None,
             #[cfg(target_os = "windows")]
-            arp: None,
+            arp: HashMap::new(),
             #[cfg(any(target_os = "freebsd", target_os = "macos"))]
-            bpf_data: BpfData::new(&iface_name, bpf_num_threads_size)?,
+            bpf_data: BpfData::new(&iface_name, bpf_num_threads_size, bpf_ip_ttl)?,
         })
     }
 
-    /// Chooses the destination IP according to RFC 2131 rules.
+    /// Applies all the commands received through the control channel so far.
     ///
-    /// Performs the ARP query in hardware unicast cases and sets the `arp` field
-    /// if ARP processing is expected to be too long for the tokio reactor.
-    /// The bool flag is `true` if hardware unicast is required.
-    fn destination(&mut self, request: &Message, response: &Message) -> (Ipv4Addr, bool) {
-        if !request.client_ip_address.is_unspecified() {
-            return (request.client_ip_address, false);
-        }
+    /// Returns `Async::NotReady` while the lease export waits for the storage.
+    fn poll_commands(&mut self) -> Async<()> {
+        loop {
+            if let Some(leases) = self.pending_export.take() {
+                self.database.rewind_journal();
+                match self.database.export_isc() {
+                    Ok(Async::Ready(text)) => {
+                        let _ = leases.unbounded_send(text);
+                    }
+                    Ok(Async::NotReady) => {
+                        self.pending_export = Some(leases);
+                        return Async::NotReady;
+                    }
+                    Err(error) => warn!("Lease exporting error: {}", error),
+                }
+                self.database.end_journal();
+            }
 
-        if request.is_broadcast {
-            return (Ipv4Addr::new(255, 255, 255, 255), false);
+            let command = match self.commands {
+                Some(ref mut commands) => match commands.poll() {
+                    Ok(Async::Ready(Some(command))) => Some(command),
+                    Ok(Async::NotReady) => return Async::Ready(()),
+                    Ok(Async::Ready(None)) | Err(_) => None,
+                },
+                None => return Async::Ready(()),
+            };
+
+            match command {
+                Some(ServerCommand::Reconfigure(config)) => self.reconfigure(config),
+                Some(ServerCommand::ExportIscLeases(leases)) => {
+                    self.database.begin_journal();
+                    self.pending_export = Some(leases);
+                }
+                None => {
+                    info!("The control channel has been closed");
+                    self.commands = None;
+                    return Async::Ready(());
+                }
+            }
         }
+    }
 
-        #[cfg(any(target_os = "linux", target_os = "windows"))]
-        {
-            info!(
-                "Injecting an ARP entry {} -> {}",
+    /// Sweeps the expired leases each time the sweep timer fires
+    /// and counts the active leases after the sweep or a lease change.
+    ///
+    /// Returns `Async::NotReady` while the sweep or the lease counting waits for the storage.
+    fn poll_sweep(&mut self) -> Async<()> {
+        loop {
+            if !self.is_sweep_pending && !self.is_count_pending {
+                let is_sweep_due = match self.sweep_timer {
+                    Some(ref mut sweep_timer) => match sweep_timer.poll() {
+                        Ok(Async::Ready(Some(_))) => true,
+                        Ok(Async::Ready(None)) | Ok(Async::NotReady) => false,
+                        Err(error) => {
+                            warn!("Sweep timer error: {}", error);
+                            false
+                        }
+                    },
+                    None => false,
+                };
+                if is_sweep_due {
+                    self.is_sweep_pending = true;
+                } else if self.is_count_requested {
+                    self.is_count_requested = false;
+                    self.is_count_pending = true;
+                    self.database.begin_journal();
+                } else {
+                    return Async::Ready(());
+                }
+            }
+
+            // the sweep resumes its walk itself, so it is not journaled
+            if self.is_sweep_pending {
+                match self.database.sweep() {
+                    Ok(Async::Ready(ref swept)) if swept.is_empty() => {}
+                    Ok(Async::Ready(swept)) => {
+                        info!("Deallocated {} addresses of expired leases", swept.len());
+                        for (client_id, address) in swept {
+                            self.client_replay_detections.remove(&client_id);
+                            self.report(LeaseEventKind::Expired, &client_id, None, address, None);
+                        }
+                    }
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    Err(error) => warn!("Lease sweeping error: {}", error),
+                }
+                self.is_sweep_pending = false;
+                self.is_count_requested = false;
+                self.is_count_pending = true;
+                self.database.begin_journal();
+            }
+
+            self.database.rewind_journal();
+            match self.database.count_active_leases() {
+                Ok(Async::Ready(active_leases)) => self.metrics.set_active_leases(active_leases),
+                Ok(Async::NotReady) => return Async::NotReady,
+                Err(error) => warn!("Lease counting error: {}", error),
+            }
+            self.database.end_journal();
+            self.is_count_pending = false;
+        }
+    }
+
+    /// Deallocates the leases left outside the pools by the last reconfiguration.
+    ///
+    /// Returns `Async::NotReady` while the reclaiming waits for the storage.
+    fn poll_reclaim(&mut self) -> Async<()> {
+        // the reclaiming resumes its walk itself, so it is not journaled
+        match self.database.reclaim() {
+            Ok(Async::Ready(reclaimed)) => {
+                if !reclaimed.is_empty() {
+                    info!(
+                        "Deallocated {} addresses outside the new pools",
+                        reclaimed.len()
+                    );
+                }
+                for (client_id, address) in reclaimed {
+                    self.client_replay_detections.remove(&client_id);
+                    self.report(LeaseEventKind::Reclaimed, &client_id, None, address, None);
+                }
+            }
+            Ok(Async::NotReady) => return Async::NotReady,
+            Err(error) => warn!("Lease reclaiming error: {}", error),
+        }
+        Async::Ready(())
+    }
+
+    /// Swaps the client configuration data, the address pools, the exclusions and the reservations.
+    ///
+    /// The requests received after the swap are processed with the new configuration.
+    /// The leases left outside the new pools are deallocated.
+    /// An invalid configuration is ignored.
+    fn reconfigure(&mut self, config: ServerConfig) {
+        if let Err(error) = config.validate() {
+            warn!("Ignoring the invalid configuration: {}", error);
+            return;
+        }
+        info!("Reconfiguring the server");
+        debug!("{:?}", config);
+
+        self.database.reconfigure(&config);
+
+        self.builder = MessageBuilder::new(
+            self.server_ip_address,
+            hostname::get_hostname(),
+            config.subnet_mask,
+            config.routers,
+            config.domain_name_servers,
+            config.static_routes,
+            config.classless_static_routes,
+            self.inform_options.to_owned(),
+        );
+        self.builder
+            .set_next_server(self.next_server, self.boot_file.to_owned());
+        self.builder.set_ntp_servers(self.ntp_servers.to_owned());
+        self.builder
+            .set_domain(self.domain_name.to_owned(), self.domain_search.to_owned());
+        for vendor_class in &mut self.vendor_classes {
+            let config = vendor_class.config.to_owned();
+            *vendor_class = VendorClass::new(config, &self.builder);
+        }
+    }
+
+    /// Names the client with the generated hostname if the generator is set.
+    ///
+    /// The hostname is put into the response and recorded in the client lease.
+    fn name_client(
+        &mut self,
+        client_id: &[u8],
+        request: &Message,
+        response: &mut Message,
+    ) -> Async<()> {
+        let requested_name = self.name_policy.requested_name(request);
+        #[allow(clippy::option_as_ref_deref)]
+        let hostname = match self.hostname_generator {
+            Some(ref mut hostname_generator) => hostname_generator(
+                request,
+                response.your_ip_address,
+                requested_name.as_ref().map(String::as_str),
+            ),
+            None => return Async::Ready(()),
+        };
+
+        match self.database.name(client_id, hostname.to_owned()) {
+            Ok(Async::Ready(())) => {}
+            Ok(Async::NotReady) => return Async::NotReady,
+            Err(error) => warn!("Client naming error: {}", error),
+        }
+        response.options.hostname = hostname;
+        Async::Ready(())
+    }
+
+    /// Reports a lease event to the handler if it is set.
+    fn report(
+        &mut self,
+        kind: LeaseEventKind,
+        client_id: &[u8],
+        client_hardware_address: Option<MacAddress>,
+        address: Ipv4Addr,
+        lease_time: Option<u32>,
+    ) {
+        // the offered leases are not active and the renewed ones have been active
+        match kind {
+            LeaseEventKind::Offered | LeaseEventKind::Renewed => {}
+            _ => self.is_count_requested = true,
+        }
+        if let Some(ref mut lease_event_handler) = self.lease_event_handler {
+            lease_event_handler(LeaseEvent {
+                kind,
+                client_id: client_id.to_vec(),
+                client_hardware_address,
+                address,
+                lease_time,
+            });
+        }
+    }
+
+    /// Checks whether the request has come from the expected UDP source port.
+    ///
+    /// Returns `false` if the request must be dropped.
+    fn check_source_port(&self, request: &Message, addr: &SocketAddr) -> bool {
+        let drop = match self.source_port_check {
+            Some(drop) => drop,
+            None => return true,
+        };
+
+        let expected = if request.addresses().is_relayed() {
+            DHCP_PORT_SERVER
+        } else {
+            DHCP_PORT_CLIENT
+        };
+        if addr.port() == expected {
+            return true;
+        }
+
+        warn!(
+            "Request from {} has come from unexpected port {} instead of {}",
+            request.client_hardware_address,
+            addr,
+            expected
+        );
+        !drop
+    }
+
+    /// Allocates an address for the client, skipping the ones found to be in use.
+    ///
+    /// Every address answering the probe is frozen, so it is not allocated again.
+    fn allocate(&mut self, client_id: &[u8], request: &Message) -> Poll<Offer, Error> {
+        loop {
+            let pool_range = match self.vendor_class(request) {
+                Some(vendor_class) => Some(vendor_class.config.dynamic_address_range),
+                None => self
+                    .subnet(request)
+                    .map(|subnet| subnet.config.dynamic_address_range),
+            };
+            let offer = if self.is_guest(client_id, &request.client_hardware_address) {
+                try_ready!(self
+                    .database
+                    .allocate_guest(client_id, request.options.address_time))
+            } else if let Some(pool_range) = pool_range {
+                try_ready!(self.database.allocate_in_subnet(
+                    client_id,
+                    request.options.address_time,
+                    request.options.address_request,
+                    pool_range,
+                ))
+            } else {
+                try_ready!(self.database.allocate(
+                    client_id,
+                    request.options.address_time,
+                    request.options.address_request,
+                ))
+            };
+
+            // the request is kept until the probe completes
+            let probed = self
+                .probed
+                .iter()
+                .find(|&&(address, _)| address == offer.address)
+                .map(|&(_, is_in_use)| is_in_use);
+            let is_in_use = match probed {
+                Some(is_in_use) => is_in_use,
+                None => match self.poll_probe(&offer.address) {
+                    Async::Ready(is_in_use) => {
+                        self.probed.push((offer.address, is_in_use));
+                        is_in_use
+                    }
+                    Async::NotReady => {
+                        self.probing_address = Some(offer.address);
+                        return Ok(Async::NotReady);
+                    }
+                },
+            };
+            if !is_in_use {
+                return Ok(Async::Ready(offer));
+            }
+
+            warn!("Address {} is already in use, freezing it", offer.address);
+            try_ready!(self.database.freeze(&offer.address));
+        }
+    }
+
+    /// Polls the probe of the address, which is not in use if it is not probed.
+    ///
+    /// A probing error is logged and the address is considered not in use.
+    fn poll_probe(&mut self, address: &Ipv4Addr) -> Async<bool> {
+        let prober = match self.prober {
+            Some(ref mut prober) => prober,
+            None => return Async::Ready(false),
+        };
+        match prober.poll_in_use(address) {
+            Ok(result) => result,
+            Err(error) => {
+                warn!("Address {} probing error: {}", address, error);
+                Async::Ready(false)
+            }
+        }
+    }
+
+    /// Checks that the replay detection value of the request is greater than
+    /// the previous one of the client and records it.
+    ///
+    /// The HMAC has already been verified by the socket.
+    fn check_replay_detection(&mut self, request: &Message) -> bool {
+        let replay_detection = match request.options.authentication {
+            Some(ref authentication) => authentication.replay_detection,
+            None => return false,
+        };
+        let client_id = match request.options.client_id {
+            Some(ref client_id) => client_id.to_owned(),
+            None => request.client_hardware_address.as_bytes().to_vec(),
+        };
+        let last = self.client_replay_detections.entry(client_id).or_insert(0);
+        if replay_detection <= *last {
+            return false;
+        }
+        *last = replay_detection;
+        true
+    }
+
+    /// Binds an address to a BOOTP client and sends the `BOOTREPLY` (RFC 1534).
+    ///
+    /// BOOTP requests cannot be authenticated, so they are dropped if authentication is enabled.
+    fn process_bootp(&mut self, request: &Message) -> Poll<(), ServerError> {
+        if !self.is_client_allowed(&request.client_hardware_address) {
+            warn!(
+                "Client {} is not allowed to be served",
+                request.client_hardware_address
+            );
+            return Ok(Async::Ready(()));
+        }
+
+        let client_id = match request.options.client_id {
+            Some(ref client_id) => client_id.as_ref(),
+            None => request.client_hardware_address.as_bytes(),
+        };
+        self.database
+            .adopt_client_hardware_address(client_id, &request.client_hardware_address);
+        let ack = match ready!(self.allocate(client_id, request)) {
+            Ok(offer) => ready!(self.database.bind(client_id, &offer.address)),
+            Err(error) => {
+                self.metrics.on_allocation_failure();
+                Err(error)
+            }
+        };
+        match ack {
+            Ok(ack) => {
+                self.report(
+                    LeaseEventKind::Acked,
+                    client_id,
+                    Some(request.client_hardware_address),
+                    ack.address,
+                    Some(ack.lease_time),
+                );
+                let response = self
+                    .client_builder(client_id, request)
+                    .bootp_request_to_reply(request, &ack);
+                let (destination, hw_unicast) = self.destination(request, &response);
+                self.send_response(response, destination, hw_unicast, None)?;
+            }
+            Err(error) => warn!("BOOTP binding error: {}", error),
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Checks whether the client is allowed to be served.
+    ///
+    /// Every client is served if the guest pool is set.
+    fn is_client_allowed(&self, client_hardware_address: &MacAddress) -> bool {
+        match self.allowed_clients {
+            Some(ref allowed_clients) => {
+                self.guest_builder.is_some() || allowed_clients.contains(client_hardware_address)
+            }
+            None => true,
+        }
+    }
+
+    /// Checks whether the client is unknown and must be served from the guest pool.
+    fn is_guest(&self, client_id: &[u8], client_hardware_address: &MacAddress) -> bool {
+        let is_allowed = match self.allowed_clients {
+            Some(ref allowed_clients) => allowed_clients.contains(client_hardware_address),
+            None => false,
+        };
+        self.guest_builder.is_some() && !is_allowed && !self.database.is_reserved(client_id)
+    }
+
+    /// Chooses the message builder with the configuration the client is served with.
+    fn client_builder(&self, client_id: &[u8], request: &Message) -> &MessageBuilder {
+        match self.guest_builder {
+            Some(ref guest_builder)
+                if self.is_guest(client_id, &request.client_hardware_address) =>
+            {
+                guest_builder
+            }
+            _ => {
+                if let Some(vendor_class) = self.vendor_class(request) {
+                    return &vendor_class.builder;
+                }
+                match self.subnet(request) {
+                    Some(subnet) => &subnet.builder,
+                    None => &self.builder,
+                }
+            }
+        }
+    }
+
+    /// Chooses the vendor class of a directly connected client by its class identifier.
+    ///
+    /// Returns `None` for the relayed requests and the clients of no added class.
+    fn vendor_class(&self, request: &Message) -> Option<&VendorClass> {
+        if request.addresses().is_relayed() {
+            return None;
+        }
+        let class_id = request.options.class_id.as_ref()?;
+        self.vendor_classes
+            .iter()
+            .find(|vendor_class| vendor_class.config.matches(class_id))
+    }
+
+    /// Chooses the relayed subnet the `giaddr` of the request belongs to.
+    ///
+    /// Returns `None` for the directly connected subnet.
+    fn subnet(&self, request: &Message) -> Option<&Subnet> {
+        if !request.addresses().is_relayed() {
+            return None;
+        }
+        self.subnets
+            .iter()
+            .find(|subnet| subnet.config.contains(&request.gateway_ip_address))
+    }
+
+    /// Chooses the destination IP according to RFC 2131 rules and the broadcast policy.
+    ///
+    /// Performs the ARP query in hardware unicast cases and adds its handle to the `arp` map
+    /// if ARP processing is expected to be too long for the tokio reactor.
+    /// The bool flag is `true` if hardware unicast is required.
+    fn destination(&mut self, request: &Message, response: &Message) -> (Ipv4Addr, bool) {
+        // the relay agent delivers the response to the client itself
+        if request.addresses().is_relayed() {
+            return (request.gateway_ip_address, false);
+        }
+        if request.addresses().is_from_configured_client() {
+            return (request.client_ip_address, false);
+        }
+
+        let is_broadcast = match self.broadcast_policy {
+            BroadcastPolicy::Honor => request.is_broadcast,
+            BroadcastPolicy::AlwaysBroadcast => true,
+            BroadcastPolicy::AlwaysUnicastWhenPossible => {
+                response.your_ip_address.is_unspecified()
+            }
+        };
+        if is_broadcast {
+            return (Ipv4Addr::new(255, 255, 255, 255), false);
+        }
+        if self.dry_run {
+            return (response.your_ip_address, true);
+        }
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "macos"
+        ))]
+        {
+            info!(
+                "Injecting an ARP entry {} -> {}",
                 request.client_hardware_address, response.your_ip_address,
             );
             match dhcp_arp::add(
@@ -230,9 +1599,13 @@ where
                 self.iface_name.to_owned(),
             ) {
                 #[cfg(target_os = "windows")]
-                Ok(result) => {
-                    self.arp = Some(result);
-                }
+                Ok(arp) => match self.arp.entry(response.your_ip_address) {
+                    // dropping the replaced handle would delete the entry
+                    Entry::Occupied(mut entry) => entry.get_mut().append(arp),
+                    Entry::Vacant(entry) => {
+                        entry.insert(arp);
+                    }
+                },
                 Err(error) => error!("ARP error: {:?}", error),
                 _ => {}
             }
@@ -251,245 +1624,2433 @@ where
         */
     }
 
+    /// Removes the ARP entry injected for the released or declined address.
+    ///
+    /// Relayed clients and dry runs never get ARP entries, so there is nothing to remove.
+    #[allow(unused)]
+    fn remove_arp_entry(&mut self, request: &Message, address: Ipv4Addr) {
+        if request.addresses().is_relayed() || self.dry_run {
+            return;
+        }
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "macos"
+        ))]
+        {
+            info!("Removing the ARP entry of {}", address);
+            // dropping the handle deletes its entry
+            #[cfg(target_os = "windows")]
+            {
+                if self.arp.remove(&address).is_some() {
+                    return;
+                }
+            }
+            if let Err(error) = dhcp_arp::remove(address, self.iface_name.to_owned()) {
+                error!("ARP error: {:?}", error);
+            }
+        }
+    }
+
     /// Sends a response using OS-specific features.
     #[allow(unused)]
     fn send_response(
         &mut self,
-        response: Message,
+        mut response: Message,
         destination: Ipv4Addr,
         hw_unicast: bool,
         max_size: Option<u16>,
-    ) -> io::Result<()> {
+    ) -> Result<(), ServerError> {
+        /*
+        RFC 2131 §4.1
+        If the 'giaddr' field in a DHCP message from a client is non-zero,
+        the server sends any return messages to the 'DHCP server' port on the
+        BOOTP relay agent whose address appears in 'giaddr'.
+        */
+        let is_relayed = response.addresses().is_relayed();
+        let (destination, hw_unicast) = if is_relayed {
+            (response.gateway_ip_address, false)
+        } else {
+            (destination, hw_unicast)
+        };
+
+        if let Some(ref auth_key) = self.auth_key {
+            self.replay_detection += 1;
+            response.set_authentication(auth_key, self.replay_detection);
+        }
+
         log_send!(response, destination);
+        if let Some(dhcp_message_type) = response.options.dhcp_message_type {
+            self.metrics.on_send(dhcp_message_type);
+        }
+        if let Some(ref mut audit_sink) = self.audit_sink {
+            audit_sink.on_send(&response, destination);
+        }
+        if self.dry_run {
+            info!("Dry run, the response to {} is not sent", destination);
+            return Ok(());
+        }
 
         #[cfg(any(target_os = "freebsd", target_os = "macos"))]
         {
             if hw_unicast {
-                return self.bpf_data.send(
-                    &self.server_ip_address,
-                    &destination,
-                    response,
-                    max_size,
-                );
+                return self
+                    .bpf_data
+                    .send(&self.server_ip_address, &destination, response, max_size)
+                    .map_err(ServerError::Bpf);
             }
         }
 
-        let destination = SocketAddr::new(IpAddr::V4(destination), DHCP_PORT_CLIENT);
+        let port = if is_relayed {
+            DHCP_PORT_SERVER
+        } else {
+            DHCP_PORT_CLIENT
+        };
+        let destination = SocketAddr::new(IpAddr::V4(destination), port);
         start_send!(self.socket, destination, response, max_size);
         Ok(())
     }
-}
-
-impl<S> Future for Server<S>
-where
-    S: Storage,
-{
-    type Item = ();
-    type Error = io::Error;
 
-    /// Works infinite time.
+    /// Processes the request, keeping it while the storage is not ready.
     ///
-    /// [RFC 2131](https://tools.ietf.org/html/rfc2131)
-    fn poll(&mut self) -> Poll<(), io::Error> {
-        loop {
-            #[cfg(target_os = "windows")]
-            {
-                poll_arp!(self.arp);
-            }
-            poll_complete!(self.socket);
-            let (addr, request) = poll!(self.socket);
-            log_receive!(request, addr.ip());
-            let dhcp_message_type = validate!(request, addr.ip());
-
-            if let Some(dhcp_server_id) = request.options.dhcp_server_id {
-                if dhcp_server_id != self.server_ip_address {
-                    warn!("Ignoring a message destined for server {}", dhcp_server_id);
-                    continue;
-                }
+    /// The request waiting for a probe is put aside and `Async::Ready` is returned,
+    /// so the other requests are processed in the meantime. The storage operations
+    /// it has made are idempotent, e.g. the offered address is offered again,
+    /// so the journal is not kept for it.
+    fn poll_request(&mut self, addr: SocketAddr, request: Message) -> Poll<(), ServerError> {
+        let result = self.process_request(&request);
+        if let Ok(Async::NotReady) = result {
+            if let Some(address) = self.probing_address.take() {
+                self.database.end_journal();
+                let key = (request.transaction_id, request.client_hardware_address);
+                let probing = ProbingRequest {
+                    addr,
+                    request,
+                    address,
+                    probed: self.probed.drain(..).collect(),
+                };
+                self.probing_requests.insert(key, probing);
+                return Ok(Async::Ready(()));
             }
+            self.pending_request = Some((addr, request));
+            return Ok(Async::NotReady);
+        }
+        self.database.end_journal();
+        self.probed.clear();
+        result
+    }
 
-            /*
-            RFC 2131 §4.1
-            If the  'ciaddr' field is nonzero, then the server unicasts
-            DHCPOFFER and DHCPACK messages to the address in 'ciaddr'.
-            If 'ciaddr' is zero, and the broadcast bit is set, then the server
-            broadcasts DHCPOFFER and DHCPACK messages to 0xffffffff. If the
-            broadcast bit is not set and the 'ciaddr' is zero, then the server
-            unicasts DHCPOFFER and DHCPACK messages to the client's hardware
-            address and 'yiaddr' address. In all cases, when 'giaddr' is zero,
-            the server broadcasts any DHCPNAK messages to 0xffffffff.
-            */
-
-            let client_id = match request.options.client_id {
-                Some(ref client_id) => client_id.as_ref(),
-                None => request.client_hardware_address.as_bytes(),
+    /// Processes again the requests whose probes have completed.
+    fn poll_probing_requests(&mut self) -> Poll<(), ServerError> {
+        let keys: Vec<(u32, MacAddress)> = self.probing_requests.keys().cloned().collect();
+        for key in keys {
+            let address = self.probing_requests[&key].address;
+            let is_in_use = match self.poll_probe(&address) {
+                Async::Ready(is_in_use) => is_in_use,
+                Async::NotReady => continue,
             };
-            let max_size = request.options.dhcp_max_message_size;
+            let mut probing = self.probing_requests.remove(&key).unwrap();
+            probing.probed.push((address, is_in_use));
+            self.probed = probing.probed;
+            self.database.begin_journal();
+            try_ready!(self.poll_request(probing.addr, probing.request));
+        }
+        Ok(Async::Ready(()))
+    }
 
-            match dhcp_message_type {
-                MessageType::DhcpDiscover => {
-                    /*
-                    RFC 2131 §4.3.1
-                    When a server receives a DHCPDISCOVER message from a client, the
-                    server chooses a network address for the requesting client.  If no
-                    address is available, the server may choose to report the problem to
-                    the system administrator.
-                    */
+    /// Processes a checked request until the storage is not ready.
+    ///
+    /// The request is processed again from the start when the storage becomes ready,
+    /// so nothing must be sent or reported before the last storage operation.
+    fn process_request(&mut self, request: &Message) -> Poll<(), ServerError> {
+        let dhcp_message_type = match request.options.dhcp_message_type {
+            Some(dhcp_message_type) => dhcp_message_type,
+            None => return self.process_bootp(request),
+        };
 
-                    match self.database.allocate(
-                        client_id,
-                        request.options.address_time,
-                        request.options.address_request,
-                    ) {
-                        Ok(offer) => {
-                            let response = self.builder.dhcp_discover_to_offer(&request, &offer);
-                            let (destination, hw_unicast) = self.destination(&request, &response);
-                            self.send_response(response, destination, hw_unicast, max_size)?;
-                        }
-                        Err(error) => warn!("Address allocation error: {}", error.to_string()),
-                    };
-                }
-                MessageType::DhcpRequest => {
-                    /*
-                    RFC 2131 §4.3.2
-                    A DHCPREQUEST message may come from a client responding to a
-                    DHCPOFFER message from a server, from a client verifying a previously
-                    allocated IP address or from a client extending the lease on a
-                    network address.  If the DHCPREQUEST message contains a 'server
-                    identifier' option, the message is in response to a DHCPOFFER
-                    message.  Otherwise, the message is a request to verify or extend an
-                    existing lease.
-
-                    RFC 2131 §4.3.6 (table 4)
-                    ---------------------------------------------------------------------
-                    |              |INIT-REBOOT  |SELECTING    |RENEWING     |REBINDING |
-                    ---------------------------------------------------------------------
-                    |broad/unicast |broadcast    |broadcast    |unicast      |broadcast |
-                    |server-ip     |MUST NOT     |MUST         |MUST NOT     |MUST NOT  |
-                    |requested-ip  |MUST         |MUST         |MUST NOT     |MUST NOT  |
-                    |ciaddr        |zero         |zero         |IP address   |IP address|
-                    ---------------------------------------------------------------------
-
-                    Note: server-ip     = request.options.dhcp_server_id
-                          ciaddr        = request.client_ip_address
-                          requested-ip  = request.options.address_request
-                    */
+        /*
+        RFC 2131 §4.1
+        If the  'ciaddr' field is nonzero, then the server unicasts
+        DHCPOFFER and DHCPACK messages to the address in 'ciaddr'.
+        If 'ciaddr' is zero, and the broadcast bit is set, then the server
+        broadcasts DHCPOFFER and DHCPACK messages to 0xffffffff. If the
+        broadcast bit is not set and the 'ciaddr' is zero, then the server
+        unicasts DHCPOFFER and DHCPACK messages to the client's hardware
+        address and 'yiaddr' address. In all cases, when 'giaddr' is zero,
+        the server broadcasts any DHCPNAK messages to 0xffffffff.
+        */
+
+        let client_id = match request.options.client_id {
+            Some(ref client_id) => client_id.as_ref(),
+            None => request.client_hardware_address.as_bytes(),
+        };
+        self.database
+            .adopt_client_hardware_address(client_id, &request.client_hardware_address);
+        let max_size = request.options.dhcp_max_message_size;
+
+        match dhcp_message_type {
+            MessageType::DhcpDiscover => {
+                /*
+                RFC 2131 §4.3.1
+                When a server receives a DHCPDISCOVER message from a client, the
+                server chooses a network address for the requesting client.  If no
+                address is available, the server may choose to report the problem to
+                the system administrator.
+                */
 
-                    // the client is in the SELECTING state
-                    if request.options.dhcp_server_id.is_some() {
-                        let address = expect!(request.options.address_request);
-                        let lease_time = request.options.address_time;
+                let result = if self.is_client_allowed(&request.client_hardware_address) {
+                    ready!(self.allocate(client_id, request))
+                } else {
+                    warn!(
+                        "Client {} is not allowed to be served",
+                        request.client_hardware_address
+                    );
+                    Err(NotAllowed)
+                };
 
-                        match self.database.assign(client_id, &address, lease_time) {
+                match result {
+                    /*
+                    RFC 4039 §4
+                    If the server is configured to use the Rapid Commit option, it
+                    commits the address assignment and responds with a DHCPACK
+                    message carrying the Rapid Commit option.
+                    */
+                    Ok(offer) if self.allow_rapid_commit && request.options.rapid_commit => {
+                        let lease_time = Some(offer.lease_time);
+                        match ready!(self.database.assign(client_id, &offer.address, lease_time)) {
                             Ok(ack) => {
-                                let response = self.builder.dhcp_request_to_ack(&request, &ack);
+                                let mut response = self
+                                    .client_builder(client_id, request)
+                                    .dhcp_discover_to_ack(request, &ack);
+                                if self
+                                    .name_client(client_id, request, &mut response)
+                                    .is_not_ready()
+                                {
+                                    return Ok(Async::NotReady);
+                                }
+                                self.report(
+                                    LeaseEventKind::Acked,
+                                    client_id,
+                                    Some(request.client_hardware_address),
+                                    ack.address,
+                                    Some(ack.lease_time),
+                                );
                                 let (destination, hw_unicast) =
-                                    self.destination(&request, &response);
+                                    self.destination(request, &response);
                                 self.send_response(response, destination, hw_unicast, max_size)?;
                             }
                             Err(error) => {
-                                warn!("Address assignment error: {}", error.to_string());
-                                let response = self.builder.dhcp_request_to_nak(&request, &error);
+                                warn!("Address assignment error: {}", error);
+                            }
+                        }
+                    }
+                    Ok(offer) => {
+                        let mut response = self
+                            .client_builder(client_id, request)
+                            .dhcp_discover_to_offer(request, &offer);
+                        if self
+                            .name_client(client_id, request, &mut response)
+                            .is_not_ready()
+                        {
+                            return Ok(Async::NotReady);
+                        }
+                        self.report(
+                            LeaseEventKind::Offered,
+                            client_id,
+                            Some(request.client_hardware_address),
+                            offer.address,
+                            Some(offer.lease_time),
+                        );
+                        let (destination, hw_unicast) = self.destination(request, &response);
+                        self.send_response(response, destination, hw_unicast, max_size)?;
+                    }
+                    Err(error) => {
+                        warn!("Address allocation error: {}", error);
+                        self.metrics.on_allocation_failure();
+                        // RFC 2563 clients announce themselves with the Auto-Configure option,
+                        // and the others would take the address-less offer for a broken one
+                        let is_signaled =
+                            self.signal_no_lease && request.options.auto_configure.is_some();
+                        match error {
+                            PoolExhausted | NotAllowed if is_signaled => {
+                                // there is no 'yiaddr' to unicast to
+                                let response = self
+                                    .client_builder(client_id, request)
+                                    .dhcp_discover_to_no_lease(request, &error);
                                 let destination = Ipv4Addr::new(255, 255, 255, 255);
                                 self.send_response(response, destination, false, max_size)?;
                             }
-                        };
-                        continue;
+                            _ => {}
+                        }
                     }
+                };
+            }
+            MessageType::DhcpRequest => {
+                /*
+                RFC 2131 §4.3.2
+                A DHCPREQUEST message may come from a client responding to a
+                DHCPOFFER message from a server, from a client verifying a previously
+                allocated IP address or from a client extending the lease on a
+                network address.  If the DHCPREQUEST message contains a 'server
+                identifier' option, the message is in response to a DHCPOFFER
+                message.  Otherwise, the message is a request to verify or extend an
+                existing lease.
 
-                    // the client is in the INIT-REBOOT state
-                    if request.client_ip_address.is_unspecified() {
-                        let address = expect!(request.options.address_request);
+                RFC 2131 §4.3.6 (table 4)
+                ---------------------------------------------------------------------
+                |              |INIT-REBOOT  |SELECTING    |RENEWING     |REBINDING |
+                ---------------------------------------------------------------------
+                |broad/unicast |broadcast    |broadcast    |unicast      |broadcast |
+                |server-ip     |MUST NOT     |MUST         |MUST NOT     |MUST NOT  |
+                |requested-ip  |MUST         |MUST         |MUST NOT     |MUST NOT  |
+                |ciaddr        |zero         |zero         |IP address   |IP address|
+                ---------------------------------------------------------------------
 
-                        match self.database.check(client_id, &address) {
-                            Ok(ack) => {
-                                let response = self.builder.dhcp_request_to_ack(&request, &ack);
-                                let (destination, hw_unicast) =
-                                    self.destination(&request, &response);
-                                self.send_response(response, destination, hw_unicast, max_size)?;
-                            }
-                            Err(error) => {
-                                warn!("Address checking error: {}", error.to_string());
-                                if let LeaseInvalid = error {
-                                    let response =
-                                        self.builder.dhcp_request_to_nak(&request, &error);
-                                    let destination = Ipv4Addr::new(255, 255, 255, 255);
-                                    self.send_response(response, destination, false, max_size)?;
-                                }
-                                /*
-                                RFC 2131 §4.3.2
-                                If the DHCP server has no record of this client, then it MUST
-                                remain silent, and MAY output a warning to the network administrator.
-                                */
+                Note: server-ip     = request.options.dhcp_server_id
+                      ciaddr        = request.client_ip_address
+                      requested-ip  = request.options.address_request
+                */
+
+                if !self.is_client_allowed(&request.client_hardware_address) {
+                    warn!(
+                        "Client {} is not allowed to be served",
+                        request.client_hardware_address
+                    );
+                    return Ok(Async::Ready(()));
+                }
+
+                // the client is in the SELECTING state
+                if request.options.dhcp_server_id.is_some() {
+                    let address = match request.options.address_request {
+                        Some(address) => address,
+                        None => {
+                            warn!("Ignoring a SELECTING request without the requested address");
+                            return Ok(Async::Ready(()));
+                        }
+                    };
+                    let lease_time = request.options.address_time;
+
+                    match ready!(self.database.assign(client_id, &address, lease_time)) {
+                        Ok(ack) => {
+                            let mut response = self
+                                .client_builder(client_id, request)
+                                .dhcp_request_to_ack(request, &ack);
+                            if self
+                                .name_client(client_id, request, &mut response)
+                                .is_not_ready()
+                            {
+                                return Ok(Async::NotReady);
                             }
+                            self.report(
+                                LeaseEventKind::Acked,
+                                client_id,
+                                Some(request.client_hardware_address),
+                                ack.address,
+                                Some(ack.lease_time),
+                            );
+                            let (destination, hw_unicast) = self.destination(request, &response);
+                            self.send_response(response, destination, hw_unicast, max_size)?;
                         }
-                        continue;
-                    }
+                        Err(error) => {
+                            warn!("Address assignment error: {}", error);
+                            let response = self
+                                .client_builder(client_id, request)
+                                .dhcp_request_to_nak(request, &error);
+                            let destination = Ipv4Addr::new(255, 255, 255, 255);
+                            self.send_response(response, destination, false, max_size)?;
+                        }
+                    };
+                    return Ok(Async::Ready(()));
+                }
+
+                // the client is in the INIT-REBOOT state
+                if !request.addresses().is_from_configured_client() {
+                    let address = match request.options.address_request {
+                        Some(address) => address,
+                        None => {
+                            warn!("Ignoring an INIT-REBOOT request without the requested address");
+                            return Ok(Async::Ready(()));
+                        }
+                    };
 
-                    // the client is in the RENEWING or REBINDING state
                     let lease_time = request.options.address_time;
-                    match self
-                        .database
-                        .renew(client_id, &request.client_ip_address, lease_time)
-                    {
+                    match ready!(self.database.check(client_id, &address, lease_time)) {
                         Ok(ack) => {
-                            let response = self.builder.dhcp_request_to_ack(&request, &ack);
-                            let (destination, hw_unicast) = self.destination(&request, &response);
+                            let mut response = self
+                                .client_builder(client_id, request)
+                                .dhcp_request_to_ack(request, &ack);
+                            if self
+                                .name_client(client_id, request, &mut response)
+                                .is_not_ready()
+                            {
+                                return Ok(Async::NotReady);
+                            }
+                            self.report(
+                                LeaseEventKind::Acked,
+                                client_id,
+                                Some(request.client_hardware_address),
+                                ack.address,
+                                Some(ack.lease_time),
+                            );
+                            let (destination, hw_unicast) = self.destination(request, &response);
                             self.send_response(response, destination, hw_unicast, max_size)?;
                         }
-                        Err(error) => warn!("Address checking error: {}", error.to_string()),
+                        Err(error) => {
+                            warn!("Address checking error: {}", error);
+                            /*
+                            RFC 2131 §4.3.2
+                            If the DHCP server has no record of this client, then it MUST
+                            remain silent, and MAY output a warning to the network administrator.
+                            */
+                            // the authoritative server knows a foreign address is wrong
+                            let must_nak = match error {
+                                LeaseInvalid | AddressOutOfRange(_) | AddressInUse(_) => true,
+                                LeaseNotFound => {
+                                    self.is_authoritative
+                                        && !self.database.is_address_in_pools(&address)
+                                }
+                                _ => false,
+                            };
+                            if must_nak {
+                                let response = self
+                                    .client_builder(client_id, request)
+                                    .dhcp_request_to_nak(request, &error);
+                                let destination = Ipv4Addr::new(255, 255, 255, 255);
+                                self.send_response(response, destination, false, max_size)?;
+                            }
+                        }
                     }
+                    return Ok(Async::Ready(()));
                 }
-                MessageType::DhcpDecline => {
-                    /*
-                    RFC 2131 §4.3.3
-                    If the server receives a DHCPDECLINE message, the client has
-                    discovered through some other means that the suggested network
-                    address is already in use.  The server MUST mark the network address
-                    as not available and SHOULD notify the local system administrator of
-                    a possible configuration problem.
-                    */
 
-                    let address = expect!(request.options.address_request);
-                    match self.database.freeze(&address) {
-                        Ok(_) => info!("Address {} has been marked as unavailable", address),
-                        Err(error) => warn!("Address freezing error: {}", error.to_string()),
-                    };
+                // the client is in the RENEWING or REBINDING state
+                let lease_time = request.options.address_time;
+                match ready!(self
+                    .database
+                    .renew(client_id, &request.client_ip_address, lease_time))
+                {
+                    Ok(ack) => {
+                        let mut response = self
+                            .client_builder(client_id, request)
+                            .dhcp_request_to_ack(request, &ack);
+                        if self
+                            .name_client(client_id, request, &mut response)
+                            .is_not_ready()
+                        {
+                            return Ok(Async::NotReady);
+                        }
+                        self.report(
+                            LeaseEventKind::Renewed,
+                            client_id,
+                            Some(request.client_hardware_address),
+                            ack.address,
+                            Some(ack.lease_time),
+                        );
+                        let (destination, hw_unicast) = self.destination(request, &response);
+                        self.send_response(response, destination, hw_unicast, max_size)?;
+                    }
+                    Err(error) => warn!("Address checking error: {}", error),
                 }
-                MessageType::DhcpRelease => {
-                    /*
-                    RFC 2131 §4.3.4
-                    Upon receipt of a DHCPRELEASE message, the server marks the network
-                    address as not allocated.  The server SHOULD retain a record of the
-                    client's initialization parameters for possible reuse in response to
-                    subsequent requests from the client.
-                    */
+            }
+            MessageType::DhcpDecline => {
+                /*
+                RFC 2131 §4.3.3
+                If the server receives a DHCPDECLINE message, the client has
+                discovered through some other means that the suggested network
+                address is already in use.  The server MUST mark the network address
+                as not available and SHOULD notify the local system administrator of
+                a possible configuration problem.
+                */
 
-                    let address = request.client_ip_address;
-                    match self.database.deallocate(client_id, &address) {
-                        Ok(_) => info!("Address {} has been released", address),
-                        Err(error) => warn!("Address releasing error: {}", error.to_string()),
-                    };
-                }
-                MessageType::DhcpInform => {
-                    /*
-                    RFC 2131 §4.3.5
-                    The server responds to a DHCPINFORM message by sending a DHCPACK
-                    message directly to the address given in the 'ciaddr' field of the
-                    DHCPINFORM message.  The server MUST NOT send a lease expiration time
-                    to the client and SHOULD NOT fill in 'yiaddr'.
-                    */
+                let address = match request.options.address_request {
+                    Some(address) => address,
+                    None => {
+                        warn!("Ignoring a DHCPDECLINE without the declined address");
+                        return Ok(Async::Ready(()));
+                    }
+                };
+                match ready!(self.database.freeze(&address)) {
+                    Ok(_) => {
+                        info!("Address {} has been marked as unavailable", address);
+                        let mac = Some(request.client_hardware_address);
+                        self.report(LeaseEventKind::Declined, client_id, mac, address, None);
+                        self.remove_arp_entry(request, address);
+                    }
+                    Err(error) => warn!("Address freezing error: {}", error),
+                };
+            }
+            MessageType::DhcpRelease => {
+                /*
+                RFC 2131 §4.3.4
+                Upon receipt of a DHCPRELEASE message, the server marks the network
+                address as not allocated.  The server SHOULD retain a record of the
+                client's initialization parameters for possible reuse in response to
+                subsequent requests from the client.
+                */
 
-                    info!(
-                        "Address {} has been taken by some client manually",
-                        request.client_ip_address
-                    );
-                    let response = self.builder.dhcp_inform_to_ack(&request, "Accepted");
-                    let (destination, hw_unicast) = self.destination(&request, &response);
-                    self.send_response(response, destination, hw_unicast, max_size)?;
-                }
-                _ => {}
+                let address = request.client_ip_address;
+                match ready!(self.database.deallocate(client_id, &address)) {
+                    Ok(_) => {
+                        info!("Address {} has been released", address);
+                        self.client_replay_detections.remove(client_id);
+                        let mac = Some(request.client_hardware_address);
+                        self.report(LeaseEventKind::Released, client_id, mac, address, None);
+                        self.remove_arp_entry(request, address);
+                    }
+                    Err(error) => warn!("Address releasing error: {}", error),
+                };
             }
+            MessageType::DhcpInform => {
+                /*
+                RFC 2131 §4.3.5
+                The server responds to a DHCPINFORM message by sending a DHCPACK
+                message directly to the address given in the 'ciaddr' field of the
+                DHCPINFORM message.  The server MUST NOT send a lease expiration time
+                to the client and SHOULD NOT fill in 'yiaddr'.
+                */
+
+                info!(
+                    "Address {} has been taken by some client manually",
+                    request.client_ip_address
+                );
+                let response = self
+                    .client_builder(client_id, request)
+                    .dhcp_inform_to_ack(request, "Accepted");
+                let (destination, hw_unicast) = self.destination(request, &response);
+                self.send_response(response, destination, hw_unicast, max_size)?;
+            }
+            _ => {}
         }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<S> Server<S>
+where
+    S: Storage,
+{
+    /// Returns the address the server socket is actually bound to.
+    ///
+    /// # Errors
+    /// `io::Error` on a socket error.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl<S, T> Future for Server<S, T>
+where
+    S: Storage,
+    T: Stream<Item = DhcpStreamItem, Error = io::Error>
+        + Sink<SinkItem = DhcpSinkItem, SinkError = io::Error>,
+{
+    type Item = ();
+    type Error = ServerError;
+
+    /// Works infinite time.
+    ///
+    /// Terminates on a socket, storage preparing or BPF error.
+    ///
+    /// [RFC 2131](https://tools.ietf.org/html/rfc2131)
+    fn poll(&mut self) -> Poll<(), ServerError> {
+        loop {
+            #[cfg(target_os = "windows")]
+            {
+                poll_arp!(self.arp);
+            }
+            poll_complete!(self.socket);
+            match self.database.poll_ready() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => {
+                    error!("Storage preparing error: {}", error);
+                    return Err(ServerError::Storage(error));
+                }
+            }
+            if let Some(ready_signal) = self.ready_signal.take() {
+                info!("The storage is ready, processing requests");
+                let _ = ready_signal.send(());
+            }
+            if let Some((addr, request)) = self.pending_request.take() {
+                self.database.rewind_journal();
+                try_ready!(self.poll_request(addr, request));
+            }
+            try_ready!(self.poll_probing_requests());
+            if self.poll_commands().is_not_ready()
+                || self.poll_reclaim().is_not_ready()
+                || self.poll_sweep().is_not_ready()
+            {
+                return Ok(Async::NotReady);
+            }
+            let (addr, request) = poll!(self.socket);
+            if let Some(ref mut rate_limiter) = self.rate_limiter {
+                if !rate_limiter.allow() {
+                    debug!("Rate limit exceeded, dropping a message from {}", addr);
+                    self.metrics.on_rate_limited();
+                    continue;
+                }
+            }
+            if let Some(ref mut client_rate_limiter) = self.client_rate_limiter {
+                let is_renewal = request.addresses().is_from_configured_client();
+                if !client_rate_limiter.allow(request.client_hardware_address, is_renewal) {
+                    trace!(
+                        "Client rate limit exceeded, dropping a message from {}",
+                        request.client_hardware_address
+                    );
+                    self.metrics.on_client_rate_limited();
+                    continue;
+                }
+            }
+            log_receive!(request, addr.ip());
+            if let Some(ref mut audit_sink) = self.audit_sink {
+                audit_sink.on_recv(&request, addr);
+            }
+            if self.allow_bootp && request.options.dhcp_message_type.is_none() {
+                if let Err(error) = request.validate_bootp() {
+                    warn!("The BOOTP request from {} is invalid: {}", addr.ip(), error);
+                    continue;
+                }
+                if self.auth_key.is_some() {
+                    warn!("Dropping an unauthenticated BOOTP request from {}", addr);
+                    continue;
+                }
+            } else {
+                let dhcp_message_type = validate!(request, addr.ip());
+                self.metrics.on_recv(dhcp_message_type);
+            }
+            if !self.check_source_port(&request, &addr) {
+                continue;
+            }
+
+            if let Some(dhcp_server_id) = request.options.dhcp_server_id {
+                if dhcp_server_id != self.server_ip_address {
+                    warn!("Ignoring a message destined for server {}", dhcp_server_id);
+                    continue;
+                }
+            }
+            // the messages for other servers must not move the client counter forward
+            if request.options.dhcp_message_type.is_some()
+                && self.auth_key.is_some()
+                && !self.check_replay_detection(&request)
+            {
+                warn!(
+                    "Dropping an unauthenticated or replayed message from {}",
+                    addr
+                );
+                continue;
+            }
+
+            let key = (request.transaction_id, request.client_hardware_address);
+            if let Some(probing) = self.probing_requests.get(&key) {
+                let message_type = |request: &Message| {
+                    request
+                        .options
+                        .dhcp_message_type
+                        .map(|dhcp_message_type| dhcp_message_type as u8)
+                };
+                if message_type(&probing.request) == message_type(&request) {
+                    debug!("Ignoring a retransmission of a request waiting for a probe");
+                    continue;
+                }
+            }
+
+            self.database.begin_journal();
+            try_ready!(self.poll_request(addr, request));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        env, fs, process,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use futures::{future, task, StartSend};
+    use tokio::{runtime::current_thread::Runtime, timer::Delay};
+
+    use dhcp_protocol::*;
+
+    use super::*;
+    use lease::Lease;
+    use metrics::MetricsSnapshot;
+    use storage::{self, Ready};
+    use storage_file::FileStorage;
+    use storage_ram::RamStorage;
+
+    /// Feeds the server with prepared requests and records its responses.
+    struct TestSocket {
+        incoming: Rc<RefCell<VecDeque<DhcpStreamItem>>>,
+        outgoing: Rc<RefCell<Vec<(SocketAddr, Message)>>>,
+    }
+
+    impl Stream for TestSocket {
+        type Item = DhcpStreamItem;
+        type Error = io::Error;
+
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            match self.incoming.borrow_mut().pop_front() {
+                Some(item) => Ok(Async::Ready(Some(item))),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    impl Sink for TestSocket {
+        type SinkItem = DhcpSinkItem;
+        type SinkError = io::Error;
+
+        fn start_send(
+            &mut self,
+            item: Self::SinkItem,
+        ) -> StartSend<Self::SinkItem, Self::SinkError> {
+            let (addr, (message, _max_size)) = item;
+            self.outgoing.borrow_mut().push((addr, message));
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    /// Runs a server over the `TestSocket`.
+    ///
+    /// The runtime provides the timers and is kept to let them survive between exchanges.
+    struct TestServer<S: Storage = RamStorage> {
+        server: Server<S, TestSocket>,
+        incoming: Rc<RefCell<VecDeque<DhcpStreamItem>>>,
+        outgoing: Rc<RefCell<Vec<(SocketAddr, Message)>>>,
+        runtime: Runtime,
+    }
+
+    impl<S: Storage> TestServer<S> {
+        fn new(builder: ServerBuilder<S>) -> Self {
+            let incoming = Rc::new(RefCell::new(VecDeque::new()));
+            let outgoing = Rc::new(RefCell::new(Vec::new()));
+            let socket = TestSocket {
+                incoming: incoming.clone(),
+                outgoing: outgoing.clone(),
+            };
+
+            TestServer {
+                server: builder.finish_with_socket(socket).unwrap(),
+                incoming,
+                outgoing,
+                runtime: Runtime::new().unwrap(),
+            }
+        }
+
+        /// Runs the server until it has processed all the requests and returns its responses.
+        fn exchange(&mut self, requests: Vec<Message>) -> Vec<(SocketAddr, Message)> {
+            self.exchange_from(68, requests)
+        }
+
+        /// Same as `exchange`, but the requests come from the specified source port.
+        fn exchange_from(
+            &mut self,
+            port: u16,
+            requests: Vec<Message>,
+        ) -> Vec<(SocketAddr, Message)> {
+            let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+            self.incoming
+                .borrow_mut()
+                .extend(requests.into_iter().map(|request| (source, request)));
+
+            // the test storages and probers notify the task at once, so the kept requests
+            // are processed again until they are answered and the leases are counted
+            loop {
+                let responses = self.poll();
+                let server = &self.server;
+                if server.pending_request.is_none()
+                    && server.probing_requests.is_empty()
+                    && !server.is_count_pending
+                {
+                    return responses;
+                }
+                self.outgoing.borrow_mut().extend(responses);
+            }
+        }
+
+        /// Polls the server once and returns its responses.
+        fn poll(&mut self) -> Vec<(SocketAddr, Message)> {
+            let server = &mut self.server;
+            let result = self
+                .runtime
+                .block_on(future::poll_fn(|| Ok::<_, ()>(Async::Ready(server.poll()))))
+                .unwrap();
+            match result {
+                Ok(Async::NotReady) => {}
+                _ => panic!("The server must wait for more requests"),
+            }
+
+            let responses = self.outgoing.borrow_mut().drain(..).collect();
+            responses
+        }
+    }
+
+    const SERVER_IP: [u8; 4] = [192, 168, 0, 2];
+    const KNOWN_CLIENT: [u8; 6] = [0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+    const UNKNOWN_CLIENT: [u8; 6] = [0x00, 0x0c, 0x29, 0x13, 0x0e, 0x38];
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            static_address_range: (
+                Ipv4Addr::new(192, 168, 0, 50),
+                Ipv4Addr::new(192, 168, 0, 99),
+            ),
+            dynamic_address_range: (
+                Ipv4Addr::new(192, 168, 0, 100),
+                Ipv4Addr::new(192, 168, 0, 199),
+            ),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            routers: vec![Ipv4Addr::new(192, 168, 0, 1)],
+            domain_name_servers: vec![Ipv4Addr::new(192, 168, 0, 1)],
+            static_routes: vec![],
+            classless_static_routes: vec![],
+            excluded_address_ranges: vec![],
+            reservations: vec![],
+            hardware_reservations: vec![],
+        }
+    }
+
+    fn builder() -> ServerBuilder<RamStorage> {
+        builder_with_storage(RamStorage::new())
+    }
+
+    fn builder_with_storage<S: Storage>(storage: S) -> ServerBuilder<S> {
+        let config = config();
+        ServerBuilder::new(
+            Ipv4Addr::from(SERVER_IP),
+            "test0".to_owned(),
+            config.static_address_range,
+            config.dynamic_address_range,
+            storage,
+            config.subnet_mask,
+            config.routers,
+            config.domain_name_servers,
+            config.static_routes,
+            config.classless_static_routes,
+        )
+    }
+
+    /// Builds a broadcast client request, so no ARP injection is performed.
+    fn request(dhcp_message_type: MessageType, client_hardware_address: [u8; 6]) -> Message {
+        let mut options = Options::default();
+        options.dhcp_message_type = Some(dhcp_message_type);
+        options.parameter_list = Some(vec![
+            OptionTag::SubnetMask.code(),
+            OptionTag::Routers.code(),
+            OptionTag::DomainNameServers.code(),
+        ]);
+
+        Message {
+            operation_code: OperationCode::BootRequest,
+            hardware_type: HardwareType::Ethernet,
+            hardware_address_length: 6,
+            hardware_options: Default::default(),
+
+            transaction_id: 42,
+            seconds: Default::default(),
+            is_broadcast: true,
+
+            client_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            your_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            server_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+            gateway_ip_address: Ipv4Addr::new(0, 0, 0, 0),
+
+            client_hardware_address: MacAddress::new(client_hardware_address),
+            server_name: Default::default(),
+            boot_filename: Default::default(),
+
+            options,
+        }
+    }
+
+    /// Performs the DISCOVER-OFFER-REQUEST-ACK exchange and returns the `DHCPACK`.
+    fn acquire(server: &mut TestServer, client_hardware_address: [u8; 6]) -> Message {
+        let discover = request(MessageType::DhcpDiscover, client_hardware_address);
+        let mut responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        let (_, offer) = responses.remove(0);
+
+        let mut request = request(MessageType::DhcpRequest, client_hardware_address);
+        request.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        request.options.address_request = Some(offer.your_ip_address);
+        let mut responses = server.exchange(vec![request]);
+        assert_eq!(responses.len(), 1);
+        let (_, ack) = responses.remove(0);
+        assert_eq!(ack.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        ack
+    }
+
+    #[test]
+    fn signals_no_lease_to_not_allowed_client() {
+        let mut builder = builder();
+        builder
+            .with_allowed_clients(vec![MacAddress::new(KNOWN_CLIENT)])
+            .with_no_lease_signal();
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, UNKNOWN_CLIENT);
+        discover.options.auto_configure = Some(AutoConfigure::AutoConfigure);
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        let (destination, ref offer) = responses[0];
+        assert_eq!(destination.ip(), IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)));
+        assert!(offer.validate().is_ok());
+        assert!(offer.your_ip_address.is_unspecified());
+        assert!(offer.options.address_time.is_none());
+        match offer.options.auto_configure {
+            Some(AutoConfigure::DoNotAutoConfigure) => {}
+            _ => panic!("The offer must forbid autoconfiguration"),
+        }
+    }
+
+    #[test]
+    fn ignores_not_allowed_client_without_auto_configure() {
+        let mut builder = builder();
+        builder
+            .with_allowed_clients(vec![MacAddress::new(KNOWN_CLIENT)])
+            .with_no_lease_signal();
+        let mut server = TestServer::new(builder);
+
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, UNKNOWN_CLIENT)]);
+
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn ignores_not_allowed_client_without_no_lease_signal() {
+        let mut builder = builder();
+        builder.with_allowed_clients(vec![MacAddress::new(KNOWN_CLIENT)]);
+        let mut server = TestServer::new(builder);
+
+        let responses = server.exchange(vec![
+            request(MessageType::DhcpDiscover, UNKNOWN_CLIENT),
+            request(MessageType::DhcpDiscover, KNOWN_CLIENT),
+        ]);
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].1.client_hardware_address,
+            MacAddress::new(KNOWN_CLIENT)
+        );
+        assert!(!responses[0].1.your_ip_address.is_unspecified());
+    }
+
+    #[test]
+    fn reconfigures_options_and_keeps_leases() {
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        let mut builder = builder();
+        builder.with_commands(commands_rx);
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(
+            ack.options.domain_name_servers,
+            Some(vec![Ipv4Addr::new(192, 168, 0, 1)])
+        );
+
+        let mut config = config();
+        config.domain_name_servers = vec![Ipv4Addr::new(8, 8, 8, 8)];
+        commands_tx
+            .unbounded_send(ServerCommand::Reconfigure(config))
+            .unwrap();
+
+        let mut renew = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        renew.client_ip_address = ack.your_ip_address;
+        let responses = server.exchange(vec![renew]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref renewal) = responses[0];
+        assert_eq!(renewal.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        assert_eq!(renewal.your_ip_address, ack.your_ip_address);
+        assert_eq!(
+            renewal.options.domain_name_servers,
+            Some(vec![Ipv4Addr::new(8, 8, 8, 8)])
+        );
+    }
+
+    #[test]
+    fn reclaims_leases_outside_shrunk_pool() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_by_server = events.clone();
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        let mut builder = builder();
+        builder.with_commands(commands_rx);
+        builder.with_lease_event_handler(Box::new(move |event| {
+            events_by_server.lock().unwrap().push(event)
+        }));
+        let mut server = TestServer::new(builder);
+
+        let kept = acquire(&mut server, KNOWN_CLIENT);
+        let dropped = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(kept.your_ip_address, Ipv4Addr::new(192, 168, 0, 100));
+        assert_eq!(dropped.your_ip_address, Ipv4Addr::new(192, 168, 0, 101));
+
+        let mut config = config();
+        config.dynamic_address_range.1 = kept.your_ip_address;
+        commands_tx
+            .unbounded_send(ServerCommand::Reconfigure(config))
+            .unwrap();
+
+        let mut renew = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        renew.client_ip_address = kept.your_ip_address;
+        let mut reboot = request(MessageType::DhcpRequest, UNKNOWN_CLIENT);
+        reboot.options.address_request = Some(dropped.your_ip_address);
+        let responses = server.exchange(vec![renew, reboot]);
+
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0].1.validate().unwrap() as u8,
+            MessageType::DhcpAck as u8
+        );
+        assert_eq!(
+            responses[1].1.validate().unwrap() as u8,
+            MessageType::DhcpNak as u8
+        );
+        let events = events.lock().unwrap();
+        let reclaimed: Vec<&LeaseEvent> = events
+            .iter()
+            .filter(|event| event.kind == LeaseEventKind::Reclaimed)
+            .collect();
+        assert_eq!(reclaimed.len(), 1);
+        assert_eq!(reclaimed[0].address, dropped.your_ip_address);
+    }
+
+    #[test]
+    fn names_clients_with_generated_hostnames() {
+        let mut builder = builder();
+        builder.with_hostname_generator(Box::new(|_, address, _| {
+            Some(format!("host-{}", address.octets()[3]))
+        }));
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        let hostname = format!("host-{}", ack.your_ip_address.octets()[3]);
+        assert_eq!(ack.options.hostname, Some(hostname.to_owned()));
+        let lease = server
+            .server
+            .database
+            .lease(MacAddress::new(KNOWN_CLIENT).as_bytes())
+            .ready()
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.hostname(), Some(hostname.as_str()));
+    }
+
+    /// Offers an address to each client with a naming request and returns the names
+    /// the hostname generator has been given.
+    fn requested_names(name_policy: NamePolicy) -> Vec<Option<String>> {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = builder();
+        let recorded = names.clone();
+        builder
+            .with_hostname_generator(Box::new(move |_, _, requested_name| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(requested_name.map(str::to_owned));
+                None
+            }))
+            .with_name_policy(name_policy);
+        let mut server = TestServer::new(builder);
+
+        let fqdn = ClientFqdn {
+            flags: ClientFqdn::FLAG_S,
+            name: "fqdn.example.org".to_owned(),
+        };
+        let mut discovers = Vec::new();
+        for (i, &(hostname, client_fqdn)) in [
+            (Some("hostname"), None),
+            (None, Some(&fqdn)),
+            (Some("hostname"), Some(&fqdn)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let client_hardware_address = [0x00, 0x0c, 0x29, 0x13, 0x0f, i as u8];
+            let mut discover = request(MessageType::DhcpDiscover, client_hardware_address);
+            discover.options.hostname = hostname.map(str::to_owned);
+            discover.options.client_fqdn = client_fqdn.cloned();
+            discovers.push(discover);
+        }
+        assert_eq!(server.exchange(discovers).len(), 3);
+
+        let names = names.lock().unwrap().clone();
+        names
+    }
+
+    #[test]
+    fn passes_client_requested_name_to_generator() {
+        assert_eq!(
+            requested_names(NamePolicy::default()),
+            vec![
+                Some("hostname".to_owned()),
+                Some("fqdn.example.org".to_owned()),
+                Some("fqdn.example.org".to_owned()),
+            ]
+        );
+        assert_eq!(
+            requested_names(NamePolicy::PreferHostname),
+            vec![
+                Some("hostname".to_owned()),
+                Some("fqdn.example.org".to_owned()),
+                Some("hostname".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_messages_beyond_rate_limit() {
+        let mut builder = builder();
+        builder.with_rate_limit(1, 3);
+        let metrics = builder.metrics_handle();
+        let mut server = TestServer::new(builder);
+
+        let discovers = (0..10)
+            .map(|i| request(MessageType::DhcpDiscover, [0x00, 0x0c, 0x29, 0x13, 0x10, i]))
+            .collect();
+        let responses = server.exchange(discovers);
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(metrics.snapshot().rate_limited, 7);
+    }
+
+    #[test]
+    fn drops_client_messages_beyond_client_rate_limit() {
+        let mut builder = builder();
+        builder.with_client_rate_limit(1, 3);
+        let metrics = builder.metrics_handle();
+        let mut server = TestServer::new(builder);
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        let discovers = (0..10)
+            .map(|_| request(MessageType::DhcpDiscover, KNOWN_CLIENT))
+            .collect();
+        let responses = server.exchange(discovers);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(metrics.snapshot().rate_limited, 9);
+        assert_eq!(metrics.snapshot().client_rate_limited, 9);
+
+        let mut renew = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        renew.client_ip_address = ack.your_ip_address;
+        let responses = server.exchange(vec![renew]);
+        assert_eq!(responses.len(), 1);
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, UNKNOWN_CLIENT)]);
+        assert_eq!(responses.len(), 1);
+    }
+
+    #[test]
+    fn binds_addresses_to_bootp_clients() {
+        let bootp = || {
+            let mut bootp = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+            bootp.options = Options::default();
+            bootp
+        };
+
+        let mut server = TestServer::new(builder());
+        assert!(server.exchange(vec![bootp()]).is_empty());
+
+        let mut builder = builder();
+        builder.with_bootp();
+        let mut server = TestServer::new(builder);
+        let responses = server.exchange(vec![bootp()]);
+
+        assert_eq!(responses.len(), 1);
+        let (destination, ref reply) = responses[0];
+        assert_eq!(
+            destination,
+            SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)),
+                DHCP_PORT_CLIENT
+            )
+        );
+        assert_eq!(reply.operation_code as u8, OperationCode::BootReply as u8);
+        assert_eq!(reply.transaction_id, 42);
+        assert!(!reply.your_ip_address.is_unspecified());
+        assert!(reply.options.dhcp_message_type.is_none());
+        assert!(reply.options.subnet_mask.is_some());
+        let lease = server
+            .server
+            .database
+            .lease(&KNOWN_CLIENT)
+            .ready()
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.address(), reply.your_ip_address);
+        assert_eq!(lease.lease_time(), 0xffff_ffff);
+        assert!(lease.is_active());
+    }
+
+    /// A storage which is not ready until the flag is set, like one waiting for a network.
+    ///
+    /// If `error` is set, the storage fails to get ready.
+    struct DelayedStorage {
+        inner: RamStorage,
+        is_ready: Arc<AtomicBool>,
+        error: Option<String>,
+    }
+
+    impl Storage for DelayedStorage {
+        fn poll_ready(&mut self) -> Poll<(), storage::Error> {
+            if let Some(ref error) = self.error {
+                Err(storage::Error::Other(error.to_owned()))
+            } else if self.is_ready.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, storage::Error> {
+            self.inner.get_client(address)
+        }
+
+        fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), storage::Error> {
+            self.inner.add_client(address, client_id)
+        }
+
+        fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.inner.delete_client(address)
+        }
+
+        fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, storage::Error> {
+            self.inner.get_lease(client_id)
+        }
+
+        fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), storage::Error> {
+            self.inner.add_lease(client_id, lease)
+        }
+
+        fn update_lease(
+            &mut self,
+            client_id: &[u8],
+            action: &mut FnMut(&mut Lease) -> (),
+        ) -> Poll<(), storage::Error> {
+            self.inner.update_lease(client_id, action)
+        }
+
+        fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, storage::Error> {
+            self.inner.check_frozen(address)
+        }
+
+        fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.inner.add_frozen(address)
+        }
+
+        fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.inner.delete_frozen(address)
+        }
+    }
+
+    #[test]
+    fn waits_for_storage_without_blocking() {
+        let is_ready = Arc::new(AtomicBool::new(false));
+        let storage = DelayedStorage {
+            inner: RamStorage::new(),
+            is_ready: is_ready.clone(),
+            error: None,
+        };
+        let (ready_tx, mut ready_rx) = oneshot::channel();
+        let mut builder = builder_with_storage(storage);
+        builder.with_ready_signal(ready_tx);
+        let mut server = TestServer::new(builder);
+
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, KNOWN_CLIENT)]);
+        assert!(responses.is_empty());
+        assert_eq!(ready_rx.try_recv().unwrap(), None);
+
+        is_ready.store(true, Ordering::SeqCst);
+        let responses = server.exchange(vec![]);
+        assert_eq!(ready_rx.try_recv().unwrap(), Some(()));
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].1.validate().unwrap() as u8,
+            MessageType::DhcpOffer as u8
+        );
+    }
+
+    #[test]
+    fn terminates_on_storage_preparing_error() {
+        let storage = DelayedStorage {
+            inner: RamStorage::new(),
+            is_ready: Arc::new(AtomicBool::new(true)),
+            error: Some("Connection refused".to_owned()),
+        };
+        let TestServer {
+            mut server,
+            mut runtime,
+            ..
+        } = TestServer::new(builder_with_storage(storage));
+
+        let result = runtime
+            .block_on(future::poll_fn(|| Ok::<_, ()>(Async::Ready(server.poll()))))
+            .unwrap();
+        match result {
+            Err(ServerError::Storage(storage::Error::Other(ref error))) => {
+                assert_eq!(error, "Connection refused")
+            }
+            _ => panic!("The server must terminate with the storage error"),
+        }
+    }
+
+    /// A storage answering each query on the next call, like one querying a database server.
+    struct SlowStorage {
+        inner: RamStorage,
+        is_in_flight: bool,
+        /// The number of the writes reaching the inner storage.
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl SlowStorage {
+        fn query<T, F>(&mut self, is_write: bool, query: F) -> Poll<T, storage::Error>
+        where
+            F: FnOnce(&mut RamStorage) -> Poll<T, storage::Error>,
+        {
+            if !self.is_in_flight {
+                self.is_in_flight = true;
+                task::current().notify();
+                return Ok(Async::NotReady);
+            }
+            self.is_in_flight = false;
+            if is_write {
+                self.writes.fetch_add(1, Ordering::SeqCst);
+            }
+            query(&mut self.inner)
+        }
+    }
+
+    impl Storage for SlowStorage {
+        fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, storage::Error> {
+            self.query(false, |inner| inner.get_client(address))
+        }
+
+        fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.add_client(address, client_id))
+        }
+
+        fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.delete_client(address))
+        }
+
+        fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, storage::Error> {
+            self.query(false, |inner| inner.get_lease(client_id))
+        }
+
+        fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.add_lease(client_id, lease))
+        }
+
+        fn update_lease(
+            &mut self,
+            client_id: &[u8],
+            action: &mut FnMut(&mut Lease) -> (),
+        ) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.update_lease(client_id, action))
+        }
+
+        fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, storage::Error> {
+            self.query(false, |inner| inner.check_frozen(address))
+        }
+
+        fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.add_frozen(address))
+        }
+
+        fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), storage::Error> {
+            self.query(true, |inner| inner.delete_frozen(address))
+        }
+    }
+
+    #[test]
+    fn keeps_request_until_storage_answers() {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let storage = SlowStorage {
+            inner: RamStorage::new(),
+            is_in_flight: false,
+            writes: writes.clone(),
+        };
+        let mut server = TestServer::new(builder_with_storage(storage));
+
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, KNOWN_CLIENT)]);
+        assert_eq!(responses.len(), 1);
+        let (_, ref offer) = responses[0];
+        assert_eq!(
+            offer.validate().unwrap() as u8,
+            MessageType::DhcpOffer as u8
+        );
+        // the client and the lease are added once, however many times the request is processed
+        assert_eq!(writes.load(Ordering::SeqCst), 2);
+
+        let mut request = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        request.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        request.options.address_request = Some(offer.your_ip_address);
+        let responses = server.exchange(vec![request]);
+        assert_eq!(responses.len(), 1);
+        let (_, ref ack) = responses[0];
+        assert_eq!(ack.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        assert_eq!(ack.your_ip_address, offer.your_ip_address);
+        assert_eq!(writes.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn sweeps_expired_leases_periodically() {
+        let mut builder = builder();
+        builder.with_sweep_interval(Duration::from_millis(10));
+        let mut server = TestServer::new(builder);
+        let address = Ipv4Addr::new(192, 168, 0, 100);
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.address_time = Some(0);
+        let mut request = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        request.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        request.options.address_request = Some(address);
+        request.options.address_time = Some(0);
+        let responses = server.exchange(vec![discover, request]);
+        assert_eq!(responses[1].1.options.address_time, Some(0));
+
+        // lets the runtime fire the expired timers
+        server
+            .runtime
+            .block_on(Delay::new(Instant::now() + Duration::from_millis(20)))
+            .unwrap();
+        server.exchange(vec![]);
+
+        let lease = server
+            .server
+            .database
+            .lease(&KNOWN_CLIENT)
+            .ready()
+            .unwrap()
+            .unwrap();
+        assert!(lease.is_released());
+        let other = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(other.your_ip_address, address);
+    }
+
+    #[test]
+    fn rejects_non_contiguous_subnet_mask() {
+        let mut config = config();
+        assert_eq!(config.prefix_len(), Some(24));
+        config.subnet_mask = Ipv4Addr::new(255, 255, 0, 255);
+        assert_eq!(config.prefix_len(), None);
+        let builder = ServerBuilder::new(
+            Ipv4Addr::from(SERVER_IP),
+            "test0".to_owned(),
+            config.static_address_range,
+            config.dynamic_address_range,
+            RamStorage::new(),
+            config.subnet_mask,
+            config.routers,
+            config.domain_name_servers,
+            config.static_routes,
+            config.classless_static_routes,
+        );
+        let socket = TestSocket {
+            incoming: Rc::new(RefCell::new(VecDeque::new())),
+            outgoing: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        let error = builder.finish_with_socket(socket).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn leases_reserved_address_infinitely() {
+        let reserved = Ipv4Addr::new(192, 168, 0, 10);
+        let mut builder = builder();
+        builder.with_reservation(KNOWN_CLIENT.to_vec(), reserved, Some(0xffff_ffff));
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        assert_eq!(ack.your_ip_address, reserved);
+        assert_eq!(ack.options.address_time, Some(0xffff_ffff));
+        let lease = server
+            .server
+            .database
+            .lease(&KNOWN_CLIENT)
+            .ready()
+            .unwrap()
+            .unwrap();
+        assert!(lease.is_active());
+        assert!(!lease.is_expired());
+        assert_eq!(lease.expires_at(), 0xffff_ffff);
+
+        let other = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_ne!(other.your_ip_address, reserved);
+    }
+
+    /// The first dynamic pool address, which would be allocated first without the reservation.
+    const RESERVED_IP: [u8; 4] = [192, 168, 0, 100];
+
+    fn mac_reserving_server() -> TestServer {
+        let mut builder = builder();
+        builder.with_mac_reservation(MacAddress::new(KNOWN_CLIENT), Ipv4Addr::from(RESERVED_IP));
+        TestServer::new(builder)
+    }
+
+    #[test]
+    fn gives_reserved_address_only_to_reserved_mac() {
+        let mut server = mac_reserving_server();
+
+        for i in 0..5 {
+            let other = acquire(&mut server, [0x00, 0x0c, 0x29, 0x13, 0x11, i]);
+            assert_ne!(other.your_ip_address, Ipv4Addr::from(RESERVED_IP));
+        }
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(ack.your_ip_address, Ipv4Addr::from(RESERVED_IP));
+    }
+
+    #[test]
+    fn matches_mac_reservation_by_client_id() {
+        let mut server = mac_reserving_server();
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        let mut client_id = vec![HardwareType::Ethernet as u8];
+        client_id.extend_from_slice(&KNOWN_CLIENT);
+        discover.options.client_id = Some(client_id);
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.your_ip_address, Ipv4Addr::from(RESERVED_IP));
+    }
+
+    #[test]
+    fn matches_mac_reservation_of_client_sending_duid() {
+        let mut server = mac_reserving_server();
+
+        // an RFC 4361 client identifier with a DUID-LL
+        let mut client_id = vec![0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00, 0x01];
+        client_id.extend_from_slice(&KNOWN_CLIENT);
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.client_id = Some(client_id);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.your_ip_address, Ipv4Addr::from(RESERVED_IP));
+    }
+
+    #[test]
+    fn serves_unknown_clients_from_guest_pool() {
+        let guest_pool = GuestPool {
+            address_range: (Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 19)),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            router: Ipv4Addr::new(10, 0, 0, 1),
+            domain_name_server: Ipv4Addr::new(10, 0, 0, 2),
+        };
+        let mut builder = builder();
+        builder
+            .with_mac_reservation(MacAddress::new(KNOWN_CLIENT), Ipv4Addr::from(RESERVED_IP))
+            .with_guest_pool(guest_pool.clone());
+        let mut server = TestServer::new(builder);
+
+        let guest = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(guest.your_ip_address, guest_pool.address_range.0);
+        assert_eq!(guest.options.subnet_mask, Some(guest_pool.subnet_mask));
+        assert_eq!(guest.options.routers, Some(vec![guest_pool.router]));
+        assert_eq!(
+            guest.options.domain_name_servers,
+            Some(vec![guest_pool.domain_name_server])
+        );
+
+        let known = acquire(&mut server, KNOWN_CLIENT);
+        let config = config();
+        assert_eq!(known.your_ip_address, Ipv4Addr::from(RESERVED_IP));
+        assert_eq!(known.options.subnet_mask, Some(config.subnet_mask));
+        assert_eq!(known.options.routers, Some(config.routers));
+        assert_eq!(
+            known.options.domain_name_servers,
+            Some(config.domain_name_servers)
+        );
+    }
+
+    fn relayed_subnet(number: u8) -> SubnetConfig {
+        SubnetConfig {
+            network: Ipv4Addr::new(10, number, 0, 0),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            dynamic_address_range: (
+                Ipv4Addr::new(10, number, 0, 100),
+                Ipv4Addr::new(10, number, 0, 199),
+            ),
+            routers: vec![Ipv4Addr::new(10, number, 0, 1)],
+            domain_name_servers: vec![Ipv4Addr::new(10, number, 0, 2)],
+        }
+    }
+
+    #[test]
+    fn selects_subnet_by_relay_agent_address() {
+        let subnets = [relayed_subnet(1), relayed_subnet(2)];
+        let mut builder = builder();
+        for subnet in subnets.iter() {
+            builder.with_subnet(subnet.clone());
+        }
+        let mut server = TestServer::new(builder);
+
+        for (i, subnet) in subnets.iter().enumerate() {
+            let relay_agent = subnet.routers[0];
+            let mut discover = request(MessageType::DhcpDiscover, [0, 0x0c, 0x29, 0, 0, i as u8]);
+            discover.gateway_ip_address = relay_agent;
+            let responses = server.exchange(vec![discover]);
+
+            assert_eq!(responses.len(), 1);
+            let (destination, ref offer) = responses[0];
+            assert_eq!(
+                destination,
+                SocketAddr::new(IpAddr::V4(relay_agent), DHCP_PORT_SERVER)
+            );
+            assert_eq!(offer.your_ip_address, subnet.dynamic_address_range.0);
+            assert_eq!(offer.options.subnet_mask, Some(subnet.subnet_mask));
+            assert_eq!(offer.options.routers, Some(subnet.routers.to_owned()));
+            assert_eq!(
+                offer.options.domain_name_servers,
+                Some(subnet.domain_name_servers.to_owned())
+            );
+        }
+
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, UNKNOWN_CLIENT)]);
+        assert_eq!(responses.len(), 1);
+        let offer = &responses[0].1;
+        assert_eq!(offer.your_ip_address, config().dynamic_address_range.0);
+        assert_eq!(offer.options.routers, Some(config().routers));
+    }
+
+    #[test]
+    fn selects_pool_by_vendor_class() {
+        let pxe = VendorClassConfig {
+            prefix: b"PXEClient".to_vec(),
+            dynamic_address_range: (
+                Ipv4Addr::new(192, 168, 0, 200),
+                Ipv4Addr::new(192, 168, 0, 209),
+            ),
+            tftp_server_name: Some("192.168.0.2".to_owned()),
+            bootfile_name: Some("pxelinux.0".to_owned()),
+        };
+        let mut builder = builder();
+        builder.with_vendor_class(pxe.clone());
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.class_id = Some(b"PXEClient:Arch:00000:UNDI:002001".to_vec());
+        let mut other = request(MessageType::DhcpDiscover, UNKNOWN_CLIENT);
+        other.options.class_id = Some(b"MSFT 5.0".to_vec());
+        let responses = server.exchange(vec![discover, other]);
+
+        assert_eq!(responses.len(), 2);
+        let offer = &responses[0].1;
+        assert_eq!(offer.your_ip_address, pxe.dynamic_address_range.0);
+        assert_eq!(offer.options.server_name, pxe.tftp_server_name);
+        assert_eq!(offer.options.bootfile_name, pxe.bootfile_name);
+        assert_eq!(offer.options.routers, Some(config().routers));
+        let offer = &responses[1].1;
+        assert_eq!(offer.your_ip_address, config().dynamic_address_range.0);
+        assert_eq!(offer.options.server_name, None);
+        assert_eq!(offer.options.bootfile_name, None);
+    }
+
+    #[test]
+    fn sends_network_boot_parameters() {
+        let mut builder = builder();
+        builder
+            .with_next_server(Ipv4Addr::new(192, 168, 0, 3))
+            .with_boot_file("pxelinux.0".to_owned());
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.class_id = Some(b"PXEClient:Arch:00000:UNDI:002001".to_vec());
+        discover.options.parameter_list = Some(vec![
+            OptionTag::SubnetMask.code(),
+            OptionTag::ServerName.code(),
+            OptionTag::BootfileName.code(),
+        ]);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        let offer = &responses[0].1;
+        assert_eq!(offer.server_ip_address, Ipv4Addr::new(192, 168, 0, 3));
+        assert_eq!(offer.boot_filename, b"pxelinux.0".to_vec());
+        assert_eq!(offer.options.server_name, Some("192.168.0.3".to_owned()));
+        assert_eq!(offer.options.bootfile_name, Some("pxelinux.0".to_owned()));
+
+        let ack = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(ack.server_ip_address, Ipv4Addr::new(192, 168, 0, 3));
+        assert_eq!(ack.boot_filename, b"pxelinux.0".to_vec());
+        assert_eq!(ack.options.server_name, None);
+        assert_eq!(ack.options.bootfile_name, None);
+    }
+
+    #[test]
+    fn sends_requested_ntp_servers() {
+        let ntp_servers = vec![Ipv4Addr::new(192, 168, 0, 4), Ipv4Addr::new(192, 168, 0, 5)];
+        let mut builder = builder();
+        builder.with_ntp_servers(ntp_servers.to_owned());
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(ack.options.ntp_servers, None);
+
+        let mut discover = request(MessageType::DhcpDiscover, UNKNOWN_CLIENT);
+        discover.options.parameter_list = Some(vec![OptionTag::NtpServers.code()]);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.options.ntp_servers, Some(ntp_servers));
+    }
+
+    #[test]
+    fn sends_requested_domain_name_and_search_list() {
+        let domain_search = vec!["eng.example.com".to_owned(), "example.com".to_owned()];
+        let mut builder = builder();
+        builder
+            .with_domain_name("example.com".to_owned())
+            .with_domain_search(domain_search.to_owned());
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(ack.options.domain_name, None);
+        assert_eq!(ack.options.domain_search, None);
+
+        let mut discover = request(MessageType::DhcpDiscover, UNKNOWN_CLIENT);
+        discover.options.parameter_list = Some(vec![
+            OptionTag::DomainName.code(),
+            OptionTag::DomainSearch.code(),
+        ]);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        let offer = &responses[0].1;
+        assert_eq!(offer.options.domain_name, Some("example.com".to_owned()));
+        assert_eq!(offer.options.domain_search, Some(domain_search));
+    }
+
+    #[test]
+    fn grants_lease_times_within_bounds() {
+        let mut builder = builder();
+        builder.with_lease_bounds(600, 7200, 3600);
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.address_time = Some(86400);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.options.address_time, Some(7200));
+
+        let ack = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(ack.options.address_time, Some(3600));
+        assert_eq!(ack.options.renewal_time, Some(1800));
+        assert_eq!(ack.options.rebinding_time, Some(3150));
+    }
+
+    #[test]
+    fn rejects_default_lease_time_out_of_bounds() {
+        let mut builder = builder();
+        builder.with_lease_bounds(600, 7200, 60);
+        let socket = TestSocket {
+            incoming: Rc::new(RefCell::new(VecDeque::new())),
+            outgoing: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        let error = builder.finish_with_socket(socket).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn never_offers_excluded_addresses() {
+        let gateway = Ipv4Addr::new(192, 168, 0, 100);
+        let mut builder = builder();
+        builder.exclude(gateway).exclude_range(
+            Ipv4Addr::new(192, 168, 0, 102),
+            Ipv4Addr::new(192, 168, 0, 199),
+        );
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.address_request = Some(gateway);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].1.your_ip_address,
+            Ipv4Addr::new(192, 168, 0, 101)
+        );
+
+        // the only address left is taken, so the pool is exhausted
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, UNKNOWN_CLIENT)]);
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn rejects_empty_excluded_range() {
+        let mut builder = builder();
+        builder.exclude_range(
+            Ipv4Addr::new(192, 168, 0, 120),
+            Ipv4Addr::new(192, 168, 0, 110),
+        );
+        let socket = TestSocket {
+            incoming: Rc::new(RefCell::new(VecDeque::new())),
+            outgoing: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        let error = builder.finish_with_socket(socket).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn keeps_arp_handles_of_back_to_back_offers() {
+        let mut server = TestServer::new(builder());
+        let clients = [
+            (KNOWN_CLIENT, Ipv4Addr::new(192, 168, 0, 100)),
+            (UNKNOWN_CLIENT, Ipv4Addr::new(192, 168, 0, 101)),
+        ];
+
+        for &(mac, address) in clients.iter() {
+            let mut discover = request(MessageType::DhcpDiscover, mac);
+            discover.is_broadcast = false;
+            let mut offer = request(MessageType::DhcpOffer, mac);
+            offer.your_ip_address = address;
+            assert_eq!(server.server.destination(&discover, &offer), (address, true));
+        }
+        // the repeated offer joins the pending handle instead of replacing it
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.is_broadcast = false;
+        let mut offer = request(MessageType::DhcpOffer, KNOWN_CLIENT);
+        offer.your_ip_address = clients[0].1;
+        server.server.destination(&discover, &offer);
+
+        assert_eq!(server.server.arp.len(), 2);
+        for &(_mac, address) in clients.iter() {
+            assert!(server.server.arp.contains_key(&address));
+        }
+    }
+
+    #[test]
+    fn survives_malformed_requests() {
+        let mut server = TestServer::new(builder());
+
+        let mut selecting = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        selecting.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        let decline = request(MessageType::DhcpDecline, KNOWN_CLIENT);
+        let mut untyped = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        untyped.options.dhcp_message_type = None;
+        assert!(server.exchange(vec![selecting, decline, untyped]).is_empty());
+
+        let responses = server.exchange(vec![request(MessageType::DhcpDiscover, KNOWN_CLIENT)]);
+        assert_eq!(responses.len(), 1);
+        let (_, ref offer) = responses[0];
+        assert_eq!(offer.validate().unwrap() as u8, MessageType::DhcpOffer as u8);
+    }
+
+    #[test]
+    fn sends_nak_to_relay_agent() {
+        let mut server = TestServer::new(builder());
+        let relay_agent = Ipv4Addr::new(10, 1, 0, 1);
+
+        let mut request = request(MessageType::DhcpRequest, UNKNOWN_CLIENT);
+        request.is_broadcast = false;
+        request.gateway_ip_address = relay_agent;
+        request.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        request.options.address_request = Some(Ipv4Addr::new(192, 168, 0, 150));
+        let responses = server.exchange(vec![request]);
+
+        assert_eq!(responses.len(), 1);
+        let (destination, ref nak) = responses[0];
+        assert_eq!(nak.validate().unwrap() as u8, MessageType::DhcpNak as u8);
+        assert_eq!(
+            destination,
+            SocketAddr::new(IpAddr::V4(relay_agent), DHCP_PORT_SERVER)
+        );
+        assert!(nak.is_broadcast);
+    }
+
+    #[test]
+    fn reports_lease_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_by_server = events.clone();
+        let mut builder = builder();
+        builder.with_lease_event_handler(Box::new(move |event| {
+            events_by_server.lock().unwrap().push(event)
+        }));
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        let events = events.lock().unwrap();
+        let kinds: Vec<LeaseEventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![LeaseEventKind::Offered, LeaseEventKind::Acked]);
+        for event in events.iter() {
+            assert_eq!(event.address, ack.your_ip_address);
+            assert_eq!(event.client_id, KNOWN_CLIENT.to_vec());
+            assert_eq!(
+                event.client_hardware_address,
+                Some(MacAddress::new(KNOWN_CLIENT))
+            );
+            assert_eq!(event.lease_time, ack.options.address_time);
+        }
+    }
+
+    #[test]
+    fn processes_requests_without_acting_in_dry_run() {
+        let path = env::temp_dir().join(format!("dhcp-storage-{}-dry-run", process::id()));
+        let _ = fs::remove_file(&path);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_by_server = events.clone();
+        let mut builder = builder_with_storage(FileStorage::new(&path).unwrap());
+        builder
+            .with_dry_run()
+            .with_lease_event_handler(Box::new(move |event| {
+                events_by_server.lock().unwrap().push(event)
+            }));
+        let mut server = TestServer::new(builder);
+
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        assert!(server.exchange(vec![discover]).is_empty());
+        let offered = events.lock().unwrap()[0].address;
+        let mut request = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        request.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        request.options.address_request = Some(offered);
+        assert!(server.exchange(vec![request]).is_empty());
+
+        let events = events.lock().unwrap();
+        let kinds: Vec<LeaseEventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![LeaseEventKind::Offered, LeaseEventKind::Acked]);
+        assert_eq!(events[1].address, offered);
+        assert!(!path.exists());
+    }
+
+    /// Sends an INIT-REBOOT `DHCPREQUEST` from a client unknown to the server.
+    fn init_reboot(builder: ServerBuilder<RamStorage>, address: Ipv4Addr) -> Vec<Message> {
+        let mut server = TestServer::new(builder);
+        let mut request = request(MessageType::DhcpRequest, UNKNOWN_CLIENT);
+        request.options.address_request = Some(address);
+
+        server
+            .exchange(vec![request])
+            .into_iter()
+            .map(|(_, response)| response)
+            .collect()
+    }
+
+    #[test]
+    fn naks_foreign_address_in_authoritative_mode() {
+        let mut builder = builder();
+        builder.with_authoritative_mode();
+
+        let responses = init_reboot(builder, Ipv4Addr::new(10, 0, 0, 5));
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].validate().unwrap() as u8,
+            MessageType::DhcpNak as u8
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_client_in_own_pool_in_authoritative_mode() {
+        let mut builder = builder();
+        builder.with_authoritative_mode();
+
+        assert!(init_reboot(builder, Ipv4Addr::new(192, 168, 0, 150)).is_empty());
+    }
+
+    #[test]
+    fn ignores_foreign_address_by_default() {
+        assert!(init_reboot(builder(), Ipv4Addr::new(10, 0, 0, 5)).is_empty());
+    }
+
+    #[test]
+    fn grants_clamped_lease_time_on_init_reboot() {
+        let mut builder = builder();
+        builder.with_lease_bounds(60, 7200, 3600);
+        let mut server = TestServer::new(builder);
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(ack.options.address_time, Some(3600));
+
+        let mut request = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        request.options.address_request = Some(ack.your_ip_address);
+        request.options.address_time = Some(86400);
+        let responses = server.exchange(vec![request]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref ack) = responses[0];
+        assert_eq!(ack.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        assert_eq!(ack.options.address_time, Some(7200));
+        assert_eq!(ack.options.renewal_time, Some(3600));
+        assert_eq!(ack.options.rebinding_time, Some(6300));
+    }
+
+    #[test]
+    fn echoes_relay_agent_information() {
+        let relay_agent_info = vec![0x01, 0x04, b'e', b't', b'h', b'0', 0x02, 0x02, 0x00, 0x2a];
+        let mut server = TestServer::new(builder());
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.relay_agent_info = Some(relay_agent_info.to_owned());
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref offer) = responses[0];
+        let mut buffer = vec![0u8; SIZE_MESSAGE_MINIMAL];
+        let size = offer.to_bytes(&mut buffer, None).unwrap();
+        let offer = Message::from_bytes(&buffer[..size]).unwrap();
+        assert_eq!(offer.options.relay_agent_info, Some(relay_agent_info));
+    }
+
+    #[test]
+    fn informs_with_restricted_options_without_lease_time() {
+        let mut builder = builder();
+        builder.with_inform_options(vec![OptionTag::DomainNameServers]);
+        let mut server = TestServer::new(builder);
+
+        let mut inform = request(MessageType::DhcpInform, KNOWN_CLIENT);
+        inform.client_ip_address = Ipv4Addr::new(192, 168, 0, 250);
+        let responses = server.exchange(vec![inform]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref ack) = responses[0];
+        assert_eq!(ack.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        assert!(ack.your_ip_address.is_unspecified());
+        assert!(ack.options.address_time.is_none());
+        assert!(ack.options.renewal_time.is_none());
+        assert!(ack.options.rebinding_time.is_none());
+        assert_eq!(
+            ack.options.domain_name_servers,
+            Some(vec![Ipv4Addr::new(192, 168, 0, 1)])
+        );
+        assert!(ack.options.subnet_mask.is_none());
+        assert!(ack.options.routers.is_none());
+    }
+
+    /// Returns the `DHCPOFFER` destination chosen under the broadcast policy.
+    fn offer_destination(broadcast_policy: BroadcastPolicy, is_broadcast: bool) -> IpAddr {
+        let mut builder = builder();
+        builder.with_broadcast_policy(broadcast_policy);
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.is_broadcast = is_broadcast;
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        responses[0].0.ip()
+    }
+
+    #[test]
+    fn honors_broadcast_flag() {
+        let broadcast = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255));
+        assert_eq!(offer_destination(BroadcastPolicy::Honor, true), broadcast);
+        assert_ne!(offer_destination(BroadcastPolicy::Honor, false), broadcast);
+    }
+
+    #[test]
+    fn always_broadcasts_by_policy() {
+        let broadcast = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255));
+        assert_eq!(offer_destination(BroadcastPolicy::AlwaysBroadcast, true), broadcast);
+        assert_eq!(offer_destination(BroadcastPolicy::AlwaysBroadcast, false), broadcast);
+    }
+
+    #[test]
+    fn always_unicasts_by_policy() {
+        let broadcast = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255));
+        for &is_broadcast in &[true, false] {
+            assert_ne!(
+                offer_destination(BroadcastPolicy::AlwaysUnicastWhenPossible, is_broadcast),
+                broadcast
+            );
+        }
+    }
+
+    #[test]
+    fn drops_requests_from_unexpected_source_port() {
+        let mut builder = builder();
+        builder.with_source_port_check(true);
+        let mut server = TestServer::new(builder);
+
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        assert!(server.exchange_from(1068, vec![discover]).is_empty());
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        assert_eq!(server.exchange(vec![discover]).len(), 1);
+    }
+
+    #[test]
+    fn accepts_requests_from_any_source_port_by_default() {
+        let mut server = TestServer::new(builder());
+
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        assert_eq!(server.exchange_from(1068, vec![discover]).len(), 1);
+    }
+
+    /// Records the direction and the type of every audited message.
+    struct TestAuditSink(Arc<Mutex<Vec<(&'static str, u8)>>>);
+
+    impl AuditSink for TestAuditSink {
+        fn on_recv(&mut self, message: &Message, _source: SocketAddr) {
+            let dhcp_message_type = message.options.dhcp_message_type.unwrap();
+            self.0.lock().unwrap().push(("recv", dhcp_message_type as u8));
+        }
+
+        fn on_send(&mut self, message: &Message, _destination: Ipv4Addr) {
+            let dhcp_message_type = message.options.dhcp_message_type.unwrap();
+            self.0.lock().unwrap().push(("send", dhcp_message_type as u8));
+        }
+    }
+
+    #[test]
+    fn audits_received_and_sent_messages_in_order() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = builder();
+        builder.with_audit_sink(Box::new(TestAuditSink(records.clone())));
+        let mut server = TestServer::new(builder);
+
+        acquire(&mut server, KNOWN_CLIENT);
+
+        assert_eq!(
+            *records.lock().unwrap(),
+            vec![
+                ("recv", MessageType::DhcpDiscover as u8),
+                ("send", MessageType::DhcpOffer as u8),
+                ("recv", MessageType::DhcpRequest as u8),
+                ("send", MessageType::DhcpAck as u8),
+            ]
+        );
+    }
+
+    #[test]
+    fn serves_on_ephemeral_bind_port() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = builder();
+        builder.with_bind_port(0);
+        builder.with_audit_sink(Box::new(TestAuditSink(records.clone())));
+        let mut server = builder.finish().unwrap();
+        let port = server.local_addr().unwrap().port();
+        assert_ne!(port, 0);
+
+        let mut buffer = vec![0u8; 1024];
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        let amount = discover.to_bytes(&mut buffer, None).unwrap();
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(&buffer[..amount], ("127.0.0.1", port))
+            .unwrap();
+
+        // sending the broadcast response may fail in a sandbox, so only the audit is checked
+        Runtime::new()
+            .unwrap()
+            .block_on(future::poll_fn(|| {
+                let result = server.poll();
+                if records.lock().unwrap().len() == 2 {
+                    return Ok(Async::Ready(()));
+                }
+                result.map(|_| Async::NotReady)
+            }))
+            .unwrap();
+        assert_eq!(
+            *records.lock().unwrap(),
+            vec![
+                ("recv", MessageType::DhcpDiscover as u8),
+                ("send", MessageType::DhcpOffer as u8),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_messages_in_metrics() {
+        let builder = builder();
+        let metrics = builder.metrics_handle();
+        let mut server = TestServer::new(builder);
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        assert_eq!(
+            metrics.snapshot(),
+            MetricsSnapshot {
+                discovers: 1,
+                offers: 1,
+                requests: 1,
+                acks: 1,
+                active_leases: 1,
+                ..Default::default()
+            }
+        );
+
+        let mut release = request(MessageType::DhcpRelease, KNOWN_CLIENT);
+        release.client_ip_address = ack.your_ip_address;
+        release.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        server.exchange(vec![release]);
+        assert_eq!(metrics.snapshot().releases, 1);
+        assert_eq!(metrics.snapshot().active_leases, 0);
+    }
+
+    #[test]
+    fn commits_lease_on_rapid_commit() {
+        let mut builder = builder();
+        builder.with_rapid_commit();
+        let mut server = TestServer::new(builder);
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.rapid_commit = true;
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref ack) = responses[0];
+        assert_eq!(ack.validate().unwrap() as u8, MessageType::DhcpAck as u8);
+        assert!(ack.options.rapid_commit);
+        let lease = server
+            .server
+            .database
+            .lease(&KNOWN_CLIENT)
+            .ready()
+            .unwrap()
+            .unwrap();
+        assert!(lease.is_active());
+        assert_eq!(lease.address(), ack.your_ip_address);
+    }
+
+    #[test]
+    fn offers_on_rapid_commit_by_default() {
+        let mut server = TestServer::new(builder());
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.options.rapid_commit = true;
+        let responses = server.exchange(vec![discover]);
+
+        assert_eq!(responses.len(), 1);
+        let (_, ref offer) = responses[0];
+        assert_eq!(offer.validate().unwrap() as u8, MessageType::DhcpOffer as u8);
+        assert!(!offer.options.rapid_commit);
+    }
+
+    /// Pretends that only the specified addresses are in use.
+    ///
+    /// Each probe completes on the next call, like one waiting for a reply,
+    /// and the probes of the held addresses do not complete until they are released.
+    struct TestProber {
+        in_use: Vec<Ipv4Addr>,
+        probing: Vec<Ipv4Addr>,
+        held: Arc<Mutex<Vec<Ipv4Addr>>>,
+    }
+
+    impl TestProber {
+        fn new(in_use: Vec<Ipv4Addr>) -> Self {
+            TestProber {
+                in_use,
+                probing: Vec::new(),
+                held: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    impl ConflictProber for TestProber {
+        fn poll_in_use(&mut self, address: &Ipv4Addr) -> Poll<bool, io::Error> {
+            if self.held.lock().unwrap().contains(address) {
+                return Ok(Async::NotReady);
+            }
+            match self.probing.iter().position(|probing| probing == address) {
+                Some(index) => {
+                    self.probing.remove(index);
+                    Ok(Async::Ready(self.in_use.contains(address)))
+                }
+                None => {
+                    self.probing.push(*address);
+                    task::current().notify();
+                    Ok(Async::NotReady)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn offers_address_if_arp_probing_fails() {
+        // the test interface does not exist, so every probe fails
+        let mut builder = builder();
+        builder.with_arp_probe(Duration::from_millis(10));
+        let mut server = TestServer::new(builder);
+        assert!(server.server.prober.is_some());
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+        assert_eq!(ack.your_ip_address, Ipv4Addr::new(192, 168, 0, 100));
+    }
+
+    #[test]
+    fn rejects_both_conflict_probes() {
+        let mut builder = builder();
+        builder
+            .with_conflict_detection(Duration::from_millis(10))
+            .with_arp_probe(Duration::from_millis(10));
+        let socket = TestSocket {
+            incoming: Rc::new(RefCell::new(VecDeque::new())),
+            outgoing: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        let error = builder.finish_with_socket(socket).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn skips_and_freezes_address_in_use() {
+        let mut server = TestServer::new(builder());
+        let in_use = Ipv4Addr::new(192, 168, 0, 100);
+        server.server.prober = Some(Box::new(TestProber::new(vec![in_use])));
+
+        let ack = acquire(&mut server, KNOWN_CLIENT);
+
+        assert_eq!(ack.your_ip_address, Ipv4Addr::new(192, 168, 0, 101));
+        let ack = acquire(&mut server, UNKNOWN_CLIENT);
+        assert_eq!(ack.your_ip_address, Ipv4Addr::new(192, 168, 0, 102));
+    }
+
+    #[test]
+    fn serves_other_clients_while_probing() {
+        let mut server = TestServer::new(builder());
+        let prober = TestProber::new(Vec::new());
+        let held = prober.held.clone();
+        held.lock().unwrap().push(Ipv4Addr::new(192, 168, 0, 100));
+        server.server.prober = Some(Box::new(prober));
+
+        let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 68);
+        server.incoming.borrow_mut().extend(vec![
+            (source, request(MessageType::DhcpDiscover, KNOWN_CLIENT)),
+            (source, request(MessageType::DhcpDiscover, UNKNOWN_CLIENT)),
+        ]);
+        let mut responses = server.poll();
+        responses.extend(server.poll());
+        assert_eq!(responses.len(), 1);
+        let offer = &responses[0].1;
+        assert_eq!(
+            offer.client_hardware_address,
+            MacAddress::new(UNKNOWN_CLIENT)
+        );
+        assert_eq!(offer.your_ip_address, Ipv4Addr::new(192, 168, 0, 101));
+
+        held.lock().unwrap().clear();
+        let responses = server.exchange(Vec::new());
+        assert_eq!(responses.len(), 1);
+        let offer = &responses[0].1;
+        assert_eq!(offer.client_hardware_address, MacAddress::new(KNOWN_CLIENT));
+        assert_eq!(offer.your_ip_address, Ipv4Addr::new(192, 168, 0, 100));
+    }
+
+    /// Creates a server requiring the requests to be signed with the returned key.
+    fn authenticating_server() -> (TestServer, AuthKey) {
+        let key = AuthKey {
+            id: 1,
+            secret: b"shared secret".to_vec(),
+        };
+        let mut builder = builder();
+        builder.with_auth_key(key.to_owned());
+        (TestServer::new(builder), key)
+    }
+
+    #[test]
+    fn drops_unauthenticated_and_replayed_requests() {
+        let (mut server, key) = authenticating_server();
+
+        let unsigned = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        assert!(server.exchange(vec![unsigned]).is_empty());
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.set_authentication(&key, 2);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        let first = responses[0].1.options.authentication.to_owned().unwrap();
+
+        let mut replayed = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        replayed.set_authentication(&key, 2);
+        assert!(server.exchange(vec![replayed]).is_empty());
+
+        let mut other = request(MessageType::DhcpDiscover, UNKNOWN_CLIENT);
+        other.set_authentication(&key, 1);
+        let responses = server.exchange(vec![other]);
+        assert_eq!(responses.len(), 1);
+        let second = responses[0].1.options.authentication.to_owned().unwrap();
+        assert!(second.replay_detection > first.replay_detection);
+    }
+
+    #[test]
+    fn ignores_replay_detection_of_requests_to_other_servers() {
+        let (mut server, key) = authenticating_server();
+
+        let mut foreign = request(MessageType::DhcpRequest, KNOWN_CLIENT);
+        foreign.options.dhcp_server_id = Some(Ipv4Addr::new(192, 168, 0, 3));
+        foreign.options.address_request = Some(Ipv4Addr::new(192, 168, 0, 100));
+        foreign.set_authentication(&key, 5);
+        assert!(server.exchange(vec![foreign]).is_empty());
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.set_authentication(&key, 3);
+        assert_eq!(server.exchange(vec![discover]).len(), 1);
+    }
+
+    #[test]
+    fn forgets_replay_detection_of_released_clients() {
+        let (mut server, key) = authenticating_server();
+
+        let mut discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        discover.set_authentication(&key, 1);
+        let responses = server.exchange(vec![discover]);
+        assert_eq!(responses.len(), 1);
+        let address = responses[0].1.your_ip_address;
+
+        let mut release = request(MessageType::DhcpRelease, KNOWN_CLIENT);
+        release.client_ip_address = address;
+        release.options.dhcp_server_id = Some(Ipv4Addr::from(SERVER_IP));
+        release.set_authentication(&key, 2);
+        assert!(server.exchange(vec![release]).is_empty());
+        assert!(server.server.client_replay_detections.is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn serves_on_bound_device() {
+        // the test interface does not exist
+        let mut builder = builder();
+        builder.with_bind_port(0).with_device_binding();
+        assert!(builder.finish().is_err());
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let config = config();
+        let mut builder = ServerBuilder::new(
+            Ipv4Addr::from(SERVER_IP),
+            "lo".to_owned(),
+            config.static_address_range,
+            config.dynamic_address_range,
+            RamStorage::new(),
+            config.subnet_mask,
+            config.routers,
+            config.domain_name_servers,
+            config.static_routes,
+            config.classless_static_routes,
+        );
+        builder.with_bind_port(0).with_device_binding();
+        builder.with_audit_sink(Box::new(TestAuditSink(records.clone())));
+        let mut server = builder.finish().unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let mut buffer = vec![0u8; 1024];
+        let discover = request(MessageType::DhcpDiscover, KNOWN_CLIENT);
+        let amount = discover.to_bytes(&mut buffer, None).unwrap();
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(&buffer[..amount], ("127.0.0.1", port))
+            .unwrap();
+
+        Runtime::new()
+            .unwrap()
+            .block_on(future::poll_fn(|| {
+                let result = server.poll();
+                if records.lock().unwrap().len() == 2 {
+                    return Ok(Async::Ready(()));
+                }
+                result.map(|_| Async::NotReady)
+            }))
+            .unwrap();
+
     }
 }
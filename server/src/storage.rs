@@ -2,6 +2,8 @@
 
 use std::net::Ipv4Addr;
 
+use futures::{Async, Poll};
+
 use lease::Lease;
 
 /// Errors generated by the `Storage` trait methods.
@@ -25,6 +27,8 @@ pub enum Error {
     CheckFrozen(String),
     #[fail(display = "Frozen address adding error: {}", _0)]
     AddFrozen(String),
+    #[fail(display = "Frozen address deleting error: {}", _0)]
+    DeleteFrozen(String),
 
     #[fail(display = "Another error: {}", _0)]
     Other(String),
@@ -33,44 +37,65 @@ pub enum Error {
 /// Must be implemented by the DHCP server crate user.
 ///
 /// Be sure your storage is `ACID`.
+///
+/// The data methods are polled like futures, so a storage backed by a database
+/// or a network service may return `Async::NotReady` while its query is in flight
+/// and notify the current task when the result arrives.
+/// The server keeps the request being processed and processes it again then,
+/// so the method is called again with the same arguments and must return the result
+/// of the query it has started. The methods completed before it are not called again,
+/// their results are replayed.
 pub trait Storage
 where
     Self: Sync + Send,
 {
+    /// Must return `Async::Ready` when the storage can serve the next request without blocking.
+    ///
+    /// If `Async::NotReady` is returned, the current task must be notified
+    /// when the storage becomes ready, like with any other future.
+    /// The default implementation is always ready.
+    ///
+    /// # Errors
+    /// Must return `Error::Other(desc)` if the storage cannot be prepared.
+    /// The server terminates with `ServerError::Storage` then.
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+
     /// Must return the client ID if the client is associated with the given address.
     ///
     /// # Errors
     /// Must return `Error::GetClient(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn get_client(&self, address: &Ipv4Addr) -> Result<Option<Vec<u8>>, Error>;
+    fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error>;
 
     /// Must associate the client with the given address.
     ///
     /// # Errors
     /// Must return `Error::AddClient(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Result<(), Error>;
+    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), Error>;
 
     /// Must disassociate the client ID from the given address.
     ///
     /// # Errors
     /// Must return `Error::DeleteClient(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn delete_client(&mut self, address: &Ipv4Addr) -> Result<(), Error>;
+    fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), Error>;
 
     /// Must return the address lease of the given client if the lease exists.
     ///
     /// # Errors
     /// Must return `Error::GetLease(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn get_lease(&self, client_id: &[u8]) -> Result<Option<Lease>, Error>;
+    fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error>;
 
     /// Must associate the client ID with the given lease.
     ///
     /// # Errors
     /// Must return `Error::AddLease(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Result<(), Error>;
+    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), Error>;
 
     /// Must update the lease associated with the given client ID if the lease exists.
     ///
@@ -81,19 +106,43 @@ where
         &mut self,
         client_id: &[u8],
         action: &mut FnMut(&mut Lease) -> (),
-    ) -> Result<(), Error>;
+    ) -> Poll<(), Error>;
 
     /// Must return `true` if the given address has been frozen, `false` otherwise.
     ///
     /// # Errors
     /// Must return `Error::CheckFrozen(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn check_frozen(&self, address: &Ipv4Addr) -> Result<bool, Error>;
+    fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error>;
 
     /// Must mark the address as frozen due to a client `DHCPDECLINE` report.
     ///
     /// # Errors
     /// Must return `Error::AddFrozen(desc)` if there is a database I/O error
     /// or `Error::Other(desc)` on another error.
-    fn add_frozen(&mut self, address: &Ipv4Addr) -> Result<(), Error>;
+    fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error>;
+
+    /// Must unmark the frozen address when its decline cooldown has elapsed.
+    ///
+    /// # Errors
+    /// Must return `Error::DeleteFrozen(desc)` if there is a database I/O error
+    /// or `Error::Other(desc)` on another error.
+    fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error>;
+}
+
+/// Unwraps the storage and database operations which are expected to be ready in tests.
+#[cfg(test)]
+pub(crate) trait Ready<T, E> {
+    fn ready(self) -> Result<T, E>;
+}
+
+#[cfg(test)]
+impl<T, E> Ready<T, E> for Poll<T, E> {
+    fn ready(self) -> Result<T, E> {
+        match self {
+            Ok(Async::Ready(item)) => Ok(item),
+            Ok(Async::NotReady) => panic!("The operation is not ready"),
+            Err(error) => Err(error),
+        }
+    }
 }
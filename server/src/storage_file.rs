@@ -0,0 +1,366 @@
+//! A file-backed lease storage of the DHCP server, which survives restarts.
+//!
+//! The storage is kept in RAM and every change is appended to the file as a record,
+//! which is replayed on opening. The file consists of lines like these:
+//! `client <address> <client_id>`
+//! `delete-client <address>`
+//! `lease <client_id> <lease record>`
+//! `frozen <address>`
+//! `delete-frozen <address>`
+//! where client IDs are hex-encoded.
+//!
+//! Each change costs a single appended line, which is written to the OS but not synced,
+//! so a process crash loses nothing, but a power loss may lose the latest changes.
+//! A change is applied in RAM only after its record is written, so a failed write
+//! leaves the storage as it was.
+//! Once the file has grown to several times the table, the whole table is written
+//! to a temporary file, synced and renamed over the old one, which costs time
+//! proportional to the table and blocks the server for that time.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use futures::{Async, Poll};
+
+use lease::{self, Lease};
+use storage::{Error, Storage};
+use storage_ram::RamStorage;
+
+/// The file is compacted once it has this many times more records than the table.
+const COMPACTION_FACTOR: usize = 4;
+/// The file is never compacted while it has fewer records than this.
+const COMPACTION_MIN_RECORDS: usize = 1024;
+
+pub struct FileStorage {
+    /// The file the table is saved to.
+    path: PathBuf,
+    /// The table itself.
+    inner: RamStorage,
+    /// The file opened for appending or `None` if it must be rewritten before the next record,
+    /// e.g. after a failed write could have left a partial line.
+    file: Option<fs::File>,
+    /// The number of records in the file.
+    records: usize,
+}
+
+impl FileStorage {
+    /// Opens the storage saved to `path` or creates an empty one if the file does not exist.
+    ///
+    /// A partial last line left by a crash during a write is ignored.
+    ///
+    /// # Errors
+    /// `io::Error` if the file cannot be read or is malformed.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut inner = RamStorage::new();
+        let mut records = 0;
+        let mut is_partial = false;
+
+        let mut contents = String::new();
+        match fs::File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut contents)?;
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        };
+        let mut lines = contents.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if Self::load_record(&mut inner, line).is_none() {
+                // the last line is not terminated if its write has been interrupted
+                if lines.peek().is_none() {
+                    warn!("Ignoring the partial storage record: {}", line);
+                    is_partial = true;
+                    break;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Malformed storage record: {}", line),
+                ));
+            }
+            records += 1;
+        }
+
+        let mut storage = FileStorage {
+            path,
+            inner,
+            file: None,
+            records,
+        };
+        // the file is opened for appending by the first change, so it is not created until then
+        if is_partial {
+            storage.compact()?;
+        }
+        Ok(storage)
+    }
+
+    fn load_record(inner: &mut RamStorage, line: &str) -> Option<()> {
+        let mut parts = line.splitn(3, ' ');
+        match parts.next()? {
+            "client" => {
+                let address = Ipv4Addr::from_str(parts.next()?).ok()?;
+                let client_id = lease::from_hex(parts.next()?)?;
+                inner.address_client_map.insert(address, client_id);
+            }
+            "delete-client" => {
+                let address = Ipv4Addr::from_str(parts.next()?).ok()?;
+                inner.address_client_map.remove(&address);
+            }
+            "lease" => {
+                let client_id = lease::from_hex(parts.next()?)?;
+                let lease = Lease::from_record(parts.next()?)?;
+                inner.client_lease_map.insert(client_id, lease);
+            }
+            "frozen" => {
+                let address = Ipv4Addr::from_str(parts.next()?).ok()?;
+                inner.frozen_addresses.push(address);
+            }
+            "delete-frozen" => {
+                let address = Ipv4Addr::from_str(parts.next()?).ok()?;
+                inner.frozen_addresses.retain(|frozen| *frozen != address);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// The number of records the whole table is written with.
+    fn table_size(&self) -> usize {
+        self.inner.address_client_map.len()
+            + self.inner.client_lease_map.len()
+            + self.inner.frozen_addresses.len()
+    }
+
+    /// Appends the record of a change, which is applied in RAM only if this succeeds.
+    fn append(&mut self, record: &str) -> io::Result<()> {
+        if self.file.is_none()
+            || self.records >= COMPACTION_MIN_RECORDS
+                && self.records >= self.table_size() * COMPACTION_FACTOR
+        {
+            self.compact()?;
+        }
+
+        let result = match self.file {
+            Some(ref mut file) => file.write_all(format!("{}\n", record).as_bytes()),
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => self.records += 1,
+            // the next change rewrites the file, so the partial line is dropped
+            Err(_) => self.file = None,
+        }
+        result
+    }
+
+    /// Writes the whole table to a temporary file and renames it over the old one,
+    /// so a crash leaves either the old or the new table.
+    fn compact(&mut self) -> io::Result<()> {
+        self.file = None;
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        {
+            let mut file = io::BufWriter::new(fs::File::create(&temporary)?);
+            for (address, client_id) in &self.inner.address_client_map {
+                writeln!(file, "client {} {}", address, lease::to_hex(client_id))?;
+            }
+            for (client_id, lease) in &self.inner.client_lease_map {
+                writeln!(file, "lease {} {}", lease::to_hex(client_id), lease.to_record())?;
+            }
+            for address in &self.inner.frozen_addresses {
+                writeln!(file, "frozen {}", address)?;
+            }
+            let file = file.into_inner().map_err(|error| error.into_error())?;
+            file.sync_all()?;
+        }
+
+        fs::rename(&temporary, &self.path)?;
+        self.records = self.table_size();
+        self.file = Some(fs::OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
+        self.inner.get_client(address)
+    }
+
+    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), Error> {
+        self.append(&format!("client {} {}", address, lease::to_hex(client_id)))
+            .map_err(|error| Error::AddClient(error.to_string()))?;
+        self.inner.add_client(address, client_id)
+    }
+
+    fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.append(&format!("delete-client {}", address))
+            .map_err(|error| Error::DeleteClient(error.to_string()))?;
+        self.inner.delete_client(address)
+    }
+
+    fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error> {
+        self.inner.get_lease(client_id)
+    }
+
+    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), Error> {
+        let record = format!("lease {} {}", lease::to_hex(client_id), lease.to_record());
+        self.append(&record)
+            .map_err(|error| Error::AddLease(error.to_string()))?;
+        self.inner.add_lease(client_id, lease)
+    }
+
+    fn update_lease(
+        &mut self,
+        client_id: &[u8],
+        action: &mut FnMut(&mut Lease),
+    ) -> Poll<(), Error> {
+        // the action is applied to a copy, which replaces the lease once it is written
+        let mut lease = match try_ready!(self.inner.get_lease(client_id)) {
+            Some(lease) => lease,
+            None => return Ok(Async::Ready(())),
+        };
+        action(&mut lease);
+        let record = format!("lease {} {}", lease::to_hex(client_id), lease.to_record());
+        self.append(&record)
+            .map_err(|error| Error::UpdateLease(error.to_string()))?;
+        self.inner.add_lease(client_id, lease)
+    }
+
+    fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        self.inner.check_frozen(address)
+    }
+
+    fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.append(&format!("frozen {}", address))
+            .map_err(|error| Error::AddFrozen(error.to_string()))?;
+        self.inner.add_frozen(address)
+    }
+
+    fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.append(&format!("delete-frozen {}", address))
+            .map_err(|error| Error::DeleteFrozen(error.to_string()))?;
+        self.inner.delete_frozen(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use storage::Ready;
+
+    fn path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("dhcp-storage-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn reloads_leases_after_reopening() {
+        let path = path("reload");
+        let _ = fs::remove_file(&path);
+        let client_id = vec![0x01, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+        let address = Ipv4Addr::new(192, 168, 0, 100);
+        let frozen = Ipv4Addr::new(192, 168, 0, 101);
+
+        let expires_at = {
+            let mut storage = FileStorage::new(&path).unwrap();
+            storage.add_client(&address, &client_id).ready().unwrap();
+            storage
+                .add_lease(&client_id, Lease::new(address, 3600))
+                .ready()
+                .unwrap();
+            storage
+                .update_lease(&client_id, &mut |lease: &mut Lease| {
+                    lease.assign(3600);
+                    lease.set_hostname(Some("client host".to_owned()));
+                })
+                .ready()
+                .unwrap();
+            storage.add_frozen(&frozen).ready().unwrap();
+            storage
+                .get_lease(&client_id)
+                .ready()
+                .unwrap()
+                .unwrap()
+                .expires_at()
+        };
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        assert_eq!(
+            storage.get_client(&address).ready().unwrap(),
+            Some(client_id.clone())
+        );
+        let lease = storage.get_lease(&client_id).ready().unwrap().unwrap();
+        assert_eq!(lease.address(), address);
+        assert_eq!(lease.lease_time(), 3600);
+        assert_eq!(lease.expires_at(), expires_at);
+        assert_eq!(lease.hostname(), Some("client host"));
+        assert!(lease.is_active());
+        assert!(storage.check_frozen(&frozen).ready().unwrap());
+        assert!(!storage.check_frozen(&address).ready().unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replays_deletions_after_reopening() {
+        let path = path("deletions");
+        let _ = fs::remove_file(&path);
+        let client_id = vec![0x01, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+        let address = Ipv4Addr::new(192, 168, 0, 100);
+
+        {
+            let mut storage = FileStorage::new(&path).unwrap();
+            for _ in 0..COMPACTION_MIN_RECORDS {
+                storage.add_client(&address, &client_id).ready().unwrap();
+                storage.add_frozen(&address).ready().unwrap();
+                storage.delete_client(&address).ready().unwrap();
+                storage.delete_frozen(&address).ready().unwrap();
+            }
+            storage.add_client(&address, &client_id).ready().unwrap();
+            storage.delete_client(&address).ready().unwrap();
+        }
+
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < COMPACTION_MIN_RECORDS * 4);
+        let mut storage = FileStorage::new(&path).unwrap();
+        assert_eq!(storage.get_client(&address).ready().unwrap(), None);
+        assert!(!storage.check_frozen(&address).ready().unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_partial_last_record() {
+        let path = path("partial");
+        fs::write(&path, "frozen 192.168.0.100\nlease 01 garb").unwrap();
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        assert!(storage
+            .check_frozen(&Ipv4Addr::new(192, 168, 0, 100))
+            .ready()
+            .unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "frozen 192.168.0.100\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_file() {
+        let path = path("malformed");
+        fs::write(&path, "lease 01 garbage\n").unwrap();
+
+        assert!(FileStorage::new(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+//! The storage journal replaying the operations of a request processed again.
+//!
+//! A request is processed from the start each time the storage becomes ready,
+//! so the operations completed before are answered from the journal instead of the storage.
+//! The writes are not repeated and the reads see the same state as the first time.
+
+use std::net::Ipv4Addr;
+
+use futures::{Async, Poll};
+
+use lease::Lease;
+use storage::{Error, Storage};
+
+/// A storage operation with the arguments identifying it.
+#[derive(PartialEq)]
+enum Operation {
+    GetClient(Ipv4Addr),
+    AddClient(Ipv4Addr, Vec<u8>),
+    DeleteClient(Ipv4Addr),
+    GetLease(Vec<u8>),
+    AddLease(Vec<u8>),
+    UpdateLease(Vec<u8>),
+    CheckFrozen(Ipv4Addr),
+    AddFrozen(Ipv4Addr),
+    DeleteFrozen(Ipv4Addr),
+}
+
+/// The result of a completed storage operation.
+enum Outcome {
+    Client(Option<Vec<u8>>),
+    Lease(Option<Lease>),
+    Frozen(bool),
+    Done,
+}
+
+/// Converts the operation results to the journal outcomes and back.
+trait Journaled: Sized {
+    fn record(&self) -> Outcome;
+
+    fn replay(outcome: &Outcome) -> Option<Self>;
+}
+
+impl Journaled for Option<Vec<u8>> {
+    fn record(&self) -> Outcome {
+        Outcome::Client(self.to_owned())
+    }
+
+    fn replay(outcome: &Outcome) -> Option<Self> {
+        match *outcome {
+            Outcome::Client(ref client_id) => Some(client_id.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl Journaled for Option<Lease> {
+    fn record(&self) -> Outcome {
+        Outcome::Lease(self.to_owned())
+    }
+
+    fn replay(outcome: &Outcome) -> Option<Self> {
+        match *outcome {
+            Outcome::Lease(ref lease) => Some(lease.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl Journaled for bool {
+    fn record(&self) -> Outcome {
+        Outcome::Frozen(*self)
+    }
+
+    fn replay(outcome: &Outcome) -> Option<Self> {
+        match *outcome {
+            Outcome::Frozen(is_frozen) => Some(is_frozen),
+            _ => None,
+        }
+    }
+}
+
+impl Journaled for () {
+    fn record(&self) -> Outcome {
+        Outcome::Done
+    }
+
+    fn replay(outcome: &Outcome) -> Option<Self> {
+        match *outcome {
+            Outcome::Done => Some(()),
+            _ => None,
+        }
+    }
+}
+
+/// Passes everything to the underlying storage, recording the operations of a request.
+pub struct StorageJournal<S> {
+    storage: S,
+    /// `None` if no request is being processed.
+    entries: Option<Vec<(Operation, Outcome)>>,
+    /// The number of entries replayed since the request processing has been restarted.
+    replayed: usize,
+}
+
+impl<S> StorageJournal<S>
+where
+    S: Storage,
+{
+    pub fn new(storage: S) -> Self {
+        StorageJournal {
+            storage,
+            entries: None,
+            replayed: 0,
+        }
+    }
+
+    /// Returns the underlying storage.
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Starts recording the operations of a new request.
+    pub fn begin(&mut self) {
+        self.entries = Some(Vec::new());
+        self.replayed = 0;
+    }
+
+    /// Replays the recorded operations from the start.
+    pub fn rewind(&mut self) {
+        self.replayed = 0;
+    }
+
+    /// Forgets the operations of the processed request.
+    pub fn end(&mut self) {
+        self.entries = None;
+        self.replayed = 0;
+    }
+
+    /// Replays the operation if it is the next one recorded or performs and records it.
+    fn journaled<T, F>(&mut self, operation: Operation, perform: F) -> Poll<T, Error>
+    where
+        T: Journaled,
+        F: FnOnce(&mut S) -> Poll<T, Error>,
+    {
+        if let Some(ref mut entries) = self.entries {
+            if self.replayed < entries.len() {
+                if entries[self.replayed].0 == operation {
+                    if let Some(item) = T::replay(&entries[self.replayed].1) {
+                        self.replayed += 1;
+                        return Ok(Async::Ready(item));
+                    }
+                }
+                // the request has taken another path, so the rest of the journal is stale
+                entries.truncate(self.replayed);
+            }
+        }
+
+        let item = try_ready!(perform(&mut self.storage));
+        if let Some(ref mut entries) = self.entries {
+            entries.push((operation, item.record()));
+            self.replayed += 1;
+        }
+        Ok(Async::Ready(item))
+    }
+}
+
+impl<S> Storage for StorageJournal<S>
+where
+    S: Storage,
+{
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        self.storage.poll_ready()
+    }
+
+    fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
+        self.journaled(Operation::GetClient(address.to_owned()), |storage| {
+            storage.get_client(address)
+        })
+    }
+
+    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), Error> {
+        let operation = Operation::AddClient(address.to_owned(), client_id.to_vec());
+        self.journaled(operation, |storage| storage.add_client(address, client_id))
+    }
+
+    fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.journaled(Operation::DeleteClient(address.to_owned()), |storage| {
+            storage.delete_client(address)
+        })
+    }
+
+    fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error> {
+        self.journaled(Operation::GetLease(client_id.to_vec()), |storage| {
+            storage.get_lease(client_id)
+        })
+    }
+
+    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), Error> {
+        self.journaled(Operation::AddLease(client_id.to_vec()), |storage| {
+            storage.add_lease(client_id, lease)
+        })
+    }
+
+    fn update_lease(
+        &mut self,
+        client_id: &[u8],
+        action: &mut FnMut(&mut Lease),
+    ) -> Poll<(), Error> {
+        self.journaled(Operation::UpdateLease(client_id.to_vec()), |storage| {
+            storage.update_lease(client_id, action)
+        })
+    }
+
+    fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        self.journaled(Operation::CheckFrozen(address.to_owned()), |storage| {
+            storage.check_frozen(address)
+        })
+    }
+
+    fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.journaled(Operation::AddFrozen(address.to_owned()), |storage| {
+            storage.add_frozen(address)
+        })
+    }
+
+    fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.journaled(Operation::DeleteFrozen(address.to_owned()), |storage| {
+            storage.delete_frozen(address)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::Ready;
+    use storage_ram::RamStorage;
+
+    #[test]
+    fn replays_completed_operations() {
+        let client_id = vec![0x01, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+        let address = Ipv4Addr::new(192, 168, 0, 100);
+        let mut journal = StorageJournal::new(RamStorage::new());
+
+        journal.begin();
+        assert_eq!(journal.get_client(&address).ready().unwrap(), None);
+        journal.add_client(&address, &client_id).ready().unwrap();
+        journal
+            .add_lease(&client_id, Lease::new(address, 3600))
+            .ready()
+            .unwrap();
+
+        // the write is not repeated, so the replayed read sees the state it has seen before
+        journal.rewind();
+        assert_eq!(journal.get_client(&address).ready().unwrap(), None);
+        journal
+            .storage_mut()
+            .delete_client(&address)
+            .ready()
+            .unwrap();
+        journal.add_client(&address, &client_id).ready().unwrap();
+        assert_eq!(
+            journal.storage_mut().get_client(&address).ready().unwrap(),
+            None
+        );
+
+        // another path discards the rest of the journal
+        journal.rewind();
+        assert_eq!(journal.get_client(&address).ready().unwrap(), None);
+        journal.delete_client(&address).ready().unwrap();
+        journal.add_client(&address, &client_id).ready().unwrap();
+        assert_eq!(
+            journal.storage_mut().get_client(&address).ready().unwrap(),
+            Some(client_id.clone())
+        );
+
+        journal.end();
+        assert_eq!(
+            journal.get_client(&address).ready().unwrap(),
+            Some(client_id)
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! The storage overlay keeping the changes of a dry-running server in RAM.
+//!
+//! The changes shadow the underlying storage, so the server decisions stay consistent
+//! across the message exchanges, while the storage itself is only read.
+
+use std::{collections::HashMap, net::Ipv4Addr};
+
+use futures::{Async, Poll};
+
+use lease::Lease;
+use storage::{Error, Storage};
+
+/// The changes made in the dry-run mode.
+#[derive(Default)]
+struct Changes {
+    /// `None` if the address has been disassociated.
+    clients: HashMap<Ipv4Addr, Option<Vec<u8>>>,
+    leases: HashMap<Vec<u8>, Lease>,
+    frozen: HashMap<Ipv4Addr, bool>,
+}
+
+/// Passes everything to the underlying storage unless the dry-run mode is enabled.
+pub struct StorageOverlay<S> {
+    storage: S,
+    changes: Option<Changes>,
+}
+
+impl<S> StorageOverlay<S>
+where
+    S: Storage,
+{
+    pub fn new(storage: S) -> Self {
+        StorageOverlay {
+            storage,
+            changes: None,
+        }
+    }
+
+    /// Keeps the further changes in RAM instead of the underlying storage.
+    pub fn set_dry_run(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(Changes::default());
+        }
+    }
+}
+
+impl<S> Storage for StorageOverlay<S>
+where
+    S: Storage,
+{
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        self.storage.poll_ready()
+    }
+
+    fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
+        if let Some(ref changes) = self.changes {
+            if let Some(client_id) = changes.clients.get(address) {
+                return Ok(Async::Ready(client_id.to_owned()));
+            }
+        }
+        self.storage.get_client(address)
+    }
+
+    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), Error> {
+        match self.changes {
+            Some(ref mut changes) => {
+                changes
+                    .clients
+                    .insert(address.to_owned(), Some(client_id.to_vec()));
+                Ok(Async::Ready(()))
+            }
+            None => self.storage.add_client(address, client_id),
+        }
+    }
+
+    fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        match self.changes {
+            Some(ref mut changes) => {
+                changes.clients.insert(address.to_owned(), None);
+                Ok(Async::Ready(()))
+            }
+            None => self.storage.delete_client(address),
+        }
+    }
+
+    fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error> {
+        if let Some(ref changes) = self.changes {
+            if let Some(lease) = changes.leases.get(client_id) {
+                return Ok(Async::Ready(Some(lease.to_owned())));
+            }
+        }
+        self.storage.get_lease(client_id)
+    }
+
+    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), Error> {
+        match self.changes {
+            Some(ref mut changes) => {
+                changes.leases.insert(client_id.to_vec(), lease);
+                Ok(Async::Ready(()))
+            }
+            None => self.storage.add_lease(client_id, lease),
+        }
+    }
+
+    fn update_lease(
+        &mut self,
+        client_id: &[u8],
+        action: &mut FnMut(&mut Lease),
+    ) -> Poll<(), Error> {
+        if self.changes.is_none() {
+            return self.storage.update_lease(client_id, action);
+        }
+        // the lease is copied from the storage on the first change
+        if let Some(mut lease) = try_ready!(self.get_lease(client_id)) {
+            action(&mut lease);
+            if let Some(ref mut changes) = self.changes {
+                changes.leases.insert(client_id.to_vec(), lease);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        if let Some(ref changes) = self.changes {
+            if let Some(&is_frozen) = changes.frozen.get(address) {
+                return Ok(Async::Ready(is_frozen));
+            }
+        }
+        self.storage.check_frozen(address)
+    }
+
+    fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        match self.changes {
+            Some(ref mut changes) => {
+                changes.frozen.insert(address.to_owned(), true);
+                Ok(Async::Ready(()))
+            }
+            None => self.storage.add_frozen(address),
+        }
+    }
+
+    fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        match self.changes {
+            Some(ref mut changes) => {
+                changes.frozen.insert(address.to_owned(), false);
+                Ok(Async::Ready(()))
+            }
+            None => self.storage.delete_frozen(address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::Ready;
+    use storage_ram::RamStorage;
+
+    #[test]
+    fn shadows_storage_in_dry_run() {
+        let client_id = vec![0x01, 0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37];
+        let address = Ipv4Addr::new(192, 168, 0, 100);
+        let mut storage = RamStorage::new();
+        storage.add_client(&address, &client_id).ready().unwrap();
+        storage
+            .add_lease(&client_id, Lease::new(address, 3600))
+            .ready()
+            .unwrap();
+
+        let mut overlay = StorageOverlay::new(storage);
+        overlay.set_dry_run();
+        overlay.delete_client(&address).ready().unwrap();
+        overlay
+            .update_lease(&client_id, &mut |lease: &mut Lease| lease.assign(60))
+            .ready()
+            .unwrap();
+        overlay.add_frozen(&address).ready().unwrap();
+
+        assert_eq!(overlay.get_client(&address).ready().unwrap(), None);
+        assert_eq!(
+            overlay
+                .get_lease(&client_id)
+                .ready()
+                .unwrap()
+                .unwrap()
+                .lease_time(),
+            60
+        );
+        assert!(overlay.check_frozen(&address).ready().unwrap());
+
+        let mut storage = overlay.storage;
+        assert_eq!(
+            storage.get_client(&address).ready().unwrap(),
+            Some(client_id.clone())
+        );
+        let lease = storage.get_lease(&client_id).ready().unwrap().unwrap();
+        assert!(lease.is_offered());
+        assert!(!storage.check_frozen(&address).ready().unwrap());
+    }
+}
@@ -3,16 +3,18 @@
 
 use std::{collections::HashMap, net::Ipv4Addr};
 
+use futures::{Async, Poll};
+
 use lease::Lease;
 use storage::{Error, Storage};
 
 pub struct RamStorage {
     /// `IPv4` to `client_id` mapping.
-    address_client_map: HashMap<Ipv4Addr, Vec<u8>>,
+    pub(crate) address_client_map: HashMap<Ipv4Addr, Vec<u8>>,
     /// `client_id` to `Lease` mapping.
-    client_lease_map: HashMap<Vec<u8>, Lease>,
+    pub(crate) client_lease_map: HashMap<Vec<u8>, Lease>,
     /// `IPv4` addresses reported by `DHCPDECLINE`.
-    frozen_addresses: Vec<Ipv4Addr>,
+    pub(crate) frozen_addresses: Vec<Ipv4Addr>,
 }
 
 impl RamStorage {
@@ -26,55 +28,60 @@ impl RamStorage {
 }
 
 impl Storage for RamStorage {
-    fn get_client(&self, address: &Ipv4Addr) -> Result<Option<Vec<u8>>, Error> {
+    fn get_client(&mut self, address: &Ipv4Addr) -> Poll<Option<Vec<u8>>, Error> {
         if let Some(client_id) = self.address_client_map.get(&address) {
-            Ok(Some(client_id.to_owned()))
+            Ok(Async::Ready(Some(client_id.to_owned())))
         } else {
-            Ok(None)
+            Ok(Async::Ready(None))
         }
     }
 
-    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Result<(), Error> {
+    fn add_client(&mut self, address: &Ipv4Addr, client_id: &[u8]) -> Poll<(), Error> {
         self.address_client_map
             .insert(address.to_owned(), client_id.to_vec());
-        Ok(())
+        Ok(Async::Ready(()))
     }
 
-    fn delete_client(&mut self, address: &Ipv4Addr) -> Result<(), Error> {
+    fn delete_client(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
         self.address_client_map.remove(&address);
-        Ok(())
+        Ok(Async::Ready(()))
     }
 
-    fn get_lease(&self, client_id: &[u8]) -> Result<Option<Lease>, Error> {
+    fn get_lease(&mut self, client_id: &[u8]) -> Poll<Option<Lease>, Error> {
         if let Some(lease) = self.client_lease_map.get(client_id) {
-            Ok(Some(lease.to_owned()))
+            Ok(Async::Ready(Some(lease.to_owned())))
         } else {
-            Ok(None)
+            Ok(Async::Ready(None))
         }
     }
 
-    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Result<(), Error> {
+    fn add_lease(&mut self, client_id: &[u8], lease: Lease) -> Poll<(), Error> {
         self.client_lease_map.insert(client_id.to_vec(), lease);
-        Ok(())
+        Ok(Async::Ready(()))
     }
 
     fn update_lease(
         &mut self,
         client_id: &[u8],
         action: &mut FnMut(&mut Lease) -> (),
-    ) -> Result<(), Error> {
+    ) -> Poll<(), Error> {
         if let Some(ref mut lease) = self.client_lease_map.get_mut(client_id) {
             action(lease);
         }
-        Ok(())
+        Ok(Async::Ready(()))
     }
 
-    fn check_frozen(&self, address: &Ipv4Addr) -> Result<bool, Error> {
-        Ok(self.frozen_addresses.contains(address))
+    fn check_frozen(&mut self, address: &Ipv4Addr) -> Poll<bool, Error> {
+        Ok(Async::Ready(self.frozen_addresses.contains(address)))
     }
 
-    fn add_frozen(&mut self, address: &Ipv4Addr) -> Result<(), Error> {
+    fn add_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
         self.frozen_addresses.push(address.to_owned());
-        Ok(())
+        Ok(Async::Ready(()))
+    }
+
+    fn delete_frozen(&mut self, address: &Ipv4Addr) -> Poll<(), Error> {
+        self.frozen_addresses.retain(|frozen| frozen != address);
+        Ok(Async::Ready(()))
     }
 }
@@ -0,0 +1,70 @@
+//! The systemd socket activation support.
+//!
+//! [sd_listen_fds](https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html)
+
+use std::{env, io, net, os::unix::io::FromRawFd, os::unix::io::RawFd, process};
+
+/// The first file descriptor passed by the service manager.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Adopts the UDP socket passed by the service manager.
+///
+/// The activation environment variables are removed, so child processes do not inherit them.
+///
+/// # Errors
+/// `io::Error` if the process has not been socket-activated.
+pub fn activated_socket() -> io::Result<net::UdpSocket> {
+    activated_socket_from(SD_LISTEN_FDS_START)
+}
+
+/// Adopts the first socket passed by the service manager starting from `listen_fds_start`.
+fn activated_socket_from(listen_fds_start: RawFd) -> io::Result<net::UdpSocket> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    match listen_pid.and_then(|pid| pid.parse::<u32>().ok()) {
+        Some(pid) if pid == process::id() => {}
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The sockets are not passed to this process",
+            ))
+        }
+    }
+    match listen_fds.and_then(|fds| fds.parse::<usize>().ok()) {
+        Some(fds) if fds > 0 => {}
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No sockets are passed by the service manager",
+            ))
+        }
+    }
+
+    Ok(unsafe { net::UdpSocket::from_raw_fd(listen_fds_start) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::IntoRawFd;
+
+    use super::*;
+
+    #[test]
+    fn adopts_the_passed_socket() {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let fd = socket.into_raw_fd();
+
+        env::set_var("LISTEN_PID", process::id().to_string());
+        env::set_var("LISTEN_FDS", "1");
+        let socket = activated_socket_from(fd).unwrap();
+
+        assert_eq!(socket.local_addr().unwrap(), address);
+        assert!(env::var("LISTEN_FDS").is_err());
+        assert!(activated_socket_from(fd).is_err());
+    }
+}
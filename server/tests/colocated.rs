@@ -0,0 +1,101 @@
+//! A server and a client of an all-in-one appliance running on the same runtime.
+//!
+//! The appliance client acquires its upstream address from an upstream server,
+//! while the appliance server configures a downstream client at the same time.
+
+extern crate dhcp_client;
+extern crate dhcp_framed;
+extern crate dhcp_protocol;
+extern crate dhcp_server;
+extern crate eui48;
+extern crate futures;
+extern crate tokio;
+
+mod common;
+
+use std::net::Ipv4Addr;
+
+use eui48::MacAddress;
+use futures::future;
+use tokio::{executor::current_thread, io, prelude::*, runtime::current_thread::Runtime};
+
+use dhcp_client::{Client, Configuration};
+use dhcp_protocol::SIZE_MESSAGE_MINIMAL;
+use dhcp_server::{RamStorage, ServerBuilder};
+
+use common::{bind, Redirect};
+
+/// Spawns a server of the `192.168.<network>.0/24` network and a client acquiring its address.
+///
+/// Each of them gets its own socket, so the pair is isolated from the other ones.
+fn acquire(
+    network: u8,
+    client_hardware_address: MacAddress,
+) -> io::Result<impl Future<Item = Configuration, Error = io::Error>> {
+    let (server_socket, server_address) = bind()?;
+    let (client_socket, client_address) = bind()?;
+
+    let server = ServerBuilder::new(
+        Ipv4Addr::new(192, 168, network, 2),
+        format!("eth{}", network),
+        (
+            Ipv4Addr::new(192, 168, network, 50),
+            Ipv4Addr::new(192, 168, network, 99),
+        ),
+        (
+            Ipv4Addr::new(192, 168, network, 100),
+            Ipv4Addr::new(192, 168, network, 199),
+        ),
+        RamStorage::new(),
+        Ipv4Addr::new(255, 255, 255, 0),
+        vec![Ipv4Addr::new(192, 168, network, 1)],
+        vec![Ipv4Addr::new(192, 168, network, 1)],
+        vec![],
+        vec![],
+    ).finish_with_socket(Redirect {
+        inner: server_socket,
+        peer: client_address,
+    })?;
+    current_thread::spawn(server.map_err(|error| panic!("{}", error)));
+
+    let (sink, stream) = Redirect {
+        inner: client_socket,
+        peer: server_address,
+    }.split();
+    let client = Client::new(
+        stream,
+        sink,
+        client_hardware_address,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Some(SIZE_MESSAGE_MINIMAL as u16),
+        Vec::new(),
+    );
+
+    Ok(client
+        .into_future()
+        .map_err(|(error, _client)| io::Error::from(error))
+        .map(|(configuration, _client)| configuration.unwrap()))
+}
+
+#[test]
+fn server_and_client_work_simultaneously() {
+    let mut runtime = Runtime::new().unwrap();
+
+    let acquisitions = future::lazy(|| -> io::Result<_> {
+        let upstream = acquire(0, MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]))?;
+        let downstream = acquire(1, MacAddress::new([0x00, 0x0c, 0x29, 0x56, 0xab, 0xcd]))?;
+        Ok(upstream.join(downstream))
+    }).flatten();
+    let (upstream, downstream) = runtime.block_on(acquisitions).unwrap();
+
+    assert_eq!(upstream.your_ip_address, Ipv4Addr::new(192, 168, 0, 100));
+    assert_eq!(upstream.server_ip_address, Ipv4Addr::new(192, 168, 0, 2));
+    assert_eq!(downstream.your_ip_address, Ipv4Addr::new(192, 168, 1, 100));
+    assert_eq!(downstream.server_ip_address, Ipv4Addr::new(192, 168, 1, 2));
+    assert_eq!(downstream.routers, Some(vec![Ipv4Addr::new(192, 168, 1, 1)]));
+}
@@ -0,0 +1,56 @@
+//! The helpers shared by the integration tests.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use futures::StartSend;
+use tokio::{io, net::UdpSocket, prelude::*};
+
+use dhcp_framed::{DhcpFramed, DhcpSinkItem, DhcpStreamItem};
+
+/// Delivers every datagram to the peer socket whatever the DHCP destination is,
+/// since broadcasting and hardware unicasting do not work on loopback.
+pub struct Redirect<T> {
+    pub inner: T,
+    pub peer: SocketAddr,
+}
+
+impl<T> Stream for Redirect<T>
+where
+    T: Stream<Item = DhcpStreamItem, Error = io::Error>,
+{
+    type Item = DhcpStreamItem;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<T> Sink for Redirect<T>
+where
+    T: Sink<SinkItem = DhcpSinkItem, SinkError = io::Error>,
+{
+    type SinkItem = DhcpSinkItem;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let (_destination, message) = item;
+        match self.inner.start_send((self.peer, message))? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady((destination, message)) => {
+                Ok(AsyncSink::NotReady((destination, message)))
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+
+/// Binds a socket to a non-privileged port on loopback.
+pub fn bind() -> io::Result<(DhcpFramed, SocketAddr)> {
+    let socket = UdpSocket::bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))?;
+    let address = socket.local_addr()?;
+    Ok((DhcpFramed::new(socket)?, address))
+}
@@ -0,0 +1,84 @@
+//! The DORA round-trip over real UDP sockets on loopback.
+
+extern crate dhcp_client;
+extern crate dhcp_framed;
+extern crate dhcp_protocol;
+extern crate dhcp_server;
+extern crate eui48;
+extern crate futures;
+extern crate tokio;
+
+mod common;
+
+use std::net::Ipv4Addr;
+
+use eui48::MacAddress;
+use futures::future;
+use tokio::{io, prelude::*, runtime::current_thread::Runtime};
+
+use dhcp_client::{Client, Configuration};
+use dhcp_protocol::SIZE_MESSAGE_MINIMAL;
+use dhcp_server::{RamStorage, ServerBuilder};
+
+use common::{bind, Redirect};
+
+#[test]
+fn client_gets_bound_over_loopback() {
+    let mut runtime = Runtime::new().unwrap();
+
+    let acquisition = future::lazy(|| -> io::Result<_> {
+        let (server_socket, server_address) = bind()?;
+        let (client_socket, client_address) = bind()?;
+
+        let server = ServerBuilder::new(
+            Ipv4Addr::new(192, 168, 0, 2),
+            "lo".to_owned(),
+            (
+                Ipv4Addr::new(192, 168, 0, 50),
+                Ipv4Addr::new(192, 168, 0, 99),
+            ),
+            (
+                Ipv4Addr::new(192, 168, 0, 100),
+                Ipv4Addr::new(192, 168, 0, 199),
+            ),
+            RamStorage::new(),
+            Ipv4Addr::new(255, 255, 255, 0),
+            vec![Ipv4Addr::new(192, 168, 0, 1)],
+            vec![Ipv4Addr::new(8, 8, 8, 8)],
+            vec![],
+            vec![],
+        ).finish_with_socket(Redirect {
+            inner: server_socket,
+            peer: client_address,
+        })?;
+        tokio::executor::current_thread::spawn(server.map_err(|error| panic!("{}", error)));
+
+        let (sink, stream) = Redirect {
+            inner: client_socket,
+            peer: server_address,
+        }.split();
+        let client = Client::new(
+            stream,
+            sink,
+            MacAddress::new([0x00, 0x0c, 0x29, 0x13, 0x0e, 0x37]),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(SIZE_MESSAGE_MINIMAL as u16),
+            Vec::new(),
+        );
+
+        Ok(client.into_future().map_err(|(error, _client)| error))
+    }).flatten();
+    let (configuration, _client) = runtime.block_on(acquisition).unwrap();
+    let configuration: Configuration = configuration.unwrap();
+
+    assert_eq!(configuration.your_ip_address, Ipv4Addr::new(192, 168, 0, 100));
+    assert_eq!(configuration.server_ip_address, Ipv4Addr::new(192, 168, 0, 2));
+    assert_eq!(configuration.subnet_mask, Some(Ipv4Addr::new(255, 255, 255, 0)));
+    assert_eq!(configuration.routers, Some(vec![Ipv4Addr::new(192, 168, 0, 1)]));
+    assert_eq!(configuration.domain_name_servers, Some(vec![Ipv4Addr::new(8, 8, 8, 8)]));
+}